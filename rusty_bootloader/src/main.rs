@@ -1,10 +1,16 @@
 #![cfg_attr(not(test), no_std)]
 #![cfg_attr(not(test), no_main)]
 
+#[cfg(test)]
+extern crate core;
 extern crate silica_duet3d_duet2;
 
-static mut A: u32 = 24;
-static mut B: u32 = 0;
+use core::cell::Cell;
+use silica_duet3d_duet2::cortexm4::interrupt;
+use silica_duet3d_duet2::silica::sync::Mutex;
+
+static A: Mutex<Cell<u32>> = Mutex::new(Cell::new(24));
+static B: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
 
 #[cfg_attr(not(test), no_mangle)]
 pub fn main() {
@@ -12,10 +18,10 @@ pub fn main() {
 
     while _a < 100 {
         _a += 1;
-        unsafe {
-            A = _a;
-            B = _a;
-        };
+        interrupt::free(|cs| {
+            A.borrow(cs).set(_a);
+            B.borrow(cs).set(_a);
+        });
     }
 }
 
@@ -0,0 +1,16 @@
+//! Storage addressed by blocks of 512 bytes.
+
+pub const BLOCK_SIZE: usize = 512;
+
+pub trait BlockDevice {
+    type Error;
+
+    /// Capacity in blocks.
+    fn blocks(&self) -> u32;
+
+    /// Reads `buffer.len() / BLOCK_SIZE` blocks from `first`.
+    fn read(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `buffer.len() / BLOCK_SIZE` blocks from `first`.
+    fn write(&mut self, first: u32, buffer: &[u8]) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,206 @@
+//! Synchronous serial buses shared by several devices.
+//!
+//! Device drivers are written against `Spi` and `I2c` and borrow the bus for each exchange, so
+//! that the stepper drivers, temperature sensors and expanders can share one controller.
+
+/// SPI clock polarity and phase.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Idle low, sampled on the rising edge.
+    Mode0,
+    /// Idle low, sampled on the falling edge.
+    Mode1,
+    /// Idle high, sampled on the falling edge.
+    Mode2,
+    /// Idle high, sampled on the rising edge.
+    Mode3,
+}
+
+impl Mode {
+    pub fn polarity(self) -> bool {
+        self == Mode::Mode2 || self == Mode::Mode3
+    }
+
+    /// Whether the data is sampled on the second edge of the clock.
+    pub fn phase(self) -> bool {
+        self == Mode::Mode1 || self == Mode::Mode3
+    }
+}
+
+pub trait Spi {
+    type Error;
+
+    /// Exchanges `buffer` with the device behind the chip select `cs`: each byte is replaced
+    /// by the one received. The chip select stays asserted for the whole buffer.
+    fn transfer(&mut self, cs: u8, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Sends `bytes`, dropping what is received.
+    fn write(&mut self, cs: u8, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// A chip select line driven by software, for more devices than the controller has lines.
+pub trait ChipSelect {
+    fn select(&mut self);
+    fn deselect(&mut self);
+}
+
+/// Runs `f` with the device behind `select` selected.
+pub fn with_select<C, T, F>(select: &mut C, f: F) -> T
+where
+    C: ChipSelect,
+    F: FnOnce() -> T,
+{
+    select.select();
+    let result = f();
+    select.deselect();
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    Seven(u8),
+    Ten(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum I2cError {
+    /// No device acknowledged the address.
+    AddressNack,
+    /// The device refused a data byte.
+    DataNack,
+    /// Another master took the bus.
+    ArbitrationLost,
+    /// The bus did not come back to idle, even after clocking a stuck device out.
+    BusStuck,
+}
+
+pub trait I2c {
+    type Error: From<I2cError>;
+
+    fn write(&mut self, address: Address, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `bytes` then reads `buffer` after a repeated start, without releasing the bus.
+    fn write_read(
+        &mut self,
+        address: Address,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Writes `value` to the register `register` of devices addressed by a register index.
+    fn write_register(
+        &mut self,
+        address: Address,
+        register: u8,
+        value: u8,
+    ) -> Result<(), Self::Error> {
+        self.write(address, &[register, value])
+    }
+
+    /// Reads successive registers from `register`.
+    fn read_registers(
+        &mut self,
+        address: Address,
+        register: u8,
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write_read(address, &[register], buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An expander with 4 registers, at address 0x20.
+    struct Expander {
+        registers: [u8; 4],
+        pointer: usize,
+        repeated_starts: usize,
+    }
+    impl Expander {
+        fn check(&self, address: Address) -> Result<(), I2cError> {
+            if address == Address::Seven(0x20) {
+                Ok(())
+            } else {
+                Err(I2cError::AddressNack)
+            }
+        }
+    }
+    impl I2c for Expander {
+        type Error = I2cError;
+        fn write(&mut self, address: Address, bytes: &[u8]) -> Result<(), I2cError> {
+            self.check(address)?;
+            self.pointer = usize::from(bytes[0]);
+            for &b in &bytes[1..] {
+                self.registers[self.pointer] = b;
+                self.pointer += 1;
+            }
+            Ok(())
+        }
+        fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<(), I2cError> {
+            self.check(address)?;
+            for b in buffer.iter_mut() {
+                *b = self.registers[self.pointer];
+                self.pointer += 1;
+            }
+            Ok(())
+        }
+        fn write_read(
+            &mut self,
+            address: Address,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), I2cError> {
+            self.write(address, bytes)?;
+            self.repeated_starts += 1;
+            self.read(address, buffer)
+        }
+    }
+
+    #[test]
+    fn test_register_access() {
+        let mut expander = Expander {
+            registers: [0; 4],
+            pointer: 0,
+            repeated_starts: 0,
+        };
+        let address = Address::Seven(0x20);
+        expander.write_register(address, 2, 0xA5).unwrap();
+        expander.write_register(address, 3, 0x5A).unwrap();
+        let mut read = [0; 2];
+        expander.read_registers(address, 2, &mut read).unwrap();
+        assert_eq!([0xA5, 0x5A], read);
+        assert_eq!(1, expander.repeated_starts);
+        assert_eq!(
+            Err(I2cError::AddressNack),
+            expander.write_register(Address::Ten(0x20), 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_mode_and_select() {
+        assert_eq!(
+            (false, false),
+            (Mode::Mode0.polarity(), Mode::Mode0.phase())
+        );
+        assert_eq!((true, true), (Mode::Mode3.polarity(), Mode::Mode3.phase()));
+
+        /// Selected, and the number of selections.
+        struct Line(bool, u32);
+        impl ChipSelect for Line {
+            fn select(&mut self) {
+                self.0 = true;
+                self.1 += 1;
+            }
+            fn deselect(&mut self) {
+                self.0 = false;
+            }
+        }
+        let mut line = Line(false, 0);
+        assert_eq!(7, with_select(&mut line, || 7));
+        assert_eq!((false, 1), (line.0, line.1));
+    }
+}
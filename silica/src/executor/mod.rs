@@ -0,0 +1,284 @@
+//! # Allocation free executor for futures
+//!
+//! Tasks are pinned futures borrowed by the executor. Each task slot owns a flag in a static
+//! `ReadyFlags` and its waker merely sets that flag, so waking a task from an interrupt handler
+//! is a single atomic store. Only the tasks whose flag is set get polled, and the executor lets
+//! the core sleep when none is.
+//!
+//! Drivers complete their futures from interrupt handlers by waking the `WakerCell` the future
+//! registered in before returning `Poll::Pending`:
+//!
+//! ```ignore
+//! static RX_WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+//!
+//! fn read_byte() -> impl Future<Output = u8> {
+//!     poll_fn(|cx| interrupt::free(|cs| match uart.try_read() {
+//!         Some(b) => Poll::Ready(b),
+//!         None => {
+//!             RX_WAKER.borrow(cs).register(cx.waker());
+//!             Poll::Pending
+//!         }
+//!     }))
+//! }
+//!
+//! // in the UART interrupt handler
+//! interrupt::free(|cs| RX_WAKER.borrow(cs).wake());
+//! ```
+
+mod timer;
+mod waker;
+
+pub use self::timer::{delay, delay_until, Delay, Timer, TimerQueue, MAX_TIMERS};
+pub use self::waker::WakerCell;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// Maximum number of tasks of an executor.
+pub const MAX_TASKS: usize = 8;
+
+/// A future borrowed by the executor until it completes.
+pub type Task<'a> = Pin<&'a mut (dyn Future<Output = ()> + 'a)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpawnError {
+    TooManyTasks,
+}
+
+/// Puts the core to sleep while the executor has nothing to do.
+pub trait Sleep {
+    /// Sleeps until an interrupt fires unless `has_work` returns true. `has_work` must be
+    /// evaluated such that a wake up happening concurrently cannot be missed.
+    fn sleep_unless<F>(&self, has_work: F)
+    where
+        F: Fn() -> bool;
+}
+
+/// Wake up flags of an executor's tasks. They are kept in a static so that wakers stay valid
+/// regardless of what happens to the executor.
+pub struct ReadyFlags {
+    flags: [AtomicBool; MAX_TASKS],
+}
+impl ReadyFlags {
+    pub const fn new() -> ReadyFlags {
+        ReadyFlags {
+            flags: [
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+            ],
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.flags.iter().any(|f| f.load(Ordering::Acquire))
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+unsafe fn clone(flag: *const ()) -> RawWaker {
+    RawWaker::new(flag, &VTABLE)
+}
+unsafe fn wake(flag: *const ()) {
+    (*(flag as *const AtomicBool)).store(true, Ordering::Release);
+}
+unsafe fn drop(_: *const ()) {}
+
+fn waker(flag: &'static AtomicBool) -> Waker {
+    unsafe {
+        Waker::from_raw(RawWaker::new(
+            flag as *const AtomicBool as *const (),
+            &VTABLE,
+        ))
+    }
+}
+
+pub struct Executor<'a> {
+    tasks: [Option<Task<'a>>; MAX_TASKS],
+    ready: &'static ReadyFlags,
+}
+impl<'a> Executor<'a> {
+    /// `ready` must not be shared with another executor.
+    pub fn new(ready: &'static ReadyFlags) -> Executor<'a> {
+        Executor {
+            tasks: [None, None, None, None, None, None, None, None],
+            ready,
+        }
+    }
+
+    /// Adds a task, it gets polled by the next call to `poll`.
+    pub fn spawn<F>(&mut self, task: Pin<&'a mut F>) -> Result<usize, SpawnError>
+    where
+        F: Future<Output = ()> + 'a,
+    {
+        let id = self
+            .tasks
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SpawnError::TooManyTasks)?;
+        self.tasks[id] = Some(task);
+        self.ready.flags[id].store(true, Ordering::Release);
+        Ok(id)
+    }
+
+    /// Number of tasks not yet completed.
+    pub fn len(&self) -> usize {
+        self.tasks.iter().filter(|t| t.is_some()).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True if a task has been woken up since it was last polled.
+    pub fn has_work(&self) -> bool {
+        self.ready.any()
+    }
+
+    /// Polls every woken up task once and returns how many were polled.
+    pub fn poll(&mut self) -> usize {
+        let ready = self.ready;
+        let mut polled = 0;
+        for (id, slot) in self.tasks.iter_mut().enumerate() {
+            let flag = &ready.flags[id];
+            if !flag.swap(false, Ordering::AcqRel) {
+                continue;
+            }
+            let done = match slot {
+                Some(task) => {
+                    polled += 1;
+                    let waker = waker(flag);
+                    let mut cx = Context::from_waker(&waker);
+                    task.as_mut().poll(&mut cx) == Poll::Ready(())
+                }
+                None => false,
+            };
+            if done {
+                *slot = None;
+            }
+        }
+        polled
+    }
+
+    /// Polls the tasks until they all complete, sleeping whenever none of them has been woken
+    /// up.
+    pub fn run<S>(&mut self, sleep: &S)
+    where
+        S: Sleep,
+    {
+        loop {
+            self.poll();
+            if self.is_empty() {
+                break;
+            }
+            let ready = self.ready;
+            sleep.sleep_unless(|| ready.any());
+        }
+    }
+}
+
+/// A future calling `f` on every poll.
+pub struct PollFn<F> {
+    f: F,
+}
+impl<T, F> Future for PollFn<F>
+where
+    F: FnMut(&mut Context) -> Poll<T> + Unpin,
+{
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        (Pin::get_mut(self).f)(cx)
+    }
+}
+
+pub fn poll_fn<T, F>(f: F) -> PollFn<F>
+where
+    F: FnMut(&mut Context) -> Poll<T> + Unpin,
+{
+    PollFn { f }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use sync::{CriticalSection, Mutex};
+
+    struct NoSleep;
+    impl Sleep for NoSleep {
+        fn sleep_unless<F>(&self, has_work: F)
+        where
+            F: Fn() -> bool,
+        {
+            assert!(has_work(), "the executor would sleep forever");
+        }
+    }
+
+    #[test]
+    fn test_wakeup_from_interrupt() {
+        static READY: ReadyFlags = ReadyFlags::new();
+        static IRQ_WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+        let cs = unsafe { CriticalSection::new() };
+        let data = Cell::new(None);
+        let polls = Cell::new(0);
+
+        let mut task = poll_fn(|cx| {
+            polls.set(polls.get() + 1);
+            match data.get() {
+                Some(_) => Poll::Ready(()),
+                None => {
+                    IRQ_WAKER.borrow(&cs).register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        });
+        let mut executor = Executor::new(&READY);
+        executor.spawn(Pin::new(&mut task)).unwrap();
+
+        assert_eq!(1, executor.poll());
+        assert!(!executor.has_work());
+        assert_eq!(0, executor.poll());
+
+        // the "interrupt handler"
+        data.set(Some(42));
+        IRQ_WAKER.borrow(&cs).wake();
+
+        assert!(executor.has_work());
+        executor.run(&NoSleep);
+        assert_eq!(2, polls.get());
+        assert!(executor.is_empty());
+    }
+
+    #[derive(Clone, Copy)]
+    struct Done;
+    impl Future for Done {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            Poll::Ready(())
+        }
+    }
+
+    #[test]
+    fn test_spawn_limit() {
+        static READY: ReadyFlags = ReadyFlags::new();
+        let mut tasks = [Done; MAX_TASKS + 1];
+        let mut executor = Executor::new(&READY);
+        let (last, tasks) = tasks.split_last_mut().unwrap();
+        for t in tasks.iter_mut() {
+            executor.spawn(Pin::new(t)).unwrap();
+        }
+        assert_eq!(
+            Err(SpawnError::TooManyTasks),
+            executor.spawn(Pin::new(last))
+        );
+        assert_eq!(MAX_TASKS, executor.poll());
+        assert!(executor.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use time::{Clock, Duration, Instant};
+
+/// Maximum number of pending deadlines of a `TimerQueue`.
+pub const MAX_TIMERS: usize = 8;
+
+/// A clock able to wake a task up at a given instant.
+pub trait Timer: Clock {
+    /// Wakes `waker` once `deadline` is reached. Registering the same waker again replaces its
+    /// previous deadline.
+    fn wake_at(&self, deadline: Instant, waker: &Waker);
+}
+
+/// Pending deadlines, usually walked from the periodic tick interrupt.
+pub struct TimerQueue {
+    timers: [Option<(Instant, Waker)>; MAX_TIMERS],
+}
+impl TimerQueue {
+    pub const fn new() -> TimerQueue {
+        TimerQueue {
+            timers: [None, None, None, None, None, None, None, None],
+        }
+    }
+
+    /// Returns false if the queue is full, the caller should then wake the task immediately so
+    /// that it polls its deadline again.
+    pub fn register(&mut self, deadline: Instant, waker: &Waker) -> bool {
+        let slot = self
+            .timers
+            .iter()
+            .position(|t| match t {
+                Some((_, w)) => w.will_wake(waker),
+                None => false,
+            })
+            .or_else(|| self.timers.iter().position(Option::is_none));
+        match slot {
+            Some(i) => {
+                self.timers[i] = Some((deadline, waker.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes the tasks whose deadline is reached and returns how many were woken up.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut woken = 0;
+        for t in self.timers.iter_mut() {
+            let expired = match t {
+                Some((deadline, _)) => *deadline <= now,
+                None => false,
+            };
+            if expired {
+                if let Some((_, w)) = t.take() {
+                    w.wake();
+                    woken += 1;
+                }
+            }
+        }
+        woken
+    }
+
+    /// Earliest pending deadline.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers
+            .iter()
+            .filter_map(Option::as_ref)
+            .map(|t| t.0)
+            .min()
+    }
+}
+impl Default for TimerQueue {
+    fn default() -> TimerQueue {
+        TimerQueue::new()
+    }
+}
+
+/// Future completing at a given instant.
+pub struct Delay<'t, T: 't> {
+    timer: &'t T,
+    deadline: Instant,
+}
+impl<'t, T: Timer> Future for Delay<'t, T> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.timer.now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            self.timer.wake_at(self.deadline, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+pub fn delay_until<T: Timer>(timer: &T, deadline: Instant) -> Delay<T> {
+    Delay { timer, deadline }
+}
+
+pub fn delay<T: Timer>(timer: &T, duration: Duration) -> Delay<T> {
+    delay_until(timer, timer.now() + duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Executor, ReadyFlags};
+    use super::*;
+    use core::cell::{Cell, RefCell};
+
+    /// Simulated clock advanced by the test.
+    struct SimTimer {
+        now: Cell<Instant>,
+        queue: RefCell<TimerQueue>,
+    }
+    impl SimTimer {
+        fn advance(&self, micros: u64) {
+            let now = self.now.get() + Duration::from_micros(micros);
+            self.now.set(now);
+            self.queue.borrow_mut().expire(now);
+        }
+    }
+    impl Clock for SimTimer {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+    }
+    impl Timer for SimTimer {
+        fn wake_at(&self, deadline: Instant, waker: &Waker) {
+            assert!(self.queue.borrow_mut().register(deadline, waker));
+        }
+    }
+
+    #[test]
+    fn test_delays() {
+        static READY: ReadyFlags = ReadyFlags::new();
+        let timer = SimTimer {
+            now: Cell::new(Instant::from_micros(0)),
+            queue: RefCell::new(TimerQueue::new()),
+        };
+        let mut short = delay(&timer, Duration::from_micros(10));
+        let mut long = delay(&timer, Duration::from_micros(25));
+        let mut executor = Executor::new(&READY);
+        executor.spawn(Pin::new(&mut short)).unwrap();
+        executor.spawn(Pin::new(&mut long)).unwrap();
+
+        assert_eq!(2, executor.poll());
+        assert_eq!(
+            Some(Instant::from_micros(10)),
+            timer.queue.borrow().next_deadline()
+        );
+        timer.advance(5);
+        assert!(!executor.has_work());
+        timer.advance(5);
+        assert_eq!(1, executor.poll());
+        assert_eq!(1, executor.len());
+        timer.advance(20);
+        assert_eq!(1, executor.poll());
+        assert!(executor.is_empty());
+        assert_eq!(None, timer.queue.borrow().next_deadline());
+    }
+}
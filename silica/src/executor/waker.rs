@@ -0,0 +1,43 @@
+use core::cell::Cell;
+use core::task::Waker;
+
+/// Storage for the waker of the task waiting on an event, usually an interrupt.
+///
+/// It holds a single waker: a peripheral event is expected to be awaited by one task at a time.
+/// Share it between the task and the interrupt handler through a `sync::Mutex`.
+pub struct WakerCell {
+    waker: Cell<Option<Waker>>,
+}
+impl WakerCell {
+    pub const fn new() -> WakerCell {
+        WakerCell {
+            waker: Cell::new(None),
+        }
+    }
+
+    /// Replaces the stored waker, unless it would wake the same task.
+    pub fn register(&self, waker: &Waker) {
+        let current = self.waker.take();
+        let keep = current.as_ref().map_or(false, |w| w.will_wake(waker));
+        self.waker.set(if keep { current } else { Some(waker.clone()) });
+    }
+
+    /// Wakes the registered task, if any.
+    pub fn wake(&self) {
+        if let Some(w) = self.waker.take() {
+            w.wake();
+        }
+    }
+
+    pub fn is_registered(&self) -> bool {
+        let waker = self.waker.take();
+        let registered = waker.is_some();
+        self.waker.set(waker);
+        registered
+    }
+}
+impl Default for WakerCell {
+    fn default() -> WakerCell {
+        WakerCell::new()
+    }
+}
@@ -0,0 +1,197 @@
+//! Write-back cache of the most recently used blocks.
+
+use block::{BlockDevice, BLOCK_SIZE};
+
+/// Cached blocks: 4KB, enough for a FAT block, a directory block and a file's data block at a
+/// time without eating much of a 128KB RAM.
+pub const CACHE_BLOCKS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    block: u32,
+    valid: bool,
+    dirty: bool,
+    last_use: u32,
+    data: [u8; BLOCK_SIZE],
+}
+
+pub struct Cache {
+    slots: [Slot; CACHE_BLOCKS],
+    clock: u32,
+    /// First block of the first FAT, blocks per FAT and number of FATs. The writes to the first
+    /// FAT are replicated to the others.
+    fats: (u32, u32, u32),
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            slots: [Slot {
+                block: 0,
+                valid: false,
+                dirty: false,
+                last_use: 0,
+                data: [0; BLOCK_SIZE],
+            }; CACHE_BLOCKS],
+            clock: 0,
+            fats: (0, 0, 0),
+        }
+    }
+
+    pub fn mirror_fats(&mut self, first: u32, size: u32, count: u32) {
+        self.fats = (first, size, count);
+    }
+
+    /// A cached block, read from the device unless `load` is false.
+    fn slot<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+        load: bool,
+    ) -> Result<usize, D::Error> {
+        self.clock = self.clock.wrapping_add(1);
+        if let Some(i) = self.slots.iter().position(|s| s.valid && s.block == block) {
+            self.slots[i].last_use = self.clock;
+            return Ok(i);
+        }
+
+        let clock = self.clock;
+        let mut victim = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            if !slot.valid {
+                victim = i;
+                break;
+            }
+            let age = clock.wrapping_sub(slot.last_use);
+            if age > clock.wrapping_sub(self.slots[victim].last_use) {
+                victim = i;
+            }
+        }
+        self.write_back(device, victim)?;
+
+        let slot = &mut self.slots[victim];
+        slot.valid = false;
+        if load {
+            device.read(block, &mut slot.data)?;
+        }
+        slot.block = block;
+        slot.valid = true;
+        slot.last_use = clock;
+        Ok(victim)
+    }
+
+    fn write_back<D: BlockDevice>(&mut self, device: &mut D, i: usize) -> Result<(), D::Error> {
+        let slot = &mut self.slots[i];
+        if !slot.valid || !slot.dirty {
+            return Ok(());
+        }
+        device.write(slot.block, &slot.data)?;
+        let (first, size, count) = self.fats;
+        if slot.block >= first && slot.block < first + size {
+            for copy in 1..count {
+                device.write(slot.block + copy * size, &slot.data)?;
+            }
+        }
+        slot.dirty = false;
+        Ok(())
+    }
+
+    pub fn read<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<&[u8; BLOCK_SIZE], D::Error> {
+        let i = self.slot(device, block, true)?;
+        Ok(&self.slots[i].data)
+    }
+
+    /// The block, marked to be written back.
+    pub fn write<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<&mut [u8; BLOCK_SIZE], D::Error> {
+        let i = self.slot(device, block, true)?;
+        self.slots[i].dirty = true;
+        Ok(&mut self.slots[i].data)
+    }
+
+    /// The block, zeroed rather than read from the device.
+    pub fn zero<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        block: u32,
+    ) -> Result<&mut [u8; BLOCK_SIZE], D::Error> {
+        let i = self.slot(device, block, false)?;
+        let slot = &mut self.slots[i];
+        slot.dirty = true;
+        for b in slot.data.iter_mut() {
+            *b = 0;
+        }
+        Ok(&mut slot.data)
+    }
+
+    /// Writes all the modified blocks back.
+    pub fn flush<D: BlockDevice>(&mut self, device: &mut D) -> Result<(), D::Error> {
+        for i in 0..CACHE_BLOCKS {
+            self.write_back(device, i)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKS: usize = 16;
+
+    struct Ram {
+        data: [[u8; BLOCK_SIZE]; BLOCKS],
+        writes: usize,
+    }
+    impl BlockDevice for Ram {
+        type Error = ();
+        fn blocks(&self) -> u32 {
+            BLOCKS as u32
+        }
+        fn read(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), ()> {
+            buffer.copy_from_slice(&self.data[first as usize]);
+            Ok(())
+        }
+        fn write(&mut self, first: u32, buffer: &[u8]) -> Result<(), ()> {
+            self.writes += 1;
+            self.data[first as usize].copy_from_slice(buffer);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_back_and_eviction() {
+        let mut ram = Ram {
+            data: [[0; BLOCK_SIZE]; BLOCKS],
+            writes: 0,
+        };
+        let mut cache = Cache::new();
+        // two FATs of 2 blocks from block 1.
+        cache.mirror_fats(1, 2, 2);
+
+        cache.write(&mut ram, 2).unwrap()[0] = 0xAA;
+        cache.write(&mut ram, 5).unwrap()[0] = 0x55;
+        assert_eq!(0, ram.writes);
+        // keep block 2 the most recently used while the others push block 5 out.
+        for block in 6..6 + CACHE_BLOCKS as u32 - 1 {
+            cache.read(&mut ram, 2).unwrap();
+            cache.read(&mut ram, block).unwrap();
+        }
+        assert_eq!(1, ram.writes);
+        assert_eq!(0x55, ram.data[5][0]);
+
+        cache.flush(&mut ram).unwrap();
+        assert_eq!(0xAA, ram.data[2][0]);
+        assert_eq!(0xAA, ram.data[4][0]);
+        assert_eq!(3, ram.writes);
+        cache.flush(&mut ram).unwrap();
+        assert_eq!(3, ram.writes);
+    }
+}
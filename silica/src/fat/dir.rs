@@ -0,0 +1,501 @@
+//! Directory entries and long file names.
+
+use core::char;
+use core::fmt;
+use core::str;
+use time::DateTime;
+
+pub const ENTRY_SIZE: usize = 32;
+/// Longest file name, in UTF-16 units.
+pub const MAX_NAME: usize = 255;
+/// UTF-16 units per long name entry.
+const LFN_UNITS: usize = 13;
+/// Offsets of the UTF-16 units in a long name entry.
+const LFN_OFFSETS: [usize; LFN_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const LFN_LAST: u8 = 0x40;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// Marks a free entry, the first byte of the name.
+pub const DELETED: u8 = 0xE5;
+/// Marks the end of the directory.
+pub const END: u8 = 0x00;
+
+/// The base, then the extension, are in lower case (a Windows NT extension).
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// A file name, in UTF-8.
+#[derive(Clone, Copy)]
+pub struct Name {
+    bytes: [u8; MAX_NAME * 3],
+    len: usize,
+}
+
+impl Name {
+    fn new() -> Name {
+        Name {
+            bytes: [0; MAX_NAME * 3],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        let len = c.len_utf8();
+        if self.len + len <= self.bytes.len() {
+            c.encode_utf8(&mut self.bytes[self.len..]);
+            self.len += len;
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole chars are pushed.
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+/// Where an entry lives: its directory, the index of its short entry and of its first long name
+/// entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub dir: u32,
+    pub index: u32,
+    pub first: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirEntry {
+    name: Name,
+    short_name: [u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+    modified: DateTime,
+    location: Location,
+}
+
+impl DirEntry {
+    /// The long name, or the short one if there is none.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    pub fn short_name(&self) -> &[u8; 11] {
+        &self.short_name
+    }
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    /// Time of the last modification, to 2 seconds.
+    pub fn modified(&self) -> DateTime {
+        self.modified
+    }
+    pub fn first_cluster(&self) -> u32 {
+        self.first_cluster
+    }
+    pub fn location(&self) -> Location {
+        self.location
+    }
+    /// Matches `name` against both names, ignoring the ASCII case.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.name().eq_ignore_ascii_case(name) {
+            return true;
+        }
+        let mut short = [0; 12];
+        let len = format_short(&self.short_name, 0, &mut short);
+        name.as_bytes().eq_ignore_ascii_case(&short[..len])
+    }
+}
+
+/// Formats a short name as `BASE.EXT` in `out`, returns the length.
+fn format_short(short: &[u8; 11], case: u8, out: &mut [u8; 12]) -> usize {
+    let mut len = 0;
+    let base_len = short[..8]
+        .iter()
+        .rposition(|&c| c != b' ')
+        .map_or(0, |i| i + 1);
+    let ext_len = short[8..]
+        .iter()
+        .rposition(|&c| c != b' ')
+        .map_or(0, |i| i + 1);
+    for &c in &short[..base_len] {
+        out[len] = if case & CASE_LOWER_BASE != 0 {
+            c.to_ascii_lowercase()
+        } else {
+            c
+        };
+        len += 1;
+    }
+    if ext_len > 0 {
+        out[len] = b'.';
+        len += 1;
+        for &c in &short[8..8 + ext_len] {
+            out[len] = if case & CASE_LOWER_EXT != 0 {
+                c.to_ascii_lowercase()
+            } else {
+                c
+            };
+            len += 1;
+        }
+    }
+    if len > 0 && out[0] == 0x05 {
+        // an initial 0xE5 is stored as 0x05.
+        out[0] = DELETED;
+    }
+    len
+}
+
+/// Checksum of a short name, stored in its long name entries.
+pub fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+pub fn read_u16(raw: &[u8], offset: usize) -> u16 {
+    u16::from(raw[offset]) | u16::from(raw[offset + 1]) << 8
+}
+
+pub fn write_u16(raw: &mut [u8], offset: usize, value: u16) {
+    raw[offset] = value as u8;
+    raw[offset + 1] = (value >> 8) as u8;
+}
+
+pub fn read_u32(raw: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(raw, offset)) | u32::from(read_u16(raw, offset + 2)) << 16
+}
+
+pub fn write_u32(raw: &mut [u8], offset: usize, value: u32) {
+    write_u16(raw, offset, value as u16);
+    write_u16(raw, offset + 2, (value >> 16) as u16);
+}
+
+/// Gathers the long name entries preceding a short entry.
+pub struct Parser {
+    units: [u16; MAX_NAME + LFN_UNITS],
+    /// Sequence number expected next, 0 when no long name is pending.
+    expected: u8,
+    checksum: u8,
+    first: u32,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            units: [0; MAX_NAME + LFN_UNITS],
+            expected: 0,
+            checksum: 0,
+            first: 0,
+        }
+    }
+
+    /// Feeds the entry at `index`, returns the complete entry when it is a short one.
+    pub fn feed(&mut self, raw: &[u8], dir: u32, index: u32) -> Option<DirEntry> {
+        if raw[0] == DELETED || raw[0] == END {
+            self.expected = 0;
+            return None;
+        }
+        let attributes = raw[11];
+        if attributes & 0x3F == ATTR_LONG_NAME {
+            let sequence = raw[0] & !LFN_LAST;
+            if raw[0] & LFN_LAST != 0 {
+                self.expected = sequence;
+                self.checksum = raw[13];
+                self.first = index;
+                let end = usize::from(sequence) * LFN_UNITS;
+                if sequence == 0 || end > self.units.len() {
+                    self.expected = 0;
+                    return None;
+                }
+                // forget the previous name.
+                for u in self.units[..end].iter_mut() {
+                    *u = 0;
+                }
+            } else if sequence != self.expected || raw[13] != self.checksum {
+                self.expected = 0;
+                return None;
+            }
+            if self.expected == 0 || sequence == 0 {
+                return None;
+            }
+            let start = usize::from(sequence - 1) * LFN_UNITS;
+            for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+                self.units[start + i] = read_u16(raw, offset);
+            }
+            self.expected = sequence - 1;
+            // the entry with sequence 1 is followed by its short entry.
+            if self.expected == 0 {
+                self.expected = 0xFF;
+            }
+            return None;
+        }
+
+        let mut short = [0; 11];
+        short.copy_from_slice(&raw[..11]);
+        let mut name = Name::new();
+        let mut first = index;
+        if self.expected == 0xFF && self.checksum == checksum(&short) {
+            first = self.first;
+            let len = self.units.iter().position(|&u| u == 0 || u == 0xFFFF);
+            let units = &self.units[..len.unwrap_or(MAX_NAME)];
+            for c in char::decode_utf16(units.iter().cloned()) {
+                name.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+        } else {
+            let mut formatted = [0; 12];
+            let len = format_short(&short, raw[12], &mut formatted);
+            for &c in &formatted[..len] {
+                name.push(char::from(c));
+            }
+        }
+        self.expected = 0;
+
+        Some(DirEntry {
+            name,
+            short_name: short,
+            attributes,
+            first_cluster: u32::from(read_u16(raw, 20)) << 16 | u32::from(read_u16(raw, 26)),
+            size: read_u32(raw, 28),
+            modified: DateTime::from_fat(read_u16(raw, 24), read_u16(raw, 22)),
+            location: Location { dir, index, first },
+        })
+    }
+}
+
+/// Fills a short entry.
+pub fn write_short(
+    raw: &mut [u8],
+    short: &[u8; 11],
+    case: u8,
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+    modified: DateTime,
+) {
+    for b in raw[..ENTRY_SIZE].iter_mut() {
+        *b = 0;
+    }
+    raw[..11].copy_from_slice(short);
+    raw[11] = attributes;
+    raw[12] = case;
+    let (date, time) = modified.to_fat();
+    write_u16(raw, 14, time);
+    write_u16(raw, 16, date);
+    write_u16(raw, 18, date);
+    write_u16(raw, 22, time);
+    write_u16(raw, 24, date);
+    set_cluster(raw, first_cluster);
+    write_u32(raw, 28, size);
+}
+
+pub fn set_cluster(raw: &mut [u8], cluster: u32) {
+    write_u16(raw, 20, (cluster >> 16) as u16);
+    write_u16(raw, 26, cluster as u16);
+}
+
+/// Fills the long name entry `sequence` (from 1) of `name`, `count` entries long.
+pub fn write_long(raw: &mut [u8], name: &str, sequence: u8, count: u8, checksum: u8) {
+    let start = usize::from(sequence - 1) * LFN_UNITS;
+    let mut units = name.encode_utf16().skip(start);
+    let mut terminated = false;
+    for &offset in LFN_OFFSETS.iter() {
+        let unit = match units.next() {
+            Some(u) => u,
+            None if !terminated => {
+                terminated = true;
+                0
+            }
+            None => 0xFFFF,
+        };
+        write_u16(raw, offset, unit);
+    }
+    raw[0] = if sequence == count {
+        sequence | LFN_LAST
+    } else {
+        sequence
+    };
+    raw[11] = ATTR_LONG_NAME;
+    raw[12] = 0;
+    raw[13] = checksum;
+    write_u16(raw, 26, 0);
+}
+
+/// Long name entries needed for `name`.
+pub fn long_entries(name: &str) -> u8 {
+    ((name.encode_utf16().count() + LFN_UNITS - 1) / LFN_UNITS) as u8
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&c) || c >= 0x80
+}
+
+/// Checks that `name` can be a file name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME
+        && !name.ends_with('.')
+        && !name.ends_with(' ')
+        && name.chars().all(|c| c >= ' ' && !"\"*/:<>?\\|".contains(c))
+}
+
+/// The short name and case flags of `name` when it needs no long name entry.
+pub fn as_short(name: &str) -> Option<([u8; 11], u8)> {
+    let bytes = name.as_bytes();
+    let (base, ext) = match bytes.iter().position(|&c| c == b'.') {
+        Some(dot) => (&bytes[..dot], &bytes[dot + 1..]),
+        None => (bytes, &bytes[..0]),
+    };
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base
+            .iter()
+            .chain(ext.iter())
+            .all(|&c| c < 0x80 && is_short_char(c))
+    {
+        return None;
+    }
+    let case_of = |part: &[u8], flag: u8| {
+        if part.iter().any(u8::is_ascii_lowercase) {
+            if part.iter().any(u8::is_ascii_uppercase) {
+                None
+            } else {
+                Some(flag)
+            }
+        } else {
+            Some(0)
+        }
+    };
+    let case = case_of(base, CASE_LOWER_BASE)? | case_of(ext, CASE_LOWER_EXT)?;
+    let mut short = [b' '; 11];
+    for (s, c) in short.iter_mut().zip(base) {
+        *s = c.to_ascii_uppercase();
+    }
+    for (s, c) in short[8..].iter_mut().zip(ext) {
+        *s = c.to_ascii_uppercase();
+    }
+    Some((short, case))
+}
+
+/// The short name `BASE~N.EXT` standing for a long name.
+pub fn numbered_short(name: &str, n: u32) -> [u8; 11] {
+    let mut short = [b' '; 11];
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    let clean = |c: char| {
+        if c.is_ascii() && is_short_char(c as u8) {
+            Some(c.to_ascii_uppercase() as u8)
+        } else if c == ' ' || c == '.' {
+            None
+        } else {
+            Some(b'_')
+        }
+    };
+
+    let mut digits = [0u8; 10];
+    let mut count = 0;
+    let mut v = n;
+    loop {
+        digits[count] = b'0' + (v % 10) as u8;
+        count += 1;
+        v /= 10;
+        if v == 0 {
+            break;
+        }
+    }
+    let mut len = 0;
+    for c in base.chars().filter_map(clean).take(7 - count) {
+        short[len] = c;
+        len += 1;
+    }
+    short[len] = b'~';
+    for i in 0..count {
+        short[len + 1 + i] = digits[count - 1 - i];
+    }
+    for (s, c) in short[8..].iter_mut().zip(ext.chars().filter_map(clean)) {
+        *s = c;
+    }
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_names() {
+        assert_eq!(Some((*b"CONFIG  G  ", 0x18)), as_short("config.g"));
+        assert_eq!(Some((*b"README     ", 0)), as_short("README"));
+        assert_eq!(None, as_short("Config.g"));
+        assert_eq!(None, as_short("toolong12.g"));
+        assert_eq!(None, as_short("a b.txt"));
+        assert_eq!(*b"MYPRIN~1GCO", numbered_short("My print.gcode", 1));
+        assert_eq!(*b"ARCHI~12GZ ", numbered_short("archive.tar.gz", 12));
+        assert!(!is_valid_name("a:b"));
+        assert!(!is_valid_name("trailing."));
+        assert!(is_valid_name("Ünïcode name.gcode"));
+    }
+
+    #[test]
+    fn test_long_name_round_trip() {
+        let name = "A rather long file name.gcode";
+        let short = numbered_short(name, 1);
+        let sum = checksum(&short);
+        let count = long_entries(name);
+        assert_eq!(3, count);
+
+        let mut parser = Parser::new();
+        let mut raw = [0u8; ENTRY_SIZE];
+        for (i, sequence) in (1..=count).rev().enumerate() {
+            write_long(&mut raw, name, sequence, count, sum);
+            assert_eq!(None, parser.feed(&raw, 0, 4 + i as u32));
+        }
+        write_short(
+            &mut raw,
+            &short,
+            0,
+            ATTR_ARCHIVE,
+            0x1234_5678,
+            42,
+            DateTime::MIN,
+        );
+        let entry = parser.feed(&raw, 0, 7).unwrap();
+        assert_eq!(name, entry.name());
+        assert_eq!(0x1234_5678, entry.first_cluster());
+        assert_eq!(42, entry.size());
+        assert_eq!(4, entry.location().first);
+        assert!(entry.matches("a RATHER long file name.GCODE"));
+        assert!(entry.matches("arathe~1.gco"));
+
+        // an orphaned short entry keeps its short name.
+        let entry = parser.feed(&raw, 0, 8).unwrap();
+        assert_eq!("ARATHE~1.GCO", entry.name());
+    }
+}
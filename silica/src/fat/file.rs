@@ -0,0 +1,198 @@
+//! File handles.
+
+use super::dir::{set_cluster, write_u16, write_u32, Location, ATTR_ARCHIVE, ATTR_READ_ONLY};
+use super::{Error, FileSystem};
+use block::{BlockDevice, BLOCK_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Read,
+    /// Creates the file, or truncates it.
+    Write,
+    /// Creates the file, or writes from its end.
+    Append,
+}
+
+/// An open file. It is operated through the `FileSystem` it was opened from and must be
+/// closed for its entry to be updated.
+#[derive(Debug)]
+pub struct File {
+    location: Location,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// Cluster holding `position` (0 when not known yet) and its rank in the chain.
+    cluster: u32,
+    cluster_index: u32,
+    writable: bool,
+    dirty: bool,
+}
+
+impl File {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Moves to `position`, clamped to the end of the file, and returns the new position.
+    pub fn seek(&mut self, position: u32) -> u32 {
+        self.position = position.min(self.size);
+        self.position
+    }
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    pub fn open(&mut self, path: &str, mode: Mode) -> Result<File, Error<D::Error>> {
+        let (dir, name) = self.parent(path)?;
+        let now = self.now();
+        let (location, first_cluster, size) = match self.find(dir, name)? {
+            Some(entry) => {
+                if entry.is_dir() {
+                    return Err(Error::IsADirectory);
+                }
+                if mode != Mode::Read && entry.attributes() & ATTR_READ_ONLY != 0 {
+                    return Err(Error::ReadOnly);
+                }
+                (entry.location(), entry.first_cluster(), entry.size())
+            }
+            None if mode == Mode::Read => return Err(Error::NotFound),
+            None => (self.create_entry(dir, name, ATTR_ARCHIVE, 0, 0, now)?, 0, 0),
+        };
+
+        let mut file = File {
+            location,
+            first_cluster,
+            size,
+            position: 0,
+            cluster: 0,
+            cluster_index: 0,
+            writable: mode != Mode::Read,
+            dirty: false,
+        };
+        match mode {
+            Mode::Write if first_cluster != 0 || size != 0 => {
+                // the entry must not keep pointing at the freed chain.
+                self.free_chain(first_cluster)?;
+                file.first_cluster = 0;
+                file.size = 0;
+                self.update_file_entry(&file)?;
+            }
+            Mode::Append => file.position = size,
+            _ => {}
+        }
+        Ok(file)
+    }
+
+    /// Cluster holding the current position, the missing ones are allocated if `grow` is set.
+    fn file_cluster(
+        &mut self,
+        file: &mut File,
+        grow: bool,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        let target = file.position / self.cluster_size();
+        if file.first_cluster == 0 {
+            if !grow {
+                return Ok(None);
+            }
+            file.first_cluster = self.allocate(None)?;
+            file.dirty = true;
+        }
+        if file.cluster == 0 || target < file.cluster_index {
+            file.cluster = file.first_cluster;
+            file.cluster_index = 0;
+        }
+        while file.cluster_index < target {
+            file.cluster = match self.next_cluster(file.cluster)? {
+                Some(next) => next,
+                None if grow => self.allocate(Some(file.cluster))?,
+                None => return Ok(None),
+            };
+            file.cluster_index += 1;
+        }
+        Ok(Some(file.cluster))
+    }
+
+    /// Block and offset of `position` in `cluster`.
+    fn file_block(&self, cluster: u32, position: u32) -> (u32, usize) {
+        let within = position % self.cluster_size();
+        (
+            self.cluster_block(cluster) + within / BLOCK_SIZE as u32,
+            within as usize % BLOCK_SIZE,
+        )
+    }
+
+    /// Reads from the current position, returns the number of bytes read: 0 at the end.
+    pub fn read(&mut self, file: &mut File, buffer: &mut [u8]) -> Result<usize, Error<D::Error>> {
+        let mut done = 0;
+        while done < buffer.len() && file.position < file.size {
+            let cluster = self.file_cluster(file, false)?.ok_or(Error::Corrupted)?;
+            let (block, offset) = self.file_block(cluster, file.position);
+            let len = (BLOCK_SIZE - offset)
+                .min(buffer.len() - done)
+                .min((file.size - file.position) as usize);
+            let data = self.read_block(block)?;
+            buffer[done..done + len].copy_from_slice(&data[offset..offset + len]);
+            done += len;
+            file.position += len as u32;
+        }
+        Ok(done)
+    }
+
+    /// Writes all of `data` at the current position.
+    pub fn write(&mut self, file: &mut File, data: &[u8]) -> Result<(), Error<D::Error>> {
+        if !file.writable {
+            return Err(Error::ReadOnly);
+        }
+        if u64::from(file.position) + data.len() as u64 > u64::from(u32::max_value()) {
+            return Err(Error::Full);
+        }
+        let mut done = 0;
+        while done < data.len() {
+            let cluster = self.file_cluster(file, true)?.ok_or(Error::Corrupted)?;
+            let (block, offset) = self.file_block(cluster, file.position);
+            let len = (BLOCK_SIZE - offset).min(data.len() - done);
+            let fresh = offset == 0 && (len == BLOCK_SIZE || file.position >= file.size);
+            let buffer = if fresh {
+                // nothing worth reading back.
+                self.zero_block(block)?
+            } else {
+                self.write_block(block)?
+            };
+            buffer[offset..offset + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+            file.position += len as u32;
+            file.size = file.size.max(file.position);
+            file.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn update_file_entry(&mut self, file: &File) -> Result<(), Error<D::Error>> {
+        let (first_cluster, size) = (file.first_cluster, file.size);
+        let (date, time) = self.now().to_fat();
+        self.update_slot(file.location.dir, file.location.index, |raw| {
+            raw[11] |= ATTR_ARCHIVE;
+            write_u16(raw, 18, date);
+            write_u16(raw, 22, time);
+            write_u16(raw, 24, date);
+            set_cluster(raw, first_cluster);
+            write_u32(raw, 28, size);
+        })
+    }
+
+    /// Updates the entry of the file and writes the pending changes to the device.
+    pub fn sync(&mut self, file: &mut File) -> Result<(), Error<D::Error>> {
+        if file.dirty {
+            self.update_file_entry(file)?;
+            file.dirty = false;
+        }
+        self.flush()
+    }
+
+    pub fn close(&mut self, mut file: File) -> Result<(), Error<D::Error>> {
+        self.sync(&mut file)
+    }
+}
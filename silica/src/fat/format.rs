@@ -0,0 +1,167 @@
+//! Creation of empty volumes.
+
+use super::dir::{write_u16, write_u32};
+use super::{Error, FatType, FAT32_MASK, FSINFO_LEAD, FSINFO_STRUCT};
+use block::{BlockDevice, BLOCK_SIZE};
+
+/// Blocks per cluster for a volume of `blocks`, none if it is too small or too large.
+fn cluster_blocks(blocks: u32, fat_type: FatType) -> Option<u32> {
+    match fat_type {
+        // the smallest clusters keeping under 65525 of them.
+        FatType::Fat16 => {
+            if blocks < 4200 {
+                return None;
+            }
+            (0..7).map(|i| 1 << i).find(|spc| blocks / spc < 65525)
+        }
+        // 4KB clusters up to 8GB, as most systems do, but enough of them for FAT32.
+        FatType::Fat32 => {
+            let mut spc = match blocks {
+                0..=16_777_216 => 8,
+                16_777_217..=33_554_432 => 16,
+                33_554_433..=67_108_864 => 32,
+                _ => 64,
+            };
+            while spc > 1 && blocks / spc < 66_600 {
+                spc /= 2;
+            }
+            if blocks / spc < 66_600 {
+                None
+            } else {
+                Some(spc)
+            }
+        }
+    }
+}
+
+/// Writes an empty file system over the whole device, without a partition table.
+pub fn format<D: BlockDevice>(device: &mut D, fat_type: FatType) -> Result<(), Error<D::Error>> {
+    let blocks = device.blocks();
+    let spc = cluster_blocks(blocks, fat_type).ok_or(Error::Unsupported)?;
+    let (reserved, root_entries, entry_size) = match fat_type {
+        FatType::Fat16 => (1, 512, 2),
+        FatType::Fat32 => (32, 0, 4),
+    };
+    let root_blocks = root_entries * 32 / BLOCK_SIZE as u32;
+
+    // the FATs shrink the data area they describe.
+    let mut fat_size = 1;
+    let clusters = loop {
+        let clusters = (blocks - reserved - 2 * fat_size - root_blocks) / spc;
+        let needed = ((clusters + 2) * entry_size + BLOCK_SIZE as u32 - 1) / BLOCK_SIZE as u32;
+        if needed <= fat_size {
+            break clusters;
+        }
+        fat_size = needed;
+    };
+    let valid = match fat_type {
+        FatType::Fat16 => clusters >= 4085 && clusters < 65525,
+        FatType::Fat32 => clusters >= 65525 && clusters < FAT32_MASK - 16,
+    };
+    if !valid {
+        return Err(Error::Unsupported);
+    }
+
+    let mut block = [0; BLOCK_SIZE];
+    block[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    block[3..11].copy_from_slice(b"SILICA  ");
+    write_u16(&mut block, 11, BLOCK_SIZE as u16);
+    block[13] = spc as u8;
+    write_u16(&mut block, 14, reserved as u16);
+    block[16] = 2;
+    write_u16(&mut block, 17, root_entries as u16);
+    if blocks < 0x1_0000 {
+        write_u16(&mut block, 19, blocks as u16);
+    } else {
+        write_u32(&mut block, 32, blocks);
+    }
+    block[21] = 0xF8;
+    write_u16(&mut block, 24, 63);
+    write_u16(&mut block, 26, 255);
+    let serial = 0x5111_CA00 ^ blocks;
+    let extended = match fat_type {
+        FatType::Fat16 => {
+            write_u16(&mut block, 22, fat_size as u16);
+            block[54..62].copy_from_slice(b"FAT16   ");
+            36
+        }
+        FatType::Fat32 => {
+            write_u32(&mut block, 36, fat_size);
+            // root directory, FSInfo and backup boot sector.
+            write_u32(&mut block, 44, 2);
+            write_u16(&mut block, 48, 1);
+            write_u16(&mut block, 50, 6);
+            block[82..90].copy_from_slice(b"FAT32   ");
+            64
+        }
+    };
+    block[extended] = 0x80;
+    block[extended + 2] = 0x29;
+    write_u32(&mut block, extended + 3, serial);
+    block[extended + 7..extended + 18].copy_from_slice(b"NO NAME    ");
+    block[510] = 0x55;
+    block[511] = 0xAA;
+    device.write(0, &block).map_err(Error::Device)?;
+
+    let zero = [0; BLOCK_SIZE];
+    if fat_type == FatType::Fat32 {
+        device.write(6, &block).map_err(Error::Device)?;
+        let mut info = [0; BLOCK_SIZE];
+        write_u32(&mut info, 0, FSINFO_LEAD);
+        write_u32(&mut info, 484, FSINFO_STRUCT);
+        write_u32(&mut info, 488, clusters - 1);
+        write_u32(&mut info, 492, 3);
+        info[510] = 0x55;
+        info[511] = 0xAA;
+        device.write(1, &info).map_err(Error::Device)?;
+        device.write(7, &info).map_err(Error::Device)?;
+        for b in (2..6).chain(8..reserved) {
+            device.write(b, &zero).map_err(Error::Device)?;
+        }
+    }
+
+    // media and end of chain markers, then the root directory cluster on FAT32.
+    let mut first = [0; BLOCK_SIZE];
+    match fat_type {
+        FatType::Fat16 => write_u32(&mut first, 0, 0xFFFF_FFF8),
+        FatType::Fat32 => {
+            write_u32(&mut first, 0, 0x0FFF_FFF8);
+            write_u32(&mut first, 4, FAT32_MASK);
+            write_u32(&mut first, 8, FAT32_MASK);
+        }
+    }
+    for fat in 0..2 {
+        let start = reserved + fat * fat_size;
+        device.write(start, &first).map_err(Error::Device)?;
+        for b in start + 1..start + fat_size {
+            device.write(b, &zero).map_err(Error::Device)?;
+        }
+    }
+    let root = reserved + 2 * fat_size;
+    let root_blocks = match fat_type {
+        FatType::Fat16 => root_blocks,
+        FatType::Fat32 => spc,
+    };
+    for b in root..root + root_blocks {
+        device.write(b, &zero).map_err(Error::Device)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_blocks() {
+        assert_eq!(None, cluster_blocks(2048, FatType::Fat16));
+        assert_eq!(Some(1), cluster_blocks(16 * 1024, FatType::Fat16));
+        assert_eq!(Some(64), cluster_blocks(4_000_000, FatType::Fat16));
+        assert_eq!(None, cluster_blocks(4 * 1024 * 1024, FatType::Fat16));
+        assert_eq!(None, cluster_blocks(64 * 1024, FatType::Fat32));
+        assert_eq!(Some(1), cluster_blocks(72 * 1024, FatType::Fat32));
+        // 4GB and 32GB cards.
+        assert_eq!(Some(8), cluster_blocks(8 * 1024 * 1024, FatType::Fat32));
+        assert_eq!(Some(32), cluster_blocks(64 * 1024 * 1024, FatType::Fat32));
+    }
+}
@@ -0,0 +1,960 @@
+//! # FAT16 and FAT32 file systems
+//!
+//! `FileSystem` mounts the first FAT partition of a `BlockDevice`, or the whole device when it
+//! has no partition table. Long file names are supported, FAT12 and exFAT volumes are not.
+//!
+//! Files are plain handles operated through the file system, so that several of them can be
+//! open at once:
+//!
+//! ```ignore
+//! let mut fs = FileSystem::mount(card)?;
+//! let mut config = fs.open("/sys/config.g", Mode::Read)?;
+//! let len = fs.read(&mut config, &mut buffer)?;
+//! ```
+//!
+//! The blocks go through a small write-back cache: the changes reach the device on `close`,
+//! `flush` and `unmount`.
+//!
+//! The entries are stamped with the time given by the clock of `set_clock`, 1980-01-01 by
+//! default.
+
+mod cache;
+mod dir;
+mod file;
+mod format;
+
+pub use self::cache::CACHE_BLOCKS;
+pub use self::dir::{
+    DirEntry, Location, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM,
+    ATTR_VOLUME_ID, MAX_NAME,
+};
+pub use self::file::{File, Mode};
+pub use self::format::format;
+
+use self::cache::Cache;
+use self::dir::{
+    as_short, checksum, is_valid_name, long_entries, numbered_short, read_u16, read_u32,
+    set_cluster, write_long, write_short, write_u32, Parser, DELETED, END, ENTRY_SIZE,
+};
+use block::{BlockDevice, BLOCK_SIZE};
+use time::DateTime;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    Device(E),
+    /// No FAT volume found.
+    NotFat,
+    /// FAT12, exFAT or blocks other than 512 bytes.
+    Unsupported,
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidName,
+    /// The file is read only or was not opened for writing.
+    ReadOnly,
+    /// No free cluster is left, the FAT16 root directory is full or a file would exceed 4GB.
+    Full,
+    /// Broken cluster chain.
+    Corrupted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+const ENTRIES_PER_BLOCK: u32 = (BLOCK_SIZE / ENTRY_SIZE) as u32;
+const FAT32_MASK: u32 = 0x0FFF_FFFF;
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+
+fn no_clock() -> DateTime {
+    DateTime::MIN
+}
+
+fn is_boot_sector(block: &[u8]) -> bool {
+    (block[0] == 0xEB || block[0] == 0xE9)
+        && read_u16(block, 11).is_power_of_two()
+        && block[13].is_power_of_two()
+        && read_u16(block, 14) != 0
+        && block[16] != 0
+}
+
+/// Position in a directory being walked.
+struct Cursor {
+    dir: u32,
+    index: u32,
+    /// Cluster holding the entry `index` (0 when not known yet) and its rank in the chain.
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl Cursor {
+    fn new(dir: u32) -> Cursor {
+        Cursor::at(dir, 0)
+    }
+
+    fn at(dir: u32, index: u32) -> Cursor {
+        Cursor {
+            dir,
+            index,
+            cluster: 0,
+            cluster_index: 0,
+        }
+    }
+}
+
+/// A mounted volume. Directories are identified by their first cluster, 0 for the root.
+pub struct FileSystem<D> {
+    device: D,
+    cache: Cache,
+    fat_type: FatType,
+    fat_start: u32,
+    /// The FAT16 root directory region.
+    root_start: u32,
+    root_entries: u32,
+    /// First cluster of the FAT32 root directory.
+    root_cluster: u32,
+    data_start: u32,
+    cluster_blocks: u32,
+    clusters: u32,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+    /// FSInfo block whose free cluster count is to be invalidated before the first change.
+    fs_info: Option<u32>,
+    clock: fn() -> DateTime,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    pub fn mount(mut device: D) -> Result<FileSystem<D>, Error<D::Error>> {
+        let mut block = [0; BLOCK_SIZE];
+        device.read(0, &mut block).map_err(Error::Device)?;
+        if block[510] != 0x55 || block[511] != 0xAA {
+            return Err(Error::NotFat);
+        }
+        let mut start = 0;
+        if &block[3..11] != b"EXFAT   " && !is_boot_sector(&block) {
+            // the first FAT16 or FAT32 partition of the MBR.
+            start = (0..4)
+                .map(|i| 446 + 16 * i)
+                .find(|&p| [0x04, 0x06, 0x0B, 0x0C, 0x0E].contains(&block[p + 4]))
+                .map(|p| read_u32(&block, p + 8))
+                .ok_or(Error::NotFat)?;
+            device.read(start, &mut block).map_err(Error::Device)?;
+        }
+        if &block[3..11] == b"EXFAT   " {
+            return Err(Error::Unsupported);
+        }
+        if !is_boot_sector(&block) {
+            return Err(Error::NotFat);
+        }
+        if usize::from(read_u16(&block, 11)) != BLOCK_SIZE {
+            return Err(Error::Unsupported);
+        }
+
+        let cluster_blocks = u32::from(block[13]);
+        let fats = u32::from(block[16]);
+        let root_entries = u32::from(read_u16(&block, 17));
+        let total = match read_u16(&block, 19) {
+            0 => read_u32(&block, 32),
+            total => u32::from(total),
+        };
+        let fat_size = match read_u16(&block, 22) {
+            0 => read_u32(&block, 36),
+            size => u32::from(size),
+        };
+        let fat_start = start + u32::from(read_u16(&block, 14));
+        let root_start = fat_start + fats * fat_size;
+        let data_start = root_start + (root_entries + ENTRIES_PER_BLOCK - 1) / ENTRIES_PER_BLOCK;
+        if start + total <= data_start {
+            return Err(Error::NotFat);
+        }
+        let clusters = (start + total - data_start) / cluster_blocks;
+        let fat_type = if clusters < 4085 {
+            return Err(Error::Unsupported);
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let (root_cluster, fs_info) = match fat_type {
+            FatType::Fat16 => (0, None),
+            FatType::Fat32 => (
+                read_u32(&block, 44),
+                match read_u16(&block, 48) {
+                    0 | 0xFFFF => None,
+                    b => Some(start + u32::from(b)),
+                },
+            ),
+        };
+
+        let mut cache = Cache::new();
+        cache.mirror_fats(fat_start, fat_size, fats);
+        Ok(FileSystem {
+            device,
+            cache,
+            fat_type,
+            fat_start,
+            root_start,
+            root_entries,
+            root_cluster,
+            data_start,
+            cluster_blocks,
+            clusters,
+            next_free: 2,
+            fs_info,
+            clock: no_clock,
+        })
+    }
+
+    /// Writes the pending changes and gives the device back.
+    pub fn unmount(mut self) -> Result<D, Error<D::Error>> {
+        self.flush()?;
+        Ok(self.device)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error<D::Error>> {
+        self.cache.flush(&mut self.device).map_err(Error::Device)
+    }
+
+    /// Sets the source of the creation and modification times.
+    pub fn set_clock(&mut self, clock: fn() -> DateTime) {
+        self.clock = clock;
+    }
+
+    fn now(&self) -> DateTime {
+        (self.clock)()
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// Bytes per cluster.
+    pub fn cluster_size(&self) -> u32 {
+        self.cluster_blocks * BLOCK_SIZE as u32
+    }
+
+    fn read_block(&mut self, block: u32) -> Result<&[u8; BLOCK_SIZE], Error<D::Error>> {
+        self.cache
+            .read(&mut self.device, block)
+            .map_err(Error::Device)
+    }
+
+    fn write_block(&mut self, block: u32) -> Result<&mut [u8; BLOCK_SIZE], Error<D::Error>> {
+        self.cache
+            .write(&mut self.device, block)
+            .map_err(Error::Device)
+    }
+
+    fn zero_block(&mut self, block: u32) -> Result<&mut [u8; BLOCK_SIZE], Error<D::Error>> {
+        self.cache
+            .zero(&mut self.device, block)
+            .map_err(Error::Device)
+    }
+
+    fn cluster_block(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.cluster_blocks
+    }
+
+    fn fat_position(&self, cluster: u32) -> (u32, usize) {
+        let offset = match self.fat_type {
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        (
+            self.fat_start + offset / BLOCK_SIZE as u32,
+            offset as usize % BLOCK_SIZE,
+        )
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, Error<D::Error>> {
+        let fat_type = self.fat_type;
+        let (block, offset) = self.fat_position(cluster);
+        let data = self.read_block(block)?;
+        Ok(match fat_type {
+            FatType::Fat16 => u32::from(read_u16(data, offset)),
+            FatType::Fat32 => read_u32(data, offset) & FAT32_MASK,
+        })
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), Error<D::Error>> {
+        self.invalidate_fs_info()?;
+        let fat_type = self.fat_type;
+        let (block, offset) = self.fat_position(cluster);
+        let data = self.write_block(block)?;
+        match fat_type {
+            FatType::Fat16 => {
+                data[offset] = value as u8;
+                data[offset + 1] = (value >> 8) as u8;
+            }
+            FatType::Fat32 => {
+                // the 4 upper bits are reserved.
+                let value = read_u32(data, offset) & !FAT32_MASK | value & FAT32_MASK;
+                write_u32(data, offset, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => FAT32_MASK,
+        }
+    }
+
+    /// The cluster following `cluster` in its chain.
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, Error<D::Error>> {
+        let next = self.fat_entry(cluster)?;
+        let last = match self.fat_type {
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        };
+        if next >= last {
+            Ok(None)
+        } else if next < 2 || next >= self.clusters + 2 {
+            Err(Error::Corrupted)
+        } else {
+            Ok(Some(next))
+        }
+    }
+
+    /// Takes a free cluster and appends it to the chain ending with `previous`.
+    fn allocate(&mut self, previous: Option<u32>) -> Result<u32, Error<D::Error>> {
+        let mut cluster = self.next_free;
+        for _ in 0..self.clusters {
+            if cluster >= self.clusters + 2 {
+                cluster = 2;
+            }
+            if self.fat_entry(cluster)? == 0 {
+                let end = self.end_of_chain();
+                self.set_fat_entry(cluster, end)?;
+                if let Some(previous) = previous {
+                    self.set_fat_entry(previous, cluster)?;
+                }
+                self.next_free = cluster + 1;
+                return Ok(cluster);
+            }
+            cluster += 1;
+        }
+        Err(Error::Full)
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), Error<D::Error>> {
+        let mut cluster = if first >= 2 { Some(first) } else { None };
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, 0)?;
+        }
+        Ok(())
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), Error<D::Error>> {
+        let first = self.cluster_block(cluster);
+        for block in first..first + self.cluster_blocks {
+            self.zero_block(block)?;
+        }
+        Ok(())
+    }
+
+    /// The free cluster count of the FSInfo block goes stale with the first change.
+    fn invalidate_fs_info(&mut self) -> Result<(), Error<D::Error>> {
+        if let Some(block) = self.fs_info.take() {
+            let data = self.write_block(block)?;
+            if read_u32(data, 0) == FSINFO_LEAD && read_u32(data, 484) == FSINFO_STRUCT {
+                write_u32(data, 488, 0xFFFF_FFFF);
+                write_u32(data, 492, 0xFFFF_FFFF);
+            }
+        }
+        Ok(())
+    }
+
+    /// Block and offset of the entry at the cursor, the directory grows when `grow` is set.
+    fn slot(
+        &mut self,
+        cursor: &mut Cursor,
+        grow: bool,
+    ) -> Result<Option<(u32, usize)>, Error<D::Error>> {
+        let first = if cursor.dir == 0 {
+            self.root_cluster
+        } else {
+            cursor.dir
+        };
+        if first == 0 {
+            if cursor.index >= self.root_entries {
+                return Ok(None);
+            }
+            return Ok(Some((
+                self.root_start + cursor.index / ENTRIES_PER_BLOCK,
+                (cursor.index % ENTRIES_PER_BLOCK) as usize * ENTRY_SIZE,
+            )));
+        }
+
+        let per_cluster = self.cluster_blocks * ENTRIES_PER_BLOCK;
+        let target = cursor.index / per_cluster;
+        if cursor.cluster == 0 || target < cursor.cluster_index {
+            cursor.cluster = first;
+            cursor.cluster_index = 0;
+        }
+        while cursor.cluster_index < target {
+            cursor.cluster = match self.next_cluster(cursor.cluster)? {
+                Some(next) => next,
+                None if grow => {
+                    let cluster = self.allocate(Some(cursor.cluster))?;
+                    self.zero_cluster(cluster)?;
+                    cluster
+                }
+                None => return Ok(None),
+            };
+            cursor.cluster_index += 1;
+        }
+        let within = cursor.index % per_cluster;
+        Ok(Some((
+            self.cluster_block(cursor.cluster) + within / ENTRIES_PER_BLOCK,
+            (within % ENTRIES_PER_BLOCK) as usize * ENTRY_SIZE,
+        )))
+    }
+
+    fn read_slot(
+        &mut self,
+        cursor: &mut Cursor,
+        grow: bool,
+    ) -> Result<Option<[u8; ENTRY_SIZE]>, Error<D::Error>> {
+        Ok(match self.slot(cursor, grow)? {
+            Some((block, offset)) => {
+                let mut raw = [0; ENTRY_SIZE];
+                raw.copy_from_slice(&self.read_block(block)?[offset..offset + ENTRY_SIZE]);
+                Some(raw)
+            }
+            None => None,
+        })
+    }
+
+    /// Modifies the entry at `index` of `dir`.
+    fn update_slot<F>(&mut self, dir: u32, index: u32, f: F) -> Result<(), Error<D::Error>>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let (block, offset) = self
+            .slot(&mut Cursor::at(dir, index), false)?
+            .ok_or(Error::Corrupted)?;
+        f(&mut self.write_block(block)?[offset..offset + ENTRY_SIZE]);
+        Ok(())
+    }
+
+    fn next_entry(
+        &mut self,
+        cursor: &mut Cursor,
+        parser: &mut Parser,
+    ) -> Result<Option<DirEntry>, Error<D::Error>> {
+        loop {
+            let raw = match self.read_slot(cursor, false)? {
+                Some(raw) => raw,
+                None => return Ok(None),
+            };
+            if raw[0] == END {
+                return Ok(None);
+            }
+            let index = cursor.index;
+            cursor.index += 1;
+            if let Some(entry) = parser.feed(&raw, cursor.dir, index) {
+                let dots = entry.name() == "." || entry.name() == "..";
+                if entry.attributes() & ATTR_VOLUME_ID == 0 && !dots {
+                    return Ok(Some(entry));
+                }
+            }
+        }
+    }
+
+    fn find(&mut self, dir: u32, name: &str) -> Result<Option<DirEntry>, Error<D::Error>> {
+        let mut cursor = Cursor::new(dir);
+        let mut parser = Parser::new();
+        while let Some(entry) = self.next_entry(&mut cursor, &mut parser)? {
+            if entry.matches(name) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    fn short_exists(&mut self, dir: u32, short: &[u8; 11]) -> Result<bool, Error<D::Error>> {
+        let mut cursor = Cursor::new(dir);
+        let mut parser = Parser::new();
+        while let Some(entry) = self.next_entry(&mut cursor, &mut parser)? {
+            if entry.short_name() == short {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The directory named by `path`.
+    fn dir_of(&mut self, path: &str) -> Result<u32, Error<D::Error>> {
+        let mut dir = 0;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let entry = self.find(dir, name)?.ok_or(Error::NotFound)?;
+            if !entry.is_dir() {
+                return Err(Error::NotADirectory);
+            }
+            dir = entry.first_cluster();
+        }
+        Ok(dir)
+    }
+
+    /// The directory holding the last component of `path`, and that component.
+    fn parent<'p>(&mut self, path: &'p str) -> Result<(u32, &'p str), Error<D::Error>> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        if name.is_empty() {
+            return Err(Error::InvalidName);
+        }
+        Ok((self.dir_of(dir)?, name))
+    }
+
+    /// The entry of a file or directory.
+    pub fn metadata(&mut self, path: &str) -> Result<DirEntry, Error<D::Error>> {
+        let (dir, name) = self.parent(path)?;
+        self.find(dir, name)?.ok_or(Error::NotFound)
+    }
+
+    /// The entries of a directory, without `.` and `..`.
+    pub fn read_dir(&mut self, path: &str) -> Result<ReadDir<D>, Error<D::Error>> {
+        let dir = self.dir_of(path)?;
+        Ok(ReadDir {
+            fs: self,
+            cursor: Cursor::new(dir),
+            parser: Parser::new(),
+        })
+    }
+
+    /// Adds an entry to `dir`, with long name entries when `name` is not a valid short name.
+    fn create_entry(
+        &mut self,
+        dir: u32,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+        size: u32,
+        modified: DateTime,
+    ) -> Result<Location, Error<D::Error>> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidName);
+        }
+        if self.find(dir, name)?.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        let (short, case, long) = match as_short(name) {
+            Some((short, case)) => (short, case, 0),
+            None => {
+                let mut n = 1;
+                loop {
+                    let short = numbered_short(name, n);
+                    if !self.short_exists(dir, &short)? {
+                        break (short, 0, long_entries(name));
+                    }
+                    n += 1;
+                    if n > 999_999 {
+                        return Err(Error::AlreadyExists);
+                    }
+                }
+            }
+        };
+
+        // the first run of free entries large enough.
+        let needed = u32::from(long) + 1;
+        let mut cursor = Cursor::new(dir);
+        let mut run = 0;
+        while run < needed {
+            let raw = self.read_slot(&mut cursor, true)?.ok_or(Error::Full)?;
+            if raw[0] == END || raw[0] == DELETED {
+                run += 1;
+            } else {
+                run = 0;
+            }
+            cursor.index += 1;
+        }
+        let first = cursor.index - needed;
+
+        let sum = checksum(&short);
+        for i in 0..needed {
+            let sequence = long - i as u8;
+            self.update_slot(dir, first + i, |raw| {
+                if sequence > 0 {
+                    write_long(raw, name, sequence, long, sum);
+                } else {
+                    write_short(raw, &short, case, attributes, first_cluster, size, modified);
+                }
+            })?;
+        }
+        Ok(Location {
+            dir,
+            index: first + needed - 1,
+            first,
+        })
+    }
+
+    fn delete_entry(&mut self, location: Location) -> Result<(), Error<D::Error>> {
+        for index in location.first..=location.index {
+            self.update_slot(location.dir, index, |raw| raw[0] = DELETED)?;
+        }
+        Ok(())
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<(), Error<D::Error>> {
+        let (parent, name) = self.parent(path)?;
+        let cluster = self.allocate(None)?;
+        self.zero_cluster(cluster)?;
+        let now = self.now();
+        {
+            let block = self.cluster_block(cluster);
+            let data = self.write_block(block)?;
+            write_short(
+                &mut data[..ENTRY_SIZE],
+                b".          ",
+                0,
+                ATTR_DIRECTORY,
+                cluster,
+                0,
+                now,
+            );
+            write_short(
+                &mut data[ENTRY_SIZE..2 * ENTRY_SIZE],
+                b"..         ",
+                0,
+                ATTR_DIRECTORY,
+                parent,
+                0,
+                now,
+            );
+        }
+        match self.create_entry(parent, name, ATTR_DIRECTORY, cluster, 0, now) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.free_chain(cluster)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Deletes a file or an empty directory.
+    pub fn remove(&mut self, path: &str) -> Result<(), Error<D::Error>> {
+        let entry = self.metadata(path)?;
+        if entry.is_dir() {
+            let mut cursor = Cursor::new(entry.first_cluster());
+            if self.next_entry(&mut cursor, &mut Parser::new())?.is_some() {
+                return Err(Error::DirectoryNotEmpty);
+            }
+        }
+        self.delete_entry(entry.location())?;
+        self.free_chain(entry.first_cluster())
+    }
+
+    /// Moves a file or a directory, possibly to another directory.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), Error<D::Error>> {
+        let entry = self.metadata(from)?;
+        let source = from.trim_matches('/');
+        let target = to.trim_start_matches('/');
+        if target.len() > source.len()
+            && target.starts_with(source)
+            && target.as_bytes()[source.len()] == b'/'
+        {
+            // a directory cannot move into itself.
+            return Err(Error::InvalidName);
+        }
+        let (parent, name) = self.parent(to)?;
+        self.create_entry(
+            parent,
+            name,
+            entry.attributes(),
+            entry.first_cluster(),
+            entry.size(),
+            entry.modified(),
+        )?;
+        self.delete_entry(entry.location())?;
+        if entry.is_dir() && parent != entry.location().dir {
+            self.update_slot(entry.first_cluster(), 1, |raw| set_cluster(raw, parent))?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the entries of a directory.
+pub struct ReadDir<'a, D: 'a> {
+    fs: &'a mut FileSystem<D>,
+    cursor: Cursor,
+    parser: Parser,
+}
+
+impl<'a, D: BlockDevice> Iterator for ReadDir<'a, D> {
+    type Item = Result<DirEntry, Error<D::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fs.next_entry(&mut self.cursor, &mut self.parser) {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// A disk image file, removed when dropped.
+    pub struct Image {
+        file: fs::File,
+        path: PathBuf,
+        blocks: u32,
+    }
+
+    impl Image {
+        pub fn new(name: &str, blocks: u32) -> Image {
+            let path =
+                std::env::temp_dir().join(format!("silica-{}-{}.img", name, std::process::id()));
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            file.set_len(u64::from(blocks) * BLOCK_SIZE as u64).unwrap();
+            Image { file, path, blocks }
+        }
+    }
+
+    impl Drop for Image {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    impl BlockDevice for Image {
+        type Error = ();
+        fn blocks(&self) -> u32 {
+            self.blocks
+        }
+        fn read(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), ()> {
+            assert!(first + (buffer.len() / BLOCK_SIZE) as u32 <= self.blocks);
+            self.file
+                .seek(SeekFrom::Start(u64::from(first) * BLOCK_SIZE as u64))
+                .map_err(|_| ())?;
+            self.file.read_exact(buffer).map_err(|_| ())
+        }
+        fn write(&mut self, first: u32, buffer: &[u8]) -> Result<(), ()> {
+            assert!(first + (buffer.len() / BLOCK_SIZE) as u32 <= self.blocks);
+            self.file
+                .seek(SeekFrom::Start(u64::from(first) * BLOCK_SIZE as u64))
+                .map_err(|_| ())?;
+            self.file.write_all(buffer).map_err(|_| ())
+        }
+    }
+
+    fn names<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> Vec<String>
+    where
+        D::Error: ::core::fmt::Debug,
+    {
+        fs.read_dir(path)
+            .unwrap()
+            .map(|e| String::from(e.unwrap().name()))
+            .collect()
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_fat16_files() {
+        let mut image = Image::new("fat16", 16 * 1024);
+        format(&mut image, FatType::Fat16).unwrap();
+        let mut fs = FileSystem::mount(image).unwrap();
+        assert_eq!(FatType::Fat16, fs.fat_type());
+
+        let mut config = fs.open("/config.g", Mode::Write).unwrap();
+        fs.write(&mut config, b"M550 P\"Duet\"\n").unwrap();
+        fs.close(config).unwrap();
+
+        fs.create_dir("/gcodes").unwrap();
+        let data = pattern(3000);
+        let mut job = fs
+            .open("gcodes/A long print name.gcode", Mode::Write)
+            .unwrap();
+        fs.write(&mut job, &data[..2900]).unwrap();
+        fs.close(job).unwrap();
+        let mut job = fs
+            .open("/gcodes/a long PRINT name.gcode", Mode::Append)
+            .unwrap();
+        fs.write(&mut job, &data[2900..]).unwrap();
+        fs.close(job).unwrap();
+        assert_eq!(
+            Err(Error::AlreadyExists),
+            fs.create_dir("/gcodes/A LONG PRINT NAME.GCODE")
+        );
+        assert_eq!(Err(Error::InvalidName), fs.create_dir("/gcodes/a?"));
+
+        assert_eq!(vec!["config.g", "gcodes"], names(&mut fs, "/"));
+        let entry = fs.metadata("/gcodes/A long print name.gcode").unwrap();
+        assert_eq!(3000, entry.size());
+        assert_eq!(*b"ALONGP~1GCO", *entry.short_name());
+
+        fs.rename("/gcodes/A long print name.gcode", "/old print.gcode")
+            .unwrap();
+        assert_eq!(
+            Err(Error::NotFound),
+            fs.remove("/gcodes/A long print name.gcode")
+        );
+        fs.remove("/gcodes").unwrap();
+        assert_eq!(vec!["config.g", "old print.gcode"], names(&mut fs, "/"));
+
+        // everything is on the image after a remount.
+        let image = fs.unmount().unwrap();
+        let mut fs = FileSystem::mount(image).unwrap();
+        let mut job = fs.open("/OLD PRINT.GCODE", Mode::Read).unwrap();
+        let mut read = vec![0; 4096];
+        assert_eq!(3000, fs.read(&mut job, &mut read).unwrap());
+        assert_eq!(&data[..], &read[..3000]);
+        job.seek(1000);
+        assert_eq!(10, fs.read(&mut job, &mut read[..10]).unwrap());
+        assert_eq!(&data[1000..1010], &read[..10]);
+        assert_eq!(Err(Error::ReadOnly), fs.write(&mut job, b"x"));
+        fs.close(job).unwrap();
+
+        assert_eq!(DateTime::MIN, fs.metadata("/config.g").unwrap().modified());
+        fs.set_clock(|| DateTime::new(2018, 6, 1, 12, 0, 31).unwrap());
+        let mut config = fs.open("CONFIG.G", Mode::Write).unwrap();
+        assert_eq!(0, config.size());
+        fs.write(&mut config, b"M552 S1\n").unwrap();
+        fs.close(config).unwrap();
+        let mut config = fs.open("config.g", Mode::Read).unwrap();
+        assert_eq!(8, fs.read(&mut config, &mut read).unwrap());
+        assert_eq!(
+            DateTime::new(2018, 6, 1, 12, 0, 30),
+            Some(fs.metadata("/config.g").unwrap().modified())
+        );
+    }
+
+    #[test]
+    fn test_fat32_directories() {
+        let mut image = Image::new("fat32", 72 * 1024);
+        format(&mut image, FatType::Fat32).unwrap();
+        let mut fs = FileSystem::mount(image).unwrap();
+        assert_eq!(FatType::Fat32, fs.fat_type());
+
+        fs.create_dir("/sys").unwrap();
+        fs.create_dir("/sys/macros").unwrap();
+        // three entries each: the directory spans several clusters.
+        for i in 0..40 {
+            let path = format!("/sys/macros/Macro number {}.g", i);
+            let mut file = fs.open(&path, Mode::Write).unwrap();
+            fs.write(&mut file, path.as_bytes()).unwrap();
+            fs.close(file).unwrap();
+        }
+        for i in (0..40).filter(|i| i % 2 == 0) {
+            fs.remove(&format!("/sys/macros/Macro number {}.g", i))
+                .unwrap();
+        }
+        assert_eq!(Err(Error::DirectoryNotEmpty), fs.remove("/sys/macros"));
+        fs.rename("/sys/macros", "/macros").unwrap();
+        assert_eq!(
+            Err(Error::InvalidName),
+            fs.rename("/macros", "/macros/inner")
+        );
+
+        let image = fs.unmount().unwrap();
+        let mut fs = FileSystem::mount(image).unwrap();
+        let listed = names(&mut fs, "/macros");
+        assert_eq!(20, listed.len());
+        assert_eq!("Macro number 39.g", listed[19]);
+        let mut file = fs.open("/macros/macro number 7.g", Mode::Read).unwrap();
+        let mut read = [0; 64];
+        let len = fs.read(&mut file, &mut read).unwrap();
+        assert_eq!(&b"/sys/macros/Macro number 7.g"[..], &read[..len]);
+        // '..' follows the move.
+        let macros = fs.metadata("/macros").unwrap().first_cluster();
+        let mut parser = Parser::new();
+        let raw = fs
+            .read_slot(&mut Cursor::at(macros, 1), false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(0, parser.feed(&raw, macros, 1).unwrap().first_cluster());
+        assert_eq!(
+            Err(Error::NotADirectory),
+            fs.open("/macros/macro number 7.g/x", Mode::Read)
+                .map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_partitioned_foreign_image() {
+        // a FAT16 partition from block 63 holding entries as written by another system.
+        struct Partition<'a>(&'a mut Image);
+        impl<'a> BlockDevice for Partition<'a> {
+            type Error = ();
+            fn blocks(&self) -> u32 {
+                self.0.blocks - 63
+            }
+            fn read(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), ()> {
+                self.0.read(first + 63, buffer)
+            }
+            fn write(&mut self, first: u32, buffer: &[u8]) -> Result<(), ()> {
+                self.0.write(first + 63, buffer)
+            }
+        }
+        let mut image = Image::new("mbr", 8 * 1024);
+        format(&mut Partition(&mut image), FatType::Fat16).unwrap();
+        let mut mbr = [0; BLOCK_SIZE];
+        mbr[446 + 4] = 0x06;
+        write_u32(&mut mbr, 446 + 8, 63);
+        write_u32(&mut mbr, 446 + 12, 8 * 1024 - 63);
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        image.write(0, &mbr).unwrap();
+
+        // "Hello World.txt" (HELLOW~1.TXT) and "config.g" in lower case.
+        let root = 63 + 1 + 2 * 32;
+        let mut block = [0; BLOCK_SIZE];
+        block[..4 * ENTRY_SIZE].copy_from_slice(
+            &[
+                0x42, 0x78, 0x00, 0x74, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00, 0x1B,
+                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00,
+                0xFF, 0xFF, 0xFF, 0xFF, //
+                0x01, 0x48, 0x00, 0x65, 0x00, 0x6C, 0x00, 0x6C, 0x00, 0x6F, 0x00, 0x0F, 0x00, 0x1B,
+                0x20, 0x00, 0x57, 0x00, 0x6F, 0x00, 0x72, 0x00, 0x6C, 0x00, 0x64, 0x00, 0x00, 0x00,
+                0x2E, 0x00, 0x74, 0x00, //
+                0x48, 0x45, 0x4C, 0x4C, 0x4F, 0x57, 0x7E, 0x31, 0x54, 0x58, 0x54, 0x20, 0x00, 0x00,
+                0x00, 0x00, 0x21, 0x4C, 0x21, 0x4C, 0x00, 0x00, 0x00, 0x00, 0x21, 0x4C, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, //
+                0x43, 0x4F, 0x4E, 0x46, 0x49, 0x47, 0x20, 0x20, 0x47, 0x20, 0x20, 0x20, 0x18, 0x00,
+                0x00, 0x00, 0x21, 0x4C, 0x21, 0x4C, 0x00, 0x00, 0x00, 0x00, 0x21, 0x4C, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00,
+            ][..],
+        );
+        image.write(root, &block).unwrap();
+        assert_eq!(
+            Err(Error::NotFat),
+            FileSystem::mount(Image::new("empty", 64)).map(|_| ())
+        );
+
+        let mut fs = FileSystem::mount(image).unwrap();
+        assert_eq!(vec!["Hello World.txt", "config.g"], names(&mut fs, "/"));
+        assert_eq!(
+            DateTime::new(2018, 1, 1, 0, 0, 0),
+            Some(fs.metadata("/hellow~1.txt").unwrap().modified())
+        );
+        assert_eq!(0, fs.metadata("/config.g").unwrap().size());
+    }
+}
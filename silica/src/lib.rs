@@ -1,5 +1,21 @@
-#![feature(macro_vis_matcher, try_from, const_fn)]
+#![feature(const_fn, futures_api)]
 #![no_std]
 
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
 #[macro_use]
 pub mod register;
+
+pub mod block;
+pub mod bus;
+pub mod executor;
+pub mod fat;
+pub mod net;
+pub mod ring;
+pub mod sd;
+pub mod serial;
+pub mod sync;
+pub mod time;
+pub mod usb;
@@ -0,0 +1,111 @@
+//! Address resolution cache.
+
+use super::Ipv4Address;
+use time::{Duration, Instant};
+
+pub const ENTRIES: usize = 8;
+/// Lifetime of a resolved address.
+const LIFETIME: Duration = Duration::from_secs(300);
+/// Minimum time between two requests for the same address.
+const RETRY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+struct Entry {
+    address: Ipv4Address,
+    mac: [u8; 6],
+    updated: Instant,
+}
+
+pub struct Cache {
+    entries: [Option<Entry>; ENTRIES],
+    /// Last request sent.
+    request: Option<(Ipv4Address, Instant)>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            entries: [None; ENTRIES],
+            request: None,
+        }
+    }
+
+    pub fn lookup(&self, address: Ipv4Address, now: Instant) -> Option<[u8; 6]> {
+        self.entries
+            .iter()
+            .filter_map(|e| *e)
+            .find(|e| e.address == address && now - e.updated < LIFETIME)
+            .map(|e| e.mac)
+    }
+
+    /// Records `address` at `mac`, replacing the oldest entry if needed.
+    pub fn insert(&mut self, address: Ipv4Address, mac: [u8; 6], now: Instant) {
+        let entry = Some(Entry {
+            address,
+            mac,
+            updated: now,
+        });
+        let slot = self
+            .entries
+            .iter()
+            .position(|e| e.map(|e| e.address) == Some(address))
+            .or_else(|| self.entries.iter().position(Option::is_none))
+            .unwrap_or_else(|| {
+                let mut oldest = 0;
+                for (i, e) in self.entries.iter().enumerate() {
+                    if let (Some(e), Some(o)) = (e, self.entries[oldest]) {
+                        if e.updated < o.updated {
+                            oldest = i;
+                        }
+                    }
+                }
+                oldest
+            });
+        self.entries[slot] = entry;
+        if self.request.map(|(a, _)| a) == Some(address) {
+            self.request = None;
+        }
+    }
+
+    /// Whether a request for `address` is due.
+    pub fn should_request(&mut self, address: Ipv4Address, now: Instant) -> bool {
+        match self.request {
+            Some((a, sent)) if a == address && now - sent < RETRY => false,
+            _ => {
+                self.request = Some((address, now));
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let mut cache = Cache::new();
+        let now = Instant::from_micros(0);
+        for i in 0..=ENTRIES as u8 {
+            let later = now + Duration::from_secs(u64::from(i));
+            cache.insert(Ipv4Address([10, 0, 0, i]), [i; 6], later);
+        }
+        let later = now + Duration::from_secs(20);
+        // the oldest entry made room for the last one.
+        assert_eq!(None, cache.lookup(Ipv4Address([10, 0, 0, 0]), later));
+        assert_eq!(
+            Some([8; 6]),
+            cache.lookup(Ipv4Address([10, 0, 0, 8]), later)
+        );
+        assert_eq!(
+            None,
+            cache.lookup(Ipv4Address([10, 0, 0, 8]), later + LIFETIME)
+        );
+
+        let address = Ipv4Address([10, 0, 0, 20]);
+        assert!(cache.should_request(address, later));
+        assert!(!cache.should_request(address, later + Duration::from_millis(500)));
+        assert!(cache.should_request(address, later + RETRY));
+    }
+}
@@ -0,0 +1,341 @@
+//! DHCP client (RFC 2131), without the rebinding state: a lease not renewed by its server
+//! expires.
+
+use super::wire::{read_address, read_u32, write_u16, write_u32};
+use super::Ipv4Address;
+use time::{Duration, Instant};
+
+pub const CLIENT_PORT: u16 = 68;
+pub const SERVER_PORT: u16 = 67;
+
+/// Length of the fixed part of a message, up to the magic cookie included.
+const HEADER: usize = 240;
+/// Messages are padded to the minimum BOOTP size some servers expect.
+pub const MESSAGE: usize = 300;
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+const OPTION_NETMASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const RETRY: Duration = Duration::from_secs(4);
+const REQUEST_TRIES: u8 = 4;
+/// Lease duration assumed when the server gives none, and maximum one.
+const DEFAULT_LEASE: Duration = Duration::from_secs(3600);
+const MAX_LEASE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lease {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+    pub server: Ipv4Address,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Discovering,
+    Requesting(Lease),
+    Bound {
+        lease: Lease,
+        renew_at: Instant,
+        expires_at: Instant,
+    },
+    Renewing {
+        lease: Lease,
+        expires_at: Instant,
+    },
+}
+
+pub struct Client {
+    mac: [u8; 6],
+    state: State,
+    xid: u32,
+    next_send: Instant,
+    tries: u8,
+}
+
+impl Client {
+    pub fn new(mac: [u8; 6]) -> Client {
+        Client {
+            mac,
+            state: State::Discovering,
+            xid: read_u32(&mac, 2),
+            next_send: Instant::from_micros(0),
+            tries: 0,
+        }
+    }
+
+    /// The address in use, kept while renewing it.
+    pub fn lease(&self) -> Option<&Lease> {
+        match self.state {
+            State::Bound { ref lease, .. } | State::Renewing { ref lease, .. } => Some(lease),
+            _ => None,
+        }
+    }
+
+    fn restart(&mut self, now: Instant) {
+        self.state = State::Discovering;
+        self.xid = self.xid.wrapping_add(1);
+        self.next_send = now;
+        self.tries = 0;
+    }
+
+    /// Writes the message due at `now` in `buffer`, at least `MESSAGE` bytes long, and returns
+    /// its length. The message is broadcast from the client port to the server port.
+    pub fn poll(&mut self, now: Instant, buffer: &mut [u8]) -> Option<usize> {
+        match self.state {
+            State::Bound {
+                lease,
+                renew_at,
+                expires_at,
+            } => {
+                if now < renew_at {
+                    return None;
+                }
+                self.state = State::Renewing { lease, expires_at };
+                self.xid = self.xid.wrapping_add(1);
+                self.next_send = now;
+            }
+            State::Renewing { expires_at, .. } if now >= expires_at => self.restart(now),
+            _ => {}
+        }
+        if now < self.next_send {
+            return None;
+        }
+        self.next_send = now + RETRY;
+
+        let len = match self.state {
+            State::Discovering => self.write(buffer, DISCOVER, None, None),
+            State::Requesting(offer) => {
+                if self.tries == REQUEST_TRIES {
+                    self.restart(now);
+                    return self.poll(now, buffer);
+                }
+                self.tries += 1;
+                self.write(buffer, REQUEST, None, Some(&offer))
+            }
+            State::Renewing { lease, .. } => self.write(buffer, REQUEST, Some(lease.address), None),
+            State::Bound { .. } => return None,
+        };
+        Some(len)
+    }
+
+    fn write(
+        &self,
+        buffer: &mut [u8],
+        kind: u8,
+        client: Option<Ipv4Address>,
+        offer: Option<&Lease>,
+    ) -> usize {
+        for b in buffer[..MESSAGE].iter_mut() {
+            *b = 0;
+        }
+        // BOOTREQUEST over Ethernet.
+        buffer[0] = 1;
+        buffer[1] = 1;
+        buffer[2] = 6;
+        write_u32(buffer, 4, self.xid);
+        if let Some(address) = client {
+            buffer[12..16].copy_from_slice(&address.0);
+        } else {
+            // replies broadcast as long as there is no address.
+            write_u16(buffer, 10, 0x8000);
+        }
+        buffer[28..34].copy_from_slice(&self.mac);
+        write_u32(buffer, 236, MAGIC_COOKIE);
+
+        let mut i = HEADER;
+        {
+            let mut option = |code: u8, value: &[u8]| {
+                buffer[i] = code;
+                buffer[i + 1] = value.len() as u8;
+                buffer[i + 2..i + 2 + value.len()].copy_from_slice(value);
+                i += 2 + value.len();
+            };
+            option(OPTION_MESSAGE_TYPE, &[kind]);
+            if let Some(offer) = offer {
+                option(OPTION_REQUESTED_ADDRESS, &offer.address.0);
+                option(OPTION_SERVER_ID, &offer.server.0);
+            }
+            option(
+                OPTION_PARAMETERS,
+                &[OPTION_NETMASK, OPTION_ROUTER, OPTION_DNS, OPTION_LEASE_TIME],
+            );
+        }
+        buffer[i] = OPTION_END;
+        MESSAGE
+    }
+
+    /// Handles a message received on the client port.
+    pub fn process(&mut self, now: Instant, message: &[u8]) {
+        if message.len() < HEADER
+            || message[0] != 2
+            || read_u32(message, 4) != self.xid
+            || message[28..34] != self.mac
+            || read_u32(message, 236) != MAGIC_COOKIE
+        {
+            return;
+        }
+        let mut kind = 0;
+        let mut lease = Lease {
+            address: read_address(message, 16),
+            netmask: Ipv4Address([255, 255, 255, 0]),
+            gateway: None,
+            dns: None,
+            server: Ipv4Address::UNSPECIFIED,
+            duration: DEFAULT_LEASE,
+        };
+        let mut i = HEADER;
+        while i < message.len() {
+            let code = message[i];
+            match code {
+                0 => {
+                    i += 1;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let len = match message.get(i + 1) {
+                Some(&len) => usize::from(len),
+                None => break,
+            };
+            let value = match message.get(i + 2..i + 2 + len) {
+                Some(value) => value,
+                None => break,
+            };
+            match (code, len) {
+                (OPTION_MESSAGE_TYPE, 1) => kind = value[0],
+                (OPTION_NETMASK, 4) => lease.netmask = read_address(value, 0),
+                (OPTION_ROUTER, _) if len >= 4 => lease.gateway = Some(read_address(value, 0)),
+                (OPTION_DNS, _) if len >= 4 => lease.dns = Some(read_address(value, 0)),
+                (OPTION_SERVER_ID, 4) => lease.server = read_address(value, 0),
+                (OPTION_LEASE_TIME, 4) => {
+                    let secs = u64::from(read_u32(value, 0));
+                    lease.duration = Duration::from_secs(secs).min(MAX_LEASE);
+                }
+                _ => {}
+            }
+            i += 2 + len;
+        }
+
+        match (self.state, kind) {
+            (State::Discovering, OFFER) => {
+                self.state = State::Requesting(lease);
+                self.next_send = now;
+                self.tries = 0;
+            }
+            (State::Requesting(_), ACK) | (State::Renewing { .. }, ACK) => {
+                self.state = State::Bound {
+                    lease,
+                    renew_at: now + Duration::from_micros(lease.duration.as_micros() / 2),
+                    expires_at: now + lease.duration,
+                };
+            }
+            (State::Requesting(_), NAK) | (State::Renewing { .. }, NAK) => self.restart(now),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(request: &[u8], kind: u8, address: [u8; 4]) -> [u8; MESSAGE] {
+        let mut message = [0; MESSAGE];
+        message[..HEADER].copy_from_slice(&request[..HEADER]);
+        message[0] = 2;
+        message[16..20].copy_from_slice(&address);
+        let options = [
+            OPTION_MESSAGE_TYPE,
+            1,
+            kind,
+            OPTION_SERVER_ID,
+            4,
+            10,
+            0,
+            0,
+            1,
+            OPTION_NETMASK,
+            4,
+            255,
+            255,
+            0,
+            0,
+            OPTION_ROUTER,
+            4,
+            10,
+            0,
+            0,
+            1,
+            OPTION_LEASE_TIME,
+            4,
+            0,
+            0,
+            0x0E,
+            0x10,
+            OPTION_END,
+        ];
+        message[HEADER..HEADER + options.len()].copy_from_slice(&options);
+        message
+    }
+
+    #[test]
+    fn test_lease() {
+        let mac = [2, 0, 0, 0, 0, 1];
+        let mut client = Client::new(mac);
+        let mut buffer = [0; MESSAGE];
+        let now = Instant::from_micros(0);
+
+        let len = client.poll(now, &mut buffer).unwrap();
+        assert_eq!(MESSAGE, len);
+        assert_eq!(
+            &[OPTION_MESSAGE_TYPE, 1, DISCOVER],
+            &buffer[HEADER..HEADER + 3]
+        );
+        assert_eq!(None, client.poll(now, &mut buffer));
+
+        client.process(now, &reply(&buffer, OFFER, [10, 0, 0, 42]));
+        client.poll(now, &mut buffer).unwrap();
+        assert_eq!(
+            &[OPTION_MESSAGE_TYPE, 1, REQUEST],
+            &buffer[HEADER..HEADER + 3]
+        );
+        assert_eq!(
+            &[OPTION_REQUESTED_ADDRESS, 4, 10, 0, 0, 42],
+            &buffer[HEADER + 3..HEADER + 9]
+        );
+        client.process(now, &reply(&buffer, ACK, [10, 0, 0, 42]));
+        let lease = *client.lease().unwrap();
+        assert_eq!(Ipv4Address([10, 0, 0, 42]), lease.address);
+        assert_eq!(Ipv4Address([255, 255, 0, 0]), lease.netmask);
+        assert_eq!(Some(Ipv4Address([10, 0, 0, 1])), lease.gateway);
+        assert_eq!(Duration::from_secs(3600), lease.duration);
+
+        // renewal at half the lease, from the address in use.
+        let later = now + Duration::from_secs(1800);
+        client.poll(later, &mut buffer).unwrap();
+        assert_eq!(&[10, 0, 0, 42], &buffer[12..16]);
+        assert!(client.lease().is_some());
+        let expired = now + Duration::from_secs(3600);
+        client.poll(expired, &mut buffer).unwrap();
+        assert_eq!(None, client.lease());
+        assert_eq!(DISCOVER, buffer[HEADER + 2]);
+    }
+}
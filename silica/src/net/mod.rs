@@ -0,0 +1,720 @@
+//! A small IPv4 stack: ARP, ICMP echo, UDP, TCP, a DHCP client and SNTP messages, over any
+//! `Device` able to send and receive Ethernet frames.
+//!
+//! The interface owns no memory besides one frame each way: the sockets are given their
+//! buffers, and everything progresses in `Interface::poll`.
+
+mod arp;
+mod dhcp;
+pub mod sntp;
+mod tcp;
+mod udp;
+pub mod wire;
+
+pub use self::dhcp::Lease;
+pub use self::tcp::{State as TcpState, TcpSocket};
+pub use self::udp::{UdpSocket, MAX_UDP_PAYLOAD};
+
+use self::wire::*;
+use time::Instant;
+
+/// Largest IP packet.
+pub const MTU: usize = 1500;
+/// Largest Ethernet frame, without the frame check sequence.
+pub const MAX_FRAME: usize = ETHERNET_HEADER + MTU;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([0xFF; 4]);
+
+    fn masked(self, netmask: Ipv4Address) -> [u8; 4] {
+        let mut masked = self.0;
+        for (b, m) in masked.iter_mut().zip(netmask.0.iter()) {
+            *b &= m;
+        }
+        masked
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+impl Endpoint {
+    pub fn new(address: Ipv4Address, port: u16) -> Endpoint {
+        Endpoint { address, port }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The socket has no local port.
+    NotBound,
+    /// The data can't fit in the socket buffer or in a packet.
+    TooLarge,
+    /// The previous datagram is not sent yet.
+    Busy,
+    /// The operation is not possible in the state of the connection.
+    InvalidState,
+    /// The peer closed the connection.
+    Closed,
+}
+
+/// An Ethernet controller, or any other way to move frames.
+pub trait Device {
+    fn mac_address(&self) -> [u8; 6];
+    fn link_up(&mut self) -> bool;
+    /// Copies a received frame into `buffer`, returns its length.
+    fn receive(&mut self, buffer: &mut [u8]) -> Option<usize>;
+    /// Queues a frame, returns false if there is no room for it.
+    fn transmit(&mut self, frame: &[u8]) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv4Config {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+}
+
+/// The device and what's needed to send packets with it.
+struct Link<D> {
+    device: D,
+    mac: [u8; 6],
+    config: Option<Ipv4Config>,
+    arp: arp::Cache,
+    ip_id: u16,
+    tx: [u8; MAX_FRAME],
+}
+
+impl<D: Device> Link<D> {
+    fn source(&self) -> Ipv4Address {
+        self.config
+            .map(|c| c.address)
+            .unwrap_or(Ipv4Address::UNSPECIFIED)
+    }
+
+    fn send_arp(&mut self, operation: u16, dst_mac: [u8; 6], target: ([u8; 6], Ipv4Address)) {
+        let mac = self.mac;
+        let source = self.source();
+        let frame = &mut self.tx[..ETHERNET_HEADER + 28];
+        write_ethernet(frame, dst_mac, mac, ETHERTYPE_ARP);
+        {
+            let packet = &mut frame[ETHERNET_HEADER..];
+            // Ethernet and IPv4.
+            write_u16(packet, 0, 1);
+            write_u16(packet, 2, ETHERTYPE_IPV4);
+            packet[4] = 6;
+            packet[5] = 4;
+            write_u16(packet, 6, operation);
+            packet[8..14].copy_from_slice(&mac);
+            packet[14..18].copy_from_slice(&source.0);
+            packet[18..24].copy_from_slice(&target.0);
+            packet[24..28].copy_from_slice(&(target.1).0);
+        }
+        self.device.transmit(frame);
+    }
+
+    /// Sends an IPv4 packet whose `len` bytes of payload are written by `fill`. Returns false
+    /// if the packet could not be sent, a request being sent if the next hop is not resolved.
+    fn send_ipv4<F>(
+        &mut self,
+        now: Instant,
+        dst: Ipv4Address,
+        protocol: u8,
+        len: usize,
+        fill: F,
+    ) -> bool
+    where
+        F: FnOnce(&mut [u8], Ipv4Address),
+    {
+        let mac = if dst == Ipv4Address::BROADCAST {
+            BROADCAST_MAC
+        } else {
+            let config = match self.config {
+                Some(config) => config,
+                None => return false,
+            };
+            let hop = if dst.masked(config.netmask) == config.address.masked(config.netmask) {
+                dst
+            } else {
+                match config.gateway {
+                    Some(gateway) => gateway,
+                    None => return false,
+                }
+            };
+            match self.arp.lookup(hop, now) {
+                Some(mac) => mac,
+                None => {
+                    if self.arp.should_request(hop, now) {
+                        self.send_arp(1, BROADCAST_MAC, ([0; 6], hop));
+                    }
+                    return false;
+                }
+            }
+        };
+        let src = self.source();
+        let own = self.mac;
+        let id = self.ip_id;
+        let end = ETHERNET_HEADER + IPV4_HEADER + len;
+        {
+            let frame = &mut self.tx[..end];
+            write_ethernet(frame, mac, own, ETHERTYPE_IPV4);
+            let packet = &mut frame[ETHERNET_HEADER..];
+            write_ipv4(packet, src, dst, protocol, id, len);
+            fill(&mut packet[IPV4_HEADER..], src);
+        }
+        if self.device.transmit(&self.tx[..end]) {
+            self.ip_id = id.wrapping_add(1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct Interface<'a, 'b: 'a, D> {
+    link: Link<D>,
+    dhcp: Option<dhcp::Client>,
+    tcp: &'a mut [TcpSocket<'b>],
+    udp: &'a mut [UdpSocket<'b>],
+    rx: [u8; MAX_FRAME],
+}
+
+impl<'a, 'b, D: Device> Interface<'a, 'b, D> {
+    /// An interface without address, serving the given sockets.
+    pub fn new(
+        device: D,
+        tcp: &'a mut [TcpSocket<'b>],
+        udp: &'a mut [UdpSocket<'b>],
+    ) -> Interface<'a, 'b, D> {
+        let mac = device.mac_address();
+        Interface {
+            link: Link {
+                device,
+                mac,
+                config: None,
+                arp: arp::Cache::new(),
+                ip_id: 0,
+                tx: [0; MAX_FRAME],
+            },
+            dhcp: None,
+            tcp,
+            udp,
+            rx: [0; MAX_FRAME],
+        }
+    }
+
+    /// Uses a static address.
+    pub fn set_config(&mut self, config: Ipv4Config) {
+        self.dhcp = None;
+        self.link.config = Some(config);
+    }
+
+    /// Gets an address from a DHCP server, and keeps renewing it.
+    pub fn use_dhcp(&mut self) {
+        self.dhcp = Some(dhcp::Client::new(self.link.mac));
+        self.link.config = None;
+    }
+
+    pub fn config(&self) -> Option<Ipv4Config> {
+        self.link.config
+    }
+
+    /// The DHCP lease in use, if any.
+    pub fn lease(&self) -> Option<Lease> {
+        self.dhcp.as_ref().and_then(dhcp::Client::lease).cloned()
+    }
+
+    pub fn device(&mut self) -> &mut D {
+        &mut self.link.device
+    }
+
+    pub fn tcp(&mut self, index: usize) -> &mut TcpSocket<'b> {
+        &mut self.tcp[index]
+    }
+
+    pub fn udp(&mut self, index: usize) -> &mut UdpSocket<'b> {
+        &mut self.udp[index]
+    }
+
+    /// Handles the received frames, then sends what the sockets and the DHCP client have to.
+    pub fn poll(&mut self, now: Instant) {
+        while let Some(len) = self.link.device.receive(&mut self.rx) {
+            let frame = &self.rx[..len.min(MAX_FRAME)];
+            if frame.len() < ETHERNET_HEADER {
+                continue;
+            }
+            match read_u16(frame, 12) {
+                ETHERTYPE_ARP => process_arp(&mut self.link, now, &frame[ETHERNET_HEADER..]),
+                ETHERTYPE_IPV4 => {
+                    let src_mac = read_mac(frame, 6);
+                    let packet = &frame[ETHERNET_HEADER..];
+                    if let Some(header) = Ipv4Header::parse(packet) {
+                        let packet = &packet[..header.total_len];
+                        let mut sockets = Sockets {
+                            dhcp: &mut self.dhcp,
+                            tcp: &mut *self.tcp,
+                            udp: &mut *self.udp,
+                        };
+                        sockets.process(&mut self.link, now, src_mac, &header, packet);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.dispatch(now);
+    }
+
+    fn dispatch(&mut self, now: Instant) {
+        let link = &mut self.link;
+        if let Some(ref mut client) = self.dhcp {
+            let mut message = [0; dhcp::MESSAGE];
+            if let Some(len) = client.poll(now, &mut message) {
+                let message = &message[..len];
+                let dst = Ipv4Address::BROADCAST;
+                link.send_ipv4(now, dst, PROTOCOL_UDP, UDP_HEADER + len, |datagram, src| {
+                    datagram[UDP_HEADER..].copy_from_slice(message);
+                    write_udp(datagram, src, dst, dhcp::CLIENT_PORT, dhcp::SERVER_PORT);
+                });
+            }
+            link.config = client.lease().map(|lease| Ipv4Config {
+                address: lease.address,
+                netmask: lease.netmask,
+                gateway: lease.gateway,
+            });
+        }
+
+        for socket in self.udp.iter_mut() {
+            let sent = match socket.queued() {
+                Some((data, to)) => {
+                    let port = socket.port();
+                    link.send_ipv4(
+                        now,
+                        to.address,
+                        PROTOCOL_UDP,
+                        UDP_HEADER + data.len(),
+                        |datagram, src| {
+                            datagram[UDP_HEADER..].copy_from_slice(data);
+                            write_udp(datagram, src, to.address, port, to.port);
+                        },
+                    )
+                }
+                None => false,
+            };
+            if sent {
+                socket.mark_sent();
+            }
+        }
+
+        for socket in self.tcp.iter_mut() {
+            while let Some(segment) = socket.next_segment(now) {
+                let header_len = if segment.header.mss.is_some() {
+                    TCP_HEADER + 4
+                } else {
+                    TCP_HEADER
+                };
+                let dst = segment.remote.address;
+                let sent = {
+                    let socket = &*socket;
+                    link.send_ipv4(
+                        now,
+                        dst,
+                        PROTOCOL_TCP,
+                        header_len + segment.len,
+                        |data, src| {
+                            write_tcp(data, &segment.header);
+                            socket.payload(&segment, &mut data[header_len..]);
+                            finish_tcp(data, src, dst);
+                        },
+                    )
+                };
+                if !sent {
+                    break;
+                }
+                socket.segment_sent(&segment, now);
+            }
+        }
+    }
+}
+
+fn process_arp<D: Device>(link: &mut Link<D>, now: Instant, packet: &[u8]) {
+    if packet.len() < 28 || read_u16(packet, 0) != 1 || read_u16(packet, 2) != ETHERTYPE_IPV4 {
+        return;
+    }
+    let sender = (read_mac(packet, 8), read_address(packet, 14));
+    let target = read_address(packet, 24);
+    let own = match link.config {
+        Some(config) => config.address,
+        None => return,
+    };
+    if target != own {
+        return;
+    }
+    link.arp.insert(sender.1, sender.0, now);
+    if read_u16(packet, 6) == 1 {
+        link.send_arp(2, sender.0, sender);
+    }
+}
+
+/// The receivers of the IPv4 packets.
+struct Sockets<'s, 'b: 's> {
+    dhcp: &'s mut Option<dhcp::Client>,
+    tcp: &'s mut [TcpSocket<'b>],
+    udp: &'s mut [UdpSocket<'b>],
+}
+
+impl<'s, 'b> Sockets<'s, 'b> {
+    fn process<D: Device>(
+        &mut self,
+        link: &mut Link<D>,
+        now: Instant,
+        src_mac: [u8; 6],
+        header: &Ipv4Header,
+        packet: &[u8],
+    ) {
+        let config = link.config;
+        let for_us = header.dst == Ipv4Address::BROADCAST
+            || config.map(|c| c.address == header.dst).unwrap_or(false);
+        // offers may be sent to the address being offered.
+        let dhcp = self.dhcp.is_some() && header.protocol == PROTOCOL_UDP;
+        if !for_us && !dhcp {
+            return;
+        }
+        let data = &packet[header.header_len..];
+        if header.protocol != PROTOCOL_ICMP
+            && !verify(header.src, header.dst, header.protocol, data)
+        {
+            return;
+        }
+        if let Some(config) = config {
+            // replies go where the requests come from.
+            if header.src.masked(config.netmask) == config.address.masked(config.netmask) {
+                link.arp.insert(header.src, src_mac, now);
+            }
+        }
+        match header.protocol {
+            PROTOCOL_ICMP => {
+                // echo request.
+                if !for_us || data.len() < 8 || data[0] != 8 || checksum(sum(0, data)) != 0 {
+                    return;
+                }
+                link.send_ipv4(now, header.src, PROTOCOL_ICMP, data.len(), |reply, _| {
+                    reply.copy_from_slice(data);
+                    reply[0] = 0;
+                    write_u16(reply, 2, 0);
+                    let checksum = checksum(sum(0, reply));
+                    write_u16(reply, 2, checksum);
+                });
+            }
+            PROTOCOL_UDP => {
+                if data.len() < UDP_HEADER {
+                    return;
+                }
+                let len = usize::from(read_u16(data, 4));
+                if len < UDP_HEADER || len > data.len() {
+                    return;
+                }
+                let dst_port = read_u16(data, 2);
+                let from = Endpoint::new(header.src, read_u16(data, 0));
+                let payload = &data[UDP_HEADER..len];
+                if dst_port == dhcp::CLIENT_PORT {
+                    if let Some(ref mut client) = *self.dhcp {
+                        client.process(now, payload);
+                        return;
+                    }
+                }
+                if !for_us {
+                    return;
+                }
+                if let Some(socket) = self.udp.iter_mut().find(|s| s.port() == dst_port) {
+                    socket.accept(payload, from);
+                }
+            }
+            PROTOCOL_TCP if for_us && header.dst != Ipv4Address::BROADCAST => {
+                let tcp = match TcpHeader::parse(data) {
+                    Some(tcp) => tcp,
+                    None => return,
+                };
+                let payload = &data[tcp.header_len..];
+                let socket = match self.tcp.iter().position(|s| s.matches(header.src, &tcp)) {
+                    Some(i) => Some(i),
+                    None => self.tcp.iter().position(|s| s.is_listening(tcp.dst_port)),
+                };
+                match socket {
+                    Some(i) => self.tcp[i].process(now, header.src, &tcp, payload),
+                    None if tcp.flags & TCP_RST == 0 => {
+                        reset(link, now, header, &tcp, payload.len())
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Answers a segment for no connection.
+fn reset<D: Device>(
+    link: &mut Link<D>,
+    now: Instant,
+    ip: &Ipv4Header,
+    tcp: &TcpHeader,
+    len: usize,
+) {
+    let (seq, ack, flags) = if tcp.flags & TCP_ACK != 0 {
+        (tcp.ack, 0, TCP_RST)
+    } else {
+        let ack = tcp.seq.wrapping_add(tcp.sequence_len(len));
+        (0, ack, TCP_RST | TCP_ACK)
+    };
+    let header = TcpHeader {
+        src_port: tcp.dst_port,
+        dst_port: tcp.src_port,
+        seq,
+        ack,
+        flags,
+        window: 0,
+        mss: None,
+        header_len: TCP_HEADER,
+    };
+    let dst = ip.src;
+    link.send_ipv4(now, dst, PROTOCOL_TCP, TCP_HEADER, |data, src| {
+        write_tcp(data, &header);
+        finish_tcp(data, src, dst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec::Vec;
+    use time::Duration;
+
+    type Queue = Rc<RefCell<VecDeque<Vec<u8>>>>;
+
+    /// One end of an in-memory cable.
+    struct Port {
+        mac: [u8; 6],
+        rx: Queue,
+        tx: Queue,
+        /// Number of frames to lose.
+        lose: usize,
+    }
+
+    impl Device for Port {
+        fn mac_address(&self) -> [u8; 6] {
+            self.mac
+        }
+
+        fn link_up(&mut self) -> bool {
+            true
+        }
+
+        fn receive(&mut self, buffer: &mut [u8]) -> Option<usize> {
+            self.rx.borrow_mut().pop_front().map(|frame| {
+                buffer[..frame.len()].copy_from_slice(&frame);
+                frame.len()
+            })
+        }
+
+        fn transmit(&mut self, frame: &[u8]) -> bool {
+            if self.lose > 0 {
+                self.lose -= 1;
+            } else {
+                self.tx.borrow_mut().push_back(frame.to_vec());
+            }
+            true
+        }
+    }
+
+    const MAC_A: [u8; 6] = [2, 0, 0, 0, 0, 0xA];
+    const MAC_B: [u8; 6] = [2, 0, 0, 0, 0, 0xB];
+    const A: Ipv4Address = Ipv4Address([192, 168, 1, 10]);
+    const B: Ipv4Address = Ipv4Address([192, 168, 1, 11]);
+
+    fn cable() -> (Port, Port) {
+        let (a, b) = (Queue::default(), Queue::default());
+        let port = |mac, rx: &Queue, tx: &Queue| Port {
+            mac,
+            rx: rx.clone(),
+            tx: tx.clone(),
+            lose: 0,
+        };
+        (port(MAC_A, &a, &b), port(MAC_B, &b, &a))
+    }
+
+    fn config(address: Ipv4Address) -> Ipv4Config {
+        Ipv4Config {
+            address,
+            netmask: Ipv4Address([255, 255, 255, 0]),
+            gateway: None,
+        }
+    }
+
+    fn run(a: &mut Interface<Port>, b: &mut Interface<Port>, now: &mut Instant) {
+        for _ in 0..8 {
+            a.poll(*now);
+            b.poll(*now);
+            *now += Duration::from_millis(1);
+        }
+    }
+
+    #[test]
+    fn test_ping() {
+        let (mut host, port) = cable();
+        let mut iface = Interface::new(port, &mut [], &mut []);
+        iface.set_config(config(B));
+
+        let mut frame = [0; ETHERNET_HEADER + IPV4_HEADER + 12];
+        write_ethernet(&mut frame, MAC_B, MAC_A, ETHERTYPE_IPV4);
+        write_ipv4(&mut frame[ETHERNET_HEADER..], A, B, PROTOCOL_ICMP, 1, 12);
+        {
+            let icmp = &mut frame[ETHERNET_HEADER + IPV4_HEADER..];
+            icmp[0] = 8;
+            icmp[4..].copy_from_slice(b"\x00\x01\x00\x01ping");
+            let checksum = checksum(sum(0, icmp));
+            write_u16(icmp, 2, checksum);
+        }
+        host.transmit(&frame);
+        iface.poll(Instant::from_micros(0));
+
+        let mut reply = [0; MAX_FRAME];
+        let len = host.receive(&mut reply).unwrap();
+        assert_eq!(frame.len(), len);
+        assert_eq!(MAC_A, read_mac(&reply, 0));
+        let header = Ipv4Header::parse(&reply[ETHERNET_HEADER..len]).unwrap();
+        assert_eq!(
+            (B, A, PROTOCOL_ICMP),
+            (header.src, header.dst, header.protocol)
+        );
+        let icmp = &reply[ETHERNET_HEADER + IPV4_HEADER..len];
+        assert_eq!(0, icmp[0]);
+        assert_eq!(0, checksum(sum(0, icmp)));
+        assert_eq!(b"ping", &icmp[8..]);
+    }
+
+    #[test]
+    fn test_udp() {
+        let (port_a, port_b) = cable();
+        let (mut rx_a, mut tx_a, mut rx_b, mut tx_b) = ([0; 64], [0; 64], [0; 64], [0; 64]);
+        let mut udp_a = [UdpSocket::new(&mut rx_a, &mut tx_a)];
+        let mut udp_b = [UdpSocket::new(&mut rx_b, &mut tx_b)];
+        let mut a = Interface::new(port_a, &mut [], &mut udp_a);
+        let mut b = Interface::new(port_b, &mut [], &mut udp_b);
+        a.set_config(config(A));
+        b.set_config(config(B));
+        a.udp(0).bind(5000);
+        b.udp(0).bind(7);
+
+        let mut now = Instant::from_micros(0);
+        a.udp(0).send_to(b"echo", Endpoint::new(B, 7)).unwrap();
+        run(&mut a, &mut b, &mut now);
+        let mut buffer = [0; 64];
+        let (len, from) = b.udp(0).recv_from(&mut buffer).unwrap();
+        assert_eq!(
+            (&b"echo"[..], Endpoint::new(A, 5000)),
+            (&buffer[..len], from)
+        );
+
+        b.udp(0).send_to(&buffer[..len], from).unwrap();
+        run(&mut a, &mut b, &mut now);
+        let (len, from) = a.udp(0).recv_from(&mut buffer).unwrap();
+        assert_eq!((&b"echo"[..], Endpoint::new(B, 7)), (&buffer[..len], from));
+    }
+
+    #[test]
+    fn test_udp_malformed() {
+        let (mut host, port) = cable();
+        let (mut rx, mut tx) = ([0; 64], [0; MTU]);
+        let mut udp = [UdpSocket::new(&mut rx, &mut tx)];
+        let mut iface = Interface::new(port, &mut [], &mut udp);
+        iface.set_config(config(B));
+        iface.udp(0).bind(7);
+
+        // the length field covers less than the header, then more than the packet.
+        for &len in &[4, 13] {
+            let mut frame = [0; ETHERNET_HEADER + IPV4_HEADER + 12];
+            write_ethernet(&mut frame, MAC_B, MAC_A, ETHERTYPE_IPV4);
+            write_ipv4(&mut frame[ETHERNET_HEADER..], A, B, PROTOCOL_UDP, 1, 12);
+            {
+                let udp = &mut frame[ETHERNET_HEADER + IPV4_HEADER..];
+                write_u16(udp, 0, 5000);
+                write_u16(udp, 2, 7);
+                write_u16(udp, 4, len);
+                udp[UDP_HEADER..].copy_from_slice(b"ping");
+            }
+            host.transmit(&frame);
+            iface.poll(Instant::from_micros(0));
+            assert_eq!(None, iface.udp(0).recv_from(&mut [0; 64]));
+        }
+
+        let datagram = [0; MAX_UDP_PAYLOAD + 1];
+        assert_eq!(
+            Err(Error::TooLarge),
+            iface.udp(0).send_to(&datagram, Endpoint::new(A, 5000))
+        );
+        assert_eq!(
+            Ok(()),
+            iface.udp(0).send_to(&datagram[1..], Endpoint::new(A, 5000))
+        );
+    }
+
+    #[test]
+    fn test_tcp() {
+        let (port_a, port_b) = cable();
+        let (mut rx_a, mut tx_a) = ([0; 1024], [0; 1024]);
+        let (mut rx_b, mut tx_b) = ([0; 1024], [0; 1024]);
+        let mut tcp_a = [TcpSocket::new(&mut rx_a, &mut tx_a)];
+        let mut tcp_b = [TcpSocket::new(&mut rx_b, &mut tx_b)];
+        let mut a = Interface::new(port_a, &mut tcp_a, &mut []);
+        let mut b = Interface::new(port_b, &mut tcp_b, &mut []);
+        a.set_config(config(A));
+        b.set_config(config(B));
+
+        let mut now = Instant::from_micros(1_000_000);
+        b.tcp(0).listen(80).unwrap();
+        a.tcp(0).connect(Endpoint::new(B, 80), 49152).unwrap();
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(TcpState::Established, a.tcp(0).state());
+        assert_eq!(TcpState::Established, b.tcp(0).state());
+        assert_eq!(Endpoint::new(A, 49152), b.tcp(0).remote());
+
+        let mut buffer = [0; 16];
+        assert_eq!(Ok(5), a.tcp(0).send(b"hello"));
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(Ok(5), b.tcp(0).recv(&mut buffer));
+        assert_eq!(b"hello", &buffer[..5]);
+
+        // the lost segment is sent again after the retransmission timeout.
+        b.device().lose = 1;
+        assert_eq!(Ok(5), b.tcp(0).send(b"world"));
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(Ok(0), a.tcp(0).recv(&mut buffer));
+        now += Duration::from_secs(1);
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(Ok(5), a.tcp(0).recv(&mut buffer));
+        assert_eq!(b"world", &buffer[..5]);
+
+        a.tcp(0).close();
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(TcpState::FinWait2, a.tcp(0).state());
+        assert_eq!(TcpState::CloseWait, b.tcp(0).state());
+        assert_eq!(Err(Error::Closed), b.tcp(0).recv(&mut buffer));
+        b.tcp(0).close();
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(TcpState::Closed, b.tcp(0).state());
+        assert_eq!(TcpState::TimeWait, a.tcp(0).state());
+        now += Duration::from_secs(10);
+        run(&mut a, &mut b, &mut now);
+        assert_eq!(TcpState::Closed, a.tcp(0).state());
+    }
+}
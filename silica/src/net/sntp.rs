@@ -0,0 +1,78 @@
+//! SNTP client messages (RFC 4330), exchanged over a `UdpSocket` to set a calendar:
+//!
+//! ```ignore
+//! let len = sntp::request(&mut buffer);
+//! socket.send_to(&buffer[..len], Endpoint::new(server, sntp::PORT))?;
+//! // later
+//! if let Some((len, _)) = socket.recv_from(&mut buffer) {
+//!     if let Some(now) = sntp::parse_reply(&buffer[..len]) {
+//!         rtc.set_date_time(now);
+//!     }
+//! }
+//! ```
+
+use super::wire::read_u32;
+use time::DateTime;
+
+pub const PORT: u16 = 123;
+pub const MESSAGE: usize = 48;
+
+/// Version 4, client mode.
+const CLIENT: u8 = 4 << 3 | 3;
+const MODE_SERVER: u8 = 4;
+/// Seconds from the NTP epoch, 1900-01-01, to the Unix one.
+const UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Writes a request in `buffer`, at least `MESSAGE` bytes long, and returns its length.
+pub fn request(buffer: &mut [u8]) -> usize {
+    for b in buffer[..MESSAGE].iter_mut() {
+        *b = 0;
+    }
+    buffer[0] = CLIENT;
+    MESSAGE
+}
+
+/// The transmit time of a server reply, None for an unsynchronized server or a kiss-o'-death.
+pub fn parse_reply(message: &[u8]) -> Option<DateTime> {
+    if message.len() < MESSAGE || message[0] & 0x7 != MODE_SERVER || message[0] >> 6 == 3 {
+        return None;
+    }
+    match message[1] {
+        1..=15 => {}
+        _ => return None,
+    }
+    let secs = u64::from(read_u32(message, 40));
+    // the seconds wrap in 2036: the earlier half of the range is the next era.
+    let secs = if secs < 1 << 31 {
+        secs + (1 << 32)
+    } else {
+        secs
+    };
+    // the times before 1970 are not representable.
+    DateTime::from_unix_time(secs.checked_sub(UNIX_OFFSET)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply() {
+        let mut buffer = [0xFF; MESSAGE];
+        assert_eq!(MESSAGE, request(&mut buffer));
+        assert_eq!(0x23, buffer[0]);
+        assert!(buffer[1..].iter().all(|&b| b == 0));
+
+        let mut reply = [0; MESSAGE];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply[40..44].copy_from_slice(&[0xDE, 0xBB, 0xB3, 0xDE]);
+        assert_eq!(DateTime::new(2018, 6, 1, 12, 0, 30), parse_reply(&reply));
+        // 1968.
+        reply[40..44].copy_from_slice(&[0x80, 0, 0, 0]);
+        assert_eq!(None, parse_reply(&reply));
+        // kiss-o'-death.
+        reply[1] = 0;
+        assert_eq!(None, parse_reply(&reply));
+    }
+}
@@ -0,0 +1,576 @@
+//! TCP sockets.
+//!
+//! Each socket owns its receive and transmit buffers, whose sizes bound the advertised window
+//! and the data in flight. Segments received out of order are dropped and the lost ones are
+//! retransmitted from the first unacknowledged byte.
+
+use super::wire::{TcpHeader, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN};
+use super::{Endpoint, Error, Ipv4Address};
+use time::{Duration, Instant};
+
+/// Maximum segment size announced, for a 1500 bytes MTU.
+pub const MSS: u16 = 1460;
+/// Maximum segment size of the peers not announcing theirs.
+const DEFAULT_MSS: u16 = 536;
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+const MAX_RETRIES: u8 = 8;
+const TIME_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Byte FIFO over a caller provided buffer, readable at an offset for the retransmissions.
+struct Buffer<'a> {
+    data: &'a mut [u8],
+    head: usize,
+    len: usize,
+}
+
+impl<'a> Buffer<'a> {
+    fn free(&self) -> usize {
+        self.data.len() - self.len
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Appends as much of `bytes` as fits, returns how much did.
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let capacity = self.data.len();
+        let count = bytes.len().min(self.free());
+        for (i, &b) in bytes[..count].iter().enumerate() {
+            self.data[(self.head + self.len + i) % capacity] = b;
+        }
+        self.len += count;
+        count
+    }
+
+    /// Copies the bytes from `offset` into `buffer`, returns their count.
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        let capacity = self.data.len();
+        let count = buffer.len().min(self.len.saturating_sub(offset));
+        for (i, b) in buffer[..count].iter_mut().enumerate() {
+            *b = self.data[(self.head + offset + i) % capacity];
+        }
+        count
+    }
+
+    fn drop_front(&mut self, count: usize) {
+        let count = count.min(self.len);
+        if count > 0 {
+            self.head = (self.head + count) % self.data.len();
+            self.len -= count;
+        }
+    }
+}
+
+/// A segment to send, see `TcpSocket::next_segment`.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub header: TcpHeader,
+    pub remote: Endpoint,
+    /// Position of the payload in the transmit buffer, and its length.
+    offset: usize,
+    pub len: usize,
+}
+
+pub struct TcpSocket<'a> {
+    state: State,
+    local_port: u16,
+    remote: Endpoint,
+    rx: Buffer<'a>,
+    tx: Buffer<'a>,
+    /// The initial sequence number is taken from the clock at the first segment.
+    iss_pending: bool,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// Highest sequence number sent, `snd_nxt` goes back to `snd_una` for a retransmission.
+    snd_max: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    remote_mss: u16,
+    /// A FIN is to follow the data.
+    closing: bool,
+    ack_pending: bool,
+    rst_pending: bool,
+    rto: Duration,
+    retransmit_at: Option<Instant>,
+    retries: u8,
+    time_wait_end: Instant,
+}
+
+impl<'a> TcpSocket<'a> {
+    pub fn new(rx: &'a mut [u8], tx: &'a mut [u8]) -> TcpSocket<'a> {
+        TcpSocket {
+            state: State::Closed,
+            local_port: 0,
+            remote: Endpoint::new(Ipv4Address::UNSPECIFIED, 0),
+            rx: Buffer {
+                data: rx,
+                head: 0,
+                len: 0,
+            },
+            tx: Buffer {
+                data: tx,
+                head: 0,
+                len: 0,
+            },
+            iss_pending: false,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            remote_mss: DEFAULT_MSS,
+            closing: false,
+            ack_pending: false,
+            rst_pending: false,
+            rto: INITIAL_RTO,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_end: Instant::from_micros(0),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    pub fn remote(&self) -> Endpoint {
+        self.remote
+    }
+
+    fn reset(&mut self, state: State) {
+        self.state = state;
+        self.rx.clear();
+        self.tx.clear();
+        self.closing = false;
+        self.ack_pending = false;
+        self.rto = INITIAL_RTO;
+        self.retransmit_at = None;
+        self.retries = 0;
+        self.remote_mss = DEFAULT_MSS;
+    }
+
+    /// Waits for a connection on `port`.
+    pub fn listen(&mut self, port: u16) -> Result<(), Error> {
+        if self.state != State::Closed {
+            return Err(Error::InvalidState);
+        }
+        self.reset(State::Listen);
+        self.local_port = port;
+        Ok(())
+    }
+
+    /// Opens a connection to `remote` from `local_port`.
+    pub fn connect(&mut self, remote: Endpoint, local_port: u16) -> Result<(), Error> {
+        if self.state != State::Closed {
+            return Err(Error::InvalidState);
+        }
+        self.reset(State::SynSent);
+        self.local_port = local_port;
+        self.remote = remote;
+        self.iss_pending = true;
+        Ok(())
+    }
+
+    /// Sends a FIN once the queued data is sent. Listening sockets are simply closed.
+    pub fn close(&mut self) {
+        match self.state {
+            State::Listen | State::SynSent => self.reset(State::Closed),
+            State::SynReceived | State::Established | State::CloseWait => self.closing = true,
+            _ => {}
+        }
+    }
+
+    /// Drops the connection, resetting it.
+    pub fn abort(&mut self) {
+        let synchronized = match self.state {
+            State::Closed | State::Listen | State::SynSent | State::TimeWait => false,
+            _ => true,
+        };
+        self.reset(State::Closed);
+        self.rst_pending = synchronized;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Established || self.state == State::CloseWait
+    }
+
+    /// Data can still be queued.
+    pub fn may_send(&self) -> bool {
+        let open = match self.state {
+            State::SynSent | State::SynReceived | State::Established | State::CloseWait => true,
+            _ => false,
+        };
+        open && !self.closing
+    }
+
+    /// Data can still be received: the peer did not close its side.
+    pub fn may_recv(&self) -> bool {
+        match self.state {
+            State::SynSent | State::SynReceived | State::Established => true,
+            State::FinWait1 | State::FinWait2 => true,
+            _ => self.rx.len > 0,
+        }
+    }
+
+    /// Queues as much of `data` as fits in the transmit buffer.
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, Error> {
+        if !self.may_send() {
+            return Err(Error::InvalidState);
+        }
+        Ok(self.tx.push(data))
+    }
+
+    /// Takes received data. `Error::Closed` tells the peer will not send more.
+    pub fn recv(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if self.rx.len == 0 {
+            return if self.may_recv() {
+                Ok(0)
+            } else {
+                Err(Error::Closed)
+            };
+        }
+        let window = self.rx.free();
+        let count = self.rx.read_at(0, buffer);
+        self.rx.drop_front(count);
+        // the peer waits for the window to open.
+        if window < usize::from(MSS) && self.rx.free() >= usize::from(MSS).min(self.rx.data.len()) {
+            self.ack_pending = true;
+        }
+        Ok(count)
+    }
+
+    fn window(&self) -> u16 {
+        self.rx.free().min(0xFFFF) as u16
+    }
+
+    /// Whether the segment from `src` belongs to this socket, listening sockets aside.
+    pub fn matches(&self, src: Ipv4Address, header: &TcpHeader) -> bool {
+        match self.state {
+            State::Closed | State::Listen => false,
+            _ => {
+                self.local_port == header.dst_port
+                    && self.remote == Endpoint::new(src, header.src_port)
+            }
+        }
+    }
+
+    pub fn is_listening(&self, port: u16) -> bool {
+        self.state == State::Listen && self.local_port == port
+    }
+
+    /// Handles a segment for the socket.
+    pub fn process(&mut self, now: Instant, src: Ipv4Address, header: &TcpHeader, data: &[u8]) {
+        if header.flags & TCP_RST != 0 {
+            let acceptable = match self.state {
+                State::Listen => false,
+                State::SynSent => header.flags & TCP_ACK != 0 && header.ack == self.snd_nxt,
+                _ => header.seq == self.rcv_nxt,
+            };
+            if acceptable {
+                self.reset(State::Closed);
+            }
+            return;
+        }
+
+        match self.state {
+            State::Closed | State::TimeWait if header.flags & TCP_FIN != 0 => {
+                self.ack_pending = true;
+                return;
+            }
+            State::Closed => return,
+            State::Listen => {
+                if header.flags & TCP_SYN != 0 {
+                    self.remote = Endpoint::new(src, header.src_port);
+                    self.rcv_nxt = header.seq.wrapping_add(1);
+                    self.snd_wnd = u32::from(header.window);
+                    self.remote_mss = header.mss.unwrap_or(DEFAULT_MSS);
+                    self.state = State::SynReceived;
+                    self.iss_pending = true;
+                }
+                return;
+            }
+            State::SynSent => {
+                let syn_ack = TCP_SYN | TCP_ACK;
+                if header.flags & syn_ack == syn_ack && header.ack == self.snd_nxt {
+                    self.rcv_nxt = header.seq.wrapping_add(1);
+                    self.snd_una = header.ack;
+                    self.snd_wnd = u32::from(header.window);
+                    self.remote_mss = header.mss.unwrap_or(DEFAULT_MSS);
+                    self.state = State::Established;
+                    self.ack_pending = true;
+                    self.acknowledged(now);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if header.flags & TCP_SYN != 0 {
+            // a retransmitted SYN: our acknowledgement was lost.
+            self.ack_pending = true;
+            return;
+        }
+
+        if header.flags & TCP_ACK != 0 {
+            let acked = header.ack.wrapping_sub(self.snd_una);
+            if acked > self.snd_max.wrapping_sub(self.snd_una) {
+                // acknowledges what was never sent.
+                self.ack_pending = true;
+                return;
+            }
+            self.snd_wnd = u32::from(header.window);
+            if acked > 0 {
+                self.advance(header.ack, acked, now);
+            }
+        }
+
+        if data.is_empty() && header.flags & TCP_FIN == 0 {
+            return;
+        }
+        self.ack_pending = true;
+        if header.seq != self.rcv_nxt {
+            return;
+        }
+        let receiving = match self.state {
+            State::Established | State::FinWait1 | State::FinWait2 => true,
+            _ => false,
+        };
+        if !receiving {
+            return;
+        }
+        let accepted = self.rx.push(data);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+        if header.flags & TCP_FIN != 0 && accepted == data.len() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.state = match self.state {
+                State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                _ => {
+                    self.time_wait_end = now + TIME_WAIT;
+                    State::TimeWait
+                }
+            };
+        }
+    }
+
+    /// Handles the acknowledgement of `acked` more bytes of the sequence space.
+    fn advance(&mut self, ack: u32, mut acked: u32, now: Instant) {
+        if self.state == State::SynReceived {
+            self.state = State::Established;
+            acked -= 1;
+        }
+        let fin_acked = acked > self.tx.len as u32;
+        self.tx.drop_front(acked as usize);
+        self.snd_una = ack;
+        if self.snd_nxt.wrapping_sub(self.snd_una) > self.snd_max.wrapping_sub(self.snd_una) {
+            self.snd_nxt = ack;
+        }
+        self.acknowledged(now);
+        if fin_acked {
+            self.state = match self.state {
+                State::FinWait1 => State::FinWait2,
+                State::Closing => {
+                    self.time_wait_end = now + TIME_WAIT;
+                    State::TimeWait
+                }
+                State::LastAck => {
+                    self.reset(State::Closed);
+                    State::Closed
+                }
+                state => state,
+            };
+        }
+    }
+
+    fn acknowledged(&mut self, now: Instant) {
+        self.retries = 0;
+        self.rto = INITIAL_RTO;
+        self.retransmit_at = if self.snd_una == self.snd_max {
+            None
+        } else {
+            Some(now + self.rto)
+        };
+    }
+
+    fn segment(&self, flags: u8, offset: usize, len: usize) -> Segment {
+        Segment {
+            header: TcpHeader {
+                src_port: self.local_port,
+                dst_port: self.remote.port,
+                seq: self.snd_nxt,
+                ack: if flags & TCP_ACK != 0 {
+                    self.rcv_nxt
+                } else {
+                    0
+                },
+                flags,
+                window: self.window(),
+                mss: None,
+                header_len: 0,
+            },
+            remote: self.remote,
+            offset,
+            len,
+        }
+    }
+
+    /// The next segment to send, if any. It only counts as sent after `segment_sent`.
+    pub fn next_segment(&mut self, now: Instant) -> Option<Segment> {
+        if self.rst_pending {
+            return Some(self.segment(TCP_RST | TCP_ACK, 0, 0));
+        }
+        match self.state {
+            State::Closed | State::Listen => return None,
+            State::TimeWait => {
+                if now >= self.time_wait_end {
+                    self.reset(State::Closed);
+                    return None;
+                }
+                return if self.ack_pending {
+                    Some(self.segment(TCP_ACK, 0, 0))
+                } else {
+                    None
+                };
+            }
+            _ => {}
+        }
+        if self.iss_pending {
+            // the clock based initial sequence number of RFC 793.
+            self.iss = (now.as_micros() / 4) as u32;
+            self.snd_una = self.iss;
+            self.snd_nxt = self.iss;
+            self.snd_max = self.iss;
+            self.iss_pending = false;
+        }
+
+        if let Some(at) = self.retransmit_at {
+            if now >= at {
+                if self.retries == MAX_RETRIES {
+                    self.abort();
+                    return self.next_segment(now);
+                }
+                self.retries += 1;
+                self.rto = (self.rto + self.rto).min(MAX_RTO);
+                self.snd_nxt = self.snd_una;
+                self.retransmit_at = None;
+            }
+        }
+
+        if self.state == State::SynSent || self.state == State::SynReceived {
+            if self.snd_nxt != self.iss {
+                return None;
+            }
+            let flags = if self.state == State::SynSent {
+                TCP_SYN
+            } else {
+                TCP_SYN | TCP_ACK
+            };
+            let mut segment = self.segment(flags, 0, 0);
+            segment.header.mss = Some(MSS);
+            return Some(segment);
+        }
+
+        let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        let offset = in_flight.min(self.tx.len);
+        let usable = (self.snd_wnd as usize).saturating_sub(in_flight);
+        let len = (self.tx.len - offset)
+            .min(usable)
+            .min(usize::from(self.remote_mss.min(MSS)));
+        let fin_done = match self.state {
+            State::FinWait2 | State::TimeWait => true,
+            _ => false,
+        };
+        let fin =
+            self.closing && !fin_done && in_flight <= self.tx.len && offset + len == self.tx.len;
+        if len == 0 && !fin && !self.ack_pending {
+            return None;
+        }
+        let mut flags = TCP_ACK;
+        if len > 0 {
+            flags |= TCP_PSH;
+        }
+        if fin {
+            flags |= TCP_FIN;
+        }
+        Some(self.segment(flags, offset, len))
+    }
+
+    /// Copies the payload of `segment` to `buffer`.
+    pub fn payload(&self, segment: &Segment, buffer: &mut [u8]) {
+        self.tx.read_at(segment.offset, &mut buffer[..segment.len]);
+    }
+
+    /// Records the transmission of `segment`.
+    pub fn segment_sent(&mut self, segment: &Segment, now: Instant) {
+        let header = &segment.header;
+        if header.flags & TCP_RST != 0 {
+            self.rst_pending = false;
+            return;
+        }
+        self.ack_pending = false;
+        let len = header.sequence_len(segment.len);
+        if len == 0 {
+            return;
+        }
+        self.snd_nxt = header.seq.wrapping_add(len);
+        if self.snd_nxt.wrapping_sub(self.snd_una) > self.snd_max.wrapping_sub(self.snd_una) {
+            self.snd_max = self.snd_nxt;
+        }
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+        if header.flags & TCP_FIN != 0 {
+            self.state = match self.state {
+                State::Established | State::SynReceived => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_wraps() {
+        let mut storage = [0; 8];
+        let mut buffer = Buffer {
+            data: &mut storage,
+            head: 0,
+            len: 0,
+        };
+        assert_eq!(6, buffer.push(b"abcdef"));
+        buffer.drop_front(4);
+        assert_eq!(6, buffer.push(b"ghijklmn"));
+        let mut read = [0; 8];
+        assert_eq!(6, buffer.read_at(2, &mut read));
+        assert_eq!(b"ghijkl", &read[..6]);
+        assert_eq!(0, buffer.free());
+    }
+}
@@ -0,0 +1,84 @@
+//! UDP sockets, holding one datagram each way.
+
+use super::wire::{IPV4_HEADER, UDP_HEADER};
+use super::{Endpoint, Error, MTU};
+
+/// Largest datagram payload sent in a single packet.
+pub const MAX_UDP_PAYLOAD: usize = MTU - IPV4_HEADER - UDP_HEADER;
+
+pub struct UdpSocket<'a> {
+    port: u16,
+    rx: &'a mut [u8],
+    received: Option<(usize, Endpoint)>,
+    tx: &'a mut [u8],
+    queued: Option<(usize, Endpoint)>,
+}
+
+impl<'a> UdpSocket<'a> {
+    /// A socket receiving and sending datagrams up to the size of the buffers.
+    pub fn new(rx: &'a mut [u8], tx: &'a mut [u8]) -> UdpSocket<'a> {
+        UdpSocket {
+            port: 0,
+            rx,
+            received: None,
+            tx,
+            queued: None,
+        }
+    }
+
+    /// Receives the datagrams sent to `port`, the port of those sent from the socket.
+    pub fn bind(&mut self, port: u16) {
+        self.port = port;
+        self.received = None;
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn can_send(&self) -> bool {
+        self.queued.is_none()
+    }
+
+    /// Queues a datagram, sent at the next poll of the interface.
+    pub fn send_to(&mut self, data: &[u8], endpoint: Endpoint) -> Result<(), Error> {
+        if self.port == 0 {
+            return Err(Error::NotBound);
+        }
+        if data.len() > self.tx.len() || data.len() > MAX_UDP_PAYLOAD {
+            return Err(Error::TooLarge);
+        }
+        if self.queued.is_some() {
+            return Err(Error::Busy);
+        }
+        self.tx[..data.len()].copy_from_slice(data);
+        self.queued = Some((data.len(), endpoint));
+        Ok(())
+    }
+
+    /// Takes the received datagram, truncated to `buffer`.
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Option<(usize, Endpoint)> {
+        self.received.take().map(|(len, from)| {
+            let len = len.min(buffer.len());
+            buffer[..len].copy_from_slice(&self.rx[..len]);
+            (len, from)
+        })
+    }
+
+    /// Stores a datagram for the socket, dropped if the previous one was not taken.
+    pub fn accept(&mut self, data: &[u8], from: Endpoint) {
+        if self.received.is_none() && data.len() <= self.rx.len() {
+            self.rx[..data.len()].copy_from_slice(data);
+            self.received = Some((data.len(), from));
+        }
+    }
+
+    /// The datagram waiting to be sent.
+    pub fn queued(&self) -> Option<(&[u8], Endpoint)> {
+        self.queued.map(move |(len, to)| (&self.tx[..len], to))
+    }
+
+    pub fn mark_sent(&mut self) {
+        self.queued = None;
+    }
+}
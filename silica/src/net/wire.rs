@@ -0,0 +1,326 @@
+//! Layouts of the Ethernet, ARP, IPv4, ICMP, UDP and TCP headers.
+
+use super::Ipv4Address;
+
+pub const ETHERNET_HEADER: usize = 14;
+pub const IPV4_HEADER: usize = 20;
+pub const UDP_HEADER: usize = 8;
+pub const TCP_HEADER: usize = 20;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+pub const BROADCAST_MAC: [u8; 6] = [0xFF; 6];
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) << 8 | u16::from(data[offset + 1])
+}
+
+pub fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset] = (value >> 8) as u8;
+    data[offset + 1] = value as u8;
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(data, offset)) << 16 | u32::from(read_u16(data, offset + 2))
+}
+
+pub fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    write_u16(data, offset, (value >> 16) as u16);
+    write_u16(data, offset + 2, value as u16);
+}
+
+pub fn read_address(data: &[u8], offset: usize) -> Ipv4Address {
+    let mut address = [0; 4];
+    address.copy_from_slice(&data[offset..offset + 4]);
+    Ipv4Address(address)
+}
+
+pub fn read_mac(data: &[u8], offset: usize) -> [u8; 6] {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&data[offset..offset + 6]);
+    mac
+}
+
+/// Adds `data` to a one's complement sum.
+pub fn sum(mut acc: u32, data: &[u8]) -> u32 {
+    for pair in data.chunks(2) {
+        let word = if pair.len() == 2 {
+            read_u16(pair, 0)
+        } else {
+            u16::from(pair[0]) << 8
+        };
+        acc += u32::from(word);
+    }
+    acc
+}
+
+/// Folds a one's complement sum into a checksum.
+pub fn checksum(mut acc: u32) -> u16 {
+    while acc > 0xFFFF {
+        acc = (acc & 0xFFFF) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// Sum of the pseudo header covered by the UDP and TCP checksums.
+pub fn pseudo_header(src: Ipv4Address, dst: Ipv4Address, protocol: u8, len: usize) -> u32 {
+    let acc = sum(sum(0, &src.0), &dst.0);
+    acc + u32::from(protocol) + len as u32
+}
+
+pub fn write_ethernet(frame: &mut [u8], dst: [u8; 6], src: [u8; 6], ethertype: u16) {
+    frame[0..6].copy_from_slice(&dst);
+    frame[6..12].copy_from_slice(&src);
+    write_u16(frame, 12, ethertype);
+}
+
+/// A received IPv4 header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ipv4Header {
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub protocol: u8,
+    pub header_len: usize,
+    pub total_len: usize,
+}
+
+impl Ipv4Header {
+    /// Checks the version, lengths and checksum of `packet`. Fragments are not supported.
+    pub fn parse(packet: &[u8]) -> Option<Ipv4Header> {
+        if packet.len() < IPV4_HEADER || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = usize::from(packet[0] & 0xF) * 4;
+        let total_len = usize::from(read_u16(packet, 2));
+        let fragmented = read_u16(packet, 6) & 0x3FFF != 0;
+        if header_len < IPV4_HEADER || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        if fragmented || checksum(sum(0, &packet[..header_len])) != 0 {
+            return None;
+        }
+        Some(Ipv4Header {
+            src: read_address(packet, 12),
+            dst: read_address(packet, 16),
+            protocol: packet[9],
+            header_len,
+            total_len,
+        })
+    }
+}
+
+/// Fills a 20 byte IPv4 header for `payload_len` bytes.
+pub fn write_ipv4(
+    packet: &mut [u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    protocol: u8,
+    id: u16,
+    payload_len: usize,
+) {
+    packet[0] = 0x45;
+    packet[1] = 0;
+    write_u16(packet, 2, (IPV4_HEADER + payload_len) as u16);
+    write_u16(packet, 4, id);
+    // don't fragment.
+    write_u16(packet, 6, 0x4000);
+    packet[8] = 64;
+    packet[9] = protocol;
+    write_u16(packet, 10, 0);
+    packet[12..16].copy_from_slice(&src.0);
+    packet[16..20].copy_from_slice(&dst.0);
+    let checksum = checksum(sum(0, &packet[..IPV4_HEADER]));
+    write_u16(packet, 10, checksum);
+}
+
+/// Fills the header of a UDP datagram whose payload follows it.
+pub fn write_udp(
+    datagram: &mut [u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    src_port: u16,
+    dst_port: u16,
+) {
+    let len = datagram.len();
+    write_u16(datagram, 0, src_port);
+    write_u16(datagram, 2, dst_port);
+    write_u16(datagram, 4, len as u16);
+    write_u16(datagram, 6, 0);
+    let acc = sum(pseudo_header(src, dst, PROTOCOL_UDP, len), datagram);
+    let checksum = match checksum(acc) {
+        // 0 means no checksum.
+        0 => 0xFFFF,
+        c => c,
+    };
+    write_u16(datagram, 6, checksum);
+}
+
+/// Checks the checksum of a UDP datagram or TCP segment.
+pub fn verify(src: Ipv4Address, dst: Ipv4Address, protocol: u8, data: &[u8]) -> bool {
+    if protocol == PROTOCOL_UDP && data.len() >= UDP_HEADER && read_u16(data, 6) == 0 {
+        return true;
+    }
+    checksum(sum(pseudo_header(src, dst, protocol, data.len()), data)) == 0
+}
+
+/// A received TCP segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    /// Maximum segment size option, on SYN segments.
+    pub mss: Option<u16>,
+    pub header_len: usize,
+}
+
+impl TcpHeader {
+    pub fn parse(segment: &[u8]) -> Option<TcpHeader> {
+        if segment.len() < TCP_HEADER {
+            return None;
+        }
+        let header_len = usize::from(segment[12] >> 4) * 4;
+        if header_len < TCP_HEADER || header_len > segment.len() {
+            return None;
+        }
+        let mut mss = None;
+        let mut i = TCP_HEADER;
+        while i < header_len {
+            match segment[i] {
+                0 => break,
+                1 => i += 1,
+                kind => {
+                    let len = usize::from(*segment.get(i + 1)?).max(2);
+                    if kind == 2 && len == 4 && i + 4 <= header_len {
+                        mss = Some(read_u16(segment, i + 2));
+                    }
+                    i += len;
+                }
+            }
+        }
+        Some(TcpHeader {
+            src_port: read_u16(segment, 0),
+            dst_port: read_u16(segment, 2),
+            seq: read_u32(segment, 4),
+            ack: read_u32(segment, 8),
+            flags: segment[13],
+            window: read_u16(segment, 14),
+            mss,
+            header_len,
+        })
+    }
+
+    /// Length in the sequence space: the data and the SYN and FIN flags.
+    pub fn sequence_len(&self, data_len: usize) -> u32 {
+        let mut len = data_len as u32;
+        if self.flags & TCP_SYN != 0 {
+            len += 1;
+        }
+        if self.flags & TCP_FIN != 0 {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// Fills the header of a TCP segment whose payload follows it, with the MSS option if `mss`.
+/// Returns the header length.
+pub fn write_tcp(segment: &mut [u8], header: &TcpHeader) -> usize {
+    let header_len = if header.mss.is_some() {
+        TCP_HEADER + 4
+    } else {
+        TCP_HEADER
+    };
+    write_u16(segment, 0, header.src_port);
+    write_u16(segment, 2, header.dst_port);
+    write_u32(segment, 4, header.seq);
+    write_u32(segment, 8, header.ack);
+    segment[12] = (header_len as u8 / 4) << 4;
+    segment[13] = header.flags;
+    write_u16(segment, 14, header.window);
+    write_u16(segment, 16, 0);
+    write_u16(segment, 18, 0);
+    if let Some(mss) = header.mss {
+        segment[20] = 2;
+        segment[21] = 4;
+        write_u16(segment, 22, mss);
+    }
+    header_len
+}
+
+/// Sets the checksum of a complete TCP segment.
+pub fn finish_tcp(segment: &mut [u8], src: Ipv4Address, dst: Ipv4Address) {
+    let acc = sum(
+        pseudo_header(src, dst, PROTOCOL_TCP, segment.len()),
+        segment,
+    );
+    write_u16(segment, 16, checksum(acc));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_header() {
+        let mut packet = [0; 28];
+        let src = Ipv4Address([192, 168, 0, 1]);
+        let dst = Ipv4Address([192, 168, 0, 199]);
+        write_ipv4(&mut packet, src, dst, PROTOCOL_UDP, 0x1C46, 8);
+        // header checksum computed by hand.
+        assert_eq!(0x9C72, read_u16(&packet, 10));
+        let header = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(
+            (src, dst, PROTOCOL_UDP),
+            (header.src, header.dst, header.protocol)
+        );
+        assert_eq!((20, 28), (header.header_len, header.total_len));
+        packet[15] = 2;
+        assert_eq!(None, Ipv4Header::parse(&packet));
+    }
+
+    #[test]
+    fn test_tcp_header() {
+        let src = Ipv4Address([10, 0, 0, 1]);
+        let dst = Ipv4Address([10, 0, 0, 2]);
+        let mut segment = [0; 24 + 3];
+        let header = TcpHeader {
+            src_port: 49152,
+            dst_port: 80,
+            seq: 0x0102_0304,
+            ack: 0,
+            flags: TCP_SYN,
+            window: 2048,
+            mss: Some(1460),
+            header_len: 0,
+        };
+        assert_eq!(24, write_tcp(&mut segment, &header));
+        segment[24..].copy_from_slice(b"abc");
+        finish_tcp(&mut segment, src, dst);
+        assert!(verify(src, dst, PROTOCOL_TCP, &segment));
+        assert!(!verify(dst, dst, PROTOCOL_TCP, &segment));
+        let parsed = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(
+            TcpHeader {
+                header_len: 24,
+                ..header
+            },
+            parsed
+        );
+        assert_eq!(4, parsed.sequence_len(3));
+    }
+}
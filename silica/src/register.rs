@@ -197,6 +197,22 @@ macro_rules! register_impl_extract_insert {
             }
         }
     };
+    (@keyed $name:ident : $t:ty, $key_mask:expr, $key:expr) => {
+        // Registers protected by a key: every write must carry the key in the `$key_mask` bits.
+        impl $name {
+            #[inline]
+            #[allow(dead_code)]
+            fn extract(&self, f: &Field) -> $t {
+                (self.0 >> f.lsb()) & f.mask::<$t>()
+            }
+            #[inline]
+            #[allow(dead_code)]
+            fn insert(&mut self, f: &Field, v: $t) {
+                let mask = !($key_mask) & !(f.mask::<$t>() << f.lsb());
+                self.0 = (self.0 & mask) | $key | ((v & f.mask::<$t>()) << f.lsb());
+            }
+        }
+    };
     (@array $name:ident : $work_t:ty => $cell_t:ty) => {
         impl $name {
             fn extract(&self, _f: &Field) -> $work_t {
@@ -0,0 +1,102 @@
+//! Fixed capacity FIFO over a caller provided buffer.
+//!
+//! It is not synchronized: share it between a task and an interrupt handler through a
+//! `sync::Mutex`.
+
+use core::marker::PhantomData;
+
+pub struct RingBuffer<T, B> {
+    buffer: B,
+    head: usize,
+    len: usize,
+    _item: PhantomData<T>,
+}
+impl<T, B> RingBuffer<T, B>
+where
+    T: Copy,
+    B: AsMut<[T]> + AsRef<[T]>,
+{
+    pub const fn new(buffer: B) -> RingBuffer<T, B> {
+        RingBuffer {
+            buffer,
+            head: 0,
+            len: 0,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.as_ref().len()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    /// Returns the item back if the buffer is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let capacity = self.capacity();
+        if self.len == capacity {
+            return Err(item);
+        }
+        self.buffer.as_mut()[(self.head + self.len) % capacity] = item;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.buffer.as_ref()[self.head];
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        Some(item)
+    }
+
+    pub fn peek(&self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.buffer.as_ref()[self.head])
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Gives the storage back.
+    pub fn release(self) -> B {
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_around() {
+        let mut ring = RingBuffer::new([0u8; 3]);
+        assert_eq!(Ok(()), ring.push(1));
+        assert_eq!(Ok(()), ring.push(2));
+        assert_eq!(Some(1), ring.pop());
+        assert_eq!(Ok(()), ring.push(3));
+        assert_eq!(Ok(()), ring.push(4));
+        assert!(ring.is_full());
+        assert_eq!(Err(5), ring.push(5));
+        assert_eq!(Some(2), ring.peek());
+        assert_eq!(Some(2), ring.pop());
+        assert_eq!(Some(3), ring.pop());
+        assert_eq!(Some(4), ring.pop());
+        assert_eq!(None, ring.pop());
+        assert!(ring.is_empty());
+    }
+}
@@ -0,0 +1,718 @@
+//! SD memory card protocol
+//!
+//! `Card` brings SD, SDHC and SDXC cards up and moves blocks through a `Host`, the controller
+//! driving the bus:
+//!
+//! ```ignore
+//! let mut card = Card::new(host);
+//! card.init()?;
+//! card.read_blocks(0, &mut buffer)?;
+//! ```
+//!
+//! Failed transfers are stopped and retried. A card that is removed or keeps failing has to be
+//! initialised again.
+
+use block::BlockDevice;
+pub use block::BLOCK_SIZE;
+
+/// Bus clock during the identification.
+pub const IDENTIFICATION_CLOCK_HZ: u32 = 400_000;
+/// Bus clock of the default speed mode.
+pub const DEFAULT_CLOCK_HZ: u32 = 25_000_000;
+
+/// Attempts of a failed transfer.
+const RETRIES: usize = 3;
+/// ACMD41 polls before giving up on a card stuck in its power up.
+const POWER_UP_POLLS: usize = 1000;
+
+/// Voltage window 2.7-3.6V of the OCR.
+const OCR_VOLTAGE_WINDOW: u32 = 0x00FF_8000;
+const OCR_POWERED_UP: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+/// CMD8 argument: 2.7-3.6V and a check pattern echoed by the card.
+const INTERFACE_CONDITION: u32 = 0x1AA;
+
+/// Error bits of the card status.
+const STATUS_ERRORS: u32 = 0xFDF9_8008;
+const STATUS_READY_FOR_DATA: u32 = 1 << 8;
+const STATE_TRANSFER: u32 = 4;
+
+/// Format of a command's response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    None,
+    /// Card status.
+    R1,
+    /// Card status, then busy on the data line.
+    R1b,
+    /// CID or CSD, 128 bits.
+    R2,
+    /// OCR, without CRC.
+    R3,
+    /// Published RCA.
+    R6,
+    /// Interface condition.
+    R7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusWidth {
+    One,
+    Four,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostError {
+    /// No response or data in time.
+    Timeout,
+    Crc,
+    /// Malformed response.
+    Response,
+    /// Data overrun, underrun or framing error.
+    Data,
+    /// More data than the controller moves at once.
+    TooLong,
+}
+
+/// A controller driving an SD bus.
+///
+/// The 48-bit responses are returned in the first word. The 128-bit ones take the four words,
+/// most significant first, without their CRC.
+pub trait Host {
+    fn card_present(&mut self) -> bool;
+    /// Sends the 74 clock cycles the card requires after its power up.
+    fn power_up(&mut self);
+    fn set_clock(&mut self, hz: u32);
+    fn set_bus_width(&mut self, width: BusWidth);
+    fn command(
+        &mut self,
+        index: u8,
+        argument: u32,
+        response: Response,
+    ) -> Result<[u32; 4], HostError>;
+    /// Sends a command and receives `buffer.len() / block_size` blocks, returns the R1 status.
+    fn read(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &mut [u8],
+    ) -> Result<u32, HostError>;
+    /// Sends a command and transmits `buffer.len() / block_size` blocks, returns the R1 status.
+    fn write(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &[u8],
+    ) -> Result<u32, HostError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    Host(HostError),
+    NoCard,
+    /// The card was not initialised, was removed or failed too many times.
+    NotInitialized,
+    /// Not an SD card or does not support the host's voltage.
+    Unusable,
+    /// Error bits of the card status.
+    Card(u32),
+    /// The buffer is not a whole number of blocks.
+    InvalidBuffer,
+    OutOfRange,
+}
+
+impl From<HostError> for Error {
+    fn from(e: HostError) -> Error {
+        Error::Host(e)
+    }
+}
+
+/// Bits `msb` to `lsb` of a 128-bit register.
+fn bits(words: &[u32; 4], msb: usize, lsb: usize) -> u32 {
+    let mut value = 0u64;
+    for bit in (lsb..=msb).rev() {
+        let word = words[3 - bit / 32];
+        value = (value << 1) | u64::from((word >> (bit % 32)) & 1);
+    }
+    value as u32
+}
+
+/// Card Specific Data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Csd {
+    /// 0 for standard capacity cards, 1 for SDHC and SDXC.
+    pub version: u8,
+    /// Capacity in 512 bytes blocks.
+    pub blocks: u32,
+    pub max_clock_hz: u32,
+}
+
+impl Csd {
+    pub fn parse(words: &[u32; 4]) -> Csd {
+        let version = bits(words, 127, 126) as u8;
+        let blocks = if version == 0 {
+            let size = bits(words, 73, 62) + 1;
+            let multiplier = 1 << (bits(words, 49, 47) + 2);
+            let block_len = 1 << bits(words, 83, 80);
+            size * multiplier * (block_len / BLOCK_SIZE as u32)
+        } else {
+            (bits(words, 69, 48) + 1) * 1024
+        };
+        Csd {
+            version,
+            blocks,
+            max_clock_hz: transfer_speed(bits(words, 103, 96) as u8),
+        }
+    }
+}
+
+/// Decodes the TRAN_SPEED field.
+fn transfer_speed(field: u8) -> u32 {
+    const UNITS: [u32; 4] = [10_000, 100_000, 1_000_000, 10_000_000];
+    const TENTHS: [u32; 16] = [
+        0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+    ];
+    let unit = usize::from(field & 0x7);
+    if unit >= UNITS.len() {
+        return 0;
+    }
+    UNITS[unit] * TENTHS[usize::from((field >> 3) & 0xF)]
+}
+
+/// Card Identification
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cid {
+    pub manufacturer: u8,
+    pub oem: [u8; 2],
+    pub product: [u8; 5],
+    pub revision: u8,
+    pub serial: u32,
+    pub year: u16,
+    pub month: u8,
+}
+
+impl Cid {
+    pub fn parse(words: &[u32; 4]) -> Cid {
+        let byte = |msb| bits(words, msb, msb - 7) as u8;
+        Cid {
+            manufacturer: byte(127),
+            oem: [byte(119), byte(111)],
+            product: [byte(103), byte(95), byte(87), byte(79), byte(71)],
+            revision: byte(63),
+            serial: bits(words, 55, 24),
+            year: 2000 + bits(words, 19, 12) as u16,
+            month: bits(words, 11, 8) as u8,
+        }
+    }
+}
+
+/// What the initialisation learnt about the card.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CardInfo {
+    pub rca: u16,
+    /// SDHC and SDXC cards are addressed by block rather than by byte.
+    pub high_capacity: bool,
+    pub cid: Cid,
+    pub csd: Csd,
+}
+
+pub struct Card<H> {
+    host: H,
+    info: Option<CardInfo>,
+}
+
+impl<H: Host> Card<H> {
+    pub fn new(host: H) -> Card<H> {
+        Card { host, info: None }
+    }
+
+    pub fn release(self) -> H {
+        self.host
+    }
+
+    /// Information on the initialised card.
+    pub fn info(&self) -> Option<&CardInfo> {
+        self.info.as_ref()
+    }
+
+    /// Capacity in blocks, 0 when there is no initialised card.
+    pub fn blocks(&self) -> u32 {
+        self.info.map_or(0, |info| info.csd.blocks)
+    }
+
+    /// Identifies the card and selects it, in 4-bit mode.
+    pub fn init(&mut self) -> Result<&CardInfo, Error> {
+        self.info = None;
+        if !self.host.card_present() {
+            return Err(Error::NoCard);
+        }
+        self.host.set_bus_width(BusWidth::One);
+        self.host.set_clock(IDENTIFICATION_CLOCK_HZ);
+        self.host.power_up();
+
+        // GO_IDLE_STATE
+        self.host.command(0, 0, Response::None)?;
+        // SEND_IF_COND, only answered by the version 2.00 cards.
+        let version2 = match self.host.command(8, INTERFACE_CONDITION, Response::R7) {
+            Ok(r) if r[0] & 0xFFF == INTERFACE_CONDITION => true,
+            Ok(_) => return Err(Error::Unusable),
+            Err(HostError::Timeout) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut argument = OCR_VOLTAGE_WINDOW;
+        if version2 {
+            argument |= OCR_HIGH_CAPACITY;
+        }
+        let mut ocr = 0;
+        for _ in 0..POWER_UP_POLLS {
+            // SD_SEND_OP_COND
+            ocr = self.app_command(0, 41, argument, Response::R3)?;
+            if ocr & OCR_POWERED_UP != 0 {
+                break;
+            }
+        }
+        if ocr & OCR_POWERED_UP == 0 || ocr & OCR_VOLTAGE_WINDOW == 0 {
+            return Err(Error::Unusable);
+        }
+
+        // ALL_SEND_CID
+        let cid = Cid::parse(&self.host.command(2, 0, Response::R2)?);
+        // SEND_RELATIVE_ADDR
+        let rca = (self.host.command(3, 0, Response::R6)?[0] >> 16) as u16;
+        let address = u32::from(rca) << 16;
+        // SEND_CSD
+        let csd = Csd::parse(&self.host.command(9, address, Response::R2)?);
+        // SELECT_CARD
+        self.card_command(7, address, Response::R1b)?;
+        // SET_BUS_WIDTH
+        self.app_command(rca, 6, 2, Response::R1)?;
+        self.host.set_bus_width(BusWidth::Four);
+        let high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+        if !high_capacity {
+            // SET_BLOCKLEN
+            self.card_command(16, BLOCK_SIZE as u32, Response::R1)?;
+        }
+        let speed = if csd.max_clock_hz == 0 {
+            DEFAULT_CLOCK_HZ
+        } else {
+            csd.max_clock_hz.min(DEFAULT_CLOCK_HZ)
+        };
+        self.host.set_clock(speed);
+
+        self.info = Some(CardInfo {
+            rca,
+            high_capacity,
+            cid,
+            csd,
+        });
+        Ok(self.info.as_ref().unwrap())
+    }
+
+    /// Reads whole blocks from `first`.
+    pub fn read_blocks(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        let (count, argument) = self.prepare(first, buffer.len())?;
+        // READ_SINGLE_BLOCK or READ_MULTIPLE_BLOCK
+        let index = if count == 1 { 17 } else { 18 };
+        self.transfer(count, |host| host.read(index, argument, BLOCK_SIZE, buffer))
+    }
+
+    /// Writes whole blocks from `first`.
+    pub fn write_blocks(&mut self, first: u32, buffer: &[u8]) -> Result<(), Error> {
+        let (count, argument) = self.prepare(first, buffer.len())?;
+        // WRITE_BLOCK or WRITE_MULTIPLE_BLOCK
+        let index = if count == 1 { 24 } else { 25 };
+        self.transfer(count, |host| {
+            host.write(index, argument, BLOCK_SIZE, buffer)
+        })
+    }
+
+    /// Checks a transfer, returns its block count and the command argument.
+    fn prepare(&mut self, first: u32, len: usize) -> Result<(u32, u32), Error> {
+        if !self.host.card_present() {
+            self.info = None;
+            return Err(Error::NoCard);
+        }
+        let info = self.info.ok_or(Error::NotInitialized)?;
+        if len == 0 || len % BLOCK_SIZE != 0 {
+            return Err(Error::InvalidBuffer);
+        }
+        let count = (len / BLOCK_SIZE) as u32;
+        if first >= info.csd.blocks || info.csd.blocks - first < count {
+            return Err(Error::OutOfRange);
+        }
+        let argument = if info.high_capacity {
+            first
+        } else {
+            first * BLOCK_SIZE as u32
+        };
+        Ok((count, argument))
+    }
+
+    /// Runs a transfer, stopping and retrying it on failures.
+    fn transfer<F>(&mut self, count: u32, mut op: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut H) -> Result<u32, HostError>,
+    {
+        let mut error = Error::NotInitialized;
+        for _ in 0..RETRIES {
+            let result = op(&mut self.host);
+            if count > 1 || result.is_err() {
+                // STOP_TRANSMISSION, also brings the card back after a failed single block.
+                let _ = self.host.command(12, 0, Response::R1b);
+            }
+            error = match result {
+                Ok(status) if status & STATUS_ERRORS == 0 => match self.wait_ready() {
+                    Ok(()) => return Ok(()),
+                    Err(e) => e,
+                },
+                Ok(status) => Error::Card(status & STATUS_ERRORS),
+                Err(e) => Error::Host(e),
+            };
+            if !self.host.card_present() {
+                self.info = None;
+                return Err(Error::NoCard);
+            }
+            if let Error::Card(_) = error {
+                // retrying a rejected command is pointless.
+                break;
+            }
+        }
+        if let Error::Host(_) = error {
+            self.info = None;
+        }
+        Err(error)
+    }
+
+    /// Waits for the end of the programming.
+    fn wait_ready(&mut self) -> Result<(), Error> {
+        loop {
+            let status = self.status()?;
+            if status & STATUS_ERRORS != 0 {
+                return Err(Error::Card(status & STATUS_ERRORS));
+            }
+            if status & STATUS_READY_FOR_DATA != 0 && (status >> 9) & 0xF == STATE_TRANSFER {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The card status.
+    pub fn status(&mut self) -> Result<u32, Error> {
+        let rca = self.info.ok_or(Error::NotInitialized)?.rca;
+        // SEND_STATUS
+        Ok(self.host.command(13, u32::from(rca) << 16, Response::R1)?[0])
+    }
+
+    fn card_command(&mut self, index: u8, argument: u32, response: Response) -> Result<u32, Error> {
+        let status = self.host.command(index, argument, response)?[0];
+        if status & STATUS_ERRORS != 0 {
+            Err(Error::Card(status & STATUS_ERRORS))
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// Sends APP_CMD then the application specific command.
+    fn app_command(
+        &mut self,
+        rca: u16,
+        index: u8,
+        argument: u32,
+        response: Response,
+    ) -> Result<u32, Error> {
+        self.card_command(55, u32::from(rca) << 16, Response::R1)?;
+        if response == Response::R3 {
+            Ok(self.host.command(index, argument, response)?[0])
+        } else {
+            self.card_command(index, argument, response)
+        }
+    }
+}
+
+impl<H: Host> BlockDevice for Card<H> {
+    type Error = Error;
+
+    fn blocks(&self) -> u32 {
+        Card::blocks(self)
+    }
+
+    fn read(&mut self, first: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        self.read_blocks(first, buffer)
+    }
+
+    fn write(&mut self, first: u32, buffer: &[u8]) -> Result<(), Error> {
+        self.write_blocks(first, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIM_BLOCKS: usize = 16;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum State {
+        Idle,
+        Ready,
+        Identification,
+        StandBy,
+        Transfer,
+    }
+
+    /// A card simulated at the command level.
+    struct SimCard {
+        present: bool,
+        version2: bool,
+        high_capacity: bool,
+        state: State,
+        app_command: bool,
+        /// ACMD41 polls before the end of the power up.
+        busy_polls: usize,
+        memory: [u8; SIM_BLOCKS * BLOCK_SIZE],
+        /// Host errors injected in the next transfers.
+        failures: usize,
+        stops: usize,
+        clock: u32,
+        width: BusWidth,
+    }
+
+    impl SimCard {
+        fn new(version2: bool, high_capacity: bool) -> SimCard {
+            SimCard {
+                present: true,
+                version2,
+                high_capacity,
+                state: State::Idle,
+                app_command: false,
+                busy_polls: 3,
+                memory: [0; SIM_BLOCKS * BLOCK_SIZE],
+                failures: 0,
+                stops: 0,
+                clock: 0,
+                width: BusWidth::One,
+            }
+        }
+
+        fn status(&self) -> u32 {
+            let state = match self.state {
+                State::Idle => 0,
+                State::Ready => 1,
+                State::Identification => 2,
+                State::StandBy => 3,
+                State::Transfer => STATE_TRANSFER,
+            };
+            state << 9 | STATUS_READY_FOR_DATA
+        }
+
+        fn csd(&self) -> [u32; 4] {
+            if self.high_capacity {
+                // C_SIZE = 0: 1024 blocks, of which only the first SIM_BLOCKS are backed.
+                [0x400E_0032, 0x5B59_0000, 0x0000_7F80, 0x0A40_4000]
+            } else {
+                // READ_BL_LEN = 9, C_SIZE = 3, C_SIZE_MULT = 0: 4 * 4 blocks.
+                [0x0026_0032, 0x5F59_0000, 0xC000_0000, 0x0000_0000]
+            }
+        }
+
+        fn offset(&self, argument: u32) -> usize {
+            if self.high_capacity {
+                argument as usize * BLOCK_SIZE
+            } else {
+                argument as usize
+            }
+        }
+
+        fn transfer(&mut self, len: usize) -> Result<usize, HostError> {
+            if !self.present {
+                return Err(HostError::Timeout);
+            }
+            if self.state != State::Transfer {
+                return Err(HostError::Timeout);
+            }
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(HostError::Crc);
+            }
+            Ok(len)
+        }
+    }
+
+    impl Host for SimCard {
+        fn card_present(&mut self) -> bool {
+            self.present
+        }
+        fn power_up(&mut self) {}
+        fn set_clock(&mut self, hz: u32) {
+            self.clock = hz;
+        }
+        fn set_bus_width(&mut self, width: BusWidth) {
+            self.width = width;
+        }
+        fn command(
+            &mut self,
+            index: u8,
+            argument: u32,
+            _response: Response,
+        ) -> Result<[u32; 4], HostError> {
+            if !self.present {
+                return Err(HostError::Timeout);
+            }
+            let app = self.app_command;
+            self.app_command = false;
+            let status = self.status();
+            let r = match (app, index, self.state) {
+                (_, 0, _) => {
+                    self.state = State::Idle;
+                    0
+                }
+                (false, 8, State::Idle) if self.version2 => argument & 0xFFF,
+                (false, 55, _) => {
+                    self.app_command = true;
+                    status | 1 << 5
+                }
+                (true, 41, State::Idle) => {
+                    if self.busy_polls > 0 {
+                        self.busy_polls -= 1;
+                        OCR_VOLTAGE_WINDOW
+                    } else {
+                        self.state = State::Ready;
+                        let ccs = argument & OCR_HIGH_CAPACITY != 0 && self.high_capacity;
+                        OCR_POWERED_UP
+                            | OCR_VOLTAGE_WINDOW
+                            | if ccs { OCR_HIGH_CAPACITY } else { 0 }
+                    }
+                }
+                (false, 2, State::Ready) => {
+                    self.state = State::Identification;
+                    return Ok([0x0353_4453, 0x4430_3847, 0x8012_3456, 0x7801_2400]);
+                }
+                (false, 3, State::Identification) => {
+                    self.state = State::StandBy;
+                    0xB368_0500
+                }
+                (false, 9, State::StandBy) if argument == 0xB368_0000 => return Ok(self.csd()),
+                (false, 7, State::StandBy) if argument == 0xB368_0000 => {
+                    self.state = State::Transfer;
+                    status
+                }
+                (true, 6, State::Transfer) => {
+                    self.width = BusWidth::Four;
+                    status
+                }
+                (false, 16, State::Transfer) => status,
+                (false, 13, _) => status,
+                (false, 12, _) => {
+                    self.stops += 1;
+                    status
+                }
+                _ => return Err(HostError::Timeout),
+            };
+            Ok([r, 0, 0, 0])
+        }
+        fn read(
+            &mut self,
+            index: u8,
+            argument: u32,
+            block_size: usize,
+            buffer: &mut [u8],
+        ) -> Result<u32, HostError> {
+            assert!(index == 17 || index == 18);
+            assert_eq!(index == 17, buffer.len() == block_size);
+            let len = self.transfer(buffer.len())?;
+            let offset = self.offset(argument);
+            buffer.copy_from_slice(&self.memory[offset..offset + len]);
+            Ok(self.status())
+        }
+        fn write(
+            &mut self,
+            index: u8,
+            argument: u32,
+            block_size: usize,
+            buffer: &[u8],
+        ) -> Result<u32, HostError> {
+            assert!(index == 24 || index == 25);
+            assert_eq!(index == 24, buffer.len() == block_size);
+            let len = self.transfer(buffer.len())?;
+            let offset = self.offset(argument);
+            self.memory[offset..offset + len].copy_from_slice(buffer);
+            Ok(self.status())
+        }
+    }
+
+    #[test]
+    fn test_csd_cid() {
+        // A 8GB SDHC card.
+        let csd = Csd::parse(&[0x400E_0032, 0x5B59_0000, 0x3B37_7F80, 0x0A40_4000]);
+        assert_eq!(1, csd.version);
+        assert_eq!(15_160 * 1024, csd.blocks);
+        assert_eq!(25_000_000, csd.max_clock_hz);
+
+        let cid = Cid::parse(&[0x0353_4453, 0x4430_3847, 0x8012_3456, 0x7801_2400]);
+        assert_eq!(3, cid.manufacturer);
+        assert_eq!(*b"SD", cid.oem);
+        assert_eq!(*b"SD08G", cid.product);
+        assert_eq!(0x1234_5678, cid.serial);
+        assert_eq!((2018, 4), (cid.year, cid.month));
+    }
+
+    #[test]
+    fn test_init_and_transfer() {
+        // A standard capacity card, addressed by bytes.
+        let mut card = Card::new(SimCard::new(false, false));
+        assert_eq!(
+            Err(Error::NotInitialized),
+            card.read_blocks(0, &mut [0; 512])
+        );
+        {
+            let info = card.init().unwrap();
+            assert!(!info.high_capacity);
+            assert_eq!(0xB368, info.rca);
+        }
+        assert_eq!(16, card.blocks());
+
+        let mut data = [0u8; 3 * BLOCK_SIZE];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(Ok(()), card.write_blocks(2, &data));
+        assert_eq!(Err(Error::OutOfRange), card.write_blocks(14, &data));
+        let mut block = [0u8; BLOCK_SIZE];
+        assert_eq!(Ok(()), card.read_blocks(3, &mut block));
+        assert_eq!(&data[BLOCK_SIZE..2 * BLOCK_SIZE], &block[..]);
+
+        let sim = card.release();
+        assert_eq!(1, sim.stops);
+        assert_eq!(BusWidth::Four, sim.width);
+        assert_eq!(DEFAULT_CLOCK_HZ, sim.clock);
+        assert_eq!(data[0], sim.memory[2 * BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut card = Card::new(SimCard::new(true, true));
+        assert!(card.init().unwrap().high_capacity);
+
+        let mut blocks = [0u8; 2 * BLOCK_SIZE];
+        card.host.memory[5 * BLOCK_SIZE] = 0x42;
+        card.host.failures = 1;
+        assert_eq!(Ok(()), card.read_blocks(5, &mut blocks));
+        assert_eq!(0x42, blocks[0]);
+        assert_eq!(2, card.host.stops);
+
+        card.host.failures = RETRIES;
+        assert_eq!(
+            Err(Error::Host(HostError::Crc)),
+            card.read_blocks(5, &mut blocks)
+        );
+        assert_eq!(None, card.info());
+        assert!(card.init().is_ok());
+
+        card.host.present = false;
+        assert_eq!(Err(Error::NoCard), card.write_blocks(0, &blocks));
+        assert_eq!(Err(Error::NoCard), card.init().map(|_| ()));
+    }
+}
@@ -0,0 +1,212 @@
+//! Byte oriented serial interfaces.
+//!
+//! The operations never block, they return `Error::WouldBlock` until they can complete. `block`
+//! and `write_all` spin on them, `read_async` and `write_async` turn them into futures for the
+//! `executor`.
+
+use core::future::Future;
+use core::task::{Poll, Waker};
+use executor::poll_fn;
+
+/// Failure of a non blocking operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    /// The operation cannot complete yet.
+    WouldBlock,
+    Other(E),
+}
+
+pub trait Read {
+    type Error;
+
+    /// Takes a received byte.
+    fn read(&mut self) -> Result<u8, Error<Self::Error>>;
+}
+
+pub trait Write {
+    type Error;
+
+    /// Queues a byte for transmission.
+    fn write(&mut self, byte: u8) -> Result<(), Error<Self::Error>>;
+
+    /// Completes once all the queued bytes are sent.
+    fn flush(&mut self) -> Result<(), Error<Self::Error>>;
+}
+
+/// Retries `op` until it does not return `WouldBlock`.
+pub fn block<T, E, F>(mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, Error<E>>,
+{
+    loop {
+        match op() {
+            Ok(v) => return Ok(v),
+            Err(Error::Other(e)) => return Err(e),
+            Err(Error::WouldBlock) => {}
+        }
+    }
+}
+
+/// Sends all of `bytes`, waiting for room as needed.
+pub fn write_all<W: Write>(w: &mut W, bytes: &[u8]) -> Result<(), W::Error> {
+    for &b in bytes {
+        block(|| w.write(b))?;
+    }
+    Ok(())
+}
+
+/// Receives `buffer.len()` bytes. Each time no byte is available, `listen` must arrange for the
+/// waker to be woken up once one is (or an error occurred), usually by registering it in a
+/// `WakerCell` and enabling the receive interrupt.
+pub fn read_async<'a, R, L>(
+    port: &'a mut R,
+    buffer: &'a mut [u8],
+    mut listen: L,
+) -> impl Future<Output = Result<(), R::Error>> + 'a
+where
+    R: Read,
+    L: FnMut(&mut R, &Waker) + Unpin + 'a,
+{
+    let mut received = 0;
+    poll_fn(move |cx| {
+        while received < buffer.len() {
+            match port.read() {
+                Ok(byte) => {
+                    buffer[received] = byte;
+                    received += 1;
+                }
+                Err(Error::Other(e)) => return Poll::Ready(Err(e)),
+                Err(Error::WouldBlock) => {
+                    listen(port, cx.waker());
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    })
+}
+
+/// Queues all of `bytes`. Each time there is no room, `listen` must arrange for the waker to be
+/// woken up once there is, see `read_async`.
+pub fn write_async<'a, W, L>(
+    port: &'a mut W,
+    bytes: &'a [u8],
+    mut listen: L,
+) -> impl Future<Output = Result<(), W::Error>> + 'a
+where
+    W: Write,
+    L: FnMut(&mut W, &Waker) + Unpin + 'a,
+{
+    let mut sent = 0;
+    poll_fn(move |cx| {
+        while sent < bytes.len() {
+            match port.write(bytes[sent]) {
+                Ok(()) => sent += 1,
+                Err(Error::Other(e)) => return Poll::Ready(Err(e)),
+                Err(Error::WouldBlock) => {
+                    listen(port, cx.waker());
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use core::pin::Pin;
+    use executor::{Executor, ReadyFlags, WakerCell};
+    use ring::RingBuffer;
+    use sync::{CriticalSection, Mutex};
+
+    struct Loopback {
+        byte: Option<u8>,
+        attempts: usize,
+    }
+    impl Write for Loopback {
+        type Error = ();
+        fn write(&mut self, byte: u8) -> Result<(), Error<()>> {
+            self.attempts += 1;
+            if self.attempts % 2 == 1 {
+                return Err(Error::WouldBlock);
+            }
+            self.byte = Some(byte);
+            Ok(())
+        }
+        fn flush(&mut self) -> Result<(), Error<()>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_retries() {
+        let mut port = Loopback {
+            byte: None,
+            attempts: 0,
+        };
+        assert_eq!(Ok(()), write_all(&mut port, b"ab"));
+        assert_eq!(Some(b'b'), port.byte);
+        assert_eq!(4, port.attempts);
+    }
+
+    /// A port whose bytes are pushed by the "interrupt handler" of the test.
+    struct Port<'a> {
+        rx: &'a RefCell<RingBuffer<u8, [u8; 4]>>,
+        listens: usize,
+    }
+    impl<'a> Read for Port<'a> {
+        type Error = ();
+        fn read(&mut self) -> Result<u8, Error<()>> {
+            self.rx.borrow_mut().pop().ok_or(Error::WouldBlock)
+        }
+    }
+
+    #[test]
+    fn test_read_async() {
+        static READY: ReadyFlags = ReadyFlags::new();
+        static RX_WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+        let cs = unsafe { CriticalSection::new() };
+        let rx = RefCell::new(RingBuffer::new([0; 4]));
+        rx.borrow_mut().push(1).unwrap();
+        let mut port = Port {
+            rx: &rx,
+            listens: 0,
+        };
+        let mut buffer = [0; 3];
+        {
+            let mut read = read_async(&mut port, &mut buffer, |port, waker| {
+                port.listens += 1;
+                RX_WAKER.borrow(&cs).register(waker);
+            });
+            let mut task = poll_fn(|cx| match Pin::new(&mut read).poll(cx) {
+                Poll::Ready(result) => {
+                    assert_eq!(Ok(()), result);
+                    Poll::Ready(())
+                }
+                Poll::Pending => Poll::Pending,
+            });
+            let mut executor = Executor::new(&READY);
+            executor.spawn(Pin::new(&mut task)).unwrap();
+            assert_eq!(1, executor.poll());
+            assert!(!executor.has_work());
+
+            // the "interrupt handler"
+            rx.borrow_mut().push(2).unwrap();
+            RX_WAKER.borrow(&cs).wake();
+            assert_eq!(1, executor.poll());
+            assert!(!executor.has_work());
+
+            rx.borrow_mut().push(3).unwrap();
+            rx.borrow_mut().push(4).unwrap();
+            RX_WAKER.borrow(&cs).wake();
+            assert_eq!(1, executor.poll());
+            assert!(executor.is_empty());
+        }
+        assert_eq!(2, port.listens);
+        assert_eq!([1, 2, 3], buffer);
+        assert_eq!(Some(4), rx.borrow_mut().pop());
+    }
+}
@@ -0,0 +1,72 @@
+//! # Interrupt safe sharing primitives
+//!
+//! These are target independent: the platform crates are responsible for handing out
+//! `CriticalSection` tokens only while interrupts are masked.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+/// Proof that the current execution context cannot be preempted.
+pub struct CriticalSection {
+    _not_send: PhantomData<*mut ()>,
+}
+impl CriticalSection {
+    /// Interrupts must be masked for the whole lifetime of the token.
+    #[inline]
+    pub unsafe fn new() -> CriticalSection {
+        CriticalSection {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// A value shared between thread mode and interrupt handlers.
+///
+/// Only shared references are handed out so interior mutability (`Cell`, `RefCell`) must be
+/// used for mutation: `Mutex<RefCell<T>>`.
+pub struct Mutex<T> {
+    inner: UnsafeCell<T>,
+}
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Borrows the data for the duration of the critical section.
+    pub fn borrow<'cs>(&'cs self, _cs: &'cs CriticalSection) -> &'cs T {
+        unsafe { &*self.inner.get() }
+    }
+
+    /// Gives a mutable access to the data, statically guaranteed to be exclusive.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.get() }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+// Access is serialized by the critical section so the data only needs to be movable between
+// contexts.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::{Cell, RefCell};
+
+    static COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+    #[test]
+    fn test_mutex_borrow() {
+        let cs = unsafe { CriticalSection::new() };
+        COUNTER.borrow(&cs).set(COUNTER.borrow(&cs).get() + 1);
+        assert_eq!(1, COUNTER.borrow(&cs).get());
+
+        let mut m = Mutex::new(RefCell::new([0u8; 4]));
+        m.borrow(&cs).borrow_mut()[1] = 3;
+        assert_eq!([0, 3, 0, 0], *m.get_mut().borrow());
+    }
+}
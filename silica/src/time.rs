@@ -0,0 +1,414 @@
+//! # Target independent time keeping
+//!
+//! `Duration` and `Instant` are both kept as a 64-bit count of microseconds. This is plenty of
+//! range (more than 500 000 years) and keeps arithmetic cheap on cores without a 64-bit divider.
+//!
+//! `DateTime` is the wall clock time kept by a calendar, in UTC.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// A span of time with a microsecond resolution.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    micros: u64,
+}
+impl Duration {
+    pub const fn from_micros(micros: u64) -> Duration {
+        Duration { micros }
+    }
+    pub const fn from_millis(millis: u64) -> Duration {
+        Duration {
+            micros: millis * 1_000,
+        }
+    }
+    pub const fn from_secs(secs: u64) -> Duration {
+        Duration {
+            micros: secs * 1_000_000,
+        }
+    }
+
+    #[inline]
+    pub fn as_micros(self) -> u64 {
+        self.micros
+    }
+    #[inline]
+    pub fn as_millis(self) -> u64 {
+        self.micros / 1_000
+    }
+    #[inline]
+    pub fn as_secs(self) -> u64 {
+        self.micros / 1_000_000
+    }
+
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        self.micros
+            .checked_add(rhs.micros)
+            .map(Duration::from_micros)
+    }
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.micros
+            .checked_sub(rhs.micros)
+            .map(Duration::from_micros)
+    }
+    pub fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(rhs.micros))
+    }
+}
+impl Add for Duration {
+    type Output = Duration;
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros + rhs.micros)
+    }
+}
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.micros += rhs.micros;
+    }
+}
+impl Sub for Duration {
+    type Output = Duration;
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros - rhs.micros)
+    }
+}
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        self.micros -= rhs.micros;
+    }
+}
+
+/// A point in time measured from the start of a monotonic clock.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    micros: u64,
+}
+impl Instant {
+    pub const fn from_micros(micros: u64) -> Instant {
+        Instant { micros }
+    }
+
+    /// Time elapsed since the clock was started.
+    #[inline]
+    pub fn as_micros(self) -> u64 {
+        self.micros
+    }
+
+    /// Returns the time elapsed from `earlier` to `self` or `None` if `earlier` is later than
+    /// `self`.
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        self.micros
+            .checked_sub(earlier.micros)
+            .map(Duration::from_micros)
+    }
+
+    /// Returns the time elapsed from `earlier` to `self`, saturating to zero.
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+
+    pub fn checked_add(self, rhs: Duration) -> Option<Instant> {
+        self.micros
+            .checked_add(rhs.as_micros())
+            .map(Instant::from_micros)
+    }
+}
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros + rhs.as_micros())
+    }
+}
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.micros += rhs.as_micros();
+    }
+}
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros - rhs.as_micros())
+    }
+}
+impl Sub for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// A source of monotonic time.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// A date and time of day, to the second, from 1980 to 2099: the range of both the FAT
+/// timestamps and the calendars of the microcontrollers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn days_in_year(year: u16) -> u32 {
+    if is_leap_year(year) {
+        366
+    } else {
+        365
+    }
+}
+
+/// Parses up to three numbers separated by `separator`.
+fn parse_fields(s: &str, separator: char, fields: &mut [u16; 3]) -> Option<usize> {
+    let mut count = 0;
+    for part in s.trim().split(separator) {
+        if count == fields.len() || part.is_empty() || part.len() > 4 {
+            return None;
+        }
+        fields[count] = part.parse().ok()?;
+        count += 1;
+    }
+    Some(count)
+}
+
+impl DateTime {
+    pub const MIN: DateTime = DateTime {
+        year: 1980,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    pub const MAX: DateTime = DateTime {
+        year: 2099,
+        month: 12,
+        day: 31,
+        hour: 23,
+        minute: 59,
+        second: 59,
+    };
+
+    /// The date and time, if valid and in range.
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<DateTime> {
+        if year < DateTime::MIN.year
+            || year > DateTime::MAX.year
+            || month < 1
+            || month > 12
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return None;
+        }
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    pub fn year(self) -> u16 {
+        self.year
+    }
+    /// From 1 for January.
+    pub fn month(self) -> u8 {
+        self.month
+    }
+    /// Day of the month, from 1.
+    pub fn day(self) -> u8 {
+        self.day
+    }
+    pub fn hour(self) -> u8 {
+        self.hour
+    }
+    pub fn minute(self) -> u8 {
+        self.minute
+    }
+    pub fn second(self) -> u8 {
+        self.second
+    }
+
+    /// Day of the week, from 1 for Monday to 7 for Sunday.
+    pub fn weekday(self) -> u8 {
+        // 1970-01-01 was a Thursday.
+        ((self.days() + 3) % 7 + 1) as u8
+    }
+
+    /// Days since 1970-01-01.
+    fn days(self) -> u32 {
+        let years: u32 = (1970..self.year).map(days_in_year).sum();
+        let months: u32 = (1..self.month)
+            .map(|m| u32::from(days_in_month(self.year, m)))
+            .sum();
+        years + months + u32::from(self.day) - 1
+    }
+
+    /// Seconds since the Unix epoch, 1970-01-01 00:00:00.
+    pub fn unix_time(self) -> u64 {
+        u64::from(self.days()) * 86_400
+            + u64::from(self.hour) * 3_600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    /// The date and time `secs` seconds after the Unix epoch, if in range.
+    pub fn from_unix_time(secs: u64) -> Option<DateTime> {
+        if secs < DateTime::MIN.unix_time() || secs > DateTime::MAX.unix_time() {
+            return None;
+        }
+        let mut days = (secs / 86_400) as u32;
+        let mut year = 1970;
+        while days >= days_in_year(year) {
+            days -= days_in_year(year);
+            year += 1;
+        }
+        let mut month = 1;
+        while days >= u32::from(days_in_month(year, month)) {
+            days -= u32::from(days_in_month(year, month));
+            month += 1;
+        }
+        let secs = (secs % 86_400) as u32;
+        DateTime::new(
+            year,
+            month,
+            days as u8 + 1,
+            (secs / 3_600) as u8,
+            (secs / 60 % 60) as u8,
+            (secs % 60) as u8,
+        )
+    }
+
+    /// The FAT date and time fields, the seconds rounded down to an even count.
+    pub fn to_fat(self) -> (u16, u16) {
+        let date = (self.year - 1980) << 9 | u16::from(self.month) << 5 | u16::from(self.day);
+        let time =
+            u16::from(self.hour) << 11 | u16::from(self.minute) << 5 | u16::from(self.second / 2);
+        (date, time)
+    }
+
+    /// The date and time of FAT fields, `MIN` for invalid ones.
+    pub fn from_fat(date: u16, time: u16) -> DateTime {
+        DateTime::new(
+            1980 + (date >> 9),
+            (date >> 5 & 0xF) as u8,
+            (date & 0x1F) as u8,
+            (time >> 11) as u8,
+            (time >> 5 & 0x3F) as u8,
+            (time & 0x1F) as u8 * 2,
+        )
+        .unwrap_or(DateTime::MIN)
+    }
+
+    /// Parses a date as `YYYY-MM-DD` and a time as `HH:MM` or `HH:MM:SS`, the parameters of
+    /// the G-code setting the clock (`M905 P"2018-01-01" S"12:30:00"`).
+    pub fn parse(date: &str, time: &str) -> Option<DateTime> {
+        let (mut d, mut t) = ([0; 3], [0; 3]);
+        if parse_fields(date, '-', &mut d)? != 3 || parse_fields(time, ':', &mut t)? < 2 {
+            return None;
+        }
+        if t.iter().any(|&f| f > 59) || d[1] > 12 || d[2] > 31 {
+            return None;
+        }
+        DateTime::new(
+            d[0], d[1] as u8, d[2] as u8, t[0] as u8, t[1] as u8, t[2] as u8,
+        )
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_units() {
+        assert_eq!(Duration::from_millis(3), Duration::from_micros(3_000));
+        assert_eq!(Duration::from_secs(2).as_millis(), 2_000);
+        assert_eq!(Duration::from_micros(1_999).as_millis(), 1);
+    }
+
+    #[test]
+    fn test_instant_arithmetic() {
+        let a = Instant::from_micros(1_000);
+        let b = a + Duration::from_millis(5);
+        assert_eq!(b - a, Duration::from_micros(5_000));
+        assert_eq!(a - b, Duration::from_micros(0));
+        assert_eq!(a.checked_duration_since(b), None);
+        assert_eq!(
+            b - Duration::from_micros(1_000),
+            Instant::from_micros(5_000)
+        );
+    }
+
+    #[test]
+    fn test_date_time() {
+        let dt = DateTime::new(2018, 2, 28, 13, 37, 42).unwrap();
+        assert_eq!(None, DateTime::new(2018, 2, 29, 0, 0, 0));
+        assert!(DateTime::new(2020, 2, 29, 0, 0, 0).is_some());
+        assert_eq!(None, DateTime::new(1979, 12, 31, 0, 0, 0));
+        assert_eq!(1_519_825_062, dt.unix_time());
+        assert_eq!(Some(dt), DateTime::from_unix_time(1_519_825_062));
+        assert_eq!(3, dt.weekday());
+        assert_eq!(
+            Some(DateTime::MAX),
+            DateTime::from_unix_time(DateTime::MAX.unix_time())
+        );
+        assert_eq!(None, DateTime::from_unix_time(0));
+
+        assert_eq!((0x4C5C, 0x6CB5), dt.to_fat());
+        assert_eq!(
+            DateTime::new(2018, 2, 28, 13, 37, 42),
+            Some(DateTime::from_fat(0x4C5C, 0x6CB5))
+        );
+        assert_eq!(DateTime::MIN, DateTime::from_fat(0, 0));
+
+        assert_eq!(Some(dt), DateTime::parse("2018-02-28", "13:37:42"));
+        assert_eq!(
+            DateTime::new(2018, 2, 28, 13, 37, 0),
+            DateTime::parse(" 2018-2-28", "13:37")
+        );
+        assert_eq!(None, DateTime::parse("2018-02-28", "13:37:42:00"));
+        assert_eq!(None, DateTime::parse("2018-02", "13:37"));
+        assert_eq!(None, DateTime::parse("2018-02-28", "13:300"));
+        assert_eq!("2018-02-28 13:37:42", format!("{}", dt));
+    }
+}
@@ -0,0 +1,329 @@
+//! CDC-ACM class: a virtual serial port.
+//!
+//! The received bytes and those to send wait in ring buffers, moved by `UsbDevice::poll`. The
+//! device is then a serial port: reading or writing polls it when the buffers are empty or full.
+//! The line coding set by the host is only informative.
+
+use super::{Bus, Class, EndpointType, RequestType, SetupPacket, UsbDevice, MAX_PACKET};
+use core::fmt;
+use ring::RingBuffer;
+use serial;
+
+/// Bulk endpoints of the data interface.
+pub const DATA_IN_ENDPOINT: u8 = 1;
+pub const DATA_OUT_ENDPOINT: u8 = 2;
+/// Interrupt endpoint of the communication interface, never used.
+pub const NOTIFY_ENDPOINT: u8 = 3;
+pub const DEVICE_CLASS: u8 = 0x02;
+
+const COMMUNICATION_INTERFACE: u16 = 0;
+
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+const SEND_BREAK: u8 = 0x23;
+
+/// Configuration descriptor of the port, with its interfaces and endpoints.
+#[rustfmt::skip]
+pub const CONFIGURATION: [u8; 67] = [
+    // configuration 1, self powered, 100mA.
+    9, 2, 67, 0, 2, 1, 0, 0xC0, 50,
+    // communication interface, abstract control model, AT commands.
+    9, 4, 0, 0, 1, 0x02, 0x02, 0x01, 0,
+    // header, CDC 1.10.
+    5, 0x24, 0x00, 0x10, 0x01,
+    // call management by the device, over the data interface.
+    5, 0x24, 0x01, 0x00, 0x01,
+    // abstract control management: line coding and control line state.
+    4, 0x24, 0x02, 0x02,
+    // union of the communication and data interfaces.
+    5, 0x24, 0x06, 0x00, 0x01,
+    7, 5, 0x80 | NOTIFY_ENDPOINT, 0x03, MAX_PACKET as u8, 0, 0xFF,
+    // data interface.
+    9, 4, 1, 0, 2, 0x0A, 0, 0, 0,
+    7, 5, DATA_OUT_ENDPOINT, 0x02, MAX_PACKET as u8, 0, 0,
+    7, 5, 0x80 | DATA_IN_ENDPOINT, 0x02, MAX_PACKET as u8, 0, 0,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineCoding {
+    pub baud_rate: u32,
+    /// 0 for 1 stop bit, 1 for 1.5 and 2 for 2.
+    pub stop_bits: u8,
+    /// None, odd, even, mark and space.
+    pub parity: u8,
+    pub data_bits: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// No terminal opened the port: the bytes written would never be read.
+    NotConnected,
+}
+
+pub struct CdcAcm<'a> {
+    rx: RingBuffer<u8, &'a mut [u8]>,
+    tx: RingBuffer<u8, &'a mut [u8]>,
+    packet: [u8; MAX_PACKET],
+    /// Length of the packet waiting for the IN endpoint.
+    packet_len: Option<usize>,
+    /// The last packet sent was full: a zero length one ends the transfer if no data follows.
+    full_sent: bool,
+    line_coding: LineCoding,
+    dtr: bool,
+    rts: bool,
+    configured: bool,
+}
+
+impl<'a> CdcAcm<'a> {
+    pub fn new(rx: &'a mut [u8], tx: &'a mut [u8]) -> CdcAcm<'a> {
+        CdcAcm {
+            rx: RingBuffer::new(rx),
+            tx: RingBuffer::new(tx),
+            packet: [0; MAX_PACKET],
+            packet_len: None,
+            full_sent: false,
+            line_coding: LineCoding {
+                baud_rate: 115_200,
+                stop_bits: 0,
+                parity: 0,
+                data_bits: 8,
+            },
+            dtr: false,
+            rts: false,
+            configured: false,
+        }
+    }
+
+    pub fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+
+    /// A terminal opened the port: it set DTR.
+    pub fn is_connected(&self) -> bool {
+        self.configured && self.dtr
+    }
+
+    pub fn rts(&self) -> bool {
+        self.rts
+    }
+
+    /// Bytes waiting in the receive buffer.
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    fn is_flushed(&self) -> bool {
+        self.tx.is_empty() && self.packet_len.is_none() && !self.full_sent
+    }
+}
+
+impl<'a> Class for CdcAcm<'a> {
+    fn configure<B: Bus>(&mut self, bus: &mut B) {
+        bus.configure_endpoint(NOTIFY_ENDPOINT, EndpointType::Interrupt, true);
+        bus.configure_endpoint(DATA_OUT_ENDPOINT, EndpointType::Bulk, false);
+        bus.configure_endpoint(DATA_IN_ENDPOINT, EndpointType::Bulk, true);
+        self.configured = true;
+    }
+
+    fn reset(&mut self) {
+        self.configured = false;
+        self.dtr = false;
+        self.rts = false;
+        self.tx.clear();
+        self.packet_len = None;
+        self.full_sent = false;
+    }
+
+    fn control_in(&mut self, setup: SetupPacket, data: &mut [u8]) -> Option<usize> {
+        if setup.kind() != RequestType::Class || setup.index != COMMUNICATION_INTERFACE {
+            return None;
+        }
+        match setup.request {
+            GET_LINE_CODING => {
+                let coding = &self.line_coding;
+                let baud_rate = coding.baud_rate;
+                for (i, b) in data[..4].iter_mut().enumerate() {
+                    *b = (baud_rate >> (8 * i)) as u8;
+                }
+                data[4] = coding.stop_bits;
+                data[5] = coding.parity;
+                data[6] = coding.data_bits;
+                Some(7)
+            }
+            _ => None,
+        }
+    }
+
+    fn control_out(&mut self, setup: SetupPacket, data: &[u8]) -> bool {
+        if setup.kind() != RequestType::Class || setup.index != COMMUNICATION_INTERFACE {
+            return false;
+        }
+        match setup.request {
+            SET_LINE_CODING if data.len() == 7 => {
+                self.line_coding = LineCoding {
+                    baud_rate: data[..4]
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, &b| acc << 8 | u32::from(b)),
+                    stop_bits: data[4],
+                    parity: data[5],
+                    data_bits: data[6],
+                };
+                true
+            }
+            SET_CONTROL_LINE_STATE => {
+                self.dtr = setup.value & 1 != 0;
+                self.rts = setup.value & 2 != 0;
+                true
+            }
+            SEND_BREAK => true,
+            _ => false,
+        }
+    }
+
+    fn poll<B: Bus>(&mut self, bus: &mut B) {
+        // the packets are left in the endpoint, which refuses the next ones, until they fit.
+        if self.rx.capacity() - self.rx.len() >= MAX_PACKET {
+            let mut packet = [0; MAX_PACKET];
+            if let Some(len) = bus.read(DATA_OUT_ENDPOINT, &mut packet) {
+                for &b in &packet[..len] {
+                    let _ = self.rx.push(b);
+                }
+            }
+        }
+
+        if self.packet_len.is_none() {
+            let mut len = 0;
+            while len < MAX_PACKET {
+                match self.tx.pop() {
+                    Some(b) => self.packet[len] = b,
+                    None => break,
+                }
+                len += 1;
+            }
+            if len > 0 || self.full_sent {
+                self.packet_len = Some(len);
+            }
+        }
+        if let Some(len) = self.packet_len {
+            if bus.write(DATA_IN_ENDPOINT, &self.packet[..len]) {
+                self.packet_len = None;
+                self.full_sent = len == MAX_PACKET;
+            }
+        }
+    }
+}
+
+impl<'a, 'b, B: Bus> serial::Read for UsbDevice<'a, B, CdcAcm<'b>> {
+    type Error = Error;
+
+    fn read(&mut self) -> Result<u8, serial::Error<Error>> {
+        if self.class.rx.is_empty() {
+            self.poll();
+        }
+        self.class.rx.pop().ok_or(serial::Error::WouldBlock)
+    }
+}
+
+impl<'a, 'b, B: Bus> serial::Write for UsbDevice<'a, B, CdcAcm<'b>> {
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), serial::Error<Error>> {
+        if !self.class.is_connected() {
+            return Err(serial::Error::Other(Error::NotConnected));
+        }
+        if self.class.tx.is_full() {
+            self.poll();
+        }
+        self.class
+            .tx
+            .push(byte)
+            .map_err(|_| serial::Error::WouldBlock)
+    }
+
+    fn flush(&mut self) -> Result<(), serial::Error<Error>> {
+        self.poll();
+        if !self.class.is_connected() {
+            Err(serial::Error::Other(Error::NotConnected))
+        } else if self.class.is_flushed() {
+            Ok(())
+        } else {
+            Err(serial::Error::WouldBlock)
+        }
+    }
+}
+
+impl<'a, 'b, B: Bus> fmt::Write for UsbDevice<'a, B, CdcAcm<'b>> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::write_all(self, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{request, request_out, MockBus};
+    use super::super::{device_descriptor, Descriptors, State};
+    use super::*;
+    use serial::{Read, Write};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_serial() {
+        let device = device_descriptor(DEVICE_CLASS, 0x1D50, 0x60EC, 0x0100);
+        let descriptors = Descriptors {
+            device: &device,
+            configuration: &CONFIGURATION,
+            strings: &[],
+        };
+        let (mut rx, mut tx) = ([0; 128], [0; 256]);
+        let mut usb = UsbDevice::new(
+            MockBus::default(),
+            CdcAcm::new(&mut rx, &mut tx),
+            &descriptors,
+        );
+        usb.bus().events.push_back(super::super::Event::Reset);
+        request(&mut usb, [0, 5, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            &CONFIGURATION[..],
+            &request(&mut usb, [0x80, 6, 0, 2, 0, 0, 0xFF, 0])[..]
+        );
+        request(&mut usb, [0, 9, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(State::Configured, usb.state());
+        assert_eq!(
+            Err(serial::Error::Other(Error::NotConnected)),
+            usb.write(b'x')
+        );
+
+        // recorded from a Linux host opening the port at 250000 baud.
+        let coding = [0x90, 0xD0, 0x03, 0, 0, 0, 8];
+        assert!(request_out(
+            &mut usb,
+            [0x21, 0x20, 0, 0, 0, 0, 7, 0],
+            &coding
+        ));
+        assert!(request_out(&mut usb, [0x21, 0x22, 3, 0, 0, 0, 0, 0], &[]));
+        assert_eq!(250_000, usb.class().line_coding().baud_rate);
+        assert_eq!(
+            &coding[..],
+            &request(&mut usb, [0xA1, 0x21, 0, 0, 0, 0, 7, 0])[..]
+        );
+        assert!(usb.class().is_connected());
+
+        usb.bus().out[2].push_back(b"M115\n".to_vec());
+        let mut line = Vec::new();
+        while let Ok(b) = usb.read() {
+            line.push(b);
+        }
+        assert_eq!(b"M115\n", &line[..]);
+
+        // a full packet is followed by a zero length one.
+        for _ in 0..MAX_PACKET {
+            usb.write(b'.').unwrap();
+        }
+        while usb.flush().is_err() {}
+        let sent = &usb.bus().sent[1];
+        assert_eq!(2, sent.len());
+        assert_eq!((MAX_PACKET, 0), (sent[0].len(), sent[1].len()));
+    }
+}
@@ -0,0 +1,651 @@
+//! USB full speed device stack.
+//!
+//! `UsbDevice` answers the standard requests of the control endpoint from `Descriptors`, and
+//! hands the class requests and the other endpoints to a `Class`, through the `Bus` implemented
+//! by the device controller:
+//!
+//! ```ignore
+//! let descriptors = Descriptors {
+//!     device: &device_descriptor(cdc::DEVICE_CLASS, 0x1D50, 0x60EC, 0x0100),
+//!     configuration: &cdc::CONFIGURATION,
+//!     strings: &["Duet3D", "Duet 2", "0001"],
+//! };
+//! let mut usb = UsbDevice::new(udp, CdcAcm::new(&mut rx, &mut tx), &descriptors);
+//! loop {
+//!     usb.poll();
+//! }
+//! ```
+
+pub mod cdc;
+
+/// Packet size of the control endpoint, and of the full speed bulk endpoints.
+pub const MAX_PACKET: usize = 64;
+/// Longest descriptor or control data stage.
+const CONTROL_BUFFER: usize = 256;
+
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+pub const DESCRIPTOR_STRING: u8 = 3;
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+const GET_STATUS: u8 = 0;
+const CLEAR_FEATURE: u8 = 1;
+const SET_FEATURE: u8 = 3;
+const SET_ADDRESS: u8 = 5;
+const GET_DESCRIPTOR: u8 = 6;
+const GET_CONFIGURATION: u8 = 8;
+const SET_CONFIGURATION: u8 = 9;
+const GET_INTERFACE: u8 = 10;
+const SET_INTERFACE: u8 = 11;
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RequestType {
+    Standard,
+    Class,
+    Vendor,
+    Reserved,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recipient {
+    Device,
+    Interface,
+    Endpoint,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn parse(data: [u8; 8]) -> SetupPacket {
+        let word = |i: usize| u16::from(data[i]) | u16::from(data[i + 1]) << 8;
+        SetupPacket {
+            request_type: data[0],
+            request: data[1],
+            value: word(2),
+            index: word(4),
+            length: word(6),
+        }
+    }
+
+    /// The data stage goes to the host.
+    pub fn is_in(self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    pub fn kind(self) -> RequestType {
+        match (self.request_type >> 5) & 3 {
+            0 => RequestType::Standard,
+            1 => RequestType::Class,
+            2 => RequestType::Vendor,
+            _ => RequestType::Reserved,
+        }
+    }
+
+    pub fn recipient(self) -> Recipient {
+        match self.request_type & 0x1F {
+            0 => Recipient::Device,
+            1 => Recipient::Interface,
+            2 => Recipient::Endpoint,
+            _ => Recipient::Other,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndpointType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Reset,
+    Suspend,
+    Resume,
+    Setup(SetupPacket),
+    /// A packet was received on the control endpoint.
+    ControlOut,
+    /// The packet queued on the control endpoint was sent.
+    ControlInComplete,
+}
+
+/// A device controller.
+pub trait Bus {
+    /// Reports what happened since the last call, one event at a time.
+    fn poll(&mut self) -> Option<Event>;
+    fn set_address(&mut self, address: u8);
+    fn set_configured(&mut self, configured: bool);
+    /// Enables an endpoint besides the control one, `is_in` giving its direction.
+    fn configure_endpoint(&mut self, endpoint: u8, kind: EndpointType, is_in: bool);
+    /// Copies a received packet into `buffer`, returns its length.
+    fn read(&mut self, endpoint: u8, buffer: &mut [u8]) -> Option<usize>;
+    /// Queues a packet, returns false while the endpoint is busy.
+    fn write(&mut self, endpoint: u8, data: &[u8]) -> bool;
+    /// Halts an endpoint. The control endpoint is released by the next setup packet.
+    fn set_stalled(&mut self, endpoint: u8, stalled: bool);
+    fn is_stalled(&self, endpoint: u8) -> bool;
+}
+
+/// The function of the device, behind its configuration.
+pub trait Class {
+    /// Enables the endpoints of the configuration picked by the host.
+    fn configure<B: Bus>(&mut self, bus: &mut B);
+    /// The configuration was lost, to a reset or a new configuration.
+    fn reset(&mut self);
+    /// Answers a class or vendor request, writing its data stage in `data`. None stalls.
+    fn control_in(&mut self, setup: SetupPacket, data: &mut [u8]) -> Option<usize>;
+    /// Handles a class or vendor request and its data stage. False stalls.
+    fn control_out(&mut self, setup: SetupPacket, data: &[u8]) -> bool;
+    /// Moves the data of the class endpoints, while configured.
+    fn poll<B: Bus>(&mut self, bus: &mut B);
+}
+
+pub struct Descriptors<'a> {
+    pub device: &'a [u8],
+    /// The configuration descriptor followed by those of its interfaces and endpoints.
+    pub configuration: &'a [u8],
+    /// The strings 1 and on, in US English.
+    pub strings: &'a [&'a str],
+}
+
+/// Descriptor of a device with a single configuration, whose strings 1, 2 and 3 are its
+/// manufacturer, product and serial number.
+pub fn device_descriptor(class: u8, vendor_id: u16, product_id: u16, release: u16) -> [u8; 18] {
+    let le = |v: u16| [v as u8, (v >> 8) as u8];
+    let (usb, vendor, product, release) = (le(0x0200), le(vendor_id), le(product_id), le(release));
+    [
+        18,
+        DESCRIPTOR_DEVICE,
+        usb[0],
+        usb[1],
+        class,
+        0,
+        0,
+        MAX_PACKET as u8,
+        vendor[0],
+        vendor[1],
+        product[0],
+        product[1],
+        release[0],
+        release[1],
+        1,
+        2,
+        3,
+        1,
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Default,
+    Address,
+    Configured,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Idle,
+    /// Sending the answer, a zero length packet ending it if `zlp`.
+    DataIn {
+        sent: usize,
+        len: usize,
+        zlp: bool,
+    },
+    DataOut {
+        setup: SetupPacket,
+        received: usize,
+    },
+    /// Sending the zero length packet acknowledging a request.
+    StatusIn,
+    /// Waiting for the host acknowledgement of an answer.
+    StatusOut,
+}
+
+pub struct UsbDevice<'a, B, C> {
+    bus: B,
+    class: C,
+    descriptors: &'a Descriptors<'a>,
+    state: State,
+    suspended: bool,
+    configuration: u8,
+    /// Applied once the request is acknowledged.
+    pending_address: Option<u8>,
+    stage: Stage,
+    buffer: [u8; CONTROL_BUFFER],
+}
+
+impl<'a, B: Bus, C: Class> UsbDevice<'a, B, C> {
+    pub fn new(bus: B, class: C, descriptors: &'a Descriptors<'a>) -> UsbDevice<'a, B, C> {
+        UsbDevice {
+            bus,
+            class,
+            descriptors,
+            state: State::Default,
+            suspended: false,
+            configuration: 0,
+            pending_address: None,
+            stage: Stage::Idle,
+            buffer: [0; CONTROL_BUFFER],
+        }
+    }
+
+    pub fn release(self) -> (B, C) {
+        (self.bus, self.class)
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn bus(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn class(&mut self) -> &mut C {
+        &mut self.class
+    }
+
+    /// Handles the bus events, then lets the class move its data.
+    pub fn poll(&mut self) {
+        while let Some(event) = self.bus.poll() {
+            match event {
+                Event::Reset => {
+                    self.state = State::Default;
+                    self.suspended = false;
+                    self.configuration = 0;
+                    self.pending_address = None;
+                    self.stage = Stage::Idle;
+                    self.bus.set_address(0);
+                    self.bus.set_configured(false);
+                    self.class.reset();
+                }
+                Event::Suspend => self.suspended = true,
+                Event::Resume => self.suspended = false,
+                Event::Setup(setup) => self.setup(setup),
+                Event::ControlOut => self.control_out(),
+                Event::ControlInComplete => self.control_in_complete(),
+            }
+        }
+        if self.state == State::Configured && !self.suspended {
+            self.class.poll(&mut self.bus);
+        }
+    }
+
+    fn stall(&mut self) {
+        self.bus.set_stalled(0, true);
+        self.stage = Stage::Idle;
+    }
+
+    fn acknowledge(&mut self) {
+        self.bus.write(0, &[]);
+        self.stage = Stage::StatusIn;
+    }
+
+    fn setup(&mut self, setup: SetupPacket) {
+        self.stage = Stage::Idle;
+        let length = usize::from(setup.length);
+        if setup.is_in() && length > 0 {
+            let len = match self.answer(setup) {
+                Some(len) => len.min(length),
+                None => return self.stall(),
+            };
+            // a short answer of whole packets is ended by a zero length one.
+            let zlp = len < length && len % MAX_PACKET == 0;
+            self.stage = Stage::DataIn { sent: 0, len, zlp };
+            self.send_next();
+        } else if length == 0 {
+            if self.apply(setup, 0) {
+                self.acknowledge();
+            } else {
+                self.stall();
+            }
+        } else if length <= CONTROL_BUFFER {
+            self.stage = Stage::DataOut { setup, received: 0 };
+        } else {
+            self.stall();
+        }
+    }
+
+    fn send_next(&mut self) {
+        if let Stage::DataIn { sent, len, zlp } = self.stage {
+            if sent == len && !zlp {
+                self.stage = Stage::StatusOut;
+                return;
+            }
+            let count = (len - sent).min(MAX_PACKET);
+            if self.bus.write(0, &self.buffer[sent..sent + count]) {
+                self.stage = Stage::DataIn {
+                    sent: sent + count,
+                    len,
+                    zlp: zlp && count != 0,
+                };
+            }
+        }
+    }
+
+    fn control_out(&mut self) {
+        let mut packet = [0; MAX_PACKET];
+        let len = self.bus.read(0, &mut packet).unwrap_or(0);
+        match self.stage {
+            Stage::DataOut { setup, received } => {
+                let length = usize::from(setup.length);
+                let end = (received + len).min(length);
+                self.buffer[received..end].copy_from_slice(&packet[..end - received]);
+                if end == length || len < MAX_PACKET {
+                    if self.apply(setup, end) {
+                        self.acknowledge();
+                    } else {
+                        self.stall();
+                    }
+                } else {
+                    self.stage = Stage::DataOut {
+                        setup,
+                        received: end,
+                    };
+                }
+            }
+            // the acknowledgement of an answer, possibly before its end.
+            _ => self.stage = Stage::Idle,
+        }
+    }
+
+    fn control_in_complete(&mut self) {
+        match self.stage {
+            Stage::DataIn { .. } => self.send_next(),
+            Stage::StatusIn => {
+                if let Some(address) = self.pending_address.take() {
+                    self.bus.set_address(address);
+                    self.state = if address == 0 {
+                        State::Default
+                    } else {
+                        State::Address
+                    };
+                }
+                self.stage = Stage::Idle;
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes the answer to an IN request in the buffer.
+    fn answer(&mut self, setup: SetupPacket) -> Option<usize> {
+        match setup.kind() {
+            RequestType::Standard => {}
+            RequestType::Class | RequestType::Vendor => {
+                return self.class.control_in(setup, &mut self.buffer);
+            }
+            RequestType::Reserved => return None,
+        }
+        match (setup.recipient(), setup.request) {
+            (Recipient::Device, GET_STATUS) => {
+                let attributes = self.descriptors.configuration.get(7).cloned().unwrap_or(0);
+                self.buffer[0] = (attributes >> 6) & 1;
+                self.buffer[1] = 0;
+                Some(2)
+            }
+            (Recipient::Interface, GET_STATUS) => {
+                self.buffer[..2].copy_from_slice(&[0, 0]);
+                Some(2)
+            }
+            (Recipient::Endpoint, GET_STATUS) => {
+                let endpoint = setup.index as u8 & 0xF;
+                self.buffer[0] = self.bus.is_stalled(endpoint) as u8;
+                self.buffer[1] = 0;
+                Some(2)
+            }
+            (Recipient::Device, GET_DESCRIPTOR) => self.descriptor(setup.value),
+            (Recipient::Device, GET_CONFIGURATION) => {
+                self.buffer[0] = self.configuration;
+                Some(1)
+            }
+            (Recipient::Interface, GET_INTERFACE) if self.state == State::Configured => {
+                self.buffer[0] = 0;
+                Some(1)
+            }
+            _ => None,
+        }
+    }
+
+    fn descriptor(&mut self, value: u16) -> Option<usize> {
+        let (kind, index) = ((value >> 8) as u8, value as u8);
+        let data = match (kind, index) {
+            (DESCRIPTOR_DEVICE, 0) => self.descriptors.device,
+            (DESCRIPTOR_CONFIGURATION, 0) => self.descriptors.configuration,
+            // US English only.
+            (DESCRIPTOR_STRING, 0) => &[4, DESCRIPTOR_STRING, 0x09, 0x04],
+            (DESCRIPTOR_STRING, _) => {
+                let string = self.descriptors.strings.get(usize::from(index) - 1)?;
+                let mut len = 2;
+                for unit in string.encode_utf16().take((CONTROL_BUFFER - 2) / 2 - 1) {
+                    self.buffer[len] = unit as u8;
+                    self.buffer[len + 1] = (unit >> 8) as u8;
+                    len += 2;
+                }
+                self.buffer[0] = len as u8;
+                self.buffer[1] = DESCRIPTOR_STRING;
+                return Some(len);
+            }
+            _ => return None,
+        };
+        let len = data.len().min(CONTROL_BUFFER);
+        self.buffer[..len].copy_from_slice(&data[..len]);
+        Some(len)
+    }
+
+    /// Handles a request without data stage or with the `len` bytes of the buffer.
+    fn apply(&mut self, setup: SetupPacket, len: usize) -> bool {
+        match setup.kind() {
+            RequestType::Standard => {}
+            RequestType::Class | RequestType::Vendor => {
+                return self.class.control_out(setup, &self.buffer[..len]);
+            }
+            RequestType::Reserved => return false,
+        }
+        let endpoint = setup.index as u8 & 0xF;
+        match (setup.recipient(), setup.request) {
+            (Recipient::Device, SET_ADDRESS) if setup.value < 128 => {
+                self.pending_address = Some(setup.value as u8);
+                true
+            }
+            (Recipient::Device, SET_CONFIGURATION) => {
+                let value = setup.value as u8;
+                if value == 0 {
+                    if self.state == State::Configured {
+                        self.class.reset();
+                        self.bus.set_configured(false);
+                        self.state = State::Address;
+                    }
+                    self.configuration = 0;
+                    true
+                } else if self.descriptors.configuration.get(5) == Some(&value)
+                    && self.state != State::Default
+                {
+                    self.class.reset();
+                    self.class.configure(&mut self.bus);
+                    self.bus.set_configured(true);
+                    self.configuration = value;
+                    self.state = State::Configured;
+                    true
+                } else {
+                    false
+                }
+            }
+            // the remote wakeup is not supported, the request is harmless.
+            (Recipient::Device, CLEAR_FEATURE) | (Recipient::Device, SET_FEATURE) => true,
+            (Recipient::Endpoint, CLEAR_FEATURE)
+                if setup.value == FEATURE_ENDPOINT_HALT && endpoint != 0 =>
+            {
+                self.bus.set_stalled(endpoint, false);
+                true
+            }
+            (Recipient::Endpoint, SET_FEATURE)
+                if setup.value == FEATURE_ENDPOINT_HALT && endpoint != 0 =>
+            {
+                self.bus.set_stalled(endpoint, true);
+                true
+            }
+            (Recipient::Interface, SET_INTERFACE) => setup.value == 0,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// Records what the stack does with the bus, and replays the events of a capture.
+    #[derive(Default)]
+    pub struct MockBus {
+        pub events: VecDeque<Event>,
+        /// Packets received, on each endpoint.
+        pub out: [VecDeque<Vec<u8>>; 4],
+        /// Packets sent, on each endpoint.
+        pub sent: [Vec<Vec<u8>>; 4],
+        pub address: u8,
+        pub configured: bool,
+        pub endpoints: Vec<(u8, EndpointType, bool)>,
+        pub stalled: [bool; 4],
+    }
+
+    impl MockBus {
+        pub fn setup(&mut self, packet: [u8; 8]) {
+            self.events
+                .push_back(Event::Setup(SetupPacket::parse(packet)));
+        }
+    }
+
+    impl Bus for MockBus {
+        fn poll(&mut self) -> Option<Event> {
+            self.events.pop_front()
+        }
+        fn set_address(&mut self, address: u8) {
+            self.address = address;
+        }
+        fn set_configured(&mut self, configured: bool) {
+            self.configured = configured;
+        }
+        fn configure_endpoint(&mut self, endpoint: u8, kind: EndpointType, is_in: bool) {
+            self.endpoints.push((endpoint, kind, is_in));
+        }
+        fn read(&mut self, endpoint: u8, buffer: &mut [u8]) -> Option<usize> {
+            self.out[usize::from(endpoint)].pop_front().map(|packet| {
+                buffer[..packet.len()].copy_from_slice(&packet);
+                packet.len()
+            })
+        }
+        fn write(&mut self, endpoint: u8, data: &[u8]) -> bool {
+            self.sent[usize::from(endpoint)].push(data.to_vec());
+            true
+        }
+        fn set_stalled(&mut self, endpoint: u8, stalled: bool) {
+            self.stalled[usize::from(endpoint)] = stalled;
+        }
+        fn is_stalled(&self, endpoint: u8) -> bool {
+            self.stalled[usize::from(endpoint)]
+        }
+    }
+
+    struct NoClass;
+    impl Class for NoClass {
+        fn configure<B: Bus>(&mut self, bus: &mut B) {
+            bus.configure_endpoint(1, EndpointType::Bulk, true);
+        }
+        fn reset(&mut self) {}
+        fn control_in(&mut self, _: SetupPacket, _: &mut [u8]) -> Option<usize> {
+            None
+        }
+        fn control_out(&mut self, _: SetupPacket, _: &[u8]) -> bool {
+            false
+        }
+        fn poll<B: Bus>(&mut self, _: &mut B) {}
+    }
+
+    /// Sends a setup packet and collects the answer, acknowledging each packet.
+    pub fn request<C: Class>(usb: &mut UsbDevice<MockBus, C>, packet: [u8; 8]) -> Vec<u8> {
+        usb.bus().setup(packet);
+        collect(usb)
+    }
+
+    /// Sends a setup packet and its data stage, returns whether it was acknowledged.
+    pub fn request_out<C: Class>(
+        usb: &mut UsbDevice<MockBus, C>,
+        packet: [u8; 8],
+        data: &[u8],
+    ) -> bool {
+        usb.bus().setup(packet);
+        usb.bus().out[0].push_back(data.to_vec());
+        usb.bus().events.push_back(Event::ControlOut);
+        collect(usb).is_empty() && !usb.bus().stalled[0]
+    }
+
+    fn collect<C: Class>(usb: &mut UsbDevice<MockBus, C>) -> Vec<u8> {
+        usb.bus().sent[0].clear();
+        usb.bus().stalled[0] = false;
+        usb.poll();
+        let mut answer = Vec::new();
+        while let Some(packet) = usb.bus().sent[0].pop() {
+            answer.extend_from_slice(&packet);
+            usb.bus().events.push_back(Event::ControlInComplete);
+            usb.poll();
+        }
+        answer
+    }
+
+    #[test]
+    fn test_enumeration() {
+        let device = device_descriptor(0xFF, 0x1D50, 0x60EC, 0x0100);
+        let mut configuration = [0; 9 + 9 + 7];
+        configuration[..9].copy_from_slice(&[9, 2, 25, 0, 1, 1, 0, 0xC0, 50]);
+        let descriptors = Descriptors {
+            device: &device,
+            configuration: &configuration,
+            strings: &["Duet3D", "Duet 2"],
+        };
+        let mut usb = UsbDevice::new(MockBus::default(), NoClass, &descriptors);
+        usb.bus().events.push_back(Event::Reset);
+
+        // recorded from a Linux host.
+        assert_eq!(
+            &device[..],
+            &request(&mut usb, [0x80, 6, 0, 1, 0, 0, 0x40, 0])[..]
+        );
+        assert_eq!(0, request(&mut usb, [0, 5, 13, 0, 0, 0, 0, 0]).len());
+        assert_eq!((13, State::Address), (usb.bus().address, usb.state()));
+        let answer = request(&mut usb, [0x80, 6, 0, 2, 0, 0, 0xFF, 0]);
+        assert_eq!(&configuration[..], &answer[..]);
+        let answer = request(&mut usb, [0x80, 6, 2, 3, 0x09, 4, 0xFF, 0]);
+        assert_eq!(
+            &[14, 3, b'D', 0, b'u', 0, b'e', 0, b't', 0, b' ', 0, b'2', 0],
+            &answer[..]
+        );
+        // full speed only: no device qualifier.
+        request(&mut usb, [0x80, 6, 0, 6, 0, 0, 10, 0]);
+        assert!(usb.bus().stalled[0]);
+
+        request(&mut usb, [0, 9, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(State::Configured, usb.state());
+        assert!(usb.bus().configured);
+        assert_eq!(vec![(1, EndpointType::Bulk, true)], usb.bus().endpoints);
+        assert_eq!(vec![1], request(&mut usb, [0x80, 8, 0, 0, 0, 0, 1, 0]));
+        assert_eq!(vec![1, 0], request(&mut usb, [0x80, 0, 0, 0, 0, 0, 2, 0]));
+    }
+}
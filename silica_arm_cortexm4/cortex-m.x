@@ -12,11 +12,19 @@ EXTERN(EXCEPTIONS);
    object file that's passed to the linker *before* this crate */
 /* EXTERN(INTERRUPTS); */
 
+PROVIDE(ITM = 0xE0000000);
+PROVIDE(DWT = 0xE0001000);
 PROVIDE(ACTLR = 0xE000E008);
 PROVIDE(SYSTICK = 0xE000E010);
+PROVIDE(NVIC = 0xE000E100);
 PROVIDE(SCB = 0xE000ED00);
+PROVIDE(FR = 0xE000ED40);
 PROVIDE(CPACR = 0xE000ED88);
+PROVIDE(MPU = 0xE000ED90);
+PROVIDE(DCB = 0xE000EDF0);
 PROVIDE(STIR = 0xE000EF00);
+PROVIDE(FPU = 0xE000EF34);
+PROVIDE(TPIU = 0xE0040000);
 
 SECTIONS
 {
@@ -52,7 +60,7 @@ SECTIONS
     } > RAM
     PROVIDE(bss_size = SIZEOF(.bss));
 
-    heap_size = LENGTH(RAM) - data_size - panic_msg_size - stack_size - bss_size;
+    PROVIDE(heap_size = LENGTH(RAM) - data_size - panic_msg_size - stack_size - bss_size);
     .heap : {
         _sheap = .;
         . = . + heap_size;
@@ -79,4 +87,5 @@ SECTIONS
 
 }
 ASSERT(EXCEPTIONS != 0, "missing start symbol");
+ASSERT(_eheap <= _sstack, "the data, heap and stack do not fit in the RAM");
 
@@ -0,0 +1,243 @@
+//! Identification of the running core.
+//!
+//! Decodes the CPUID, feature, MVFR and MPU type registers so that firmware can check at boot
+//! that it runs on the core it was built for.
+
+use core::fmt;
+use ppb::fpu::MVFRegister0;
+use ppb::mpu::TypeRegister;
+use ppb::scb::{CPUIDRegister, ISARegister1, ISARegister3};
+
+const IMPLEMENTER_ARM: u8 = 0x41;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Core {
+    CortexM0,
+    CortexM0Plus,
+    CortexM1,
+    CortexM3,
+    CortexM4,
+    CortexM7,
+    Unknown { implementer: u8, part_number: u16 },
+}
+impl Core {
+    fn decode(implementer: u8, part_number: u16) -> Core {
+        match (implementer, part_number) {
+            (IMPLEMENTER_ARM, 0xC20) => Core::CortexM0,
+            (IMPLEMENTER_ARM, 0xC60) => Core::CortexM0Plus,
+            (IMPLEMENTER_ARM, 0xC21) => Core::CortexM1,
+            (IMPLEMENTER_ARM, 0xC23) => Core::CortexM3,
+            (IMPLEMENTER_ARM, 0xC24) => Core::CortexM4,
+            (IMPLEMENTER_ARM, 0xC27) => Core::CortexM7,
+            (implementer, part_number) => Core::Unknown {
+                implementer,
+                part_number,
+            },
+        }
+    }
+}
+impl fmt::Display for Core {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Core::CortexM0 => f.write_str("Cortex-M0"),
+            Core::CortexM0Plus => f.write_str("Cortex-M0+"),
+            Core::CortexM1 => f.write_str("Cortex-M1"),
+            Core::CortexM3 => f.write_str("Cortex-M3"),
+            Core::CortexM4 => f.write_str("Cortex-M4"),
+            Core::CortexM7 => f.write_str("Cortex-M7"),
+            Core::Unknown {
+                implementer,
+                part_number,
+            } => write!(f, "unknown core {:#04x}:{:#05x}", implementer, part_number),
+        }
+    }
+}
+
+/// Core revision, displayed in ARM's `rXpY` notation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Revision {
+    pub variant: u8,
+    pub patch: u8,
+}
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r{}p{}", self.variant, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fpu {
+    None,
+    SinglePrecision,
+    DoublePrecision,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoreInfo {
+    pub core: Core,
+    pub revision: Revision,
+    pub fpu: Fpu,
+    /// Number of MPU regions, 0 if no MPU is implemented.
+    pub mpu_regions: u8,
+    /// Support for the DSP extension (SIMD, saturating and packing instructions).
+    pub dsp: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoreMismatch {
+    Core(Core),
+    MissingFpu,
+    MissingMpu,
+    MissingDsp,
+}
+
+impl CoreInfo {
+    pub fn decode(
+        cpuid: CPUIDRegister,
+        isar1: ISARegister1,
+        isar3: ISARegister3,
+        mvfr0: MVFRegister0,
+        mpu_type: TypeRegister,
+    ) -> CoreInfo {
+        let fpu = if mvfr0.double_precision() != 0 {
+            Fpu::DoublePrecision
+        } else if mvfr0.single_precision() != 0 {
+            Fpu::SinglePrecision
+        } else {
+            Fpu::None
+        };
+        CoreInfo {
+            core: Core::decode(cpuid.implementer_code(), cpuid.part_number()),
+            revision: Revision {
+                variant: cpuid.variant(),
+                patch: cpuid.revision(),
+            },
+            fpu,
+            mpu_regions: mpu_type.data_regions(),
+            dsp: isar1.extend_instrs() >= 2 || isar3.simd_instrs() >= 3,
+        }
+    }
+
+    /// Reads the identification registers of the running core.
+    pub fn read() -> CoreInfo {
+        unsafe {
+            CoreInfo::decode(
+                ::ppb::SCB.cpuid.get(),
+                ::ppb::FR.isar1(),
+                ::ppb::FR.isar3(),
+                ::ppb::FPU.mvfr0.get(),
+                ::ppb::MPU.mpu_type.get(),
+            )
+        }
+    }
+
+    /// Checks that the core matches the expectations of the firmware.
+    pub fn verify(&self, core: Core, fpu: bool, mpu: bool, dsp: bool) -> Result<(), CoreMismatch> {
+        if self.core != core {
+            Err(CoreMismatch::Core(self.core))
+        } else if fpu && self.fpu == Fpu::None {
+            Err(CoreMismatch::MissingFpu)
+        } else if mpu && self.mpu_regions == 0 {
+            Err(CoreMismatch::MissingMpu)
+        } else if dsp && !self.dsp {
+            Err(CoreMismatch::MissingDsp)
+        } else {
+            Ok(())
+        }
+    }
+}
+impl fmt::Display for CoreInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.core, self.revision)?;
+        match self.fpu {
+            Fpu::None => {}
+            Fpu::SinglePrecision => f.write_str(", FPv4-SP")?,
+            Fpu::DoublePrecision => f.write_str(", FPv5-DP")?,
+        }
+        if self.mpu_regions != 0 {
+            write!(f, ", MPU({} regions)", self.mpu_regions)?;
+        }
+        if self.dsp {
+            f.write_str(", DSP")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Buf {
+        data: [u8; 64],
+        len: usize,
+    }
+    impl fmt::Write for Buf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn decode(cpuid: u32, isar1: u32, isar3: u32, mvfr0: u32, mpu_type: u32) -> CoreInfo {
+        CoreInfo::decode(
+            cpuid.into(),
+            isar1.into(),
+            isar3.into(),
+            mvfr0.into(),
+            mpu_type.into(),
+        )
+    }
+
+    #[test]
+    fn test_cortex_m4f() {
+        let info = decode(
+            0x410F_C241,
+            0x0211_2000,
+            0x0111_1110,
+            0x1011_0021,
+            0x0000_0800,
+        );
+        assert_eq!(Core::CortexM4, info.core);
+        assert_eq!(Fpu::SinglePrecision, info.fpu);
+        assert_eq!(8, info.mpu_regions);
+        assert!(info.dsp);
+        assert_eq!(Ok(()), info.verify(Core::CortexM4, true, true, true));
+
+        use core::fmt::Write;
+        let mut buf = Buf {
+            data: [0; 64],
+            len: 0,
+        };
+        write!(buf, "{}", info).unwrap();
+        assert_eq!(
+            &b"Cortex-M4 r0p1, FPv4-SP, MPU(8 regions), DSP"[..],
+            &buf.data[..buf.len]
+        );
+    }
+
+    #[test]
+    fn test_cortex_m3() {
+        let info = decode(0x412F_C230, 0x0211_1000, 0x0111_1110, 0, 0);
+        assert_eq!(Core::CortexM3, info.core);
+        assert_eq!(
+            Revision {
+                variant: 2,
+                patch: 0
+            },
+            info.revision
+        );
+        assert_eq!(Fpu::None, info.fpu);
+        assert!(!info.dsp);
+        assert_eq!(
+            Err(CoreMismatch::Core(Core::CortexM3)),
+            info.verify(Core::CortexM4, false, false, false)
+        );
+        assert_eq!(
+            Err(CoreMismatch::MissingMpu),
+            info.verify(Core::CortexM3, false, true, false)
+        );
+    }
+}
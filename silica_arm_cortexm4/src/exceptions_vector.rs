@@ -1,4 +1,6 @@
 use core;
+use scheduler::port::pendsv_handler;
+use syscall::svcall_handler;
 use Exceptions;
 
 extern "C" {
@@ -35,23 +37,45 @@ unsafe extern "C" fn start() -> ! {
 
 unsafe extern "C" fn default_handler() {}
 unsafe extern "C" fn hf_handler() {}
-unsafe extern "C" fn pendsv_handler() {}
-unsafe extern "C" fn systick_handler() {}
+/// Faults of unprivileged tasks end them, they are fatal anywhere else.
+unsafe extern "C" fn mem_manage_handler() {
+    let cfsr = ::ppb::SCB.cfsr.get();
+    // flags are write-one-to-clear
+    ::ppb::SCB.cfsr.set(cfsr.mmsr_flags());
+    let from_thread = ::ppb::SCB.icsr.get().return_to_base();
+    if from_thread && !::syscall::is_thread_privileged() {
+        ::scheduler::kill_current();
+    } else {
+        panic!("MemManage fault: {:?}", cfsr.get_mmsr());
+    }
+}
+unsafe extern "C" fn debug_monitor_handler() {
+    let dfsr = ::ppb::SCB.dfsr.get();
+    // flags are write-one-to-clear
+    ::ppb::SCB.dfsr.set(dfsr);
+    if dfsr.dwt_trap() {
+        panic!("DWT watchpoint hit: {:?}", ::ppb::DWT.matched_comparator());
+    }
+}
+unsafe extern "C" fn systick_handler() {
+    ::time::on_systick();
+    ::scheduler::on_systick();
+}
 
 #[cfg(target_arch = "arm")]
 #[link_section = ".vector_table.exceptions_vector"]
 #[no_mangle]
 #[used]
 pub static EXCEPTIONS: Exceptions = Exceptions {
-    reset: start,                 // RESET
-    nmi: default_handler,         // NMI
-    hard_fault: hf_handler,       // Hardfault
-    mem_manage: default_handler,  // MemManage
-    bus_fault: default_handler,   // BusFault
-    usage_fault: default_handler, // UsageFault
+    reset: start,                   // RESET
+    nmi: default_handler,           // NMI
+    hard_fault: hf_handler,         // Hardfault
+    mem_manage: mem_manage_handler, // MemManage
+    bus_fault: default_handler,     // BusFault
+    usage_fault: default_handler,   // UsageFault
     reserved1: [0; 4],
-    sv_call: default_handler,       // SVCall
-    debug_monitor: default_handler, // Debug Monitor
+    sv_call: svcall_handler,              // SVCall
+    debug_monitor: debug_monitor_handler, // Debug Monitor
     reserved2: 0,
     pendsv: pendsv_handler,   // PendSV
     systick: systick_handler, // Systick
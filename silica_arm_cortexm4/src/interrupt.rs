@@ -0,0 +1,248 @@
+//! Interrupt masking.
+//!
+//! PRIMASK masks every configurable exception, BASEPRI only those with a priority value greater
+//! than or equal to its value (lower values have a higher priority) and FAULTMASK also masks the
+//! HardFault.
+//!
+//! Host builds simulate the mask registers so that code relying on them can be unit tested.
+
+use core::cell::{Cell, UnsafeCell};
+pub use silica::sync::{CriticalSection, Mutex};
+
+/// Each host thread, e.g. each test, simulates its own core.
+#[cfg(not(target_arch = "arm"))]
+mod sim {
+    use core::cell::Cell;
+
+    ::std::thread_local! {
+        pub static PRIMASK: Cell<bool> = Cell::new(false);
+        pub static FAULTMASK: Cell<bool> = Cell::new(false);
+        pub static BASEPRI: Cell<u8> = Cell::new(0);
+    }
+}
+
+/// Masks all interrupts (`cpsid i`).
+#[inline]
+pub fn disable() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("cpsid i" : : : "memory" : "volatile");
+    }
+    #[cfg(not(target_arch = "arm"))]
+    sim::PRIMASK.with(|r| r.set(true));
+}
+
+/// Unmasks interrupts (`cpsie i`).
+/// This is unsafe as it would break a critical section if called within one.
+#[inline]
+pub unsafe fn enable() {
+    #[cfg(target_arch = "arm")]
+    asm!("cpsie i" : : : "memory" : "volatile");
+    #[cfg(not(target_arch = "arm"))]
+    sim::PRIMASK.with(|r| r.set(false));
+}
+
+/// Returns true if PRIMASK is set (interrupts are masked).
+#[inline]
+pub fn primask() -> bool {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("mrs $0, PRIMASK" : "=r"(r) : : : "volatile");
+        }
+        r & 1 == 1
+    }
+    #[cfg(not(target_arch = "arm"))]
+    sim::PRIMASK.with(Cell::get)
+}
+
+/// Masks all interrupts and the HardFault (`cpsid f`).
+#[inline]
+pub fn disable_faults() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("cpsid f" : : : "memory" : "volatile");
+    }
+    #[cfg(not(target_arch = "arm"))]
+    sim::FAULTMASK.with(|r| r.set(true));
+}
+
+/// Clears FAULTMASK (`cpsie f`).
+#[inline]
+pub unsafe fn enable_faults() {
+    #[cfg(target_arch = "arm")]
+    asm!("cpsie f" : : : "memory" : "volatile");
+    #[cfg(not(target_arch = "arm"))]
+    sim::FAULTMASK.with(|r| r.set(false));
+}
+
+#[inline]
+pub fn faultmask() -> bool {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("mrs $0, FAULTMASK" : "=r"(r) : : : "volatile");
+        }
+        r & 1 == 1
+    }
+    #[cfg(not(target_arch = "arm"))]
+    sim::FAULTMASK.with(Cell::get)
+}
+
+/// Current BASEPRI value. 0 means that no exception is masked by BASEPRI.
+#[inline]
+pub fn basepri() -> u8 {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("mrs $0, BASEPRI" : "=r"(r) : : : "volatile");
+        }
+        r as u8
+    }
+    #[cfg(not(target_arch = "arm"))]
+    sim::BASEPRI.with(Cell::get)
+}
+
+/// Sets BASEPRI.
+/// This is unsafe as lowering the mask would break a priority ceiling.
+#[inline]
+pub unsafe fn set_basepri(priority: u8) {
+    #[cfg(target_arch = "arm")]
+    asm!("msr BASEPRI, $0" : : "r"(u32::from(priority)) : "memory" : "volatile");
+    #[cfg(not(target_arch = "arm"))]
+    sim::BASEPRI.with(|r| r.set(priority));
+}
+
+/// Raises BASEPRI to `priority` if this increases the masking, a no-op otherwise.
+#[inline]
+pub fn set_basepri_max(priority: u8) {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("msr BASEPRI_MAX, $0" : : "r"(u32::from(priority)) : "memory" : "volatile");
+    }
+    #[cfg(not(target_arch = "arm"))]
+    {
+        let current = basepri();
+        if priority != 0 && (current == 0 || priority < current) {
+            unsafe { set_basepri(priority) }
+        }
+    }
+}
+
+/// Runs `f` with all interrupts masked. PRIMASK is restored on return so that critical sections
+/// can be nested.
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    let was_masked = primask();
+    disable();
+    let r = f(&unsafe { CriticalSection::new() });
+    if !was_masked {
+        unsafe { enable() }
+    }
+    r
+}
+
+/// Runs `f` with all exceptions whose priority value is greater than or equal to `ceiling`
+/// masked. Exceptions with a higher priority (lower value) keep preempting `f`.
+///
+/// A `ceiling` of 0 masks everything and is equivalent to `free`.
+pub fn with_ceiling<F, R>(ceiling: u8, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if ceiling == 0 {
+        return free(|_| f());
+    }
+    let previous = basepri();
+    set_basepri_max(ceiling);
+    let r = f();
+    unsafe { set_basepri(previous) }
+    r
+}
+
+/// A value shared between contexts running at or above a priority ceiling.
+///
+/// Unlike `Mutex`, locking only masks the exceptions up to the ceiling, leaving the higher
+/// priority ones (e.g. the stepper interrupts) unaffected. The ceiling must be the priority
+/// value of the highest priority exception accessing the data.
+pub struct CeilingMutex<T> {
+    ceiling: u8,
+    locked: Cell<bool>,
+    inner: UnsafeCell<T>,
+}
+impl<T> CeilingMutex<T> {
+    pub const fn new(ceiling: u8, value: T) -> CeilingMutex<T> {
+        CeilingMutex {
+            ceiling,
+            locked: Cell::new(false),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn ceiling(&self) -> u8 {
+        self.ceiling
+    }
+
+    /// Runs `f` with an exclusive access to the data.
+    ///
+    /// # Panics
+    ///
+    /// Panics on re-entrant locking.
+    pub fn lock<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        with_ceiling(self.ceiling, || {
+            assert!(!self.locked.replace(true), "CeilingMutex locked twice");
+            let r = f(unsafe { &mut *self.inner.get() });
+            self.locked.set(false);
+            r
+        })
+    }
+}
+unsafe impl<T: Send> Sync for CeilingMutex<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_masks() {
+        assert!(!primask());
+        free(|_| {
+            assert!(primask());
+            free(|_| {});
+            assert!(primask());
+        });
+        assert!(!primask());
+
+        with_ceiling(0x40, || {
+            assert_eq!(0x40, basepri());
+            with_ceiling(0x80, || assert_eq!(0x40, basepri()));
+            with_ceiling(0x20, || assert_eq!(0x20, basepri()));
+            assert_eq!(0x40, basepri());
+        });
+        assert_eq!(0, basepri());
+
+        let shared = Mutex::new(RefCell::new(0u32));
+        free(|cs| *shared.borrow(cs).borrow_mut() += 2);
+        assert_eq!(2, shared.into_inner().into_inner());
+
+        let m = CeilingMutex::new(0x30, 5u32);
+        assert_eq!(
+            6,
+            m.lock(|v| {
+                assert_eq!(0x30, basepri());
+                *v += 1;
+                *v
+            })
+        );
+        assert_eq!(0, basepri());
+    }
+}
@@ -1,19 +1,28 @@
 #![feature(
-    lang_items, asm, linkage, used, panic_runtime, naked_functions, core_intrinsics,
-    integer_atomics, ptr_internals, const_fn, macro_vis_matcher, try_from, never_type,
-    panic_implementation
+    lang_items, asm, linkage, panic_runtime, naked_functions, core_intrinsics, ptr_internals,
+    const_fn, never_type, futures_api
 )]
 #![no_std]
 #![cfg_attr(target_arch = "arm", panic_runtime)]
 
 #[macro_use]
-extern crate silica;
+pub extern crate silica;
+// the host builds simulate the core registers per thread.
+#[cfg(not(target_arch = "arm"))]
+extern crate std;
+
+#[macro_use]
+pub mod ppb;
+pub mod core_info;
+pub mod interrupt;
+pub mod power;
+pub mod scheduler;
+pub mod syscall;
+pub mod time;
 
 #[cfg(target_arch = "arm")]
 pub mod panic_runtime;
 
-pub mod ppb;
-
 pub type Handler = unsafe extern "C" fn();
 
 #[repr(C)]
@@ -16,9 +16,10 @@ pub fn rust_eh_unwind_resume() {
 }*/
 
 #[no_mangle]
-#[panic_implementation]
-pub fn panic_handler(_: &::core::panic::PanicInfo) -> ! {
-    // use semihosting or failure cause buffer or stdout(a peripheral) or ITM
+#[panic_handler]
+pub fn panic_handler(info: &::core::panic::PanicInfo) -> ! {
+    // use semihosting or failure cause buffer or stdout(a peripheral)
+    iprintln!("{}", info);
     unsafe {
         asm!("bkpt");
         ::ppb::SCB.aircr.get_mut().sys_reset_request();
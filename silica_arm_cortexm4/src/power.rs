@@ -0,0 +1,112 @@
+//! Core sleep modes.
+//!
+//! `wfi` suspends the core until an interrupt (even masked by PRIMASK) becomes pending, `wfe`
+//! until an event: an interrupt, a `sev` from another context or, when `SEVONPEND` is set, any
+//! pending bit transitioning to 1. The depth of the sleep is selected by `SCRegister::sleep_mode`
+//! and is defined by the vendor.
+
+use interrupt::{self, CriticalSection};
+use ppb::scb::SleepMode;
+use ppb::SCB;
+use silica::executor::Sleep;
+
+/// Wait For Interrupt
+#[inline]
+pub fn wfi() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("wfi" : : : "memory" : "volatile");
+    }
+}
+
+/// Wait For Event
+#[inline]
+pub fn wfe() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("wfe" : : : "memory" : "volatile");
+    }
+}
+
+/// Send Event
+#[inline]
+pub fn sev() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("sev" : : : "memory" : "volatile");
+    }
+}
+
+/// Data and instruction synchronisation barriers, required after changing `SCR` before going to
+/// sleep.
+#[inline]
+fn barrier() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("dsb
+              isb" : : : "memory" : "volatile");
+    }
+}
+
+pub fn set_sleep_mode(mode: SleepMode) {
+    unsafe {
+        SCB.scr.get_mut().set_sleep_mode(mode);
+    }
+    barrier();
+}
+
+/// When enabled, the core goes back to sleep when returning from the last active exception to
+/// thread mode instead of resuming the main loop. This suits firmware entirely driven by
+/// interrupts.
+pub fn set_sleep_on_exit(enabled: bool) {
+    unsafe {
+        SCB.scr.get_mut().set_sleep_on_exit(enabled);
+    }
+    barrier();
+}
+
+/// When enabled, any interrupt becoming pending wakes the core up from `wfe`, even if it is
+/// disabled.
+pub fn set_send_event_on_pending(enabled: bool) {
+    unsafe {
+        SCB.scr.get_mut().send_event_on_pending_bit(enabled);
+    }
+}
+
+/// Sleeps until an interrupt fires unless `has_work` returns true.
+///
+/// `has_work` is evaluated with interrupts masked so that an interrupt occurring between the
+/// check and the `wfi` cannot be missed: it still wakes the core and is serviced once interrupts
+/// are unmasked again. Returns true if the core went to sleep.
+pub fn sleep_unless<F>(has_work: F) -> bool
+where
+    F: FnOnce(&CriticalSection) -> bool,
+{
+    interrupt::free(|cs| {
+        if has_work(cs) {
+            false
+        } else {
+            wfi();
+            true
+        }
+    })
+}
+
+/// Enters the vendor's deep sleep mode until an interrupt fires then restores the regular sleep
+/// mode.
+pub fn deep_sleep() {
+    set_sleep_mode(SleepMode::DeepSleep);
+    wfi();
+    set_sleep_mode(SleepMode::Sleep);
+}
+
+/// Lets `silica::executor::Executor::run` sleep with `wfi` while no task is ready.
+pub struct Wfi;
+impl Sleep for Wfi {
+    fn sleep_unless<F>(&self, has_work: F)
+    where
+        F: Fn() -> bool,
+    {
+        sleep_unless(|_| has_work());
+    }
+}
@@ -0,0 +1,88 @@
+//! Debug Control Block (a.k.a. CoreDebug)
+use core::convert::Into;
+use core::fmt;
+use silica::register::{Field, RegisterCell};
+
+register! {
+    @impl_debug;
+    @optout_extract_insert;
+    /// Debug Halting Control and Status Register
+    /// Writes are ignored unless the upper half-word holds the debug key, this is taken care of
+    /// by the setters.
+    #[derive(Copy, Clone)]
+    pub struct DHCSRegister(u32) {
+        bool: pub reset_since_last_read, _: 25;
+        bool: pub instr_retired_since_last_read, _: 24;
+        bool: pub is_locked_up, _: 19;
+        bool: pub is_sleeping, _: 18;
+        bool: pub is_halted, _: 17;
+        bool: pub register_transfer_ready, _: 16;
+        bool: pub snap_stall_enabled, pub enable_snap_stall: 5;
+        bool: pub interrupts_masked, pub mask_interrupts: 3;
+        bool: pub stepping, pub step: 2;
+        bool: pub halt_requested, pub halt: 1;
+        /// True when a debugger has enabled halting debug. This cannot be set by software.
+        bool: pub debugger_connected, _: 0;
+    }
+}
+impl DHCSRegister {
+    #[inline]
+    fn extract(self, f: &Field) -> u32 {
+        (self.0 >> f.lsb()) & f.mask::<u32>()
+    }
+    #[inline]
+    fn insert(&mut self, f: &Field, v: u32) {
+        // status bits read back in the upper half-word must be replaced by the key.
+        let mask = 0x0000_FFFF & !(f.mask::<u32>() << f.lsb());
+        let value = 0xA05F_0000 | ((v & f.mask::<u32>()) << f.lsb());
+        self.0 = (self.0 & mask) | value;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Debug Exception and Monitor Control Register
+    #[derive(Copy, Clone)]
+    pub struct DEMCRegister(u32) {
+        /// Global enable for the DWT and ITM units.
+        bool: pub trace_enabled, pub enable_trace: 24;
+        bool: pub monitor_requested, pub request_monitor: 19;
+        bool: pub monitor_stepping, pub step_monitor: 18;
+        bool: pub monitor_pending, pub pend_monitor: 17;
+        /// Enables the DebugMonitor exception. Debug events such as watchpoint hits trigger it
+        /// when halting debug is disabled.
+        bool: pub monitor_enabled, pub enable_monitor: 16;
+        bool: pub catch_hardfault, pub set_catch_hardfault: 10;
+        bool: pub catch_interrupt_error, pub set_catch_interrupt_error: 9;
+        bool: pub catch_busfault, pub set_catch_busfault: 8;
+        bool: pub catch_state_error, pub set_catch_state_error: 7;
+        bool: pub catch_check_error, pub set_catch_check_error: 6;
+        bool: pub catch_nocp_error, pub set_catch_nocp_error: 5;
+        bool: pub catch_memfault, pub set_catch_memfault: 4;
+        bool: pub catch_core_reset, pub set_catch_core_reset: 0;
+    }
+}
+
+/// Debug Control Block
+/// http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.ddi0337e/CEGHJDCF.html
+#[repr(C)]
+pub struct DebugControlBlock {
+    pub dhcsr: RegisterCell<DHCSRegister>,
+    /// Debug Core Register Selector Register (write only)
+    pub dcrsr: RegisterCell<u32>,
+    /// Debug Core Register Data Register
+    pub dcrdr: RegisterCell<u32>,
+    pub demcr: RegisterCell<DEMCRegister>,
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_dhcsr_key() {
+        let mut dhcsr = super::DHCSRegister(0x0003_0001);
+        assert!(dhcsr.is_halted());
+        assert!(dhcsr.debugger_connected());
+        dhcsr.mask_interrupts(true);
+        assert_eq!(0xA05F_0009, dhcsr.0);
+    }
+}
@@ -0,0 +1,335 @@
+//! Data Watchpoint and Trace unit
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    @impl_debug;
+    /// DWT Control Register
+    #[derive(Copy, Clone)]
+    pub struct DWTCRegister(u32) {
+        u8: pub comparator_count, _: 31, 28;
+        bool: pub no_trace_packets, _: 27;
+        bool: pub no_external_trigger, _: 26;
+        bool: pub no_cycle_counter, _: 25;
+        bool: pub no_profiling_counters, _: 24;
+        bool: pub cycle_event_enabled, pub enable_cycle_event: 22;
+        bool: pub fold_event_enabled, pub enable_fold_event: 21;
+        bool: pub lsu_event_enabled, pub enable_lsu_event: 20;
+        bool: pub sleep_event_enabled, pub enable_sleep_event: 19;
+        bool: pub exception_event_enabled, pub enable_exception_event: 18;
+        bool: pub cpi_event_enabled, pub enable_cpi_event: 17;
+        bool: pub exception_trace_enabled, pub enable_exception_trace: 16;
+        bool: pub pc_sampling_enabled, pub enable_pc_sampling: 12;
+        /// Selects the CYCCNT tap bit used for ITM synchronisation packets:
+        /// 0 = disabled, 1 = bit 24, 2 = bit 26, 3 = bit 28.
+        u8: pub sync_tap, pub set_sync_tap: 11, 10;
+        bool: pub cycle_tap, pub set_cycle_tap: 9;
+        u8: pub post_init, pub set_post_init: 8, 5;
+        u8: pub post_preset, pub set_post_preset: 4, 1;
+        bool: pub cycle_counter_enabled, pub enable_cycle_counter: 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoComparatorFunctionError(());
+
+/// Action taken by a comparator on match (with data value matching disabled).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComparatorFunction {
+    Disabled,
+    /// Emits the PC (or the data address if `EMITRANGE` is set) as a trace packet.
+    SamplePc,
+    SampleDataValue,
+    SamplePcAndDataValue,
+    /// Generates a debug event on instruction address match.
+    WatchpointOnPc,
+    /// Generates a debug event on data read.
+    WatchpointOnRead,
+    /// Generates a debug event on data write.
+    WatchpointOnWrite,
+    /// Generates a debug event on data read or write.
+    WatchpointOnAccess,
+    EtmTriggerOnPc,
+    EtmTriggerOnRead,
+    EtmTriggerOnWrite,
+    EtmTriggerOnAccess,
+    SampleDataValueOnRead,
+    SampleDataAddressOnWrite,
+    SampleDataValueOnWrite,
+    SampleDataAddressAndValueOnAccess,
+}
+impl TryFrom<u32> for ComparatorFunction {
+    type Error = TryIntoComparatorFunctionError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0b0000 => Ok(ComparatorFunction::Disabled),
+            0b0001 => Ok(ComparatorFunction::SamplePc),
+            0b0010 => Ok(ComparatorFunction::SampleDataValue),
+            0b0011 => Ok(ComparatorFunction::SamplePcAndDataValue),
+            0b0100 => Ok(ComparatorFunction::WatchpointOnPc),
+            0b0101 => Ok(ComparatorFunction::WatchpointOnRead),
+            0b0110 => Ok(ComparatorFunction::WatchpointOnWrite),
+            0b0111 => Ok(ComparatorFunction::WatchpointOnAccess),
+            0b1000 => Ok(ComparatorFunction::EtmTriggerOnPc),
+            0b1001 => Ok(ComparatorFunction::EtmTriggerOnRead),
+            0b1010 => Ok(ComparatorFunction::EtmTriggerOnWrite),
+            0b1011 => Ok(ComparatorFunction::EtmTriggerOnAccess),
+            0b1100 => Ok(ComparatorFunction::SampleDataValueOnRead),
+            0b1101 => Ok(ComparatorFunction::SampleDataAddressOnWrite),
+            0b1110 => Ok(ComparatorFunction::SampleDataValueOnWrite),
+            0b1111 => Ok(ComparatorFunction::SampleDataAddressAndValueOnAccess),
+            _ => Err(TryIntoComparatorFunctionError(())),
+        }
+    }
+}
+impl From<ComparatorFunction> for u32 {
+    fn from(v: ComparatorFunction) -> u32 {
+        match v {
+            ComparatorFunction::Disabled => 0b0000,
+            ComparatorFunction::SamplePc => 0b0001,
+            ComparatorFunction::SampleDataValue => 0b0010,
+            ComparatorFunction::SamplePcAndDataValue => 0b0011,
+            ComparatorFunction::WatchpointOnPc => 0b0100,
+            ComparatorFunction::WatchpointOnRead => 0b0101,
+            ComparatorFunction::WatchpointOnWrite => 0b0110,
+            ComparatorFunction::WatchpointOnAccess => 0b0111,
+            ComparatorFunction::EtmTriggerOnPc => 0b1000,
+            ComparatorFunction::EtmTriggerOnRead => 0b1001,
+            ComparatorFunction::EtmTriggerOnWrite => 0b1010,
+            ComparatorFunction::EtmTriggerOnAccess => 0b1011,
+            ComparatorFunction::SampleDataValueOnRead => 0b1100,
+            ComparatorFunction::SampleDataAddressOnWrite => 0b1101,
+            ComparatorFunction::SampleDataValueOnWrite => 0b1110,
+            ComparatorFunction::SampleDataAddressAndValueOnAccess => 0b1111,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Comparator Mask Register
+    #[derive(Copy, Clone)]
+    pub struct MaskRegister(u32) {
+        /// Number of least significant address bits ignored by the comparison.
+        u8: pub ignored_bits, pub set_ignored_bits: 4, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Comparator Function Register
+    #[derive(Copy, Clone)]
+    pub struct FunctionRegister(u32) {
+        /// Set when the comparator matched since the last read of the register.
+        bool: pub matched, _: 24;
+        u8: pub data_value_addr1, pub set_data_value_addr1: 19, 16;
+        u8: pub data_value_addr0, pub set_data_value_addr0: 15, 12;
+        u8: pub data_value_size, pub set_data_value_size: 11, 10;
+        bool: pub second_link_enabled, _: 9;
+        bool: pub data_value_match, pub enable_data_value_match: 8;
+        bool: pub cycle_match, pub enable_cycle_match: 7;
+        bool: pub emit_range, pub set_emit_range: 5;
+        ComparatorFunction: pub function, pub set_function: 3, 0;
+    }
+}
+
+#[repr(C)]
+pub struct Comparator {
+    pub comp: RegisterCell<u32>,
+    pub mask: RegisterCell<MaskRegister>,
+    pub function: RegisterCell<FunctionRegister>,
+    reserved: ReservedCell<u32>,
+}
+
+/// Data Watchpoint and Trace unit
+/// http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.ddi0439b/BABJFFGJ.html
+#[repr(C)]
+pub struct DataWatchpointAndTrace {
+    pub ctrl: RegisterCell<DWTCRegister>,
+    /// Cycle Count Register
+    pub cyccnt: RegisterCell<u32>,
+    /// CPI Count Register
+    pub cpicnt: RegisterCell<u32>,
+    /// Exception Overhead Count Register
+    pub exccnt: RegisterCell<u32>,
+    /// Sleep Count Register
+    pub sleepcnt: RegisterCell<u32>,
+    /// LSU Count Register
+    pub lsucnt: RegisterCell<u32>,
+    /// Folded-instruction Count Register
+    pub foldcnt: RegisterCell<u32>,
+    /// Program Counter Sample Register
+    pub pcsr: RoRegisterCell<u32>,
+    /// The Cortex-M4 implements 4 comparators, `ctrl.comparator_count()` tells how many are
+    /// actually available.
+    pub comparators: [Comparator; 4],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatchpointError {
+    /// The comparator does not exist on this core.
+    InvalidComparator,
+    /// The watched range must be a power of 2 in size and aligned to its size.
+    MisalignedRange,
+    /// Only `WatchpointOn*` functions can be used as watchpoints.
+    InvalidFunction,
+}
+
+/// Computes the value of the MASK register for a `size` bytes range starting at `address`.
+pub fn watchpoint_mask(address: u32, size: u32) -> Result<u8, WatchpointError> {
+    // the comparator supports ignoring up to 15 bits on the cortex-m4.
+    if !size.is_power_of_two() || size > (1 << 15) || address & (size - 1) != 0 {
+        return Err(WatchpointError::MisalignedRange);
+    }
+    Ok(size.trailing_zeros() as u8)
+}
+
+impl DataWatchpointAndTrace {
+    /// Resets and starts the cycle counter.
+    /// The DWT unit must have been enabled with `DebugControlBlock::demcr::enable_trace`, see
+    /// `enable_cycle_counter`.
+    pub fn start_cycle_counter(&mut self) {
+        self.cyccnt.set(0);
+        self.ctrl.get_mut().enable_cycle_counter(true);
+    }
+
+    pub fn stop_cycle_counter(&mut self) {
+        self.ctrl.get_mut().enable_cycle_counter(false);
+    }
+
+    #[inline]
+    pub fn cycle_count(&self) -> u32 {
+        self.cyccnt.get()
+    }
+
+    /// Sets a watchpoint on a `size` bytes range starting at `address`.
+    /// Without a debugger, a hit only raises the DebugMonitor exception once the DWT unit and the
+    /// monitor have been enabled in `DebugControlBlock::demcr`, see `enable_watchpoints`.
+    pub fn set_watchpoint(
+        &mut self,
+        n: usize,
+        address: u32,
+        size: u32,
+        function: ComparatorFunction,
+    ) -> Result<(), WatchpointError> {
+        match function {
+            ComparatorFunction::WatchpointOnPc
+            | ComparatorFunction::WatchpointOnRead
+            | ComparatorFunction::WatchpointOnWrite
+            | ComparatorFunction::WatchpointOnAccess => {}
+            _ => return Err(WatchpointError::InvalidFunction),
+        }
+        if n >= usize::from(self.ctrl.get().comparator_count()) || n >= self.comparators.len() {
+            return Err(WatchpointError::InvalidComparator);
+        }
+        let mask = watchpoint_mask(address, size)?;
+
+        let comparator = &mut self.comparators[n];
+        comparator
+            .function
+            .get_mut()
+            .set_function(ComparatorFunction::Disabled);
+        comparator.comp.set(address);
+        comparator.mask.get_mut().set_ignored_bits(mask);
+
+        let mut function_register = comparator.function.get_mut();
+        function_register.enable_data_value_match(false);
+        function_register.enable_cycle_match(false);
+        function_register.set_emit_range(false);
+        function_register.set_function(function);
+        Ok(())
+    }
+
+    pub fn clear_watchpoint(&mut self, n: usize) {
+        if n < self.comparators.len() {
+            self.comparators[n]
+                .function
+                .get_mut()
+                .set_function(ComparatorFunction::Disabled);
+        }
+    }
+
+    /// Returns the index of the first comparator that matched since the last call.
+    /// Reading the function registers clears their `matched` flag.
+    pub fn matched_comparator(&self) -> Option<usize> {
+        let count = usize::from(self.ctrl.get().comparator_count()).min(self.comparators.len());
+        self.comparators[..count]
+            .iter()
+            .position(|c| c.function.get().matched())
+    }
+}
+
+/// Enables the trace blocks and starts the cycle counter.
+pub fn enable_cycle_counter() {
+    unsafe {
+        ::ppb::DCB.demcr.get_mut().enable_trace(true);
+        ::ppb::DWT.start_cycle_counter();
+    }
+}
+
+/// Enables the trace blocks and the DebugMonitor exception taken on watchpoint hits.
+pub fn enable_watchpoints() {
+    unsafe {
+        let mut demcr = ::ppb::DCB.demcr.get_mut();
+        demcr.enable_trace(true);
+        demcr.enable_monitor(true);
+    }
+}
+
+/// Measures code execution time in core clock cycles.
+/// The cycle counter wraps every 2^32 cycles (~35s at 120MHz).
+#[derive(Clone, Copy, Debug)]
+pub struct Stopwatch {
+    start: u32,
+}
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            start: unsafe { ::ppb::DWT.cycle_count() },
+        }
+    }
+
+    pub fn elapsed(self) -> u32 {
+        unsafe { ::ppb::DWT.cycle_count() }.wrapping_sub(self.start)
+    }
+}
+
+/// Runs `f` and returns its result along with the number of cycles it took.
+pub fn measure<F, R>(f: F) -> (R, u32)
+where
+    F: FnOnce() -> R,
+{
+    let sw = Stopwatch::start();
+    let r = f();
+    (r, sw.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchpoint_mask() {
+        assert_eq!(Ok(0), watchpoint_mask(0x2000_0001, 1));
+        assert_eq!(Ok(2), watchpoint_mask(0x2000_0004, 4));
+        assert_eq!(Ok(8), watchpoint_mask(0x2000_0100, 256));
+        assert_eq!(
+            Err(WatchpointError::MisalignedRange),
+            watchpoint_mask(0x2000_0002, 4)
+        );
+        assert_eq!(
+            Err(WatchpointError::MisalignedRange),
+            watchpoint_mask(0x2000_0000, 12)
+        );
+    }
+
+    #[test]
+    fn test_function_register() {
+        let mut f = FunctionRegister(0x0100_0000);
+        assert!(f.matched());
+        f.set_function(ComparatorFunction::WatchpointOnWrite);
+        assert_eq!(0x0100_0006, f.0);
+        assert_eq!(ComparatorFunction::WatchpointOnWrite, f.function());
+    }
+}
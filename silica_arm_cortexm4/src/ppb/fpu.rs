@@ -1,6 +1,6 @@
 use core::convert::{Into, TryFrom, TryInto};
 use core::fmt;
-use silica::register::{Field, RegisterCell};
+use silica::register::{Field, RegisterCell, RoRegisterCell};
 
 #[derive(Debug)]
 pub struct TryIntoCoProcessorAccessError(());
@@ -73,10 +73,50 @@ register! {
     }
 }
 
+register! {
+    @impl_debug;
+    /// Media and VFP Feature Register 0
+    /// Reads as zero when no FPU is implemented.
+    #[derive(Copy, Clone)]
+    pub struct MVFRegister0(u32) {
+        u8: pub rounding_modes, _: 31, 28;
+        u8: pub short_vectors, _: 27, 24;
+        u8: pub square_root, _: 23, 20;
+        u8: pub divide, _: 19, 16;
+        u8: pub exception_trapping, _: 15, 12;
+        /// 0 = not supported, 2 = supported.
+        u8: pub double_precision, _: 11, 8;
+        /// 0 = not supported, 2 = supported.
+        u8: pub single_precision, _: 7, 4;
+        /// 1 = 16 x 64-bit registers.
+        u8: pub register_bank, _: 3, 0;
+    }
+}
+impl From<u32> for MVFRegister0 {
+    fn from(v: u32) -> MVFRegister0 {
+        MVFRegister0(v)
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Media and VFP Feature Register 1
+    #[derive(Copy, Clone)]
+    pub struct MVFRegister1(u32) {
+        u8: pub fused_mac, _: 31, 28;
+        u8: pub half_precision, _: 27, 24;
+        u8: pub default_nan_mode, _: 7, 4;
+        u8: pub flush_to_zero_mode, _: 3, 0;
+    }
+}
+
+/// Floating Point Unit
+/// The FPSCR is not memory mapped, see `FPSCRegister`.
 #[repr(C)]
 pub struct FloatingPointUnit {
     pub ccr: RegisterCell<FPCCRegister>,
     pub car: RegisterCell<FPCARegister>,
-    pub scr: RegisterCell<FPSCRegister>,
     pub dscr: RegisterCell<FPDSCRegister>,
+    pub mvfr0: RoRegisterCell<MVFRegister0>,
+    pub mvfr1: RoRegisterCell<MVFRegister1>,
 }
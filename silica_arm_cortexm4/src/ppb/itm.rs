@@ -0,0 +1,157 @@
+//! Instrumentation Trace Macrocell
+use core::convert::{Into, TryInto};
+use core::fmt;
+use core::ptr;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+/// Key to write to the Lock Access Register to unlock the ITM registers.
+pub const UNLOCK_KEY: u32 = 0xC5AC_CE55;
+
+/// A stimulus port.
+/// The packet size depends on the width of the access, hence the dedicated accessors.
+#[repr(C)]
+pub struct StimulusPort(u32);
+impl StimulusPort {
+    /// True when the port can accept a new packet.
+    #[inline]
+    pub fn is_fifo_ready(&self) -> bool {
+        unsafe { ptr::read_volatile(&self.0 as *const u32) & 1 == 1 }
+    }
+    #[inline]
+    pub fn write_u8(&mut self, v: u8) {
+        unsafe { ptr::write_volatile(&mut self.0 as *mut u32 as *mut u8, v) }
+    }
+    #[inline]
+    pub fn write_u16(&mut self, v: u16) {
+        unsafe { ptr::write_volatile(&mut self.0 as *mut u32 as *mut u16, v) }
+    }
+    #[inline]
+    pub fn write_u32(&mut self, v: u32) {
+        unsafe { ptr::write_volatile(&mut self.0 as *mut u32, v) }
+    }
+
+    /// Blocks until the FIFO is ready then sends `bytes`, packing them in words when possible.
+    pub fn write_all(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            while !self.is_fifo_ready() {}
+            match chunk.len() {
+                4 => self.write_u32(
+                    u32::from(chunk[0])
+                        | u32::from(chunk[1]) << 8
+                        | u32::from(chunk[2]) << 16
+                        | u32::from(chunk[3]) << 24,
+                ),
+                2 => self.write_u16(u16::from(chunk[0]) | u16::from(chunk[1]) << 8),
+                _ => {
+                    for (i, b) in chunk.iter().enumerate() {
+                        if i != 0 {
+                            while !self.is_fifo_ready() {}
+                        }
+                        self.write_u8(*b);
+                    }
+                }
+            }
+        }
+    }
+}
+impl fmt::Write for StimulusPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes());
+        Ok(())
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Trace Control Register
+    #[derive(Copy, Clone)]
+    pub struct TCRegister(u32) {
+        bool: pub is_busy, _: 23;
+        /// Identifier of the trace stream, must be non-zero when the ITM is enabled.
+        u8: pub trace_bus_id, pub set_trace_bus_id: 22, 16;
+        u8: pub global_timestamp_frequency, pub set_global_timestamp_frequency: 11, 10;
+        u8: pub timestamp_prescaler, pub set_timestamp_prescaler: 9, 8;
+        /// Clocks the local timestamp counter with the SWO clock instead of the core clock.
+        bool: pub swo_timestamp_clock, pub use_swo_timestamp_clock: 4;
+        /// Forwards DWT packets to the ITM.
+        bool: pub dwt_forwarding_enabled, pub enable_dwt_forwarding: 3;
+        bool: pub sync_packets_enabled, pub enable_sync_packets: 2;
+        bool: pub local_timestamps_enabled, pub enable_local_timestamps: 1;
+        bool: pub itm_enabled, pub enable_itm: 0;
+    }
+}
+
+/// Instrumentation Trace Macrocell
+/// http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.ddi0439b/BIIFBHIF.html
+#[repr(C)]
+pub struct InstrumentationTraceMacrocell {
+    pub stim: [StimulusPort; 256],
+    reserved0: ReservedCell<[u32; 640]>,
+    /// Trace Enable Register, one bit per stimulus port.
+    pub ter: RegisterCell<u32>,
+    reserved1: ReservedCell<[u32; 15]>,
+    /// Trace Privilege Register, each bit makes a group of 8 ports privileged access only.
+    pub tpr: RegisterCell<u32>,
+    reserved2: ReservedCell<[u32; 15]>,
+    pub tcr: RegisterCell<TCRegister>,
+    reserved3: ReservedCell<[u32; 75]>,
+    /// Lock Access Register
+    pub lar: RegisterCell<u32>,
+    /// Lock Status Register
+    pub lsr: RoRegisterCell<u32>,
+}
+
+impl InstrumentationTraceMacrocell {
+    /// Unlocks and enables the ITM with the stimulus ports selected by `port_mask`.
+    /// The DWT/ITM blocks must have been enabled through `DEMCRegister::enable_trace`.
+    pub fn enable(&mut self, port_mask: u32) {
+        self.lar.set(UNLOCK_KEY);
+        {
+            let mut tcr = self.tcr.get_mut();
+            tcr.set_trace_bus_id(1);
+            tcr.enable_sync_packets(true);
+            tcr.enable_itm(true);
+        }
+        self.tpr.set(0);
+        self.ter.set(port_mask);
+    }
+
+    pub fn disable(&mut self) {
+        self.tcr.get_mut().enable_itm(false);
+        while self.tcr.get().is_busy() {}
+    }
+
+    pub fn is_port_enabled(&self, port: usize) -> bool {
+        port < 32 && self.tcr.get().itm_enabled() && (self.ter.get() & (1 << port)) != 0
+    }
+
+    /// Sends `s` on a stimulus port. The message is dropped if the port is disabled so that
+    /// tracing does not block when no debugger is listening.
+    pub fn write_str(&mut self, port: usize, s: &str) {
+        if self.is_port_enabled(port) {
+            self.stim[port].write_all(s.as_bytes());
+        }
+    }
+}
+
+/// Prints to the ITM stimulus port 0.
+#[macro_export]
+macro_rules! iprint {
+    ($($arg:tt)*) => {
+        #[allow(unused_unsafe)]
+        unsafe {
+            use core::fmt::Write;
+            if $crate::ppb::ITM.is_port_enabled(0) {
+                let _ = write!($crate::ppb::ITM.stim[0], $($arg)*);
+            }
+        }
+    };
+}
+
+/// Prints to the ITM stimulus port 0, with a newline.
+#[macro_export]
+macro_rules! iprintln {
+    () => { iprint!("\n") };
+    ($fmt:expr) => { iprint!(concat!($fmt, "\n")) };
+    ($fmt:expr, $($arg:tt)*) => { iprint!(concat!($fmt, "\n"), $($arg)*) };
+}
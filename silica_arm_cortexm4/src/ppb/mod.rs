@@ -2,10 +2,16 @@
 use core::fmt;
 use silica::register::{Field, RegisterCell};
 
+pub mod dcb;
+pub mod dwt;
 pub mod fpu;
+#[macro_use]
+pub mod itm;
 pub mod mpu;
+pub mod nvic;
 pub mod scb;
 pub mod systick;
+pub mod tpiu;
 
 register! {
     @impl_debug;
@@ -27,7 +33,12 @@ extern "C" {
     pub static mut SYSTICK: systick::SystickBlock;
     pub static FR: scb::FeatureRegisters;
     pub static mut MPU: mpu::MemoryProtectionUnit;
+    pub static mut NVIC: nvic::NestedVectoredInterruptController;
     pub static mut FPU: fpu::FloatingPointUnit;
+    pub static mut DCB: dcb::DebugControlBlock;
+    pub static mut DWT: dwt::DataWatchpointAndTrace;
+    pub static mut ITM: itm::InstrumentationTraceMacrocell;
+    pub static mut TPIU: tpiu::TracePortInterfaceUnit;
 }
 
 #[cfg(test)]
@@ -1,7 +1,9 @@
-use core::convert::{Into, TryInto};
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
 use silica::register::{Field, RegisterCell};
 
 register! {
+    #[derive(Copy, Clone)]
     pub struct TypeRegister(u32) {
         bool: pub separate, _: 0;
         u8: pub data_regions, _: 15, 8;
@@ -9,7 +11,14 @@ register! {
     }
 }
 
+impl From<u32> for TypeRegister {
+    fn from(v: u32) -> TypeRegister {
+        TypeRegister(v)
+    }
+}
+
 register! {
+    #[derive(Copy, Clone)]
     pub struct ControlRegister(u32) {
         bool: pub enabled, pub enable: 0;
         bool: pub hf_nmi_enabled, pub enable_in_hf_and_nmi: 1;
@@ -18,12 +27,14 @@ register! {
 }
 
 register! {
+    #[derive(Copy, Clone)]
     pub struct RegionNumberRegister(u32) {
         u8: pub region, pub set_region: 7, 0;
     }
 }
 
 register! {
+    #[derive(Copy, Clone)]
     pub struct RegionBaseAddressRegister(u32) {
         /// The ADDR field is bits[31:N] of the MPU_RBAR. The region size, as specified by the SIZE field in the MPU_RASR, defines the value of N:
         ///    N = Log2(Region size in bytes),
@@ -35,15 +46,173 @@ register! {
     }
 }
 
+#[derive(Debug)]
+pub struct TryIntoAccessPermissionError(());
+
+/// Data access permissions of a region, for privileged and unprivileged code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessPermission {
+    NoAccess,
+    PrivilegedOnly,
+    /// Privileged read/write, unprivileged read only.
+    UnprivilegedReadOnly,
+    FullAccess,
+    PrivilegedReadOnly,
+    ReadOnly,
+}
+impl TryFrom<u32> for AccessPermission {
+    type Error = TryIntoAccessPermissionError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(AccessPermission::NoAccess),
+            1 => Ok(AccessPermission::PrivilegedOnly),
+            2 => Ok(AccessPermission::UnprivilegedReadOnly),
+            3 => Ok(AccessPermission::FullAccess),
+            5 => Ok(AccessPermission::PrivilegedReadOnly),
+            6 | 7 => Ok(AccessPermission::ReadOnly),
+            _ => Err(TryIntoAccessPermissionError(())),
+        }
+    }
+}
+impl From<AccessPermission> for u32 {
+    fn from(v: AccessPermission) -> u32 {
+        match v {
+            AccessPermission::NoAccess => 0,
+            AccessPermission::PrivilegedOnly => 1,
+            AccessPermission::UnprivilegedReadOnly => 2,
+            AccessPermission::FullAccess => 3,
+            AccessPermission::PrivilegedReadOnly => 5,
+            AccessPermission::ReadOnly => 6,
+        }
+    }
+}
+
 register! {
-    /// TODO: This register requires some special mechanics to validate the values before writing
-    /// to memory as some fields aren't available depending on others value.
-    /// e.g.: SubRegionDisable is only available if Size > 128bytes
+    @impl_debug;
+    /// Region Attribute and Size Register
+    ///
+    /// Some fields depend on others (e.g. the sub-regions need a size of at least 256 bytes), use
+    /// `Region` to build consistent values.
+    #[derive(Copy, Clone)]
     pub struct RegionAttributeAndSizeRegister(u32) {
-        bool: pub enabled, pub enable: 0;
+        bool: pub execute_never, pub set_execute_never: 28;
+        AccessPermission: pub access_permission, pub set_access_permission: 26, 24;
+        u8: pub type_extension, pub set_type_extension: 21, 19;
+        bool: pub shareable, pub set_shareable: 18;
+        bool: pub cacheable, pub set_cacheable: 17;
+        bool: pub bufferable, pub set_bufferable: 16;
+        /// One bit per eighth of the region.
+        u8: pub disabled_subregions, pub disable_subregions: 15, 8;
+        /// The region spans 2^(size+1) bytes.
         u8: pub size, pub set_size: 5, 1;
-        // missing fields here !
+        bool: pub enabled, pub enable: 0;
+    }
+}
+
+/// Memory type and cache policy of a region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryType {
+    StronglyOrdered,
+    /// Peripherals.
+    Device,
+    NormalWriteThrough,
+    NormalWriteBack,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionError {
+    /// The size must be a power of two of at least 32 bytes.
+    InvalidSize,
+    /// The base address must be aligned to the size.
+    Misaligned,
+    /// Sub-regions are only available on regions of 256 bytes or more.
+    SubregionsUnavailable,
+}
+
+/// Description of an MPU region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    base: u32,
+    size_log2: u8,
+    access: AccessPermission,
+    execute_never: bool,
+    memory: MemoryType,
+    disabled_subregions: u8,
+}
+impl Region {
+    /// A region of `size` bytes at `base`, accessible by privileged code only, executable and
+    /// normal write-back memory.
+    pub fn new(base: u32, size: u32) -> Result<Region, RegionError> {
+        if size < 32 || !size.is_power_of_two() {
+            return Err(RegionError::InvalidSize);
+        }
+        if base & (size - 1) != 0 {
+            return Err(RegionError::Misaligned);
+        }
+        Ok(Region {
+            base,
+            size_log2: size.trailing_zeros() as u8,
+            access: AccessPermission::PrivilegedOnly,
+            execute_never: false,
+            memory: MemoryType::NormalWriteBack,
+            disabled_subregions: 0,
+        })
+    }
+
+    pub fn access(mut self, access: AccessPermission) -> Region {
+        self.access = access;
+        self
+    }
+    pub fn execute_never(mut self) -> Region {
+        self.execute_never = true;
+        self
+    }
+    pub fn memory(mut self, memory: MemoryType) -> Region {
+        self.memory = memory;
+        self
+    }
+    /// Excludes the eighths of the region whose bit is set in `mask`.
+    pub fn disable_subregions(mut self, mask: u8) -> Result<Region, RegionError> {
+        if self.size_log2 < 8 {
+            return Err(RegionError::SubregionsUnavailable);
+        }
+        self.disabled_subregions = mask;
+        Ok(self)
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+    pub fn size(&self) -> u32 {
+        1 << self.size_log2
+    }
 
+    /// Base address register value selecting the region `number`.
+    pub fn rbar(&self, number: u8) -> RegionBaseAddressRegister {
+        let mut rbar = RegionBaseAddressRegister(0);
+        rbar.set_addr(self.base >> 5);
+        rbar.valid(true);
+        rbar.set_region(number);
+        rbar
+    }
+
+    pub fn rasr(&self) -> RegionAttributeAndSizeRegister {
+        let mut rasr = RegionAttributeAndSizeRegister(0);
+        rasr.set_execute_never(self.execute_never);
+        rasr.set_access_permission(self.access);
+        let (cacheable, bufferable) = match self.memory {
+            MemoryType::StronglyOrdered => (false, false),
+            MemoryType::Device => (false, true),
+            MemoryType::NormalWriteThrough => (true, false),
+            MemoryType::NormalWriteBack => (true, true),
+        };
+        rasr.set_shareable(self.memory == MemoryType::Device);
+        rasr.set_cacheable(cacheable);
+        rasr.set_bufferable(bufferable);
+        rasr.disable_subregions(self.disabled_subregions);
+        rasr.set_size(self.size_log2 - 1);
+        rasr.enable(true);
+        rasr
     }
 }
 
@@ -61,3 +230,72 @@ pub struct MemoryProtectionUnit {
     pub rbar_a3: RegisterCell<RegionBaseAddressRegister>,
     pub rsar_a3: RegisterCell<RegionAttributeAndSizeRegister>,
 }
+
+impl MemoryProtectionUnit {
+    /// Number of regions supported, 0 if there is no MPU.
+    pub fn region_count(&self) -> u8 {
+        self.mpu_type.get().data_regions()
+    }
+
+    pub fn set_region(&mut self, number: u8, region: &Region) {
+        self.rbar.set(region.rbar(number));
+        self.rsar.set(region.rasr());
+    }
+
+    pub fn clear_region(&mut self, number: u8) {
+        self.rnr.get_mut().set_region(number);
+        self.rsar.set(RegionAttributeAndSizeRegister(0));
+    }
+
+    /// Enables the MPU. With `privileged_default_map`, privileged code may access the memory not
+    /// covered by any region as if the MPU was off.
+    pub fn enable(&mut self, privileged_default_map: bool) {
+        {
+            let mut ctrl = self.control.get_mut();
+            ctrl.enable_default_map_in_privileged(privileged_default_map);
+            ctrl.enable(true);
+        }
+        barrier();
+    }
+
+    pub fn disable(&mut self) {
+        barrier();
+        self.control.get_mut().enable(false);
+    }
+}
+
+/// Makes sure the new settings apply to the next instructions.
+#[inline]
+fn barrier() {
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        asm!("dsb
+              isb" : : : "memory" : "volatile");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region() {
+        assert_eq!(Err(RegionError::InvalidSize), Region::new(0, 48));
+        assert_eq!(Err(RegionError::InvalidSize), Region::new(0, 16));
+        assert_eq!(
+            Err(RegionError::Misaligned),
+            Region::new(0x2000_0100, 0x400)
+        );
+
+        let r = Region::new(0x2000_0400, 0x400)
+            .unwrap()
+            .access(AccessPermission::FullAccess)
+            .execute_never();
+        assert_eq!(0x2000_0415, r.rbar(5).0);
+        assert_eq!(0x1303_0013, r.rasr().0);
+        assert_eq!(
+            Err(RegionError::SubregionsUnavailable),
+            Region::new(0, 128).unwrap().disable_subregions(1)
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Nested Vectored Interrupt Controller
+//!
+//! Interrupts are identified by their number in the vendor's table (the exception number minus
+//! 16).
+use silica::register::{RegisterCell, ReservedCell, RoRegisterCell};
+
+#[repr(C)]
+pub struct NestedVectoredInterruptController {
+    /// Interrupt Set-Enable Registers
+    pub iser: [RegisterCell<u32>; 8],
+    reserved0: ReservedCell<[u32; 24]>,
+    /// Interrupt Clear-Enable Registers
+    pub icer: [RegisterCell<u32>; 8],
+    reserved1: ReservedCell<[u32; 24]>,
+    /// Interrupt Set-Pending Registers
+    pub ispr: [RegisterCell<u32>; 8],
+    reserved2: ReservedCell<[u32; 24]>,
+    /// Interrupt Clear-Pending Registers
+    pub icpr: [RegisterCell<u32>; 8],
+    reserved3: ReservedCell<[u32; 24]>,
+    /// Interrupt Active Bit Registers
+    pub iabr: [RoRegisterCell<u32>; 8],
+    reserved4: ReservedCell<[u32; 56]>,
+    /// Interrupt Priority Registers, one byte per interrupt.
+    pub ipr: [RegisterCell<u8>; 240],
+}
+
+impl NestedVectoredInterruptController {
+    fn split(irq: u8) -> (usize, u32) {
+        (usize::from(irq / 32), 1 << (irq % 32))
+    }
+
+    pub fn enable(&mut self, irq: u8) {
+        let (i, mask) = Self::split(irq);
+        self.iser[i].set(mask);
+    }
+    pub fn disable(&mut self, irq: u8) {
+        let (i, mask) = Self::split(irq);
+        self.icer[i].set(mask);
+    }
+    pub fn is_enabled(&self, irq: u8) -> bool {
+        let (i, mask) = Self::split(irq);
+        self.iser[i].get() & mask != 0
+    }
+
+    pub fn pend(&mut self, irq: u8) {
+        let (i, mask) = Self::split(irq);
+        self.ispr[i].set(mask);
+    }
+    pub fn unpend(&mut self, irq: u8) {
+        let (i, mask) = Self::split(irq);
+        self.icpr[i].set(mask);
+    }
+    pub fn is_pending(&self, irq: u8) -> bool {
+        let (i, mask) = Self::split(irq);
+        self.ispr[i].get() & mask != 0
+    }
+    pub fn is_active(&self, irq: u8) -> bool {
+        let (i, mask) = Self::split(irq);
+        self.iabr[i].get() & mask != 0
+    }
+
+    /// Lower values have a higher priority. Only the implemented most significant bits are kept
+    /// (4 on the SAM4E).
+    pub fn set_priority(&mut self, irq: u8, priority: u8) {
+        self.ipr[usize::from(irq)].set(priority);
+    }
+    pub fn priority(&self, irq: u8) -> u8 {
+        self.ipr[usize::from(irq)].get()
+    }
+}
@@ -1,7 +1,7 @@
 use core::convert::{Into, TryFrom, TryInto};
 use core::fmt;
 use core::num::TryFromIntError;
-use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use silica::register::{Field, RegisterCell, RoRegisterCell};
 
 #[derive(Debug, Copy, Clone)]
 pub struct InvalidEndiannessError(());
@@ -33,6 +33,11 @@ register! {
         u8: pub revision, _: 3, 0;
     }
 }
+impl From<u32> for CPUIDRegister {
+    fn from(v: u32) -> CPUIDRegister {
+        CPUIDRegister(v)
+    }
+}
 
 register! {
     @impl_debug;
@@ -196,7 +201,7 @@ register! {
     /// System Control Register
     #[derive(Copy, Clone)]
     pub struct SCRegister(u32) {
-        bool: pub sleep_on_exit, _: 1;
+        bool: pub sleep_on_exit, pub set_sleep_on_exit: 1;
         SleepMode: pub sleep_mode,
                    pub set_sleep_mode: 2;
         bool: pub event_sent_on_pending_bit,
@@ -242,6 +247,12 @@ register! {
         // UFSRegister: pub get_ufsr, _: 31, 16;
     }
 }
+impl CFSRegister {
+    /// Keeps the MemManage flags only, clearing them must not discard the bus and usage faults.
+    pub fn mmsr_flags(self) -> CFSRegister {
+        CFSRegister(self.0 & 0xFF)
+    }
+}
 register! {
     @impl_debug;
     /// System Handler Priority Register 1
@@ -317,6 +328,25 @@ impl HFSRegister {
     }
 }
 
+register! {
+    @impl_debug;
+    /// Debug Fault Status Register
+    /// Bits are sticky and cleared by writing 1 to them.
+    #[derive(Copy, Clone)]
+    pub struct DFSRegister(u32) {
+        /// EDBGRQ was asserted.
+        bool: pub external, pub clear_external: 4;
+        /// A vector catch was triggered.
+        bool: pub vector_catch, pub clear_vector_catch: 3;
+        /// A DWT watchpoint matched.
+        bool: pub dwt_trap, pub clear_dwt_trap: 2;
+        /// A BKPT instruction was executed or a breakpoint matched.
+        bool: pub breakpoint, pub clear_breakpoint: 1;
+        /// A halt request or step was handled.
+        bool: pub halted, pub clear_halted: 0;
+    }
+}
+
 /// System Control Block
 /// http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dui0553a/CIHFDJCA.html
 #[repr(C)]
@@ -332,22 +362,79 @@ pub struct SystemControlBlock {
     pub shp2: RegisterCell<SHPRegister2>,
     pub shp3: RegisterCell<SHPRegister3>,
     pub shcsr: RegisterCell<SHCSRegister>,
-    pub cfsr: RoRegisterCell<CFSRegister>,
+    pub cfsr: RegisterCell<CFSRegister>,
     pub hfsr: RegisterCell<HFSRegister>,
-    reserved1: ReservedCell<u32>,
+    pub dfsr: RegisterCell<DFSRegister>,
     pub mmar: RoRegisterCell<u32>,
     pub bfar: RoRegisterCell<u32>,
     /// AFSR is implementation defined
     pub afsr: RegisterCell<u32>,
 }
 
+register! {
+    @impl_debug;
+    /// Debug Feature Register 0
+    #[derive(Copy, Clone)]
+    pub struct DFRegister(u32) {
+        /// 0 = not supported, 2 = ARMv7-M debug architecture (with memory mapped access).
+        u8: pub m_profile_debug_model, _: 23, 20;
+    }
+}
+impl DFRegister {
+    pub fn has_debug_support(self) -> bool {
+        self.m_profile_debug_model() != 0
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Instruction Set Attribute Register 1
+    #[derive(Copy, Clone)]
+    pub struct ISARegister1(u32) {
+        u8: pub interwork_instrs, _: 27, 24;
+        u8: pub immediate_instrs, _: 23, 20;
+        u8: pub if_then_instrs, _: 19, 16;
+        /// 1 = SXTB, SXTH, UXTB, UXTH
+        /// 2 = adds the SXTB16, SXTAB16, UXTB16... instructions of the DSP extension.
+        u8: pub extend_instrs, _: 15, 12;
+    }
+}
+impl From<u32> for ISARegister1 {
+    fn from(v: u32) -> ISARegister1 {
+        ISARegister1(v)
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Instruction Set Attribute Register 3
+    #[derive(Copy, Clone)]
+    pub struct ISARegister3(u32) {
+        u8: pub true_nop_instrs, _: 27, 24;
+        u8: pub thumb_copy_instrs, _: 23, 20;
+        u8: pub table_branch_instrs, _: 19, 16;
+        u8: pub synch_prim_instrs, _: 15, 12;
+        u8: pub svc_instrs, _: 11, 8;
+        /// 1 = SSAT, USAT and the Q bit
+        /// 3 = adds the SIMD instructions of the DSP extension.
+        u8: pub simd_instrs, _: 7, 4;
+        /// 1 = QADD, QDADD, QDSUB, QSUB
+        u8: pub saturate_instrs, _: 3, 0;
+    }
+}
+impl From<u32> for ISARegister3 {
+    fn from(v: u32) -> ISARegister3 {
+        ISARegister3(v)
+    }
+}
+
 /// Feature registers
 #[repr(C)]
 pub struct FeatureRegisters {
     /// Processor Feature Register
     pub pfr: [RoRegisterCell<u32>; 2],
     /// Debug Feature Register
-    pub dfr: RoRegisterCell<u32>,
+    pub dfr: RoRegisterCell<DFRegister>,
     /// Auxiliary Feature Register
     pub adr: RoRegisterCell<u32>,
     /// Memory model Feature Register
@@ -355,3 +442,11 @@ pub struct FeatureRegisters {
     /// Instruction Set Feature Register
     pub isar: [RoRegisterCell<u32>; 5],
 }
+impl FeatureRegisters {
+    pub fn isar1(&self) -> ISARegister1 {
+        self.isar[1].get().into()
+    }
+    pub fn isar3(&self) -> ISARegister3 {
+        self.isar[3].get().into()
+    }
+}
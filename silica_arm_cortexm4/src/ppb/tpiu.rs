@@ -0,0 +1,164 @@
+//! Trace Port Interface Unit
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+#[derive(Debug)]
+pub struct TryIntoPinProtocolError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PinProtocol {
+    /// Synchronous parallel trace port.
+    Parallel,
+    /// Serial Wire Output with Manchester encoding.
+    SwoManchester,
+    /// Serial Wire Output with NRZ (UART like) encoding.
+    SwoNrz,
+}
+impl TryFrom<u32> for PinProtocol {
+    type Error = TryIntoPinProtocolError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(PinProtocol::Parallel),
+            1 => Ok(PinProtocol::SwoManchester),
+            2 => Ok(PinProtocol::SwoNrz),
+            _ => Err(TryIntoPinProtocolError(())),
+        }
+    }
+}
+impl From<PinProtocol> for u32 {
+    fn from(v: PinProtocol) -> u32 {
+        match v {
+            PinProtocol::Parallel => 0,
+            PinProtocol::SwoManchester => 1,
+            PinProtocol::SwoNrz => 2,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Asynchronous Clock Prescaler Register
+    #[derive(Copy, Clone)]
+    pub struct ACPRegister(u32) {
+        /// SWO baudrate = TRACECLKIN / (prescaler + 1)
+        u16: pub prescaler, pub set_prescaler: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Selected Pin Protocol Register
+    #[derive(Copy, Clone)]
+    pub struct SPPRegister(u32) {
+        PinProtocol: pub protocol, pub set_protocol: 1, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Formatter and Flush Control Register
+    #[derive(Copy, Clone)]
+    pub struct FFCRegister(u32) {
+        bool: pub trig_in, pub set_trig_in: 8;
+        /// The formatter must be bypassed when using the SWO.
+        bool: pub continuous_formatting, pub enable_continuous_formatting: 1;
+    }
+}
+
+/// Trace Port Interface Unit
+/// http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.ddi0439b/BIHIJEII.html
+#[repr(C)]
+pub struct TracePortInterfaceUnit {
+    /// Supported Parallel Port Size Register
+    pub sspsr: RoRegisterCell<u32>,
+    /// Current Parallel Port Size Register
+    pub cspsr: RegisterCell<u32>,
+    reserved0: ReservedCell<[u32; 2]>,
+    pub acpr: RegisterCell<ACPRegister>,
+    reserved1: ReservedCell<[u32; 55]>,
+    pub sppr: RegisterCell<SPPRegister>,
+    reserved2: ReservedCell<[u32; 131]>,
+    /// Formatter and Flush Status Register
+    pub ffsr: RoRegisterCell<u32>,
+    pub ffcr: RegisterCell<FFCRegister>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SwoError {
+    /// The requested baudrate cannot be reached within 3% from the trace clock.
+    UnreachableBaudrate,
+}
+
+/// Computes the ACPR prescaler for a `baudrate` SWO out of a `trace_clock_hz` TRACECLKIN.
+pub fn swo_prescaler(trace_clock_hz: u32, baudrate: u32) -> Result<u16, SwoError> {
+    if baudrate == 0 || baudrate > trace_clock_hz {
+        return Err(SwoError::UnreachableBaudrate);
+    }
+    let divisor = (trace_clock_hz + baudrate / 2) / baudrate;
+    if divisor == 0 || divisor > 0x1_0000 {
+        return Err(SwoError::UnreachableBaudrate);
+    }
+    let actual = trace_clock_hz / divisor;
+    let error = if actual > baudrate {
+        actual - baudrate
+    } else {
+        baudrate - actual
+    };
+    if u64::from(error) * 100 > u64::from(baudrate) * 3 {
+        return Err(SwoError::UnreachableBaudrate);
+    }
+    Ok((divisor - 1) as u16)
+}
+
+impl TracePortInterfaceUnit {
+    /// Configures the TPIU to output the trace stream on the SWO pin.
+    /// The chip specific pin multiplexing of TRACESWO must be done separately.
+    pub fn setup_swo(
+        &mut self,
+        trace_clock_hz: u32,
+        baudrate: u32,
+        protocol: PinProtocol,
+    ) -> Result<(), SwoError> {
+        let prescaler = swo_prescaler(trace_clock_hz, baudrate)?;
+        self.cspsr.set(1);
+        self.acpr.get_mut().set_prescaler(prescaler);
+        self.sppr.get_mut().set_protocol(protocol);
+        let mut ffcr = self.ffcr.get_mut();
+        ffcr.set_trig_in(true);
+        ffcr.enable_continuous_formatting(false);
+        Ok(())
+    }
+}
+
+/// Enables the trace blocks and routes the ITM `port_mask` stimulus ports to the SWO pin using
+/// NRZ encoding.
+pub fn setup_swo_trace(core_clock_hz: u32, baudrate: u32, port_mask: u32) -> Result<(), SwoError> {
+    unsafe {
+        ::ppb::DCB.demcr.get_mut().enable_trace(true);
+        ::ppb::TPIU.setup_swo(core_clock_hz, baudrate, PinProtocol::SwoNrz)?;
+        ::ppb::ITM.enable(port_mask);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swo_prescaler() {
+        assert_eq!(Ok(59), swo_prescaler(120_000_000, 2_000_000));
+        assert_eq!(Ok(0), swo_prescaler(2_000_000, 2_000_000));
+        // 120MHz / 53 = 2.264MHz
+        assert_eq!(Ok(52), swo_prescaler(120_000_000, 2_250_000));
+        assert_eq!(
+            Err(SwoError::UnreachableBaudrate),
+            swo_prescaler(120_000_000, 1_000)
+        );
+        assert_eq!(
+            Err(SwoError::UnreachableBaudrate),
+            swo_prescaler(120_000_000, 45_000_000)
+        );
+    }
+}
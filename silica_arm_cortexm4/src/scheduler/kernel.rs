@@ -0,0 +1,380 @@
+//! Target independent core of the scheduler.
+//!
+//! The kernel only keeps track of the tasks' state and saved stack pointers and decides which
+//! one runs next. It never switches contexts by itself which makes it usable in a host
+//! simulation.
+
+use ppb::mpu::Region;
+
+/// Maximum number of tasks, including the idle task.
+pub const MAX_TASKS: usize = 8;
+/// Number of MPU regions reprogrammed on each switch to an unprivileged task.
+pub const TASK_REGIONS: usize = 3;
+
+pub type TaskId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// The slot is not in use.
+    Free,
+    Ready,
+    /// Waiting on the object identified by the key (usually its address).
+    Blocked(usize),
+    /// Sleeping until the given tick.
+    Sleeping(u64),
+    /// The task returned from its entry point.
+    Dead,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpawnError {
+    TooManyTasks,
+    OutOfStackMemory,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Task {
+    state: State,
+    /// Higher values preempt lower ones.
+    priority: u8,
+    /// Saved stack pointer while the task is not running.
+    sp: usize,
+    /// Lowest address of the task's stack.
+    stack_bottom: usize,
+    /// Address past the highest word of the task's stack.
+    stack_top: usize,
+    /// Ticks left before the task must yield to its peers.
+    slice: u32,
+    /// Unprivileged tasks only access the memory of their regions (and the system ones).
+    privileged: bool,
+    regions: [Option<Region>; TASK_REGIONS],
+}
+impl Task {
+    const FREE: Task = Task {
+        state: State::Free,
+        priority: 0,
+        sp: 0,
+        stack_bottom: 0,
+        stack_top: 0,
+        slice: 0,
+        privileged: true,
+        regions: [None; TASK_REGIONS],
+    };
+}
+
+pub struct Kernel {
+    tasks: [Task; MAX_TASKS],
+    current: Option<TaskId>,
+    /// Set when the current task must give way to its equal priority peers.
+    rotate: bool,
+    time_slice: u32,
+    ticks: u64,
+}
+
+impl Kernel {
+    /// `time_slice` is the number of ticks a task may run before an equal priority task gets
+    /// the core.
+    pub const fn new(time_slice: u32) -> Kernel {
+        Kernel {
+            tasks: [Task::FREE; MAX_TASKS],
+            current: None,
+            rotate: false,
+            time_slice,
+            ticks: 0,
+        }
+    }
+
+    /// Slot that `add_task` would use.
+    pub fn free_slot(&self) -> Option<TaskId> {
+        // the slot of a dead task is reused once it has been switched out.
+        (0..MAX_TASKS).find(|id| match self.tasks[*id].state {
+            State::Free => true,
+            State::Dead => self.current != Some(*id),
+            _ => false,
+        })
+    }
+
+    /// Adds a task running on the `(bottom, top)` stack.
+    pub fn add_task(
+        &mut self,
+        priority: u8,
+        sp: usize,
+        stack: (usize, usize),
+    ) -> Result<TaskId, SpawnError> {
+        let id = self.free_slot().ok_or(SpawnError::TooManyTasks)?;
+        self.tasks[id] = Task {
+            state: State::Ready,
+            priority,
+            sp,
+            stack_bottom: stack.0,
+            stack_top: stack.1,
+            slice: self.time_slice,
+            privileged: true,
+            regions: [None; TASK_REGIONS],
+        };
+        Ok(id)
+    }
+
+    pub fn current(&self) -> Option<TaskId> {
+        self.current
+    }
+    pub fn state(&self, id: TaskId) -> State {
+        self.tasks[id].state
+    }
+    pub fn priority(&self, id: TaskId) -> u8 {
+        self.tasks[id].priority
+    }
+    pub fn stack_bottom(&self, id: TaskId) -> usize {
+        self.tasks[id].stack_bottom
+    }
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Frees the slot of a task that ended and was switched out, returning its stack.
+    pub fn reap(&mut self) -> Option<(usize, usize)> {
+        let current = self.current;
+        let task = self
+            .tasks
+            .iter_mut()
+            .enumerate()
+            .find(|&(id, ref t)| t.state == State::Dead && current != Some(id))
+            .map(|(_, t)| t)?;
+        task.state = State::Free;
+        Some((task.stack_bottom, task.stack_top))
+    }
+
+    /// Makes a task run unprivileged with access to the given memory regions.
+    pub fn restrict(&mut self, id: TaskId, regions: [Option<Region>; TASK_REGIONS]) {
+        let task = &mut self.tasks[id];
+        task.privileged = false;
+        task.regions = regions;
+    }
+    pub fn is_privileged(&self, id: TaskId) -> bool {
+        self.tasks[id].privileged
+    }
+    pub fn regions(&self, id: TaskId) -> &[Option<Region>; TASK_REGIONS] {
+        &self.tasks[id].regions
+    }
+
+    /// Selects the task that should be running.
+    pub fn next(&self) -> Option<TaskId> {
+        let ready = |id: &TaskId| self.tasks[*id].state == State::Ready;
+        let top = (0..MAX_TASKS)
+            .filter(&ready)
+            .map(|id| self.tasks[id].priority)
+            .max()?;
+        let start = match self.current {
+            // keep running unless asked to give way
+            Some(c) if ready(&c) && self.tasks[c].priority == top && !self.rotate => {
+                return Some(c)
+            }
+            Some(c) => c + 1,
+            None => 0,
+        };
+        (0..MAX_TASKS)
+            .map(|i| (start + i) % MAX_TASKS)
+            .find(|id| ready(id) && self.tasks[*id].priority == top)
+    }
+
+    /// True if `switch` would select another task.
+    pub fn needs_switch(&self) -> bool {
+        self.next() != self.current
+    }
+
+    /// Saves the stack pointer of the outgoing task and returns the one of the incoming task.
+    pub fn switch(&mut self, sp: usize) -> usize {
+        if let Some(c) = self.current {
+            self.tasks[c].sp = sp;
+        }
+        let next = self.next();
+        self.rotate = false;
+        match next {
+            Some(n) => {
+                if next != self.current {
+                    self.tasks[n].slice = self.time_slice;
+                }
+                self.current = next;
+                self.tasks[n].sp
+            }
+            // nothing to run, this does not happen once the idle task exists.
+            None => sp,
+        }
+    }
+
+    /// Lets the equal priority tasks run.
+    pub fn yield_current(&mut self) {
+        self.rotate = true;
+    }
+
+    pub fn block_current(&mut self, key: usize) {
+        if let Some(c) = self.current {
+            self.tasks[c].state = State::Blocked(key);
+        }
+    }
+
+    pub fn sleep_current(&mut self, ticks: u64) {
+        if let Some(c) = self.current {
+            self.tasks[c].state = State::Sleeping(self.ticks + ticks);
+        }
+    }
+
+    pub fn exit_current(&mut self) {
+        if let Some(c) = self.current {
+            self.tasks[c].state = State::Dead;
+        }
+    }
+
+    /// Wakes the highest priority task blocked on `key`, the oldest entry of the table on ties.
+    pub fn wake_one(&mut self, key: usize) -> Option<TaskId> {
+        let mut woken: Option<TaskId> = None;
+        for id in 0..MAX_TASKS {
+            if self.tasks[id].state == State::Blocked(key)
+                && woken.map_or(true, |w| self.tasks[id].priority > self.tasks[w].priority)
+            {
+                woken = Some(id);
+            }
+        }
+        if let Some(id) = woken {
+            self.tasks[id].state = State::Ready;
+        }
+        woken
+    }
+
+    pub fn wake_all(&mut self, key: usize) -> usize {
+        let mut count = 0;
+        for t in self.tasks.iter_mut() {
+            if t.state == State::Blocked(key) {
+                t.state = State::Ready;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Advances the kernel time by a tick, wakes the sleeping tasks and accounts the time slice
+    /// of the running task. Returns true if a context switch is required.
+    pub fn tick(&mut self) -> bool {
+        self.ticks += 1;
+        let now = self.ticks;
+        for t in self.tasks.iter_mut() {
+            if let State::Sleeping(deadline) = t.state {
+                if deadline <= now {
+                    t.state = State::Ready;
+                }
+            }
+        }
+        if let Some(c) = self.current {
+            let task = &mut self.tasks[c];
+            task.slice = task.slice.saturating_sub(1);
+            if task.slice == 0 {
+                task.slice = self.time_slice;
+                self.rotate = true;
+            }
+        }
+        self.current.is_some() && self.needs_switch()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_preemption() {
+        let mut k = Kernel::new(10);
+        let low = k.add_task(1, 0x100, (0, 0)).unwrap();
+        assert_eq!(0x100, k.switch(0));
+        assert_eq!(Some(low), k.current());
+
+        let high = k.add_task(5, 0x200, (0, 0)).unwrap();
+        assert!(k.needs_switch());
+        assert_eq!(0x200, k.switch(0x1F0));
+        assert_eq!(Some(high), k.current());
+
+        k.block_current(0xCAFE);
+        assert_eq!(0x1F0, k.switch(0x2F0));
+        assert_eq!(Some(low), k.current());
+        assert_eq!(Some(high), k.wake_one(0xCAFE));
+        assert!(k.needs_switch());
+    }
+
+    #[test]
+    fn test_time_slicing() {
+        let mut k = Kernel::new(2);
+        let a = k.add_task(1, 0xA, (0, 0)).unwrap();
+        let b = k.add_task(1, 0xB, (0, 0)).unwrap();
+        k.switch(0);
+        assert_eq!(Some(a), k.current());
+        assert!(!k.tick());
+        assert!(k.tick());
+        assert_eq!(0xB, k.switch(0xA));
+        assert_eq!(Some(b), k.current());
+        k.yield_current();
+        assert_eq!(0xA, k.switch(0xB));
+        assert_eq!(Some(a), k.current());
+    }
+
+    #[test]
+    fn test_sleep() {
+        let mut k = Kernel::new(100);
+        let idle = k.add_task(0, 0x10, (0, 0)).unwrap();
+        let t = k.add_task(3, 0x20, (0, 0)).unwrap();
+        k.switch(0);
+        k.sleep_current(2);
+        k.switch(0x20);
+        assert_eq!(Some(idle), k.current());
+        assert!(!k.tick());
+        assert!(k.tick());
+        k.switch(0x10);
+        assert_eq!(Some(t), k.current());
+    }
+
+    #[test]
+    fn test_restricted_task() {
+        let mut k = Kernel::new(1);
+        let id = k.add_task(1, 0, (0x2000_0000, 0x2000_0400)).unwrap();
+        assert!(k.is_privileged(id));
+        let stack = Region::new(0x2000_0000, 0x400).unwrap();
+        k.restrict(id, [Some(stack), None, None]);
+        assert!(!k.is_privileged(id));
+        assert_eq!(Some(stack), k.regions(id)[0]);
+
+        k.switch(0);
+        k.exit_current();
+        k.switch(0);
+        let id = k.add_task(1, 0, (0, 0)).unwrap();
+        assert!(k.is_privileged(id));
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut k = Kernel::new(1);
+        for _ in 0..MAX_TASKS {
+            k.add_task(1, 0, (0, 0)).unwrap();
+        }
+        assert_eq!(Err(SpawnError::TooManyTasks), k.add_task(1, 0, (0, 0)));
+        k.switch(0);
+        k.exit_current();
+        assert_eq!(Err(SpawnError::TooManyTasks), k.add_task(2, 0, (0, 0)));
+        k.switch(0);
+        assert_eq!(Some(1), k.current());
+        assert_eq!(Ok(0), k.add_task(2, 0, (0, 0)));
+    }
+
+    #[test]
+    fn test_reap() {
+        let mut k = Kernel::new(1);
+        k.add_task(1, 0, (0x100, 0x200)).unwrap();
+        k.add_task(1, 0, (0x200, 0x300)).unwrap();
+        k.switch(0);
+        assert_eq!(None, k.reap());
+        k.exit_current();
+        // the stack is still in use until the task is switched out.
+        assert_eq!(None, k.reap());
+        k.switch(0);
+        assert_eq!(Some((0x100, 0x200)), k.reap());
+        assert_eq!(State::Free, k.state(0));
+        assert_eq!(None, k.reap());
+    }
+}
@@ -0,0 +1,199 @@
+//! Preemptive priority scheduler.
+//!
+//! Tasks run in thread mode on their own process stack. The highest priority ready task always
+//! runs, tasks of equal priority share the core in slices of `TIME_SLICE` SysTick periods. Context
+//! switches happen in PendSV which runs at the lowest exception priority so that it never delays
+//! an interrupt handler.
+//!
+//! ```ignore
+//! extern "C" fn blink(_: usize) {
+//!     loop {
+//!         // ...
+//!         scheduler::sleep_ticks(500);
+//!     }
+//! }
+//!
+//! scheduler::spawn(blink, 0, 1, 1024).unwrap();
+//! scheduler::start()
+//! ```
+
+pub mod kernel;
+#[cfg(target_arch = "arm")]
+pub mod port;
+pub mod stack;
+pub mod sync;
+
+pub use self::kernel::{Kernel, SpawnError, State, TaskId, MAX_TASKS, TASK_REGIONS};
+
+use self::stack::StackAllocator;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use interrupt::{self, Mutex};
+use ppb::mpu::{AccessPermission, Region};
+use ppb::SCB;
+use syscall;
+
+/// Number of SysTick periods a task may run before its equal priority peers get the core.
+pub const TIME_SLICE: u32 = 10;
+#[cfg(target_arch = "arm")]
+const IDLE_STACK_SIZE: usize = 256;
+
+static KERNEL: Mutex<RefCell<Kernel>> = Mutex::new(RefCell::new(Kernel::new(TIME_SLICE)));
+static STACKS: Mutex<RefCell<Option<StackAllocator>>> = Mutex::new(RefCell::new(None));
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Runs `f` on the kernel with interrupts masked.
+pub fn with_kernel<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Kernel) -> R,
+{
+    interrupt::free(|cs| f(&mut KERNEL.borrow(cs).borrow_mut()))
+}
+
+/// Requests a context switch. It happens as soon as no other exception is active.
+pub fn reschedule() {
+    unsafe {
+        SCB.icsr.get_mut().set_pendsv_pending(true);
+    }
+}
+
+/// Creates a task running `entry(arg)` on a stack of `stack_size` bytes taken from the heap
+/// region of the linker script. Returning from `entry` ends the task, its stack is reused by the
+/// next tasks spawned.
+pub fn spawn(
+    entry: extern "C" fn(usize),
+    arg: usize,
+    priority: u8,
+    stack_size: usize,
+) -> Result<TaskId, SpawnError> {
+    interrupt::free(|cs| {
+        let mut kernel = KERNEL.borrow(cs).borrow_mut();
+        let mut stacks = STACKS.borrow(cs).borrow_mut();
+        let stacks = stacks.get_or_insert_with(StackAllocator::from_linker);
+        reap(&mut kernel, stacks);
+        if kernel.free_slot().is_none() {
+            return Err(SpawnError::TooManyTasks);
+        }
+        let (bottom, top) = stacks
+            .allocate(stack_size)
+            .ok_or(SpawnError::OutOfStackMemory)?;
+        let exit = task_exit as extern "C" fn() -> !;
+        let sp = unsafe {
+            stack::paint(bottom);
+            stack::init_frame(top, entry as usize, arg, exit as usize)
+        };
+        kernel.add_task(priority, sp, (bottom, top))
+    })
+}
+
+/// Creates a task running unprivileged: it may only access its stack, the given `regions` and
+/// the regions set up by the application for every task (typically the flash), and must use
+/// `syscall` to reach the kernel services.
+///
+/// `stack_size` must be a power of two as the stack is also an MPU region. The regions are
+/// enforced by `start` enabling the MPU, the privileged code keeping the default memory map.
+pub fn spawn_unprivileged(
+    entry: extern "C" fn(usize),
+    arg: usize,
+    priority: u8,
+    stack_size: usize,
+    regions: [Option<Region>; TASK_REGIONS - 1],
+) -> Result<TaskId, SpawnError> {
+    interrupt::free(|cs| {
+        let mut kernel = KERNEL.borrow(cs).borrow_mut();
+        let mut stacks = STACKS.borrow(cs).borrow_mut();
+        let stacks = stacks.get_or_insert_with(StackAllocator::from_linker);
+        reap(&mut kernel, stacks);
+        if kernel.free_slot().is_none() {
+            return Err(SpawnError::TooManyTasks);
+        }
+        let (bottom, top) = stacks
+            .allocate_aligned(stack_size)
+            .ok_or(SpawnError::OutOfStackMemory)?;
+        let stack = match Region::new(bottom as u32, stack_size as u32) {
+            Ok(region) => region.access(AccessPermission::FullAccess).execute_never(),
+            Err(_) => {
+                stacks.release((bottom, top));
+                return Err(SpawnError::OutOfStackMemory);
+            }
+        };
+        let exit = syscall::exit as extern "C" fn() -> !;
+        let sp = unsafe {
+            stack::paint(bottom);
+            stack::init_frame(top, entry as usize, arg, exit as usize)
+        };
+        let id = kernel.add_task(priority, sp, (bottom, top))?;
+        kernel.restrict(id, [Some(stack), regions[0], regions[1]]);
+        Ok(id)
+    })
+}
+
+/// Gives back the stacks of the tasks that ended.
+fn reap(kernel: &mut Kernel, stacks: &mut StackAllocator) {
+    while let Some(stack) = kernel.reap() {
+        stacks.release(stack);
+    }
+}
+
+/// Identifier of the running task.
+pub fn current() -> Option<TaskId> {
+    with_kernel(|k| Kernel::current(k))
+}
+
+/// Gives the core to the other ready tasks of the same priority.
+pub fn yield_now() {
+    with_kernel(Kernel::yield_current);
+    reschedule();
+}
+
+/// Suspends the current task for `ticks` SysTick periods.
+pub fn sleep_ticks(ticks: u64) {
+    with_kernel(|k| k.sleep_current(ticks));
+    reschedule();
+}
+
+/// Ends the current task, e.g. after it caused a fault.
+pub fn kill_current() {
+    with_kernel(Kernel::exit_current);
+    reschedule();
+}
+
+/// Accounts a SysTick period, called from the SysTick handler.
+pub fn on_systick() {
+    if STARTED.load(Ordering::Relaxed) && with_kernel(Kernel::tick) {
+        reschedule();
+    }
+}
+
+/// Return address of the privileged tasks' entry points.
+extern "C" fn task_exit() -> ! {
+    loop {
+        kill_current();
+    }
+}
+
+#[cfg(target_arch = "arm")]
+extern "C" fn idle(_: usize) {
+    loop {
+        ::power::wfi();
+    }
+}
+
+/// Starts the scheduler, the caller's context is never resumed.
+///
+/// SysTick should be running (see `time::Monotonic`) for sleeping tasks to wake up and for the
+/// time slicing to happen.
+#[cfg(target_arch = "arm")]
+pub fn start() -> ! {
+    spawn(idle, 0, 0, IDLE_STACK_SIZE).expect("no room left for the idle task");
+    unsafe {
+        SCB.shp3.get_mut().set_pendsv_priority(0xFF);
+        // faults of unprivileged tasks end them instead of escalating to a HardFault.
+        SCB.shcsr.get_mut().enable_memfault(true);
+        // the regions only restrict the unprivileged tasks, the others keep the default map.
+        ::ppb::MPU.enable(true);
+        interrupt::disable();
+        STARTED.store(true, Ordering::Relaxed);
+        port::start_first_task()
+    }
+}
@@ -0,0 +1,85 @@
+//! Cortex-M4 context switch.
+//!
+//! The core stacks r0-r3, r12, lr, pc and xPSR on the process stack on exception entry, PendSV
+//! completes the frame with r4-r11 and EXC_RETURN (and s16-s31 if the task used the FPU) before
+//! handing the stack pointer to the kernel.
+
+use super::kernel::TASK_REGIONS;
+use super::{stack, with_kernel};
+use ppb::mpu::Region;
+use ppb::MPU;
+use syscall::{self, FIRST_TASK_REGION};
+
+/// Process stack used by the first PendSV, until the first task is restored.
+static mut BOOT_SCRATCH: [u32; 40] = [0; 40];
+
+/// Called by `pendsv_handler` with the outgoing task's stack pointer, returns the one of the
+/// incoming task.
+#[no_mangle]
+unsafe extern "C" fn silica_scheduler_switch(sp: usize) -> usize {
+    with_kernel(|k| {
+        if let Some(c) = k.current() {
+            if !stack::is_intact(k.stack_bottom(c)) {
+                panic!("stack overflow in task {}", c);
+            }
+        }
+        let sp = k.switch(sp);
+        if let Some(c) = k.current() {
+            apply_protection(k.is_privileged(c), k.regions(c));
+        }
+        sp
+    })
+}
+
+/// Sets the privilege level of thread mode and the per task MPU regions for the incoming task.
+unsafe fn apply_protection(privileged: bool, regions: &[Option<Region>; TASK_REGIONS]) {
+    syscall::set_thread_privileged(privileged);
+    for (i, region) in regions.iter().enumerate() {
+        let number = FIRST_TASK_REGION + i as u8;
+        match region {
+            Some(region) => MPU.set_region(number, region),
+            None => MPU.clear_region(number),
+        }
+    }
+}
+
+#[cfg(target_feature = "vfp4")]
+#[naked]
+pub unsafe extern "C" fn pendsv_handler() {
+    asm!("mrs r0, psp
+          tst lr, #0x10
+          it eq
+          vstmdbeq r0!, {s16-s31}
+          stmdb r0!, {r4-r11, lr}
+          bl silica_scheduler_switch
+          ldmia r0!, {r4-r11, lr}
+          tst lr, #0x10
+          it eq
+          vldmiaeq r0!, {s16-s31}
+          msr psp, r0
+          bx lr" : : : : "volatile");
+}
+
+#[cfg(not(target_feature = "vfp4"))]
+#[naked]
+pub unsafe extern "C" fn pendsv_handler() {
+    asm!("mrs r0, psp
+          stmdb r0!, {r4-r11, lr}
+          bl silica_scheduler_switch
+          ldmia r0!, {r4-r11, lr}
+          msr psp, r0
+          bx lr" : : : : "volatile");
+}
+
+/// Points the process stack to a scratch area and triggers the first context switch. The main
+/// stack is only used by exception handlers from there on.
+pub unsafe fn start_first_task() -> ! {
+    let scratch = BOOT_SCRATCH.as_mut_ptr().add(BOOT_SCRATCH.len()) as u32;
+    asm!("msr psp, $0
+          isb" : : "r"(scratch) : "memory" : "volatile");
+    super::reschedule();
+    ::interrupt::enable();
+    loop {
+        ::power::wfi();
+    }
+}
@@ -0,0 +1,201 @@
+//! Task stacks.
+//!
+//! Stacks are carved out of the RAM left free by the linker script between `_sheap` and `_eheap`
+//! and given back when their task ends. Their lowest word holds a canary checked on every
+//! context switch.
+
+use super::kernel::MAX_TASKS;
+use core::mem::size_of;
+
+/// Value written at the bottom of every task stack.
+pub const CANARY: u32 = 0xDEAD_C0DE;
+
+/// Initial program status register: only the Thumb bit is set.
+const INITIAL_XPSR: u32 = 0x0100_0000;
+/// Return to thread mode, using the process stack, without floating point context.
+pub const EXC_RETURN_THREAD_PSP: u32 = 0xFFFF_FFFD;
+
+/// Number of words pushed by `init_frame`: r4-r11 and EXC_RETURN saved by PendSV followed by the
+/// frame stacked by the core on exception entry (r0-r3, r12, lr, pc, xPSR).
+pub const FRAME_WORDS: usize = 17;
+
+/// Smallest stack: the initial frame and the canary.
+const MIN_STACK: usize = FRAME_WORDS * size_of::<u32>() + 8;
+
+/// Bump allocator handing out 8 bytes aligned stacks as required by the AAPCS. The stacks given
+/// back are reused first, the ones that do not fit in the free list are lost.
+pub struct StackAllocator {
+    next: usize,
+    end: usize,
+    /// (bottom, top) of the stacks given back.
+    freed: [Option<(usize, usize)>; MAX_TASKS],
+}
+impl StackAllocator {
+    pub const fn new(start: usize, end: usize) -> StackAllocator {
+        StackAllocator {
+            next: (start + 7) & !7,
+            end,
+            freed: [None; MAX_TASKS],
+        }
+    }
+
+    /// Allocator over the heap region reserved by the linker script.
+    pub fn from_linker() -> StackAllocator {
+        extern "C" {
+            static _sheap: u32;
+            static _eheap: u32;
+        }
+        unsafe {
+            StackAllocator::new(
+                &_sheap as *const u32 as usize,
+                &_eheap as *const u32 as usize,
+            )
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.end.saturating_sub(self.next)
+    }
+
+    /// Reserves `size` bytes (rounded up to 8) and returns the (bottom, top) addresses of the
+    /// stack.
+    pub fn allocate(&mut self, size: usize) -> Option<(usize, usize)> {
+        let size = (size + 7) & !7;
+        if size < MIN_STACK {
+            return None;
+        }
+        if let Some(stack) = self.reuse(size, 8) {
+            return Some(stack);
+        }
+        if size > self.remaining() {
+            return None;
+        }
+        let bottom = self.next;
+        self.next += size;
+        Some((bottom, self.next))
+    }
+
+    /// Reserves a stack usable as an MPU region: `size` must be a power of two and the stack is
+    /// aligned on its size. The memory skipped to align it is lost.
+    pub fn allocate_aligned(&mut self, size: usize) -> Option<(usize, usize)> {
+        if !size.is_power_of_two() {
+            return None;
+        }
+        if let Some(stack) = self.reuse(size, size) {
+            return Some(stack);
+        }
+        let start = (self.next + size - 1) & !(size - 1);
+        if start > self.end {
+            return None;
+        }
+        let mut aligned = StackAllocator::new(start, self.end);
+        let stack = aligned.allocate(size)?;
+        self.next = aligned.next;
+        Some(stack)
+    }
+
+    /// Gives back a stack returned by `allocate` or `allocate_aligned`.
+    pub fn release(&mut self, (bottom, top): (usize, usize)) {
+        if top == self.next {
+            self.next = bottom;
+        } else if let Some(slot) = self.freed.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some((bottom, top));
+        }
+    }
+
+    /// Takes `size` bytes aligned on `align` at the bottom of a freed stack, its remainder
+    /// staying free.
+    fn reuse(&mut self, size: usize, align: usize) -> Option<(usize, usize)> {
+        let slot = self.freed.iter_mut().find(|slot| match **slot {
+            Some((bottom, top)) => bottom % align == 0 && top - bottom >= size,
+            None => false,
+        })?;
+        let (bottom, top) = slot.take()?;
+        if top - bottom - size >= MIN_STACK {
+            *slot = Some((bottom + size, top));
+        }
+        Some((bottom, bottom + size))
+    }
+}
+
+/// Writes the canary at the bottom of a stack.
+pub unsafe fn paint(bottom: usize) {
+    *(bottom as *mut u32) = CANARY;
+}
+
+/// True if the canary at the bottom of the stack is intact.
+pub unsafe fn is_intact(bottom: usize) -> bool {
+    *(bottom as *const u32) == CANARY
+}
+
+/// Builds the frame restored by the first context switch to a task and returns the resulting
+/// stack pointer. The task starts in `entry` with `arg` in r0 and returns to `exit`.
+pub unsafe fn init_frame(top: usize, entry: usize, arg: usize, exit: usize) -> usize {
+    let sp = (top & !7) - FRAME_WORDS * size_of::<u32>();
+    let frame = sp as *mut u32;
+    // r4-r11
+    for i in 0..8 {
+        *frame.add(i) = 0;
+    }
+    *frame.add(8) = EXC_RETURN_THREAD_PSP;
+    // r0-r3, r12
+    *frame.add(9) = arg as u32;
+    for i in 10..14 {
+        *frame.add(i) = 0;
+    }
+    *frame.add(14) = exit as u32;
+    // the pc must be halfword aligned, the thumb bit lives in xPSR.
+    *frame.add(15) = (entry as u32) & !1;
+    *frame.add(16) = INITIAL_XPSR;
+    sp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocator() {
+        let mut a = StackAllocator::new(0x2000_0003, 0x2000_0400);
+        assert_eq!(Some((0x2000_0008, 0x2000_0108)), a.allocate(0x100));
+        assert_eq!(Some((0x2000_0108, 0x2000_0208)), a.allocate(0xFA));
+        assert_eq!(None, a.allocate(0x300));
+        assert_eq!(None, a.allocate(16));
+        assert_eq!(0x1F8, a.remaining());
+
+        assert_eq!(Some((0x2000_0300, 0x2000_0400)), a.allocate_aligned(0x100));
+        assert_eq!(None, a.allocate_aligned(0x20));
+        assert_eq!(None, a.allocate_aligned(0x60));
+
+        // the last stack moves the end back, the others are reused.
+        a.release((0x2000_0300, 0x2000_0400));
+        assert_eq!(Some((0x2000_0300, 0x2000_0400)), a.allocate_aligned(0x100));
+        a.release((0x2000_0008, 0x2000_0108));
+        assert_eq!(Some((0x2000_0008, 0x2000_0088)), a.allocate(0x80));
+        assert_eq!(Some((0x2000_0088, 0x2000_0108)), a.allocate(0x80));
+        assert_eq!(None, a.allocate(0x80));
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut stack = [0u32; 32];
+        let bottom = stack.as_mut_ptr() as usize;
+        let top = bottom + 32 * 4;
+        unsafe {
+            paint(bottom);
+            assert!(is_intact(bottom));
+            let sp = init_frame(top, 0x0040_1235, 42, 0x0040_2001);
+            assert_eq!(top & !7, sp + FRAME_WORDS * 4);
+            let frame = &stack[(sp - bottom) / 4..];
+            assert_eq!(EXC_RETURN_THREAD_PSP, frame[8]);
+            assert_eq!(42, frame[9]);
+            assert_eq!(0x0040_2001, frame[14]);
+            assert_eq!(0x0040_1234, frame[15]);
+            assert_eq!(INITIAL_XPSR, frame[16]);
+        }
+        unsafe {
+            *(bottom as *mut u32) = 0;
+            assert!(!is_intact(bottom));
+        }
+    }
+}
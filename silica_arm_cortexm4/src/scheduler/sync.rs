@@ -0,0 +1,272 @@
+//! Blocking synchronisation primitives between tasks.
+//!
+//! The `*_or_block` methods hold the logic and operate on the kernel passed by the caller; the
+//! other methods wrap them with the global kernel and retry after a context switch until they
+//! succeed. The non blocking variants (`try_*`, `signal`) may be used from interrupt handlers.
+//!
+//! They mask interrupts and pend PendSV, which unprivileged tasks cannot do: a task started with
+//! `spawn_unprivileged` calling them is ended as if it faulted. Such a task reaches a primitive
+//! through a service registered with `syscall::register` that uses the non blocking variants.
+
+use super::kernel::{Kernel, TaskId};
+use super::{reschedule, with_kernel};
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+use syscall;
+
+/// `with_kernel` restricted to privileged code.
+fn privileged<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Kernel) -> R,
+{
+    if !syscall::is_thread_privileged() && !syscall::is_handler_mode() {
+        syscall::exit();
+    }
+    with_kernel(f)
+}
+
+/// Counting semaphore.
+pub struct Semaphore {
+    count: Cell<usize>,
+}
+impl Semaphore {
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: Cell::new(count),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Semaphore as usize
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.get()
+    }
+
+    /// Takes a unit if available, blocks the current task otherwise.
+    pub fn acquire_or_block(&self, kernel: &mut Kernel) -> bool {
+        match self.count.get() {
+            0 => {
+                kernel.block_current(self.key());
+                false
+            }
+            n => {
+                self.count.set(n - 1);
+                true
+            }
+        }
+    }
+
+    /// Gives a unit back and wakes up the highest priority task waiting for it.
+    pub fn release_and_wake(&self, kernel: &mut Kernel) -> Option<TaskId> {
+        self.count.set(self.count.get() + 1);
+        kernel.wake_one(self.key())
+    }
+
+    pub fn try_wait(&self) -> bool {
+        privileged(|_| match self.count.get() {
+            0 => false,
+            n => {
+                self.count.set(n - 1);
+                true
+            }
+        })
+    }
+
+    /// Blocks until a unit is available.
+    pub fn wait(&self) {
+        while !privileged(|k| self.acquire_or_block(k)) {
+            reschedule();
+        }
+    }
+
+    pub fn signal(&self) {
+        if privileged(|k| self.release_and_wake(k)).is_some() {
+            reschedule();
+        }
+    }
+}
+unsafe impl Sync for Semaphore {}
+
+/// Fixed size message queue storing its items in a `B` buffer (usually an array).
+pub struct Queue<T, B> {
+    buffer: UnsafeCell<B>,
+    head: Cell<usize>,
+    len: Cell<usize>,
+    _item: PhantomData<T>,
+}
+impl<T, B> Queue<T, B>
+where
+    T: Copy,
+    B: AsMut<[T]>,
+{
+    pub const fn new(buffer: B) -> Queue<T, B> {
+        Queue {
+            buffer: UnsafeCell::new(buffer),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            _item: PhantomData,
+        }
+    }
+
+    fn not_full_key(&self) -> usize {
+        self as *const Self as usize
+    }
+    fn not_empty_key(&self) -> usize {
+        self.not_full_key() + 1
+    }
+    /// The kernel is only lent under its lock: borrowing it proves the access is exclusive.
+    fn slots<'a>(&'a self, _kernel: &'a mut Kernel) -> &'a mut [T] {
+        unsafe { (*self.buffer.get()).as_mut() }
+    }
+
+    pub fn capacity(&self) -> usize {
+        privileged(|k| self.slots(k).len())
+    }
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    fn push(&self, kernel: &mut Kernel, item: T) -> Result<Option<TaskId>, T> {
+        let len = self.len.get();
+        {
+            let slots = self.slots(kernel);
+            if len == slots.len() {
+                return Err(item);
+            }
+            slots[(self.head.get() + len) % slots.len()] = item;
+        }
+        self.len.set(len + 1);
+        Ok(kernel.wake_one(self.not_empty_key()))
+    }
+
+    fn pop(&self, kernel: &mut Kernel) -> Option<(T, Option<TaskId>)> {
+        if self.len.get() == 0 {
+            return None;
+        }
+        let head = self.head.get();
+        let (item, capacity) = {
+            let slots = self.slots(kernel);
+            (slots[head], slots.len())
+        };
+        self.head.set((head + 1) % capacity);
+        self.len.set(self.len.get() - 1);
+        Some((item, kernel.wake_one(self.not_full_key())))
+    }
+
+    /// Appends `item` or blocks the current task if the queue is full, handing `item` back.
+    pub fn send_or_block(&self, kernel: &mut Kernel, item: T) -> Result<(), T> {
+        self.push(kernel, item).map(|_| ()).map_err(|item| {
+            kernel.block_current(self.not_full_key());
+            item
+        })
+    }
+
+    /// Takes the oldest item or blocks the current task if the queue is empty.
+    pub fn receive_or_block(&self, kernel: &mut Kernel) -> Option<T> {
+        match self.pop(kernel) {
+            Some((item, _)) => Some(item),
+            None => {
+                kernel.block_current(self.not_empty_key());
+                None
+            }
+        }
+    }
+
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        let woken = privileged(|k| self.push(k, item))?;
+        if woken.is_some() {
+            reschedule();
+        }
+        Ok(())
+    }
+
+    pub fn try_receive(&self) -> Option<T> {
+        let (item, woken) = privileged(|k| self.pop(k))?;
+        if woken.is_some() {
+            reschedule();
+        }
+        Some(item)
+    }
+
+    /// Blocks until there is room for `item`.
+    pub fn send(&self, mut item: T) {
+        loop {
+            match privileged(|k| self.send_or_block(k, item)) {
+                Ok(()) => break,
+                Err(i) => item = i,
+            }
+            reschedule();
+        }
+        // let a woken up receiver preempt us.
+        reschedule();
+    }
+
+    /// Blocks until an item is available.
+    pub fn receive(&self) -> T {
+        loop {
+            if let Some(item) = privileged(|k| self.receive_or_block(k)) {
+                reschedule();
+                return item;
+            }
+            reschedule();
+        }
+    }
+}
+unsafe impl<T: Send, B: Send> Sync for Queue<T, B> {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::kernel::State;
+    use super::*;
+
+    #[test]
+    fn test_semaphore() {
+        let mut k = Kernel::new(10);
+        let low = k.add_task(1, 0, (0, 0)).unwrap();
+        let high = k.add_task(2, 0, (0, 0)).unwrap();
+        let s = Semaphore::new(1);
+
+        k.switch(0);
+        assert!(s.acquire_or_block(&mut k));
+        assert!(!s.acquire_or_block(&mut k));
+        assert!(k.state(high) != State::Ready);
+        k.switch(0);
+        assert_eq!(Some(low), k.current());
+
+        assert_eq!(Some(high), s.release_and_wake(&mut k));
+        assert!(k.needs_switch());
+        k.switch(0);
+        assert!(s.acquire_or_block(&mut k));
+        assert_eq!(0, s.count());
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut k = Kernel::new(10);
+        let producer = k.add_task(1, 0, (0, 0)).unwrap();
+        let consumer = k.add_task(2, 0, (0, 0)).unwrap();
+        let q: Queue<u8, [u8; 2]> = Queue::new([0; 2]);
+
+        k.switch(0);
+        assert_eq!(None, q.receive_or_block(&mut k));
+        k.switch(0);
+        assert_eq!(Some(producer), k.current());
+
+        assert_eq!(Ok(()), q.send_or_block(&mut k, 1));
+        assert_eq!(State::Ready, k.state(consumer));
+        assert_eq!(Ok(()), q.send_or_block(&mut k, 2));
+        assert_eq!(Err(3), q.send_or_block(&mut k, 3));
+        k.switch(0);
+        assert_eq!(Some(consumer), k.current());
+
+        assert_eq!(Some(1), q.receive_or_block(&mut k));
+        assert_eq!(State::Ready, k.state(producer));
+        assert_eq!(Some(2), q.receive_or_block(&mut k));
+        assert!(q.is_empty());
+    }
+}
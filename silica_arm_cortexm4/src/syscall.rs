@@ -0,0 +1,318 @@
+//! Supervisor calls.
+//!
+//! Unprivileged tasks can neither mask interrupts nor touch the system control space, they ask
+//! the kernel through `svc` instead. The call number goes in r0, up to three arguments in r1-r3
+//! and the result comes back in r0.
+//!
+//! The kernel services have fixed numbers (`Call`), the application registers its own services
+//! from `FIRST_USER_CALL` on, e.g. to let a plugin task feed the motion planner:
+//!
+//! ```ignore
+//! fn queue_move(args: &[u32; 3]) -> u32 {
+//!     PLANNER_QUEUE.try_send(Move::decode(args)).is_ok() as u32
+//! }
+//! syscall::register(FIRST_USER_CALL, queue_move).unwrap();
+//! // from the unprivileged task
+//! syscall::call(FIRST_USER_CALL, [x, y, feed]);
+//! ```
+//!
+//! The SVCall exception must not be masked while calling (PRIMASK set or BASEPRI at or above
+//! its priority): the call would escalate to a HardFault.
+
+use core::cell::RefCell;
+use interrupt::{self, Mutex};
+use scheduler;
+
+/// The MPU regions from this one on are reprogrammed for each task, the lower ones are left to
+/// the application for settings common to all tasks.
+pub const FIRST_TASK_REGION: u8 = 5;
+
+pub const FIRST_USER_CALL: u32 = 16;
+pub const MAX_USER_CALLS: usize = 16;
+/// Returned in r0 for an unknown call.
+pub const ERROR: u32 = 0xFFFF_FFFF;
+
+/// Registers stacked by the core on exception entry.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// Kernel services.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Call {
+    Yield,
+    /// Sleep for r1 ticks.
+    Sleep,
+    Exit,
+    /// Returns the identifier of the calling task.
+    CurrentTask,
+    /// Registered by the application.
+    User(u32),
+}
+impl Call {
+    pub fn decode(number: u32) -> Option<Call> {
+        match number {
+            0 => Some(Call::Yield),
+            1 => Some(Call::Sleep),
+            2 => Some(Call::Exit),
+            3 => Some(Call::CurrentTask),
+            n if n >= FIRST_USER_CALL && n < FIRST_USER_CALL + MAX_USER_CALLS as u32 => {
+                Some(Call::User(n))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            Call::Yield => 0,
+            Call::Sleep => 1,
+            Call::Exit => 2,
+            Call::CurrentTask => 3,
+            Call::User(n) => n,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyscallError {
+    /// The number is not in the user range.
+    InvalidNumber,
+    AlreadyRegistered,
+}
+
+/// A service run in handler mode on behalf of the caller. It must not block.
+pub type Handler = fn(args: &[u32; 3]) -> u32;
+
+/// The application's services.
+pub struct SyscallTable {
+    handlers: [Option<Handler>; MAX_USER_CALLS],
+}
+impl SyscallTable {
+    pub const fn new() -> SyscallTable {
+        SyscallTable {
+            handlers: [None; MAX_USER_CALLS],
+        }
+    }
+
+    pub fn register(&mut self, number: u32, handler: Handler) -> Result<(), SyscallError> {
+        let slot = match Call::decode(number) {
+            Some(Call::User(n)) => &mut self.handlers[(n - FIRST_USER_CALL) as usize],
+            _ => return Err(SyscallError::InvalidNumber),
+        };
+        if slot.is_some() {
+            return Err(SyscallError::AlreadyRegistered);
+        }
+        *slot = Some(handler);
+        Ok(())
+    }
+
+    pub fn handler(&self, number: u32) -> Option<Handler> {
+        match Call::decode(number) {
+            Some(Call::User(n)) => self.handlers[(n - FIRST_USER_CALL) as usize],
+            _ => None,
+        }
+    }
+}
+
+static TABLE: Mutex<RefCell<SyscallTable>> = Mutex::new(RefCell::new(SyscallTable::new()));
+
+/// Makes `handler` available to the tasks as call `number`.
+pub fn register(number: u32, handler: Handler) -> Result<(), SyscallError> {
+    interrupt::free(|cs| TABLE.borrow(cs).borrow_mut().register(number, handler))
+}
+
+/// Runs the call described by the stacked registers and stores its result in r0.
+pub fn dispatch(frame: &mut ExceptionFrame) {
+    let args = [frame.r1, frame.r2, frame.r3];
+    frame.r0 = match Call::decode(frame.r0) {
+        Some(Call::Yield) => {
+            scheduler::yield_now();
+            0
+        }
+        Some(Call::Sleep) => {
+            scheduler::sleep_ticks(u64::from(args[0]));
+            0
+        }
+        Some(Call::Exit) => {
+            scheduler::kill_current();
+            0
+        }
+        Some(Call::CurrentTask) => scheduler::current().map_or(ERROR, |id| id as u32),
+        Some(Call::User(n)) => {
+            // the handler runs with interrupts enabled
+            match interrupt::free(|cs| TABLE.borrow(cs).borrow().handler(n)) {
+                Some(handler) => handler(&args),
+                None => ERROR,
+            }
+        }
+        None => ERROR,
+    };
+}
+
+#[cfg(target_arch = "arm")]
+#[no_mangle]
+unsafe extern "C" fn silica_svc_dispatch(frame: &mut ExceptionFrame) {
+    dispatch(frame)
+}
+
+/// Hands the frame of the caller, on the stack it was using, to `dispatch`.
+#[cfg(target_arch = "arm")]
+#[naked]
+pub unsafe extern "C" fn svcall_handler() {
+    asm!("tst lr, #4
+          ite eq
+          mrseq r0, msp
+          mrsne r0, psp
+          b silica_svc_dispatch" : : : : "volatile");
+}
+
+/// Issues a supervisor call.
+#[inline]
+pub fn call(number: u32, args: [u32; 3]) -> u32 {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("svc 0"
+                 : "={r0}"(r)
+                 : "{r0}"(number), "{r1}"(args[0]), "{r2}"(args[1]), "{r3}"(args[2])
+                 : "memory"
+                 : "volatile");
+        }
+        r
+    }
+    #[cfg(not(target_arch = "arm"))]
+    {
+        let mut frame = ExceptionFrame {
+            r0: number,
+            r1: args[0],
+            r2: args[1],
+            r3: args[2],
+            ..ExceptionFrame::default()
+        };
+        dispatch(&mut frame);
+        frame.r0
+    }
+}
+
+pub fn yield_now() {
+    call(Call::Yield.number(), [0; 3]);
+}
+
+pub fn sleep_ticks(ticks: u32) {
+    call(Call::Sleep.number(), [ticks, 0, 0]);
+}
+
+/// Ends the calling task. This is also the return address of the unprivileged tasks.
+pub extern "C" fn exit() -> ! {
+    loop {
+        call(Call::Exit.number(), [0; 3]);
+    }
+}
+
+pub fn current_task() -> Option<usize> {
+    match call(Call::CurrentTask.number(), [0; 3]) {
+        ERROR => None,
+        id => Some(id as usize),
+    }
+}
+
+#[cfg(not(target_arch = "arm"))]
+static NPRIV: ::core::sync::atomic::AtomicBool = ::core::sync::atomic::AtomicBool::new(false);
+
+/// True if thread mode runs privileged (CONTROL.nPRIV cleared).
+#[inline]
+pub fn is_thread_privileged() -> bool {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("mrs $0, CONTROL" : "=r"(r) : : : "volatile");
+        }
+        r & 1 == 0
+    }
+    #[cfg(not(target_arch = "arm"))]
+    !NPRIV.load(::core::sync::atomic::Ordering::SeqCst)
+}
+
+/// True while an exception handler runs, handler mode is always privileged.
+#[inline]
+pub fn is_handler_mode() -> bool {
+    #[cfg(target_arch = "arm")]
+    {
+        let r: u32;
+        unsafe {
+            asm!("mrs $0, IPSR" : "=r"(r) : : : "volatile");
+        }
+        r & 0x1FF != 0
+    }
+    #[cfg(not(target_arch = "arm"))]
+    false
+}
+
+/// Sets the privilege level of thread mode. Only privileged code may call it: once dropped, the
+/// privileges can only be restored from an exception handler.
+#[inline]
+pub unsafe fn set_thread_privileged(privileged: bool) {
+    #[cfg(target_arch = "arm")]
+    {
+        let mut control: u32;
+        asm!("mrs $0, CONTROL" : "=r"(control) : : : "volatile");
+        if privileged {
+            control &= !1;
+        } else {
+            control |= 1;
+        }
+        asm!("msr CONTROL, $0
+              isb" : : "r"(control) : "memory" : "volatile");
+    }
+    #[cfg(not(target_arch = "arm"))]
+    NPRIV.store(!privileged, ::core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(args: &[u32; 3]) -> u32 {
+        args[0] + args[1] + args[2]
+    }
+
+    #[test]
+    fn test_decode() {
+        for n in 0..64 {
+            if let Some(call) = Call::decode(n) {
+                assert_eq!(n, call.number());
+            }
+        }
+        assert_eq!(None, Call::decode(4));
+        assert_eq!(Some(Call::User(31)), Call::decode(31));
+        assert_eq!(None, Call::decode(32));
+    }
+
+    #[test]
+    fn test_user_calls() {
+        // the global table is not used: interrupt::free would race with the interrupt tests.
+        let mut table = SyscallTable::new();
+        assert_eq!(Err(SyscallError::InvalidNumber), table.register(1, add));
+        assert_eq!(Ok(()), table.register(FIRST_USER_CALL + 1, add));
+        assert_eq!(
+            Err(SyscallError::AlreadyRegistered),
+            table.register(FIRST_USER_CALL + 1, add)
+        );
+        let handler = table.handler(FIRST_USER_CALL + 1).unwrap();
+        assert_eq!(6, handler(&[1, 2, 3]));
+        assert!(table.handler(FIRST_USER_CALL).is_none());
+        assert!(table.handler(0).is_none());
+    }
+}
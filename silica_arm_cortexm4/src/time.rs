@@ -0,0 +1,242 @@
+//! SysTick based monotonic clock.
+//!
+//! The SysTick counter is reloaded every tick and the SysTick exception keeps a 64-bit tick
+//! count. `Monotonic::now` combines that count with the current value of the down counter so
+//! that instants have a sub-tick resolution.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Waker;
+use interrupt::{self, Mutex};
+use ppb::{SCB, SYSTICK};
+use silica::executor::TimerQueue;
+pub use silica::executor::{delay, delay_until, Delay, Timer};
+pub use silica::time::{Clock, Duration, Instant};
+
+/// The SysTick reload value is 24 bits wide.
+const MAX_RELOAD: u32 = 0x00FF_FFFF;
+
+static TICKS_LO: AtomicU32 = AtomicU32::new(0);
+static TICKS_HI: AtomicU32 = AtomicU32::new(0);
+/// Tick period of the running `Monotonic`, used to expire the async timers.
+static MICROS_PER_TICK: AtomicU32 = AtomicU32::new(0);
+static TIMERS: Mutex<RefCell<TimerQueue>> = Mutex::new(RefCell::new(TimerQueue::new()));
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MonotonicError {
+    /// The tick rate is higher than the core clock or does not divide a second into a whole
+    /// number of microseconds.
+    InvalidTickRate,
+    /// The number of core cycles per tick does not fit in the 24 bits reload register.
+    ReloadOutOfRange,
+    /// The core clock is not a multiple of the tick rate.
+    InexactTickRate,
+}
+
+/// Must be called from the SysTick exception handler.
+#[inline]
+pub fn on_systick() {
+    // only the exception handler writes to the counter, readers retry on torn reads.
+    let lo = TICKS_LO.load(Ordering::Relaxed).wrapping_add(1);
+    if lo == 0 {
+        TICKS_HI.fetch_add(1, Ordering::Relaxed);
+    }
+    TICKS_LO.store(lo, Ordering::Release);
+
+    // the deadlines are checked against the start of the tick: a timer fires at most a tick
+    // late.
+    let now = ticks() * u64::from(MICROS_PER_TICK.load(Ordering::Relaxed));
+    interrupt::free(|cs| {
+        TIMERS
+            .borrow(cs)
+            .borrow_mut()
+            .expire(Instant::from_micros(now))
+    });
+}
+
+/// Number of SysTick exceptions handled since the clock was started.
+pub fn ticks() -> u64 {
+    loop {
+        let hi = TICKS_HI.load(Ordering::Acquire);
+        let lo = TICKS_LO.load(Ordering::Acquire);
+        if hi == TICKS_HI.load(Ordering::Acquire) {
+            return (u64::from(hi) << 32) | u64::from(lo);
+        }
+    }
+}
+
+/// A monotonic clock driven by SysTick.
+#[derive(Debug, Copy, Clone)]
+pub struct Monotonic {
+    core_clock_hz: u32,
+    tick_hz: u32,
+    reload: u32,
+    micros_per_tick: u32,
+}
+
+impl Monotonic {
+    /// Computes the SysTick configuration for a `tick_hz` rate out of a `core_clock_hz` processor
+    /// clock.
+    pub fn new(core_clock_hz: u32, tick_hz: u32) -> Result<Monotonic, MonotonicError> {
+        if tick_hz == 0 || tick_hz > core_clock_hz || 1_000_000 % tick_hz != 0 {
+            return Err(MonotonicError::InvalidTickRate);
+        }
+        if core_clock_hz % tick_hz != 0 {
+            return Err(MonotonicError::InexactTickRate);
+        }
+        let cycles = core_clock_hz / tick_hz;
+        if cycles - 1 > MAX_RELOAD {
+            return Err(MonotonicError::ReloadOutOfRange);
+        }
+        Ok(Monotonic {
+            core_clock_hz,
+            tick_hz,
+            reload: cycles - 1,
+            micros_per_tick: 1_000_000 / tick_hz,
+        })
+    }
+
+    pub fn core_clock_hz(&self) -> u32 {
+        self.core_clock_hz
+    }
+    pub fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+    pub fn tick_period(&self) -> Duration {
+        Duration::from_micros(u64::from(self.micros_per_tick))
+    }
+
+    /// Configures and starts the SysTick counter and its exception.
+    pub fn start(&self) {
+        MICROS_PER_TICK.store(self.micros_per_tick, Ordering::Relaxed);
+        unsafe {
+            SYSTICK.control_and_status.get_mut().enable_systick(false);
+            SYSTICK.reload_value.get_mut().set_reload(self.reload);
+            SYSTICK.current_value.set(0);
+
+            let mut csr = SYSTICK.control_and_status.get_mut();
+            csr.use_processor_clock(true);
+            csr.enable_tick_interrupt(true);
+            csr.enable_systick(true);
+        }
+    }
+
+    /// Stops the SysTick counter. The tick count is kept.
+    pub fn stop(&self) {
+        unsafe {
+            let mut csr = SYSTICK.control_and_status.get_mut();
+            csr.enable_tick_interrupt(false);
+            csr.enable_systick(false);
+        }
+    }
+
+    /// Converts a tick count and the SysTick current value to microseconds.
+    fn to_micros(&self, ticks: u64, current_value: u32) -> u64 {
+        let elapsed_cycles = u64::from(self.reload - current_value.min(self.reload));
+        let sub_tick =
+            elapsed_cycles * u64::from(self.micros_per_tick) / (u64::from(self.reload) + 1);
+        ticks * u64::from(self.micros_per_tick) + sub_tick
+    }
+
+    /// Returns the current instant.
+    ///
+    /// This remains correct when called with the SysTick exception masked, as long as it has
+    /// not been masked for more than a whole tick.
+    pub fn now(&self) -> Instant {
+        loop {
+            let t0 = ticks();
+            let (cv1, pending, cv2) = unsafe {
+                let cv1 = SYSTICK.current_value.get();
+                let pending = SCB.icsr.get().is_systick_pending();
+                let cv2 = SYSTICK.current_value.get();
+                (cv1, pending, cv2)
+            };
+            if t0 != ticks() {
+                // the exception fired while sampling
+                continue;
+            }
+            // The counter counts down: a larger second sample means it wrapped in between and
+            // the exception is necessarily pending.
+            let (current_value, extra) = if cv2 > cv1 {
+                (cv2, 1)
+            } else {
+                (cv1, u64::from(pending))
+            };
+            return Instant::from_micros(self.to_micros(t0 + extra, current_value));
+        }
+    }
+
+    /// Busy waits for `duration`.
+    pub fn delay(&self, duration: Duration) {
+        let deadline = self.now() + duration;
+        while self.now() < deadline {}
+    }
+
+    pub fn delay_us(&self, us: u32) {
+        self.delay(Duration::from_micros(u64::from(us)))
+    }
+
+    pub fn delay_ms(&self, ms: u32) {
+        self.delay(Duration::from_millis(u64::from(ms)))
+    }
+}
+
+impl Clock for Monotonic {
+    fn now(&self) -> Instant {
+        Monotonic::now(self)
+    }
+}
+
+/// Async timers are expired from the SysTick exception, `start` must have been called.
+impl Timer for Monotonic {
+    fn wake_at(&self, deadline: Instant, waker: &Waker) {
+        let queued = interrupt::free(|cs| TIMERS.borrow(cs).borrow_mut().register(deadline, waker));
+        if !queued {
+            // too many timers: poll again until the deadline is reached.
+            waker.wake_by_ref();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_computation() {
+        let m = Monotonic::new(120_000_000, 1_000).unwrap();
+        assert_eq!(m.reload, 119_999);
+        assert_eq!(m.tick_period(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_invalid_configurations() {
+        assert_eq!(
+            Monotonic::new(120_000_000, 3).unwrap_err(),
+            MonotonicError::InvalidTickRate
+        );
+        assert_eq!(
+            Monotonic::new(120_000_000, 1).unwrap_err(),
+            MonotonicError::ReloadOutOfRange
+        );
+        assert_eq!(
+            Monotonic::new(12_000_007, 1_000).unwrap_err(),
+            MonotonicError::InexactTickRate
+        );
+    }
+
+    #[test]
+    fn test_sub_tick_resolution() {
+        let m = Monotonic::new(120_000_000, 1_000).unwrap();
+        assert_eq!(m.to_micros(0, 119_999), 0);
+        assert_eq!(m.to_micros(2, 59_999), 2_500);
+        assert_eq!(m.to_micros(3, 0), 3_999);
+    }
+
+    #[test]
+    fn test_tick_counter_carry() {
+        TICKS_LO.store(0xFFFF_FFFF, Ordering::Relaxed);
+        on_systick();
+        assert_eq!(ticks(), 1 << 32);
+    }
+}
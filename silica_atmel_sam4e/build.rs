@@ -0,0 +1,90 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A member of the family, selected by the feature of the same name.
+struct Variant {
+    name: &'static str,
+    /// Kilobytes of flash and RAM.
+    flash: u32,
+    ram: u32,
+    /// Pins of the package: the 100 pins one has no port C nor E.
+    pins: u32,
+}
+
+#[rustfmt::skip]
+const VARIANTS: &[Variant] = &[
+    Variant { name: "sam4e8c", flash: 512, ram: 128, pins: 100 },
+    Variant { name: "sam4e8e", flash: 512, ram: 128, pins: 144 },
+    Variant { name: "sam4e16c", flash: 1024, ram: 128, pins: 100 },
+    Variant { name: "sam4e16e", flash: 1024, ram: 128, pins: 144 },
+];
+
+/// Sizes in bytes the application sets through the environment, with their defaults.
+const SIZES: &[(&str, &str, Option<u32>)] = &[
+    ("SILICA_PANIC_MSG_SIZE", "panic_msg_size", Some(512)),
+    ("SILICA_STACK_SIZE", "stack_size", Some(1024)),
+    // the heap takes the rest of the RAM by default.
+    ("SILICA_HEAP_SIZE", "heap_size", None),
+];
+
+fn link_script(variant: &Variant) -> String {
+    let mut script = format!(
+        "/* Memory layout of the AT{} */\n\
+         MEMORY\n\
+         {{\n  \
+         FLASH : ORIGIN = 0x00400000, LENGTH = {}K\n  \
+         RAM   : ORIGIN = 0x20000000, LENGTH = {}K\n\
+         }}\n\n",
+        variant.name.to_uppercase(),
+        variant.flash,
+        variant.ram
+    );
+    for &(var, symbol, default) in SIZES {
+        println!("cargo:rerun-if-env-changed={}", var);
+        let size = env::var(var).ok().map(|size| {
+            size.parse::<u32>()
+                .unwrap_or_else(|_| panic!("{} is not a size in bytes: {}", var, size))
+        });
+        if let Some(size) = size.or(default) {
+            script += &format!("{} = {};\n", symbol, size);
+        }
+    }
+    script + "\nINCLUDE sam4e.x\nINCLUDE cortex-m.x\n"
+}
+
+fn main() {
+    // Put the linker scripts somewhere the linker can find it
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    File::create(out.join("sam4e.x"))
+        .unwrap()
+        .write_all(include_bytes!("sam4e.x"))
+        .unwrap();
+
+    let selected: Vec<&Variant> = VARIANTS
+        .iter()
+        .filter(|v| env::var_os(format!("CARGO_FEATURE_{}", v.name.to_uppercase())).is_some())
+        .collect();
+    // host builds, e.g. for the tests, need no memory layout.
+    let custom = env::var_os("CARGO_FEATURE_CUSTOM_MEMORY").is_some()
+        || !env::var("TARGET").unwrap().starts_with("thumb");
+    match selected.len() {
+        0 if custom => {}
+        0 => panic!("select the SAM4E variant, or the custom-memory feature to provide the layout"),
+        1 => {
+            File::create(out.join("link.x"))
+                .unwrap()
+                .write_all(link_script(selected[0]).as_bytes())
+                .unwrap();
+            println!("cargo:rustc-cfg=pins=\"{}\"", selected[0].pins);
+        }
+        _ => panic!("a single SAM4E variant must be selected"),
+    }
+
+    println!("cargo:rustc-link-search={}", out.display());
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=sam4e.x");
+}
@@ -0,0 +1,78 @@
+/* Peripheral addresses of the ATSAM4E family */
+PROVIDE(PWM = 0x40000000);
+PROVIDE(CAN0 = 0x40010000);
+PROVIDE(CAN1 = 0x40014000);
+PROVIDE(GMAC = 0x40034000);
+PROVIDE(UART1 = 0x40060600);
+PROVIDE(HSMCI = 0x40080000);
+PROVIDE(UDP = 0x40084000);
+PROVIDE(SPI = 0x40088000);
+PROVIDE(TC0 = 0x40090000);
+PROVIDE(TC1 = 0x40094000);
+PROVIDE(TC2 = 0x40098000);
+PROVIDE(USART0 = 0x400A0000);
+PROVIDE(USART1 = 0x400A4000);
+PROVIDE(TWI0 = 0x400A8000);
+PROVIDE(TWI1 = 0x400AC000);
+PROVIDE(AFEC0 = 0x400B0000);
+PROVIDE(AFEC1 = 0x400B4000);
+PROVIDE(PMC = 0x400E0400);
+PROVIDE(UART0 = 0x400E0600);
+PROVIDE(EFC = 0x400E0A00);
+PROVIDE(PIOA = 0x400E0E00);
+PROVIDE(PIOB = 0x400E1000);
+PROVIDE(PIOC = 0x400E1200);
+PROVIDE(PIOD = 0x400E1400);
+PROVIDE(PIOE = 0x400E1600);
+PROVIDE(RSTC = 0x400E1800);
+PROVIDE(SUPC = 0x400E1810);
+PROVIDE(RTT = 0x400E1830);
+PROVIDE(WDT = 0x400E1850);
+PROVIDE(RTC = 0x400E1860);
+
+/* Force the linker to keep the interrupts vector. */
+EXTERN(INTERRUPTS);
+
+/* Interrupt handlers, to be overridden by the application */
+PROVIDE(supc_handler = default_interrupt_handler);
+PROVIDE(rstc_handler = default_interrupt_handler);
+PROVIDE(rtc_handler = default_interrupt_handler);
+PROVIDE(rtt_handler = default_interrupt_handler);
+PROVIDE(wdt_handler = default_interrupt_handler);
+PROVIDE(pmc_handler = default_interrupt_handler);
+PROVIDE(efc_handler = default_interrupt_handler);
+PROVIDE(uart0_handler = default_interrupt_handler);
+PROVIDE(smc_handler = default_interrupt_handler);
+PROVIDE(pioa_handler = default_interrupt_handler);
+PROVIDE(piob_handler = default_interrupt_handler);
+PROVIDE(pioc_handler = default_interrupt_handler);
+PROVIDE(piod_handler = default_interrupt_handler);
+PROVIDE(pioe_handler = default_interrupt_handler);
+PROVIDE(usart0_handler = default_interrupt_handler);
+PROVIDE(usart1_handler = default_interrupt_handler);
+PROVIDE(hsmci_handler = default_interrupt_handler);
+PROVIDE(twi0_handler = default_interrupt_handler);
+PROVIDE(twi1_handler = default_interrupt_handler);
+PROVIDE(spi_handler = default_interrupt_handler);
+PROVIDE(dmac_handler = default_interrupt_handler);
+PROVIDE(tc0_handler = default_interrupt_handler);
+PROVIDE(tc1_handler = default_interrupt_handler);
+PROVIDE(tc2_handler = default_interrupt_handler);
+PROVIDE(tc3_handler = default_interrupt_handler);
+PROVIDE(tc4_handler = default_interrupt_handler);
+PROVIDE(tc5_handler = default_interrupt_handler);
+PROVIDE(tc6_handler = default_interrupt_handler);
+PROVIDE(tc7_handler = default_interrupt_handler);
+PROVIDE(tc8_handler = default_interrupt_handler);
+PROVIDE(afec0_handler = default_interrupt_handler);
+PROVIDE(afec1_handler = default_interrupt_handler);
+PROVIDE(dacc_handler = default_interrupt_handler);
+PROVIDE(acc_handler = default_interrupt_handler);
+PROVIDE(fpu_handler = default_interrupt_handler);
+PROVIDE(udp_handler = default_interrupt_handler);
+PROVIDE(pwm_handler = default_interrupt_handler);
+PROVIDE(can0_handler = default_interrupt_handler);
+PROVIDE(can1_handler = default_interrupt_handler);
+PROVIDE(aes_handler = default_interrupt_handler);
+PROVIDE(gmac_handler = default_interrupt_handler);
+PROVIDE(uart1_handler = default_interrupt_handler);
@@ -0,0 +1,620 @@
+//! Analog Front-End Controller
+//!
+//! Enabling a channel connects its pin to the AFEC, whatever the PIO configuration.
+//!
+//! ```ignore
+//! let mut adc = Afec::new(Afec0, Config::new().averaging(Averaging::X16), &clocks, pmc).unwrap();
+//! adc.enable_channel(3, Gain::X1).unwrap();
+//! adc.enable_channel(TEMPERATURE_SENSOR, Gain::X1).unwrap();
+//! let thermistor = adc.convert(3).unwrap();
+//! let sample = adc.convert(TEMPERATURE_SENSOR).unwrap();
+//! let die = temperature_millicelsius(sample, adc.resolution());
+//! ```
+
+use clock::Clocks;
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use pdc::{Pdc, PeripheralDmaController};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+/// Channel of the on-die temperature sensor (AFEC0 only).
+pub const TEMPERATURE_SENSOR: u8 = 15;
+/// Maximum AFEC clock.
+pub const MAX_CLOCK_HZ: u32 = 20_000_000;
+/// Mid-scale of the offset compensation DAC.
+pub const DEFAULT_OFFSET: u16 = 0x200;
+/// Reference voltage of the board, in mV.
+pub const VREF_MV: u32 = 3_300;
+
+register! {
+    /// Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        bool: _, pub start: 1;
+        bool: _, pub software_reset: 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoTriggerError(());
+
+/// Start of the conversions, besides `Afec::start`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// ADTRG pin.
+    External,
+    Tioa0,
+    Tioa1,
+    Tioa2,
+    PwmEvent0,
+    PwmEvent1,
+}
+impl TryFrom<u32> for Trigger {
+    type Error = TryIntoTriggerError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Trigger::External),
+            1 => Ok(Trigger::Tioa0),
+            2 => Ok(Trigger::Tioa1),
+            3 => Ok(Trigger::Tioa2),
+            5 => Ok(Trigger::PwmEvent0),
+            6 => Ok(Trigger::PwmEvent1),
+            _ => Err(TryIntoTriggerError(())),
+        }
+    }
+}
+impl From<Trigger> for u32 {
+    fn from(v: Trigger) -> u32 {
+        match v {
+            Trigger::External => 0,
+            Trigger::Tioa0 => 1,
+            Trigger::Tioa1 => 2,
+            Trigger::Tioa2 => 3,
+            Trigger::PwmEvent0 => 5,
+            Trigger::PwmEvent1 => 6,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// Converts along SEQ1R and SEQ2R instead of the channel numbers order.
+        bool: pub user_sequence, pub set_user_sequence: 31;
+        u8: pub transfer, pub set_transfer: 29, 28;
+        u8: pub tracking_time, pub set_tracking_time: 27, 24;
+        /// Gain and offset are set per channel.
+        bool: pub analog_change, pub set_analog_change: 23;
+        u8: pub settling, pub set_settling: 21, 20;
+        /// 0 = 0, 1 = 8, 2 = 16, 3 = 24, 4 = 64 AFEC clock periods, and more.
+        u8: pub startup, pub set_startup: 19, 16;
+        /// The AFEC clock is MCK / ((prescaler + 1) * 2).
+        u8: pub prescaler, pub set_prescaler: 15, 8;
+        bool: pub free_run, pub set_free_run: 7;
+        bool: pub fast_wake_up, pub set_fast_wake_up: 6;
+        bool: pub sleep, pub set_sleep: 5;
+        Trigger: pub trigger, pub set_trigger: 3, 1;
+        bool: pub trigger_enabled, pub enable_trigger: 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoAveragingError(());
+
+/// Hardware averaging, each step adds a bit of resolution.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Averaging {
+    /// 12 bits.
+    None,
+    /// 13 bits.
+    X4,
+    /// 14 bits.
+    X16,
+    /// 15 bits.
+    X64,
+    /// 16 bits.
+    X256,
+}
+impl Averaging {
+    pub fn resolution(self) -> u8 {
+        match self {
+            Averaging::None => 12,
+            Averaging::X4 => 13,
+            Averaging::X16 => 14,
+            Averaging::X64 => 15,
+            Averaging::X256 => 16,
+        }
+    }
+}
+impl TryFrom<u32> for Averaging {
+    type Error = TryIntoAveragingError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Averaging::None),
+            2 => Ok(Averaging::X4),
+            3 => Ok(Averaging::X16),
+            4 => Ok(Averaging::X64),
+            5 => Ok(Averaging::X256),
+            _ => Err(TryIntoAveragingError(())),
+        }
+    }
+}
+impl From<Averaging> for u32 {
+    fn from(v: Averaging) -> u32 {
+        match v {
+            Averaging::None => 0,
+            Averaging::X4 => 2,
+            Averaging::X16 => 3,
+            Averaging::X64 => 4,
+            Averaging::X256 => 5,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Extended Mode Register
+    #[derive(Copy, Clone)]
+    pub struct EMRegister(u32) {
+        /// The channel number is reported in the last converted data register.
+        bool: pub tag, pub set_tag: 24;
+        Averaging: pub averaging, pub set_averaging: 18, 16;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Last Converted Data Register
+    #[derive(Copy, Clone)]
+    pub struct LCDRegister(u32) {
+        u8: pub channel, _: 27, 24;
+        u16: pub data, _: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Interrupt Status Register, also the layout of the Interrupt Enable, Disable and Mask
+    /// Registers.
+    #[derive(Copy, Clone)]
+    pub struct ISRegister(u32) {
+        bool: pub end_of_calibration, pub set_end_of_calibration: 31;
+        bool: pub temperature_change, pub set_temperature_change: 30;
+        bool: pub rx_buffer_full, pub set_rx_buffer_full: 28;
+        bool: pub end_of_rx, pub set_end_of_rx: 27;
+        bool: pub comparison, pub set_comparison: 26;
+        bool: pub general_overrun, pub set_general_overrun: 25;
+        bool: pub data_ready, pub set_data_ready: 24;
+        /// One bit per channel.
+        u16: pub end_of_conversion, pub set_end_of_conversion: 15, 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoGainError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gain {
+    X1,
+    X2,
+    X4,
+}
+impl TryFrom<u32> for Gain {
+    type Error = TryIntoGainError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 | 1 => Ok(Gain::X1),
+            2 => Ok(Gain::X2),
+            3 => Ok(Gain::X4),
+            _ => Err(TryIntoGainError(())),
+        }
+    }
+}
+impl From<Gain> for u32 {
+    fn from(v: Gain) -> u32 {
+        match v {
+            Gain::X1 => 0,
+            Gain::X2 => 2,
+            Gain::X4 => 3,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct AnalogFrontEnd {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    pub emr: RegisterCell<EMRegister>,
+    /// Channel Sequence 1 Register, 4 bits per slot.
+    pub seq1r: RegisterCell<u32>,
+    /// Channel Sequence 2 Register
+    pub seq2r: RegisterCell<u32>,
+    /// Channel Enable Register
+    pub cher: RegisterCell<u32>,
+    /// Channel Disable Register
+    pub chdr: RegisterCell<u32>,
+    /// Channel Status Register
+    pub chsr: RoRegisterCell<u32>,
+    pub lcdr: RoRegisterCell<LCDRegister>,
+    pub ier: RegisterCell<ISRegister>,
+    pub idr: RegisterCell<ISRegister>,
+    pub imr: RoRegisterCell<ISRegister>,
+    pub isr: RoRegisterCell<ISRegister>,
+    reserved0: ReservedCell<[u32; 6]>,
+    /// Overrun Status Register
+    pub over: RoRegisterCell<u32>,
+    /// Compare Window Register
+    pub cwr: RegisterCell<u32>,
+    /// Channel Gain Register, 2 bits per channel.
+    pub cgr: RegisterCell<u32>,
+    reserved1: ReservedCell<[u32; 2]>,
+    /// Channel Differential Register
+    pub diffr: RegisterCell<u32>,
+    /// Channel Selection Register, selects the channel of CDR and COCR.
+    pub cselr: RegisterCell<u32>,
+    /// Channel Data Register
+    pub cdr: RoRegisterCell<u32>,
+    /// Channel Offset Compensation Register
+    pub cocr: RegisterCell<u32>,
+    /// Temperature Sensor Mode Register
+    pub tempmr: RegisterCell<u32>,
+    /// Temperature Compare Window Register
+    pub tempcwr: RegisterCell<u32>,
+    reserved2: ReservedCell<[u32; 7]>,
+    /// Analog Control Register
+    pub acr: RegisterCell<u32>,
+    reserved3: ReservedCell<[u32; 19]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    reserved4: ReservedCell<[u32; 5]>,
+    pub pdc: PeripheralDmaController,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AfecError {
+    /// MCK is too fast for the prescaler.
+    ClockTooFast,
+    InvalidChannel,
+    /// The channel is not converted: neither enabled nor part of the user sequence.
+    ChannelDisabled,
+    /// More than 16 slots.
+    SequenceTooLong,
+}
+
+/// Prescaler bringing MCK down to at most `MAX_CLOCK_HZ`.
+pub fn prescaler_for(mck: u32) -> Result<u8, AfecError> {
+    let divider = (mck + 2 * MAX_CLOCK_HZ - 1) / (2 * MAX_CLOCK_HZ);
+    if divider > 256 {
+        return Err(AfecError::ClockTooFast);
+    }
+    Ok(divider.saturating_sub(1) as u8)
+}
+
+/// Die temperature, in m°C, of a temperature sensor sample: 1.44V at 27°C and 4.7mV/°C.
+pub fn temperature_millicelsius(sample: u16, resolution: u8) -> i32 {
+    let microvolts = (u64::from(sample) * u64::from(VREF_MV) * 1_000) >> resolution;
+    27_000 + (microvolts as i32 - 1_440_000) * 10 / 47
+}
+
+/// Two point software calibration of a channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    offset: i32,
+    numerator: i32,
+    denominator: i32,
+}
+impl Calibration {
+    pub fn identity() -> Calibration {
+        Calibration {
+            offset: 0,
+            numerator: 1,
+            denominator: 1,
+        }
+    }
+
+    /// Maps the raw samples `low.0` and `high.0` to the expected values `low.1` and `high.1`.
+    pub fn from_points(low: (u16, i32), high: (u16, i32)) -> Calibration {
+        let denominator = i32::from(high.0) - i32::from(low.0);
+        if denominator == 0 {
+            return Calibration::identity();
+        }
+        let numerator = high.1 - low.1;
+        Calibration {
+            offset: low.1 * denominator - i32::from(low.0) * numerator,
+            numerator,
+            denominator,
+        }
+    }
+
+    pub fn apply(&self, sample: u16) -> i32 {
+        (i32::from(sample) * self.numerator + self.offset) / self.denominator
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub averaging: Averaging,
+    /// None converts on `start` only.
+    pub trigger: Option<Trigger>,
+    /// Converts continuously, ignoring the trigger.
+    pub free_run: bool,
+}
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            averaging: Averaging::None,
+            trigger: None,
+            free_run: false,
+        }
+    }
+
+    pub fn averaging(mut self, averaging: Averaging) -> Config {
+        self.averaging = averaging;
+        self
+    }
+
+    pub fn trigger(mut self, trigger: Trigger) -> Config {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    pub fn free_run(mut self) -> Config {
+        self.free_run = true;
+        self
+    }
+}
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+pub trait Instance {
+    const ID: PeripheralId;
+    const CHANNELS: u8;
+    fn regs() -> &'static mut AnalogFrontEnd;
+}
+
+pub struct Afec0;
+pub struct Afec1;
+impl Instance for Afec0 {
+    const ID: PeripheralId = PeripheralId::Afec0;
+    const CHANNELS: u8 = 16;
+    fn regs() -> &'static mut AnalogFrontEnd {
+        unsafe { &mut ::AFEC0 }
+    }
+}
+impl Instance for Afec1 {
+    const ID: PeripheralId = PeripheralId::Afec1;
+    const CHANNELS: u8 = 16;
+    fn regs() -> &'static mut AnalogFrontEnd {
+        unsafe { &mut ::AFEC1 }
+    }
+}
+
+pub struct Afec<A> {
+    instance: A,
+    averaging: Averaging,
+    /// Channels enabled with `enable_channel`.
+    enabled: u16,
+    /// Channels of the user sequence, 0 without one.
+    sequenced: u16,
+    pdc: Pdc<u16>,
+}
+
+impl<A: Instance> Afec<A> {
+    pub fn new(
+        instance: A,
+        config: Config,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Afec<A>, AfecError> {
+        let prescaler = prescaler_for(clocks.peripheral(A::ID))?;
+        pmc.enable_peripheral_clock(A::ID);
+        let regs = A::regs();
+        regs.cr.get_mut().software_reset(true);
+        {
+            let mut mr = regs.mr.get_mut();
+            mr.set_prescaler(prescaler);
+            mr.set_startup(4);
+            mr.set_settling(3);
+            mr.set_tracking_time(15);
+            mr.set_transfer(2);
+            mr.set_analog_change(true);
+            mr.set_free_run(config.free_run);
+            if let Some(trigger) = config.trigger {
+                mr.set_trigger(trigger);
+            }
+            mr.enable_trigger(config.trigger.is_some());
+        }
+        {
+            let mut emr = regs.emr.get_mut();
+            emr.set_averaging(config.averaging);
+            emr.set_tag(true);
+        }
+        // the programmable gain amplifiers and their bias current
+        regs.acr.set(0x0000_010C);
+        Ok(Afec {
+            instance,
+            averaging: config.averaging,
+            enabled: 0,
+            sequenced: 0,
+            pdc: Pdc::new(&mut A::regs().pdc),
+        })
+    }
+
+    pub fn release(self, pmc: &mut PowerManagementController) -> A {
+        self.pdc.release();
+        A::regs().chdr.set(0xFFFF);
+        pmc.disable_peripheral_clock(A::ID);
+        self.instance
+    }
+
+    /// Bits of the conversion results.
+    pub fn resolution(&self) -> u8 {
+        self.averaging.resolution()
+    }
+
+    pub fn max_value(&self) -> u16 {
+        ((1u32 << self.resolution()) - 1) as u16
+    }
+
+    fn check(channel: u8) -> Result<(), AfecError> {
+        if channel < A::CHANNELS {
+            Ok(())
+        } else {
+            Err(AfecError::InvalidChannel)
+        }
+    }
+
+    pub fn enable_channel(&mut self, channel: u8, gain: Gain) -> Result<(), AfecError> {
+        Self::check(channel)?;
+        let regs = A::regs();
+        let shift = 2 * u32::from(channel);
+        let cgr = regs.cgr.get() & !(3 << shift);
+        regs.cgr.set(cgr | (u32::from(gain) << shift));
+        regs.cselr.set(u32::from(channel));
+        regs.cocr.set(u32::from(DEFAULT_OFFSET));
+        self.enabled |= 1 << channel;
+        // the enables select the slots while a user sequence is set.
+        if self.sequenced == 0 {
+            regs.cher.set(1 << channel);
+        }
+        Ok(())
+    }
+
+    pub fn disable_channel(&mut self, channel: u8) -> Result<(), AfecError> {
+        Self::check(channel)?;
+        self.enabled &= !(1 << channel);
+        if self.sequenced == 0 {
+            A::regs().chdr.set(1 << channel);
+        }
+        Ok(())
+    }
+
+    /// Hardware offset compensation of a channel, `DEFAULT_OFFSET` being neutral.
+    pub fn set_offset(&mut self, channel: u8, offset: u16) -> Result<(), AfecError> {
+        Self::check(channel)?;
+        let regs = A::regs();
+        regs.cselr.set(u32::from(channel));
+        regs.cocr.set(u32::from(offset & 0x3FF));
+        Ok(())
+    }
+
+    /// Converts the channels in the order of `sequence` instead of their number's. An empty
+    /// sequence restores the default order and the channels enabled before.
+    pub fn set_sequence(&mut self, sequence: &[u8]) -> Result<(), AfecError> {
+        if sequence.len() > 16 {
+            return Err(AfecError::SequenceTooLong);
+        }
+        let mut seq = [0u32; 2];
+        let mut sequenced = 0u16;
+        for (slot, &channel) in sequence.iter().enumerate() {
+            Self::check(channel)?;
+            seq[slot / 8] |= u32::from(channel) << (4 * (slot % 8));
+            sequenced |= 1 << channel;
+        }
+        let regs = A::regs();
+        regs.seq1r.set(seq[0]);
+        regs.seq2r.set(seq[1]);
+        regs.mr.get_mut().set_user_sequence(!sequence.is_empty());
+        // the sequence converts as many slots as channels are enabled.
+        let enables = if sequence.is_empty() {
+            u32::from(self.enabled)
+        } else {
+            (1 << sequence.len()) - 1
+        };
+        regs.chdr.set(!enables & 0xFFFF);
+        regs.cher.set(enables);
+        self.sequenced = sequenced;
+        Ok(())
+    }
+
+    /// Starts converting the enabled channels.
+    pub fn start(&mut self) {
+        A::regs().cr.get_mut().start(true);
+    }
+
+    /// Last conversion of `channel`, if it completed since it was last read.
+    pub fn read(&mut self, channel: u8) -> Result<Option<u16>, AfecError> {
+        Self::check(channel)?;
+        let regs = A::regs();
+        if regs.isr.get().end_of_conversion() & (1 << channel) == 0 {
+            return Ok(None);
+        }
+        regs.cselr.set(u32::from(channel));
+        Ok(Some(regs.cdr.get() as u16))
+    }
+
+    /// Converts an enabled channel and waits for the result.
+    pub fn convert(&mut self, channel: u8) -> Result<u16, AfecError> {
+        Self::check(channel)?;
+        let converted = if self.sequenced != 0 {
+            self.sequenced
+        } else {
+            self.enabled
+        };
+        if converted & (1 << channel) == 0 {
+            return Err(AfecError::ChannelDisabled);
+        }
+        self.start();
+        loop {
+            if let Some(sample) = self.read(channel)? {
+                return Ok(sample);
+            }
+        }
+    }
+
+    /// Stores the next conversions into `buffer` through the PDC once the queued buffers are
+    /// full, `buffer` is given back if two are queued. The conversions are tagged with their
+    /// channel in bits 12 to 15 unless the averaging uses them.
+    pub fn read_dma(&mut self, buffer: &'static mut [u16]) -> Result<(), &'static mut [u16]> {
+        self.pdc.read(buffer)
+    }
+
+    /// The oldest buffer of conversions, if full.
+    pub fn read_dma_done(&mut self) -> Option<&'static mut [u16]> {
+        self.pdc.read_done()
+    }
+
+    /// Stops the PDC and returns the conversions stored in the oldest buffer. `read_dma_done`
+    /// then hands back all the buffers.
+    pub fn stop_dma(&mut self) -> usize {
+        self.pdc.stop_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prescaler() {
+        assert_eq!(Ok(2), prescaler_for(120_000_000));
+        assert_eq!(Ok(0), prescaler_for(4_000_000));
+        assert_eq!(0x100, ::core::mem::size_of::<AnalogFrontEnd>() - 0x28);
+    }
+
+    #[test]
+    fn test_temperature() {
+        // 1.44V and 1.487V on 16 bits at 3.3V
+        let t = temperature_millicelsius(28_599, 16);
+        assert!(t > 26_900 && t < 27_100);
+        let t = temperature_millicelsius(29_532, 16);
+        assert!(t > 36_900 && t < 37_100);
+    }
+
+    #[test]
+    fn test_calibration() {
+        let c = Calibration::from_points((100, 0), (4000, 3_000));
+        assert_eq!(0, c.apply(100));
+        assert_eq!(3_000, c.apply(4000));
+        assert_eq!(1_500, c.apply(2050));
+        assert_eq!(2048, Calibration::identity().apply(2048));
+    }
+}
@@ -0,0 +1,849 @@
+//! Controller Area Network
+//!
+//! `Can` moves the frames between ring buffers and the mailboxes from the interrupt handler, in
+//! which it is shared through a `Mutex`:
+//!
+//! ```ignore
+//! static TOOLS: Mutex<RefCell<Option<Can<Can0>>>> = Mutex::new(RefCell::new(None));
+//! static mut RX_FRAMES: [Frame; 32] = [Frame::EMPTY; 32];
+//! static mut TX_FRAMES: [Frame; 16] = [Frame::EMPTY; 16];
+//!
+//! let config = Config::new(1_000_000).filter(Filter::new(Id::Standard(0x100), 0x700));
+//! let can = Can::new(Can0, pins, &config, unsafe { &mut RX_FRAMES }, unsafe { &mut TX_FRAMES },
+//!                    &clocks, unsafe { &mut PMC })?;
+//!
+//! #[no_mangle]
+//! pub unsafe extern "C" fn can0_handler() {
+//!     interrupt::free(|cs| TOOLS.borrow(cs).borrow_mut().as_mut().map(Can::on_interrupt));
+//! }
+//! ```
+//!
+//! Each acceptance filter takes a receive mailbox. The last mailbox transmits: the frames leave
+//! in the order they were queued. A controller gone bus-off recovers by itself after 128
+//! sequences of 11 recessive bits, keeping the queued frames.
+use clock::Clocks;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use interrupts;
+use pio::piob::{PB2, PB3};
+#[cfg(not(pins = "100"))]
+use pio::pioc::{PC12, PC15};
+#[cfg(not(pins = "100"))]
+use pio::C;
+use pio::{Peripheral, Pin, A};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use silica::ring::RingBuffer;
+
+pub const MAILBOXES: usize = 8;
+const TX_MAILBOX: usize = MAILBOXES - 1;
+
+/// Time quanta per bit.
+const MIN_QUANTA: u32 = 8;
+const MAX_QUANTA: u32 = 25;
+/// Peripheral clock cycles per time quantum, BRP = 0 is forbidden.
+const MIN_PRESCALER: u32 = 2;
+const MAX_PRESCALER: u32 = 128;
+/// Length of the propagation and phase 1 segments, in time quanta.
+const MAX_SEGMENT: u32 = 8;
+/// Phase 2 lasts at least the information processing time.
+const MIN_PHASE2: u32 = 2;
+const MAX_SJW: u32 = 4;
+/// Sample points, in thousandths of the bit, the bit timing is computed for.
+pub const MIN_SAMPLE_POINT: u32 = 500;
+pub const MAX_SAMPLE_POINT: u32 = 900;
+
+register! {
+    @impl_debug;
+    /// CAN Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        u8: pub rx_sync, pub set_rx_sync: 26, 24;
+        /// Disables the automatic retransmission.
+        bool: pub disable_repeat, pub set_disable_repeat: 7;
+        bool: pub timer_freeze, pub set_timer_freeze: 6;
+        bool: pub time_triggered, pub set_time_triggered: 5;
+        bool: pub timestamp_end_of_frame, pub set_timestamp_end_of_frame: 4;
+        bool: pub overload, pub set_overload: 3;
+        /// Listen only mode.
+        bool: pub autobaud, pub set_autobaud: 2;
+        bool: pub low_power, pub set_low_power: 1;
+        bool: pub enabled, pub set_enabled: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Interrupt Enable Register, also the layout of the Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct IRegister(u32) {
+        bool: pub bit_error, pub set_bit_error: 28;
+        bool: pub form_error, pub set_form_error: 27;
+        bool: pub ack_error, pub set_ack_error: 26;
+        bool: pub stuff_error, pub set_stuff_error: 25;
+        bool: pub crc_error, pub set_crc_error: 24;
+        bool: pub timestamp, pub set_timestamp: 23;
+        bool: pub timer_overflow, pub set_timer_overflow: 22;
+        bool: pub wakeup, pub set_wakeup: 21;
+        bool: pub sleep, pub set_sleep: 20;
+        bool: pub bus_off, pub set_bus_off: 19;
+        bool: pub error_passive, pub set_error_passive: 18;
+        bool: pub warning, pub set_warning: 17;
+        bool: pub error_active, pub set_error_active: 16;
+        /// One bit per mailbox.
+        u8: pub mailboxes, pub set_mailboxes: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Status Register, the error flags are cleared by reading it.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub overload_busy, _: 31;
+        bool: pub tx_busy, _: 30;
+        bool: pub rx_busy, _: 29;
+        bool: pub bit_error, _: 28;
+        bool: pub form_error, _: 27;
+        bool: pub ack_error, _: 26;
+        bool: pub stuff_error, _: 25;
+        bool: pub crc_error, _: 24;
+        bool: pub timestamp, _: 23;
+        bool: pub timer_overflow, _: 22;
+        bool: pub wakeup, _: 21;
+        bool: pub sleep, _: 20;
+        bool: pub bus_off, _: 19;
+        bool: pub error_passive, _: 18;
+        /// An error counter reached 96.
+        bool: pub warning, _: 17;
+        bool: pub error_active, _: 16;
+        /// Mailboxes with their ready flag set.
+        u8: pub mailboxes, _: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Baudrate Register, written while the controller is disabled.
+    #[derive(Copy, Clone)]
+    pub struct BRegister(u32) {
+        /// Samples the bit three times.
+        bool: pub three_samples, pub set_three_samples: 24;
+        /// Peripheral clock cycles per time quantum, minus 1.
+        u8: pub prescaler, pub set_prescaler: 22, 16;
+        /// The lengths in time quanta, minus 1.
+        u8: pub sjw, pub set_sjw: 13, 12;
+        u8: pub propagation, pub set_propagation: 10, 8;
+        u8: pub phase1, pub set_phase1: 6, 4;
+        u8: pub phase2, pub set_phase2: 2, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Error Counter Register
+    #[derive(Copy, Clone)]
+    pub struct ECRegister(u32) {
+        u8: pub transmit_errors, _: 23, 16;
+        u8: pub receive_errors, _: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Message Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MMRegister(u32) {
+        /// 0 disabled, 1 receive, 2 receive with overwrite, 3 transmit, 4 consumer, 5 producer.
+        u8: pub object_type, pub set_object_type: 26, 24;
+        /// 0 is the highest.
+        u8: pub priority, pub set_priority: 19, 16;
+        u16: pub time_mark, pub set_time_mark: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Message ID Register, also the layout of the Acceptance Mask Register.
+    #[derive(Copy, Clone)]
+    pub struct MIDRegister(u32) {
+        bool: pub extended, pub set_extended: 29;
+        /// A standard identifier takes the 11 most significant bits.
+        u32: pub id, pub set_id: 28, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Message Status Register
+    #[derive(Copy, Clone)]
+    pub struct MSRegister(u32) {
+        /// Frames were lost, overwritten or not.
+        bool: pub message_ignored, _: 24;
+        /// A frame was received, or the mailbox is free to transmit.
+        bool: pub ready, _: 23;
+        bool: pub aborted, _: 22;
+        bool: pub remote, _: 20;
+        u8: pub len, _: 19, 16;
+        u16: pub timestamp, _: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// CAN Message Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct MCRegister(u32) {
+        /// Sends the frame, or lets the mailbox receive the next one.
+        bool: _, pub set_transfer: 23;
+        bool: _, pub set_abort: 22;
+        bool: _, pub set_remote: 20;
+        u8: _, pub set_len: 19, 16;
+    }
+}
+
+#[repr(C)]
+pub struct Mailbox {
+    pub mmr: RegisterCell<MMRegister>,
+    pub mam: RegisterCell<MIDRegister>,
+    pub mid: RegisterCell<MIDRegister>,
+    /// Message Family ID Register
+    pub mfid: RoRegisterCell<u32>,
+    pub msr: RoRegisterCell<MSRegister>,
+    /// Message Data Low and High Registers, the first byte in the least significant one.
+    pub mdl: RegisterCell<u32>,
+    pub mdh: RegisterCell<u32>,
+    pub mcr: RegisterCell<MCRegister>,
+}
+
+/// Controller Area Network
+#[repr(C)]
+pub struct ControllerAreaNetwork {
+    pub mr: RegisterCell<MRegister>,
+    pub ier: RegisterCell<IRegister>,
+    pub idr: RegisterCell<IRegister>,
+    pub imr: RoRegisterCell<IRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub br: RegisterCell<BRegister>,
+    /// Timer Register
+    pub tim: RoRegisterCell<u32>,
+    /// Timestamp Register
+    pub timestp: RoRegisterCell<u32>,
+    pub ecr: RoRegisterCell<ECRegister>,
+    /// Transfer Command Register, one bit per mailbox.
+    pub tcr: RegisterCell<u32>,
+    /// Abort Command Register, one bit per mailbox.
+    pub acr: RegisterCell<u32>,
+    _reserved0: ReservedCell<[u32; 46]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    _reserved1: ReservedCell<[u32; 69]>,
+    pub mailboxes: [Mailbox; MAILBOXES],
+}
+
+/// Lengths of the bit segments, in time quanta.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitTiming {
+    /// Peripheral clock cycles per time quantum.
+    pub prescaler: u8,
+    pub propagation: u8,
+    pub phase1: u8,
+    pub phase2: u8,
+    /// Synchronization jump width.
+    pub sjw: u8,
+}
+
+impl BitTiming {
+    /// Time quanta per bit, the synchronization segment included.
+    pub fn quanta(self) -> u32 {
+        1 + u32::from(self.propagation) + u32::from(self.phase1) + u32::from(self.phase2)
+    }
+
+    /// Position of the sample point in the bit, in thousandths.
+    pub fn sample_point(self) -> u32 {
+        (self.quanta() - u32::from(self.phase2)) * 1000 / self.quanta()
+    }
+
+    fn register(self) -> BRegister {
+        let mut br = BRegister(0);
+        br.set_prescaler(self.prescaler - 1);
+        br.set_propagation(self.propagation - 1);
+        br.set_phase1(self.phase1 - 1);
+        br.set_phase2(self.phase2 - 1);
+        br.set_sjw(self.sjw - 1);
+        br
+    }
+}
+
+/// The bit timing giving exactly `bitrate` from the peripheral clock, with the sample point
+/// closest to `sample_point` thousandths of the bit.
+pub fn bit_timing(clock: u32, bitrate: u32, sample_point: u32) -> Option<BitTiming> {
+    if sample_point < MIN_SAMPLE_POINT || sample_point > MAX_SAMPLE_POINT {
+        return None;
+    }
+    let mut best: Option<(u32, BitTiming)> = None;
+    // the most time quanta first: they give the finest resynchronization.
+    for quanta in (MIN_QUANTA..=MAX_QUANTA).rev() {
+        let per_bit = match bitrate.checked_mul(quanta) {
+            Some(per_bit) if per_bit != 0 && clock % per_bit == 0 => per_bit,
+            _ => continue,
+        };
+        let prescaler = clock / per_bit;
+        if prescaler < MIN_PRESCALER || prescaler > MAX_PRESCALER {
+            continue;
+        }
+        let sampled = (quanta * sample_point + 500) / 1000;
+        let phase2 = quanta
+            .saturating_sub(sampled)
+            .max(MIN_PHASE2)
+            .max((quanta - 1).saturating_sub(2 * MAX_SEGMENT))
+            .min(MAX_SEGMENT);
+        // the propagation and phase 1 segments, kept balanced with phase 2.
+        let segments = match (quanta - 1).checked_sub(phase2) {
+            Some(segments) if segments >= 2 => segments,
+            _ => continue,
+        };
+        let phase1 = phase2
+            .min(segments - 1)
+            .max(segments.saturating_sub(MAX_SEGMENT));
+        if phase1 == 0 {
+            continue;
+        }
+        let timing = BitTiming {
+            prescaler: prescaler as u8,
+            propagation: (segments - phase1) as u8,
+            phase1: phase1 as u8,
+            phase2: phase2 as u8,
+            sjw: phase1.min(phase2).min(MAX_SJW) as u8,
+        };
+        let error = (timing.sample_point() as i32 - sample_point as i32).abs() as u32;
+        match best {
+            Some((best_error, _)) if best_error <= error => {}
+            _ => best = Some((error, timing)),
+        }
+    }
+    best.map(|(_, timing)| timing)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Id {
+    /// 11 bits.
+    Standard(u16),
+    /// 29 bits.
+    Extended(u32),
+}
+
+impl Id {
+    fn is_valid(self) -> bool {
+        match self {
+            Id::Standard(id) => id <= 0x7FF,
+            Id::Extended(id) => id <= 0x1FFF_FFFF,
+        }
+    }
+
+    fn register(self) -> MIDRegister {
+        let mut mid = MIDRegister(0);
+        match self {
+            Id::Standard(id) => mid.set_id(u32::from(id) << 18),
+            Id::Extended(id) => {
+                mid.set_extended(true);
+                mid.set_id(id);
+            }
+        }
+        mid
+    }
+
+    fn from_register(mid: MIDRegister) -> Id {
+        if mid.extended() {
+            Id::Extended(mid.id())
+        } else {
+            Id::Standard((mid.id() >> 18) as u16)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    len: u8,
+    data: [u8; 8],
+}
+
+impl Frame {
+    /// Placeholder filling the frame buffers.
+    pub const EMPTY: Frame = Frame {
+        id: Id::Standard(0),
+        remote: false,
+        len: 0,
+        data: [0; 8],
+    };
+
+    /// A data frame, None if `id` is out of range or `data` longer than 8 bytes.
+    pub fn new(id: Id, data: &[u8]) -> Option<Frame> {
+        if !id.is_valid() || data.len() > 8 {
+            return None;
+        }
+        let mut frame = Frame {
+            id,
+            len: data.len() as u8,
+            ..Frame::EMPTY
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// A remote frame requesting `len` bytes.
+    pub fn remote(id: Id, len: u8) -> Option<Frame> {
+        if !id.is_valid() || len > 8 {
+            return None;
+        }
+        Some(Frame {
+            id,
+            remote: true,
+            len,
+            ..Frame::EMPTY
+        })
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+    /// The data, empty for a remote frame.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..usize::from(self.len)]
+        }
+    }
+}
+
+/// Accepts the frames of the same kind of identifier matching `id` on the bits set in `mask`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    id: Id,
+    mask: u32,
+}
+
+impl Filter {
+    pub fn new(id: Id, mask: u32) -> Filter {
+        Filter { id, mask }
+    }
+
+    /// The acceptance mask and identifier registers.
+    fn registers(&self) -> (MIDRegister, MIDRegister) {
+        let mut mam = match self.id {
+            Id::Standard(_) => Id::Standard(self.mask as u16 & 0x7FF),
+            Id::Extended(_) => Id::Extended(self.mask & 0x1FFF_FFFF),
+        }
+        .register();
+        // the kind of identifier is always compared.
+        mam.set_extended(true);
+        (mam, self.id.register())
+    }
+}
+
+pub struct Config {
+    bitrate: u32,
+    sample_point: u32,
+    filters: [Option<Filter>; MAILBOXES],
+    filter_count: usize,
+}
+impl Config {
+    /// Sampling at 87.5% of the bit. Without filters, all the frames are received.
+    pub fn new(bitrate: u32) -> Config {
+        Config {
+            bitrate,
+            sample_point: 875,
+            filters: [None; MAILBOXES],
+            filter_count: 0,
+        }
+    }
+    /// Position of the sample point in the bit, in thousandths, from `MIN_SAMPLE_POINT` to
+    /// `MAX_SAMPLE_POINT`.
+    pub fn sample_point(mut self, sample_point: u32) -> Config {
+        self.sample_point = sample_point;
+        self
+    }
+    /// Adds a receive mailbox, up to 7.
+    pub fn filter(mut self, filter: Filter) -> Config {
+        if self.filter_count < MAILBOXES {
+            self.filters[self.filter_count] = Some(filter);
+        }
+        self.filter_count += 1;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CanError {
+    /// The peripheral clock cannot give the bitrate.
+    InvalidBitrate,
+    /// The sample point is not between `MIN_SAMPLE_POINT` and `MAX_SAMPLE_POINT`.
+    InvalidSamplePoint,
+    /// One mailbox is left to transmit: 7 filters at most.
+    TooManyFilters,
+}
+
+/// Fault confinement state, from the error counters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorState {
+    Active,
+    /// An error counter reached 96: the bus is disturbed.
+    Warning,
+    /// An error counter reached 128: the errors are signaled without disturbing the bus.
+    Passive,
+    /// The transmit error counter reached 256: the controller is off the bus.
+    BusOff,
+}
+
+pub trait Instance {
+    const ID: PeripheralId;
+    type Tx;
+    type Rx;
+
+    fn regs() -> &'static mut ControllerAreaNetwork;
+}
+
+pub struct Can0;
+/// Not on the 100 pins packages.
+#[cfg(not(pins = "100"))]
+pub struct Can1;
+
+impl Instance for Can0 {
+    const ID: PeripheralId = PeripheralId::Can0;
+    type Tx = Pin<PB2, Peripheral<A>>;
+    type Rx = Pin<PB3, Peripheral<A>>;
+    fn regs() -> &'static mut ControllerAreaNetwork {
+        unsafe { &mut ::CAN0 }
+    }
+}
+#[cfg(not(pins = "100"))]
+impl Instance for Can1 {
+    const ID: PeripheralId = PeripheralId::Can1;
+    type Tx = Pin<PC15, Peripheral<C>>;
+    type Rx = Pin<PC12, Peripheral<C>>;
+    fn regs() -> &'static mut ControllerAreaNetwork {
+        unsafe { &mut ::CAN1 }
+    }
+}
+
+pub struct Pins<I: Instance> {
+    pub tx: I::Tx,
+    pub rx: I::Rx,
+}
+
+pub struct Can<I: Instance> {
+    instance: I,
+    pins: Pins<I>,
+    rx: RingBuffer<Frame, &'static mut [Frame]>,
+    tx: RingBuffer<Frame, &'static mut [Frame]>,
+    /// Mask of the receive mailboxes.
+    rx_mailboxes: u8,
+    bus_off_count: u32,
+    overruns: u32,
+}
+
+impl<I: Instance> Can<I> {
+    /// Joins the bus and enables the interrupt of the controller in the NVIC: its handler must
+    /// call `on_interrupt`.
+    pub fn new(
+        instance: I,
+        pins: Pins<I>,
+        config: &Config,
+        rx_buffer: &'static mut [Frame],
+        tx_buffer: &'static mut [Frame],
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Can<I>, CanError> {
+        if config.filter_count >= MAILBOXES {
+            return Err(CanError::TooManyFilters);
+        }
+        if config.sample_point < MIN_SAMPLE_POINT || config.sample_point > MAX_SAMPLE_POINT {
+            return Err(CanError::InvalidSamplePoint);
+        }
+        let timing = bit_timing(
+            clocks.peripheral(I::ID),
+            config.bitrate,
+            config.sample_point,
+        )
+        .ok_or(CanError::InvalidBitrate)?;
+        pmc.enable_peripheral_clock(I::ID);
+        let regs = I::regs();
+        regs.mr.set(MRegister(0));
+        regs.idr.set(IRegister(!0));
+        regs.br.set(timing.register());
+
+        let all = [
+            Some(Filter::new(Id::Standard(0), 0)),
+            Some(Filter::new(Id::Extended(0), 0)),
+        ];
+        let filters = if config.filter_count == 0 {
+            &all[..]
+        } else {
+            &config.filters[..config.filter_count]
+        };
+        let mut rx_mailboxes = 0;
+        for (i, mailbox) in regs.mailboxes.iter_mut().enumerate() {
+            mailbox.mmr.set(MMRegister(0));
+            let mut mmr = MMRegister(0);
+            if let Some(&Some(filter)) = filters.get(i) {
+                let (mam, mid) = filter.registers();
+                mailbox.mam.set(mam);
+                mailbox.mid.set(mid);
+                mmr.set_object_type(1);
+                mailbox.mmr.set(mmr);
+                let mut mcr = MCRegister(0);
+                mcr.set_transfer(true);
+                mailbox.mcr.set(mcr);
+                rx_mailboxes |= 1 << i;
+            } else if i == TX_MAILBOX {
+                mmr.set_object_type(3);
+                mailbox.mmr.set(mmr);
+            }
+        }
+
+        let mut ier = IRegister(0);
+        ier.set_mailboxes(rx_mailboxes);
+        ier.set_bus_off(true);
+        regs.ier.set(ier);
+        regs.mr.get_mut().set_enabled(true);
+        interrupts::enable(I::ID);
+
+        Ok(Can {
+            instance,
+            pins,
+            rx: RingBuffer::new(rx_buffer),
+            tx: RingBuffer::new(tx_buffer),
+            rx_mailboxes,
+            bus_off_count: 0,
+            overruns: 0,
+        })
+    }
+
+    pub fn release(
+        self,
+        pmc: &mut PowerManagementController,
+    ) -> (I, Pins<I>, &'static mut [Frame], &'static mut [Frame]) {
+        interrupts::disable(I::ID);
+        let regs = I::regs();
+        regs.idr.set(IRegister(!0));
+        regs.mr.set(MRegister(0));
+        pmc.disable_peripheral_clock(I::ID);
+        (
+            self.instance,
+            self.pins,
+            self.rx.release(),
+            self.tx.release(),
+        )
+    }
+
+    /// Moves the received frames to the receive buffer and the next frame to send to the
+    /// transmit mailbox, and follows the bus-off state.
+    pub fn on_interrupt(&mut self) {
+        let regs = I::regs();
+        let sr = regs.sr.get();
+
+        // the state flags are levels: the interrupt watches the way out of the current state.
+        let mut bus_off = IRegister(0);
+        bus_off.set_bus_off(true);
+        let mut active = IRegister(0);
+        active.set_error_active(true);
+        if sr.bus_off() && regs.imr.get().bus_off() {
+            self.bus_off_count += 1;
+            regs.idr.set(bus_off);
+            regs.ier.set(active);
+        } else if !sr.bus_off() && regs.imr.get().error_active() {
+            regs.idr.set(active);
+            regs.ier.set(bus_off);
+        }
+
+        for i in 0..MAILBOXES {
+            if self.rx_mailboxes & sr.mailboxes() & 1 << i == 0 {
+                continue;
+            }
+            let mailbox = &mut regs.mailboxes[i];
+            let msr = mailbox.msr.get();
+            let mut frame = Frame {
+                id: Id::from_register(mailbox.mid.get()),
+                remote: msr.remote(),
+                len: msr.len().min(8),
+                data: [0; 8],
+            };
+            let (low, high) = (mailbox.mdl.get(), mailbox.mdh.get());
+            for (j, b) in frame.data.iter_mut().enumerate() {
+                let word = if j < 4 { low } else { high };
+                *b = (word >> (8 * (j % 4))) as u8;
+            }
+            let mut mcr = MCRegister(0);
+            mcr.set_transfer(true);
+            mailbox.mcr.set(mcr);
+            if self.rx.push(frame).is_err() || msr.message_ignored() {
+                self.overruns += 1;
+            }
+        }
+
+        if sr.mailboxes() & 1 << TX_MAILBOX != 0
+            && regs.imr.get().mailboxes() & 1 << TX_MAILBOX != 0
+        {
+            match self.tx.pop() {
+                Some(frame) => {
+                    let mailbox = &mut regs.mailboxes[TX_MAILBOX];
+                    mailbox.mid.set(frame.id.register());
+                    let word = |bytes: &[u8]| {
+                        bytes
+                            .iter()
+                            .rev()
+                            .fold(0, |acc, &b| acc << 8 | u32::from(b))
+                    };
+                    mailbox.mdl.set(word(&frame.data[..4]));
+                    mailbox.mdh.set(word(&frame.data[4..]));
+                    let mut mcr = MCRegister(0);
+                    mcr.set_remote(frame.remote);
+                    mcr.set_len(frame.len);
+                    mcr.set_transfer(true);
+                    mailbox.mcr.set(mcr);
+                }
+                None => {
+                    let mut idr = IRegister(0);
+                    idr.set_mailboxes(1 << TX_MAILBOX);
+                    regs.idr.set(idr);
+                }
+            }
+        }
+    }
+
+    /// Queues a frame, given back if the transmit buffer is full.
+    pub fn transmit(&mut self, frame: Frame) -> Result<(), Frame> {
+        self.tx.push(frame)?;
+        let mut ier = IRegister(0);
+        ier.set_mailboxes(1 << TX_MAILBOX);
+        I::regs().ier.set(ier);
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> Option<Frame> {
+        self.rx.pop()
+    }
+
+    /// Frames waiting in the receive buffer.
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// The frames queued are sent.
+    pub fn is_flushed(&self) -> bool {
+        self.tx.is_empty() && I::regs().mailboxes[TX_MAILBOX].msr.get().ready()
+    }
+
+    pub fn error_state(&self) -> ErrorState {
+        let sr = I::regs().sr.get();
+        if sr.bus_off() {
+            ErrorState::BusOff
+        } else if sr.error_passive() {
+            ErrorState::Passive
+        } else if sr.warning() {
+            ErrorState::Warning
+        } else {
+            ErrorState::Active
+        }
+    }
+
+    /// The transmit and receive error counters.
+    pub fn error_counters(&self) -> (u8, u8) {
+        let ecr = I::regs().ecr.get();
+        (ecr.transmit_errors(), ecr.receive_errors())
+    }
+
+    /// Times the controller went bus-off.
+    pub fn bus_off_count(&self) -> u32 {
+        self.bus_off_count
+    }
+
+    /// Frames lost because the receive buffer or a mailbox was full.
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_timing() {
+        let timing = bit_timing(120_000_000, 250_000, 875).unwrap();
+        assert_eq!(
+            BitTiming {
+                prescaler: 30,
+                propagation: 8,
+                phase1: 5,
+                phase2: 2,
+                sjw: 2,
+            },
+            timing
+        );
+        assert_eq!(875, timing.sample_point());
+        assert_eq!(0x001D_1741, timing.register().0);
+
+        // 15 quanta sample at 86.7%, closer than the 12 quanta at 83.3%.
+        let timing = bit_timing(120_000_000, 1_000_000, 875).unwrap();
+        assert_eq!((8, 15), (timing.prescaler, timing.quanta()));
+        // 24 quanta cannot fit the segments before a sample point at 75%.
+        let timing = bit_timing(120_000_000, 500_000, 750).unwrap();
+        assert_eq!(
+            (12, 20, 750),
+            (timing.prescaler, timing.quanta(), timing.sample_point())
+        );
+
+        // too slow, or not a divisor of the clock.
+        assert_eq!(None, bit_timing(120_000_000, 10_000, 875));
+        assert_eq!(None, bit_timing(120_000_000, 833_333, 875));
+        assert_eq!(None, bit_timing(12_000_000, 1_000_000, 875));
+        assert_eq!(None, bit_timing(120_000_000, 0, 875));
+
+        assert_eq!(None, bit_timing(120_000_000, 500_000, 100));
+        assert_eq!(None, bit_timing(120_000_000, 500_000, 950));
+        for sample_point in MIN_SAMPLE_POINT..=MAX_SAMPLE_POINT {
+            for &bitrate in &[125_000, 250_000, 500_000, 1_000_000] {
+                let timing = bit_timing(120_000_000, bitrate, sample_point).unwrap();
+                assert!(timing.propagation > 0 && timing.phase1 > 0 && timing.sjw > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(None, Frame::new(Id::Standard(0x800), &[]));
+        assert_eq!(None, Frame::new(Id::Extended(0x1234), &[0; 9]));
+        let frame = Frame::new(Id::Standard(0x123), &[1, 2, 3]).unwrap();
+        assert_eq!(&[1, 2, 3], frame.data());
+        assert_eq!(
+            &[] as &[u8],
+            Frame::remote(Id::Standard(1), 4).unwrap().data()
+        );
+
+        let mid = Id::Standard(0x123).register();
+        assert_eq!(0x048C_0000, mid.0);
+        assert_eq!(Id::Standard(0x123), Id::from_register(mid));
+        let mid = Id::Extended(0x1ABC_DEF0).register();
+        assert_eq!(0x3ABC_DEF0, mid.0);
+        assert_eq!(Id::Extended(0x1ABC_DEF0), Id::from_register(mid));
+
+        let (mam, mid) = Filter::new(Id::Standard(0x100), 0x700).registers();
+        assert_eq!((0x3C00_0000, 0x0400_0000), (mam.0, mid.0));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0x20, size_of::<Mailbox>());
+        assert_eq!(0x300, size_of::<ControllerAreaNetwork>());
+    }
+}
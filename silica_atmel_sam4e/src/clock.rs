@@ -0,0 +1,338 @@
+//! Clock tree configuration.
+//!
+//! MAINCK comes from the fast RC oscillator (4, 8 or 12MHz) or the 3-20MHz crystal oscillator
+//! and feeds PLLA (80-240MHz). The master clock (MCK), which clocks the core and the
+//! peripherals, is the slow clock, MAINCK or PLLA divided by the prescaler.
+//!
+//! ```ignore
+//! let clocks = ClockConfig::new()
+//!     .main_clock(MainClock::Crystal(12_000_000))
+//!     .plla(20, 1)
+//!     .master_clock(ClockSource::Plla, Prescaler::Div2)
+//!     .freeze(unsafe { &mut PMC }, unsafe { &mut EFC })
+//!     .unwrap();
+//! assert_eq!(120_000_000, clocks.mck());
+//! ```
+
+use efc::{self, EnhancedEmbeddedFlashController};
+use pmc::{ClockSource, PeripheralId, PowerManagementController, Prescaler};
+
+/// Frequency of the slow clock.
+pub const SLOW_CLOCK_HZ: u32 = 32_768;
+/// Maximum frequency of the master clock.
+pub const MAX_MCK_HZ: u32 = 120_000_000;
+
+/// Crystal start up time, in units of 8 slow clock cycles (~15ms).
+const CRYSTAL_STARTUP: u8 = 62;
+/// Slow clock cycles before PLLA reports a lock.
+const PLLA_COUNT: u8 = 0x3F;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FastRc {
+    Mhz4,
+    Mhz8,
+    Mhz12,
+}
+impl FastRc {
+    fn hz(self) -> u32 {
+        match self {
+            FastRc::Mhz4 => 4_000_000,
+            FastRc::Mhz8 => 8_000_000,
+            FastRc::Mhz12 => 12_000_000,
+        }
+    }
+    fn bits(self) -> u8 {
+        match self {
+            FastRc::Mhz4 => 0,
+            FastRc::Mhz8 => 1,
+            FastRc::Mhz12 => 2,
+        }
+    }
+}
+
+/// Source of MAINCK.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MainClock {
+    FastRc(FastRc),
+    /// Crystal of the given frequency between XIN and XOUT.
+    Crystal(u32),
+    /// External clock of the given frequency on XIN.
+    Bypass(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockError {
+    /// The crystal or external clock is not within 3-20MHz.
+    InvalidMainClock,
+    /// The multiplier is not within 2-63 or the divider is 0.
+    InvalidPllRatio,
+    /// MAINCK divided by the PLL divider is not within 3-32MHz.
+    InvalidPllInput,
+    /// The PLL output is not within 80-240MHz.
+    InvalidPllOutput,
+    /// PLLA is selected but not configured.
+    PllDisabled,
+    /// The master clock would exceed 120MHz.
+    MasterClockTooFast,
+}
+
+/// Frequencies of the clock tree once configured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clocks {
+    main_hz: u32,
+    plla_hz: u32,
+    mck_hz: u32,
+}
+impl Clocks {
+    /// The clocks after a reset: MCK runs from the 4MHz fast RC oscillator.
+    pub fn at_reset() -> Clocks {
+        Clocks {
+            main_hz: 4_000_000,
+            plla_hz: 0,
+            mck_hz: 4_000_000,
+        }
+    }
+
+    pub fn main(&self) -> u32 {
+        self.main_hz
+    }
+    /// 0 when PLLA is off.
+    pub fn plla(&self) -> u32 {
+        self.plla_hz
+    }
+    pub fn mck(&self) -> u32 {
+        self.mck_hz
+    }
+    /// Processor clock (HCLK), identical to MCK.
+    pub fn core(&self) -> u32 {
+        self.mck_hz
+    }
+    /// Clock of a peripheral, all peripherals run from MCK.
+    pub fn peripheral(&self, _id: PeripheralId) -> u32 {
+        self.mck_hz
+    }
+    pub fn slow(&self) -> u32 {
+        SLOW_CLOCK_HZ
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockConfig {
+    main: MainClock,
+    /// Multiplier and divider.
+    plla: Option<(u16, u8)>,
+    source: ClockSource,
+    prescaler: Prescaler,
+}
+impl ClockConfig {
+    /// The configuration out of reset: MCK is MAINCK from the 4MHz fast RC oscillator.
+    pub fn new() -> ClockConfig {
+        ClockConfig {
+            main: MainClock::FastRc(FastRc::Mhz4),
+            plla: None,
+            source: ClockSource::Main,
+            prescaler: Prescaler::Div1,
+        }
+    }
+
+    pub fn main_clock(mut self, main: MainClock) -> ClockConfig {
+        self.main = main;
+        self
+    }
+
+    /// PLLA outputs MAINCK * `multiplier` / `divider`.
+    pub fn plla(mut self, multiplier: u16, divider: u8) -> ClockConfig {
+        self.plla = Some((multiplier, divider));
+        self
+    }
+
+    pub fn master_clock(mut self, source: ClockSource, prescaler: Prescaler) -> ClockConfig {
+        self.source = source;
+        self.prescaler = prescaler;
+        self
+    }
+
+    /// Checks the configuration and computes the resulting frequencies.
+    pub fn clocks(&self) -> Result<Clocks, ClockError> {
+        let main_hz = match self.main {
+            MainClock::FastRc(rc) => rc.hz(),
+            MainClock::Crystal(hz) | MainClock::Bypass(hz) => {
+                if hz < 3_000_000 || hz > 20_000_000 {
+                    return Err(ClockError::InvalidMainClock);
+                }
+                hz
+            }
+        };
+        let plla_hz = match self.plla {
+            None => 0,
+            Some((mul, div)) => {
+                if mul < 2 || mul > 63 || div == 0 {
+                    return Err(ClockError::InvalidPllRatio);
+                }
+                let input = main_hz / u32::from(div);
+                if input < 3_000_000 || input > 32_000_000 {
+                    return Err(ClockError::InvalidPllInput);
+                }
+                let output = input * u32::from(mul);
+                if output < 80_000_000 || output > 240_000_000 {
+                    return Err(ClockError::InvalidPllOutput);
+                }
+                output
+            }
+        };
+        let source_hz = match self.source {
+            ClockSource::Slow => SLOW_CLOCK_HZ,
+            ClockSource::Main => main_hz,
+            ClockSource::Plla if plla_hz == 0 => return Err(ClockError::PllDisabled),
+            ClockSource::Plla => plla_hz,
+        };
+        let mck_hz = source_hz / self.prescaler.divider();
+        if mck_hz > MAX_MCK_HZ {
+            return Err(ClockError::MasterClockTooFast);
+        }
+        Ok(Clocks {
+            main_hz,
+            plla_hz,
+            mck_hz,
+        })
+    }
+
+    /// Applies the configuration. The flash wait states are set for the highest frequency
+    /// during the transition and lowered afterwards if possible.
+    pub fn freeze(
+        self,
+        pmc: &mut PowerManagementController,
+        efc: &mut EnhancedEmbeddedFlashController,
+    ) -> Result<Clocks, ClockError> {
+        let clocks = self.clocks()?;
+        efc.set_wait_states(efc::wait_states_for(MAX_MCK_HZ));
+
+        // MCK must not depend on what is about to change.
+        {
+            let mut mckr = pmc.mckr.get_mut();
+            if mckr.source() == ClockSource::Plla {
+                mckr.set_source(ClockSource::Main);
+            }
+        }
+        while !pmc.sr.get().master_clock_ready() {}
+
+        match self.main {
+            MainClock::FastRc(rc) => {
+                {
+                    let mut mor = pmc.ckgr_mor.get_mut();
+                    mor.enable_fast_rc(true);
+                    mor.set_fast_rc_frequency(rc.bits());
+                }
+                while !pmc.sr.get().fast_rc_stabilized() {}
+                pmc.ckgr_mor.get_mut().select_crystal(false);
+            }
+            MainClock::Crystal(_) | MainClock::Bypass(_) => {
+                {
+                    let mut mor = pmc.ckgr_mor.get_mut();
+                    if let MainClock::Bypass(_) = self.main {
+                        mor.enable_crystal(false);
+                        mor.bypass_crystal(true);
+                    } else {
+                        mor.bypass_crystal(false);
+                        mor.set_crystal_startup_time(CRYSTAL_STARTUP);
+                        mor.enable_crystal(true);
+                    }
+                }
+                if let MainClock::Crystal(_) = self.main {
+                    while !pmc.sr.get().crystal_stabilized() {}
+                }
+                pmc.ckgr_mor.get_mut().select_crystal(true);
+            }
+        }
+        while !pmc.sr.get().main_oscillator_selection_done() {}
+
+        {
+            let mut pllar = pmc.ckgr_pllar.get_mut();
+            pllar.set_one(true);
+            match self.plla {
+                Some((mul, div)) => {
+                    pllar.set_multiplier(mul - 1);
+                    pllar.set_count(PLLA_COUNT);
+                    pllar.set_divider(div);
+                }
+                None => {
+                    pllar.set_multiplier(0);
+                    pllar.set_divider(0);
+                }
+            }
+        }
+        if self.plla.is_some() {
+            while !pmc.sr.get().plla_locked() {}
+        }
+
+        // Switching to PLLA the prescaler is set first, otherwise the source is.
+        if self.source == ClockSource::Plla {
+            pmc.mckr.get_mut().set_prescaler(self.prescaler);
+            while !pmc.sr.get().master_clock_ready() {}
+            pmc.mckr.get_mut().set_source(self.source);
+        } else {
+            pmc.mckr.get_mut().set_source(self.source);
+            while !pmc.sr.get().master_clock_ready() {}
+            pmc.mckr.get_mut().set_prescaler(self.prescaler);
+        }
+        while !pmc.sr.get().master_clock_ready() {}
+
+        efc.set_wait_states(efc::wait_states_for(clocks.mck_hz));
+        Ok(clocks)
+    }
+}
+impl Default for ClockConfig {
+    fn default() -> ClockConfig {
+        ClockConfig::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_120mhz_from_crystal() {
+        let clocks = ClockConfig::new()
+            .main_clock(MainClock::Crystal(12_000_000))
+            .plla(20, 1)
+            .master_clock(ClockSource::Plla, Prescaler::Div2)
+            .clocks()
+            .unwrap();
+        assert_eq!(240_000_000, clocks.plla());
+        assert_eq!(120_000_000, clocks.mck());
+        assert_eq!(Clocks::at_reset(), ClockConfig::new().clocks().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_configurations() {
+        let crystal = ClockConfig::new().main_clock(MainClock::Crystal(12_000_000));
+        assert_eq!(
+            Err(ClockError::InvalidMainClock),
+            ClockConfig::new()
+                .main_clock(MainClock::Bypass(25_000_000))
+                .clocks()
+        );
+        assert_eq!(
+            Err(ClockError::InvalidPllInput),
+            crystal.plla(40, 6).clocks()
+        );
+        assert_eq!(
+            Err(ClockError::InvalidPllOutput),
+            crystal.plla(25, 1).clocks()
+        );
+        assert_eq!(
+            Err(ClockError::PllDisabled),
+            crystal
+                .master_clock(ClockSource::Plla, Prescaler::Div1)
+                .clocks()
+        );
+        assert_eq!(
+            Err(ClockError::MasterClockTooFast),
+            crystal
+                .plla(20, 1)
+                .master_clock(ClockSource::Plla, Prescaler::Div1)
+                .clocks()
+        );
+    }
+}
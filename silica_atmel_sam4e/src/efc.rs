@@ -0,0 +1,644 @@
+//! Enhanced Embedded Flash Controller
+//!
+//! The flash cannot be read while a command runs: the code waiting for its completion runs
+//! from RAM with the interrupts masked.
+//!
+//! ```ignore
+//! let mut flash = Flash::new(unsafe { &mut EFC })?;
+//! flash.erase_and_write_page(page, &data)?;
+//! flash.boot_from_flash()?;
+//! ```
+//!
+//! `Flash` drives the controller through the `Backend` trait so that the command sequences can
+//! be checked on the host against a simulated controller.
+use core::convert::{Into, TryInto};
+use core::fmt;
+use core::intrinsics::{volatile_load, volatile_store};
+use core::ptr;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+use silica_arm_cortexm4::interrupt;
+
+/// Address of the flash.
+pub const FLASH_BASE: usize = 0x0040_0000;
+pub const PAGE_SIZE: usize = 512;
+pub const PAGE_WORDS: usize = PAGE_SIZE / 4;
+/// Pages per lock region.
+pub const LOCK_REGION_PAGES: u16 = 16;
+/// GPNVM bit selecting the boot from flash instead of ROM (SAM-BA).
+pub const GPNVM_BOOT_FROM_FLASH: u8 = 1;
+
+register! {
+    @impl_debug;
+    /// EEFC Flash Mode Register
+    #[derive(Copy, Clone)]
+    pub struct FMRegister(u32) {
+        bool: pub code_loop_optimization_enabled, pub enable_code_loop_optimization: 26;
+        /// Disables the 128-bit read access mode, reducing the power consumption at the expense
+        /// of the performance.
+        bool: pub read_64bit_access, pub set_read_64bit_access: 24;
+        bool: pub sequential_code_optimization_disabled, pub disable_sequential_code_optimization: 16;
+        /// Number of wait states of a flash access, minus one.
+        u8: pub wait_states, pub set_wait_states: 11, 8;
+        bool: pub ready_interrupt_enabled, pub enable_ready_interrupt: 0;
+    }
+}
+
+register! {
+    @optout_extract_insert;
+    /// EEFC Flash Command Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct FCRegister(u32) {
+        u16: _, pub set_argument: 23, 8;
+        u8: _, pub set_command: 7, 0;
+    }
+}
+register_impl_extract_insert!(@keyed FCRegister: u32, 0xFF00_0000, 0x5A00_0000);
+
+register! {
+    @impl_debug;
+    /// EEFC Flash Status Register
+    #[derive(Copy, Clone)]
+    pub struct FSRegister(u32) {
+        /// An error occurred during the last program or erase.
+        bool: pub flash_error, pub set_flash_error: 3;
+        /// The command tried to modify a locked region.
+        bool: pub lock_error, pub set_lock_error: 2;
+        /// Invalid command or argument.
+        bool: pub command_error, pub set_command_error: 1;
+        bool: pub ready, pub set_ready: 0;
+    }
+}
+impl FSRegister {
+    /// A status value, for backends not based on the registers.
+    pub fn new(ready: bool) -> FSRegister {
+        let mut r = FSRegister(0);
+        r.set_ready(ready);
+        r
+    }
+}
+
+/// Enhanced Embedded Flash Controller
+#[repr(C)]
+pub struct EnhancedEmbeddedFlashController {
+    pub fmr: RegisterCell<FMRegister>,
+    pub fcr: RegisterCell<FCRegister>,
+    pub fsr: RoRegisterCell<FSRegister>,
+    /// Flash Result Register
+    pub frr: RoRegisterCell<u32>,
+}
+
+/// Flash wait states required to run at `mck_hz` with VDDCORE at 1.2V: one more every 20MHz,
+/// up to 5 for the maximum frequency.
+pub fn wait_states_for(mck_hz: u32) -> u8 {
+    (mck_hz.saturating_sub(1) / 20_000_000).min(5) as u8
+}
+
+impl EnhancedEmbeddedFlashController {
+    /// Programs the wait states, `cycles` being the number of cycles per access minus one.
+    pub fn set_wait_states(&mut self, cycles: u8) {
+        self.fmr.get_mut().set_wait_states(cycles);
+    }
+}
+
+/// Flash commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    GetDescriptor,
+    WritePage,
+    WritePageAndLock,
+    EraseAndWritePage,
+    EraseAndWritePageAndLock,
+    EraseAll,
+    ErasePages,
+    SetLockBit,
+    ClearLockBit,
+    GetLockBit,
+    SetGpnvmBit,
+    ClearGpnvmBit,
+    GetGpnvmBit,
+    StartReadUniqueId,
+    StopReadUniqueId,
+    EraseSector,
+}
+impl Command {
+    pub fn code(self) -> u8 {
+        match self {
+            Command::GetDescriptor => 0x00,
+            Command::WritePage => 0x01,
+            Command::WritePageAndLock => 0x02,
+            Command::EraseAndWritePage => 0x03,
+            Command::EraseAndWritePageAndLock => 0x04,
+            Command::EraseAll => 0x05,
+            Command::ErasePages => 0x07,
+            Command::SetLockBit => 0x08,
+            Command::ClearLockBit => 0x09,
+            Command::GetLockBit => 0x0A,
+            Command::SetGpnvmBit => 0x0B,
+            Command::ClearGpnvmBit => 0x0C,
+            Command::GetGpnvmBit => 0x0D,
+            Command::StartReadUniqueId => 0x0E,
+            Command::StopReadUniqueId => 0x0F,
+            Command::EraseSector => 0x11,
+        }
+    }
+}
+
+/// Access to the controller and to the flash array.
+///
+/// The methods are called while the flash is busy: they must not execute from flash.
+pub trait Backend {
+    /// Runs `f` with the interrupts masked: the vector table is in flash.
+    fn critical<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R;
+    /// Issues a command and returns the status once it completed.
+    fn run(&mut self, command: Command, argument: u16) -> FSRegister;
+    fn result(&mut self) -> u32;
+    /// Writes a word of the page buffer, through the flash address space.
+    fn write_buffer(&mut self, page: u16, word: usize, value: u32);
+    /// Reads a word of the flash.
+    fn read(&mut self, word: usize) -> u32;
+    /// Runs the whole unique identifier read and returns it with the status of the command
+    /// ending it: the flash cannot be read meanwhile.
+    fn read_unique_id(&mut self) -> ([u32; 4], FSRegister);
+}
+
+impl<'a> Backend for &'a mut EnhancedEmbeddedFlashController {
+    #[inline(always)]
+    fn critical<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+        interrupt::free(|_| f(self))
+    }
+    #[inline(always)]
+    fn run(&mut self, command: Command, argument: u16) -> FSRegister {
+        let fcr = FCR_KEY | u32::from(argument) << 8 | u32::from(command.code());
+        unsafe { run_command(*self, fcr) }
+    }
+    #[inline(always)]
+    fn result(&mut self) -> u32 {
+        self.frr.get()
+    }
+    #[inline(always)]
+    fn write_buffer(&mut self, page: u16, word: usize, value: u32) {
+        let address = FLASH_BASE + usize::from(page) * PAGE_SIZE + word * 4;
+        unsafe { ptr::write_volatile(address as *mut u32, value) }
+    }
+    #[inline(always)]
+    fn read(&mut self, word: usize) -> u32 {
+        unsafe { ptr::read_volatile((FLASH_BASE + word * 4) as *const u32) }
+    }
+    #[inline(always)]
+    fn read_unique_id(&mut self) -> ([u32; 4], FSRegister) {
+        unsafe { read_unique_id(*self) }
+    }
+}
+
+const FCR_KEY: u32 = 0x5A00_0000;
+const FSR_READY: u32 = 1;
+
+/// Writes `command` (key included) to the command register and waits for the controller to be ready
+/// again. The routines running while the flash is busy only use the volatile intrinsics, which
+/// are always inlined unlike `ptr::read_volatile`, so that nothing is fetched from flash even in
+/// debug builds; the interrupts must be masked.
+#[inline(never)]
+#[cfg_attr(target_arch = "arm", link_section = ".data.efc")]
+unsafe fn run_command(efc: &mut EnhancedEmbeddedFlashController, command: u32) -> FSRegister {
+    let fcr = &mut efc.fcr as *mut RegisterCell<FCRegister> as *mut u32;
+    let fsr = &efc.fsr as *const RoRegisterCell<FSRegister> as *const u32;
+    volatile_store(fcr, command);
+    let mut status = volatile_load(fsr);
+    while status & FSR_READY == 0 {
+        status = volatile_load(fsr);
+    }
+    FSRegister(status)
+}
+
+/// Reads the unique identifier, the controller mapping it over the flash from the start command
+/// to the completion of the stop command.
+#[inline(never)]
+#[cfg_attr(target_arch = "arm", link_section = ".data.efc")]
+unsafe fn read_unique_id(efc: &mut EnhancedEmbeddedFlashController) -> ([u32; 4], FSRegister) {
+    let fcr = &mut efc.fcr as *mut RegisterCell<FCRegister> as *mut u32;
+    let fsr = &efc.fsr as *const RoRegisterCell<FSRegister> as *const u32;
+    volatile_store(fcr, FCR_KEY | 0x0E);
+    while volatile_load(fsr) & FSR_READY != 0 {}
+    let id = [
+        volatile_load(FLASH_BASE as *const u32),
+        volatile_load((FLASH_BASE + 4) as *const u32),
+        volatile_load((FLASH_BASE + 8) as *const u32),
+        volatile_load((FLASH_BASE + 12) as *const u32),
+    ];
+    volatile_store(fcr, FCR_KEY | 0x0F);
+    let mut status = volatile_load(fsr);
+    while status & FSR_READY == 0 {
+        status = volatile_load(fsr);
+    }
+    (id, FSRegister(status))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashError {
+    /// The controller rejected the command or its argument.
+    Command,
+    /// The page is in a locked region.
+    Locked,
+    /// Programming or erasing failed.
+    Flash,
+    InvalidPage,
+}
+
+/// Number of pages erased at once, the first page must be aligned on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageCount {
+    Four,
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+impl PageCount {
+    pub fn pages(self) -> u16 {
+        match self {
+            PageCount::Four => 4,
+            PageCount::Eight => 8,
+            PageCount::Sixteen => 16,
+            PageCount::ThirtyTwo => 32,
+        }
+    }
+    fn code(self) -> u16 {
+        match self {
+            PageCount::Four => 0,
+            PageCount::Eight => 1,
+            PageCount::Sixteen => 2,
+            PageCount::ThirtyTwo => 3,
+        }
+    }
+}
+
+/// Organisation of the flash, as reported by the controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Descriptor {
+    pub id: u32,
+    /// In bytes.
+    pub size: u32,
+    pub page_size: u32,
+    pub planes: u32,
+    pub lock_regions: u32,
+}
+impl Descriptor {
+    pub fn pages(&self) -> u32 {
+        self.size / self.page_size
+    }
+}
+
+pub struct Flash<B> {
+    backend: B,
+    pages: u16,
+}
+
+impl<B: Backend> Flash<B> {
+    /// Reads the flash descriptor to learn its size.
+    pub fn new(backend: B) -> Result<Flash<B>, FlashError> {
+        let mut flash = Flash { backend, pages: 0 };
+        flash.pages = flash.descriptor()?.pages() as u16;
+        Ok(flash)
+    }
+
+    pub fn release(self) -> B {
+        self.backend
+    }
+
+    pub fn pages(&self) -> u16 {
+        self.pages
+    }
+
+    /// Issues a command and waits for its completion.
+    fn run(backend: &mut B, command: Command, argument: u16) -> Result<(), FlashError> {
+        Self::check_status(backend.run(command, argument))
+    }
+
+    fn check_status(status: FSRegister) -> Result<(), FlashError> {
+        if status.command_error() {
+            Err(FlashError::Command)
+        } else if status.lock_error() {
+            Err(FlashError::Locked)
+        } else if status.flash_error() {
+            Err(FlashError::Flash)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn command(&mut self, command: Command, argument: u16) -> Result<(), FlashError> {
+        self.backend
+            .critical(|backend| Self::run(backend, command, argument))
+    }
+
+    fn check_page(&self, page: u16) -> Result<(), FlashError> {
+        if page < self.pages {
+            Ok(())
+        } else {
+            Err(FlashError::InvalidPage)
+        }
+    }
+
+    pub fn descriptor(&mut self) -> Result<Descriptor, FlashError> {
+        self.backend.critical(|backend| {
+            Self::run(backend, Command::GetDescriptor, 0)?;
+            let id = backend.result();
+            let size = backend.result();
+            let page_size = backend.result();
+            let planes = backend.result();
+            for _ in 0..planes {
+                backend.result();
+            }
+            let lock_regions = backend.result();
+            for _ in 0..lock_regions {
+                backend.result();
+            }
+            Ok(Descriptor {
+                id,
+                size,
+                page_size,
+                planes,
+                lock_regions,
+            })
+        })
+    }
+
+    fn program(
+        &mut self,
+        command: Command,
+        page: u16,
+        data: &[u32; PAGE_WORDS],
+    ) -> Result<(), FlashError> {
+        self.check_page(page)?;
+        for (i, &word) in data.iter().enumerate() {
+            self.backend.write_buffer(page, i, word);
+        }
+        self.command(command, page)
+    }
+
+    /// Programs a page that was erased beforehand.
+    pub fn write_page(&mut self, page: u16, data: &[u32; PAGE_WORDS]) -> Result<(), FlashError> {
+        self.program(Command::WritePage, page, data)
+    }
+
+    pub fn erase_and_write_page(
+        &mut self,
+        page: u16,
+        data: &[u32; PAGE_WORDS],
+    ) -> Result<(), FlashError> {
+        self.program(Command::EraseAndWritePage, page, data)
+    }
+
+    /// Erases `count` pages from `first`, which must be a multiple of `count`.
+    pub fn erase_pages(&mut self, first: u16, count: PageCount) -> Result<(), FlashError> {
+        self.check_page(first)?;
+        if first % count.pages() != 0 {
+            return Err(FlashError::InvalidPage);
+        }
+        self.command(Command::ErasePages, first | count.code())
+    }
+
+    /// Erases the sector holding `page`.
+    pub fn erase_sector(&mut self, page: u16) -> Result<(), FlashError> {
+        self.check_page(page)?;
+        self.command(Command::EraseSector, page)
+    }
+
+    /// Prevents the region holding `page` from being written or erased.
+    pub fn lock(&mut self, page: u16) -> Result<(), FlashError> {
+        self.check_page(page)?;
+        self.command(Command::SetLockBit, page)
+    }
+
+    pub fn unlock(&mut self, page: u16) -> Result<(), FlashError> {
+        self.check_page(page)?;
+        self.command(Command::ClearLockBit, page)
+    }
+
+    pub fn locked(&mut self, page: u16) -> Result<bool, FlashError> {
+        self.check_page(page)?;
+        let region = u32::from(page / LOCK_REGION_PAGES);
+        self.backend.critical(|backend| {
+            Self::run(backend, Command::GetLockBit, 0)?;
+            let mut bits = 0;
+            for _ in 0..=region / 32 {
+                bits = backend.result();
+            }
+            Ok(bits & (1 << (region % 32)) != 0)
+        })
+    }
+
+    pub fn set_gpnvm(&mut self, bit: u8) -> Result<(), FlashError> {
+        self.command(Command::SetGpnvmBit, u16::from(bit))
+    }
+
+    pub fn clear_gpnvm(&mut self, bit: u8) -> Result<(), FlashError> {
+        self.command(Command::ClearGpnvmBit, u16::from(bit))
+    }
+
+    pub fn gpnvm(&mut self) -> Result<u32, FlashError> {
+        self.backend.critical(|backend| {
+            Self::run(backend, Command::GetGpnvmBit, 0)?;
+            Ok(backend.result())
+        })
+    }
+
+    /// Boots the application from flash on the next reset instead of the ROM.
+    pub fn boot_from_flash(&mut self) -> Result<(), FlashError> {
+        self.set_gpnvm(GPNVM_BOOT_FROM_FLASH)
+    }
+
+    /// The 128-bit unique identifier, read from the start of the flash while the controller
+    /// maps it there.
+    pub fn unique_id(&mut self) -> Result<[u32; 4], FlashError> {
+        let (id, status) = self.backend.critical(B::read_unique_id);
+        Self::check_status(status)?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_states() {
+        assert_eq!(0, wait_states_for(4_000_000));
+        assert_eq!(2, wait_states_for(48_000_000));
+        assert_eq!(5, wait_states_for(120_000_000));
+    }
+
+    const PAGES: usize = 64;
+
+    /// Simulated controller with a 32KB flash of 4 lock regions.
+    struct SimFlash {
+        memory: [u32; PAGES * PAGE_WORDS],
+        buffer: [u32; PAGE_WORDS],
+        locks: u32,
+        gpnvm: u32,
+        unique_id: bool,
+        results: [u32; 12],
+        next_result: usize,
+        status: FSRegister,
+        log: [Option<(Command, u16)>; 8],
+        issued: usize,
+    }
+    impl SimFlash {
+        fn new() -> SimFlash {
+            SimFlash {
+                memory: [0xFFFF_FFFF; PAGES * PAGE_WORDS],
+                buffer: [0xFFFF_FFFF; PAGE_WORDS],
+                locks: 0,
+                gpnvm: 0,
+                unique_id: false,
+                results: [0; 12],
+                next_result: 0,
+                status: FSRegister::new(true),
+                log: [None; 8],
+                issued: 0,
+            }
+        }
+
+        fn page(&mut self, page: u16) -> &mut [u32] {
+            let start = usize::from(page) * PAGE_WORDS;
+            &mut self.memory[start..start + PAGE_WORDS]
+        }
+
+        fn locked(&self, page: u16) -> bool {
+            self.locks & (1 << (page / LOCK_REGION_PAGES)) != 0
+        }
+
+        fn issue(&mut self, command: Command, argument: u16) {
+            self.log[self.issued % 8] = Some((command, argument));
+            self.issued += 1;
+            self.status = FSRegister::new(true);
+            self.next_result = 0;
+            match command {
+                Command::GetDescriptor => {
+                    let size = (PAGES * PAGE_SIZE) as u32;
+                    let region = (LOCK_REGION_PAGES as usize * PAGE_SIZE) as u32;
+                    self.results = [
+                        0xA3CC_0CE0,
+                        size,
+                        PAGE_SIZE as u32,
+                        1,
+                        size,
+                        4,
+                        region,
+                        region,
+                        region,
+                        region,
+                        0,
+                        0,
+                    ];
+                }
+                Command::WritePage | Command::EraseAndWritePage => {
+                    if self.locked(argument) {
+                        self.status.set_lock_error(true);
+                        return;
+                    }
+                    let erase = command == Command::EraseAndWritePage;
+                    let buffer = self.buffer;
+                    for (w, b) in self.page(argument).iter_mut().zip(buffer.iter()) {
+                        // programming only clears bits.
+                        *w = if erase { *b } else { *w & *b };
+                    }
+                    self.buffer = [0xFFFF_FFFF; PAGE_WORDS];
+                }
+                Command::ErasePages => {
+                    let count = 4 << (argument & 3);
+                    let first = argument & !3;
+                    for page in first..first + count {
+                        for w in self.page(page).iter_mut() {
+                            *w = 0xFFFF_FFFF;
+                        }
+                    }
+                }
+                Command::SetLockBit => self.locks |= 1 << (argument / LOCK_REGION_PAGES),
+                Command::ClearLockBit => self.locks &= !(1 << (argument / LOCK_REGION_PAGES)),
+                Command::GetLockBit => self.results[0] = self.locks,
+                Command::SetGpnvmBit if argument < 2 => self.gpnvm |= 1 << argument,
+                Command::ClearGpnvmBit if argument < 2 => self.gpnvm &= !(1 << argument),
+                Command::GetGpnvmBit => self.results[0] = self.gpnvm,
+                Command::StartReadUniqueId => {
+                    self.unique_id = true;
+                    self.status = FSRegister::new(false);
+                }
+                Command::StopReadUniqueId => self.unique_id = false,
+                _ => self.status.set_command_error(true),
+            }
+        }
+    }
+    impl Backend for SimFlash {
+        fn critical<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+            f(self)
+        }
+        fn run(&mut self, command: Command, argument: u16) -> FSRegister {
+            self.issue(command, argument);
+            self.status
+        }
+        fn result(&mut self) -> u32 {
+            self.next_result += 1;
+            self.results[self.next_result - 1]
+        }
+        fn write_buffer(&mut self, _page: u16, word: usize, value: u32) {
+            self.buffer[word] = value;
+        }
+        fn read(&mut self, word: usize) -> u32 {
+            if self.unique_id {
+                0x1000 + word as u32
+            } else {
+                self.memory[word]
+            }
+        }
+        fn read_unique_id(&mut self) -> ([u32; 4], FSRegister) {
+            self.issue(Command::StartReadUniqueId, 0);
+            assert!(!self.status.ready());
+            let id = [self.read(0), self.read(1), self.read(2), self.read(3)];
+            self.issue(Command::StopReadUniqueId, 0);
+            (id, self.status)
+        }
+    }
+
+    #[test]
+    fn test_program_and_erase() {
+        let mut flash = Flash::new(SimFlash::new()).unwrap();
+        assert_eq!(64, flash.pages());
+        let mut data = [0u32; PAGE_WORDS];
+        data[0] = 0xDEAD_BEEF;
+        assert_eq!(Ok(()), flash.write_page(17, &data));
+        assert_eq!(Err(FlashError::InvalidPage), flash.write_page(64, &data));
+        assert_eq!(
+            Err(FlashError::InvalidPage),
+            flash.erase_pages(18, PageCount::Eight)
+        );
+        assert_eq!(Ok(()), flash.erase_pages(16, PageCount::Four));
+
+        assert_eq!(Ok(()), flash.lock(20));
+        assert_eq!(Ok(true), flash.locked(31));
+        assert_eq!(Ok(false), flash.locked(32));
+        assert_eq!(
+            Err(FlashError::Locked),
+            flash.erase_and_write_page(17, &data)
+        );
+        assert_eq!(Ok(()), flash.unlock(20));
+        assert_eq!(Ok(()), flash.erase_and_write_page(17, &data));
+
+        let sim = flash.release();
+        assert_eq!(0xDEAD_BEEF, sim.memory[17 * PAGE_WORDS]);
+        assert_eq!(0xFFFF_FFFF, sim.memory[16 * PAGE_WORDS]);
+        assert_eq!(Some((Command::ErasePages, 16)), sim.log[2]);
+    }
+
+    #[test]
+    fn test_unique_id_and_gpnvm() {
+        let mut flash = Flash::new(SimFlash::new()).unwrap();
+        assert_eq!(Ok([0x1000, 0x1001, 0x1002, 0x1003]), flash.unique_id());
+        assert_eq!(Ok(()), flash.boot_from_flash());
+        assert_eq!(Ok(2), flash.gpnvm());
+        assert_eq!(Err(FlashError::Command), flash.set_gpnvm(7));
+        let sim = flash.release();
+        assert!(!sim.unique_id);
+        assert_eq!(Some((Command::StartReadUniqueId, 0)), sim.log[1]);
+        assert_eq!(Some((Command::StopReadUniqueId, 0)), sim.log[2]);
+    }
+}
@@ -0,0 +1,600 @@
+//! Ethernet MAC
+//!
+//! `Gmac` moves the frames through rings of DMA descriptors held in a static `Buffers`, and
+//! manages the PHY through its MDIO interface. It is the `silica::net::Device` of the board:
+//!
+//! ```ignore
+//! static mut GMAC_BUFFERS: Buffers = Buffers::new();
+//!
+//! let config = Config::new(mac).phy_address(1);
+//! let gmac = Gmac::new(unsafe { &mut GMAC }, unsafe { &mut GMAC_BUFFERS }, pins, &config,
+//!                      &clocks, unsafe { &mut PMC })?;
+//! let mut iface = Interface::new(gmac, &mut tcp_sockets, &mut udp_sockets);
+//! ```
+//!
+//! Each buffer holds a whole frame: the frames are copied out of the receive ring, and into
+//! the transmit ring.
+use clock::Clocks;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{compiler_fence, Ordering};
+use pio::piod::{
+    PD0, PD1, PD10, PD11, PD12, PD13, PD14, PD15, PD16, PD17, PD2, PD3, PD4, PD5, PD6, PD7, PD8,
+    PD9,
+};
+use pio::{Peripheral, Pin, A};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::net::Device;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    @impl_debug;
+    /// GMAC Network Control Register
+    #[derive(Copy, Clone)]
+    pub struct NCRegister(u32) {
+        bool: pub transmit_halt, pub set_transmit_halt: 10;
+        bool: pub start_transmission, pub set_start_transmission: 9;
+        bool: pub clear_statistics, pub set_clear_statistics: 5;
+        bool: pub management_port, pub set_management_port: 4;
+        bool: pub transmit_enable, pub set_transmit_enable: 3;
+        bool: pub receive_enable, pub set_receive_enable: 2;
+        bool: pub local_loopback, pub set_local_loopback: 1;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC Network Configuration Register
+    #[derive(Copy, Clone)]
+    pub struct NCFGRegister(u32) {
+        bool: pub rx_checksum_offload, pub set_rx_checksum_offload: 24;
+        /// MCK divider of the management clock, see `mdc_divider`.
+        u8: pub mdc_divider, pub set_mdc_divider: 20, 18;
+        /// Strips the frame check sequence from the received frames.
+        bool: pub remove_fcs, pub set_remove_fcs: 17;
+        /// Accepts frames up to 1536 bytes.
+        bool: pub max_frame_size, pub set_max_frame_size: 8;
+        bool: pub unicast_hash, pub set_unicast_hash: 7;
+        bool: pub multicast_hash, pub set_multicast_hash: 6;
+        bool: pub no_broadcast, pub set_no_broadcast: 5;
+        bool: pub copy_all_frames, pub set_copy_all_frames: 4;
+        bool: pub full_duplex, pub set_full_duplex: 1;
+        bool: pub speed_100, pub set_speed_100: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC Network Status Register
+    #[derive(Copy, Clone)]
+    pub struct NSRegister(u32) {
+        /// The management logic is idle.
+        bool: pub idle, _: 2;
+        bool: pub mdio, _: 1;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC User Register
+    #[derive(Copy, Clone)]
+    pub struct URegister(u32) {
+        /// Set for MII, the only interface of the SAM4E.
+        bool: pub mii, pub set_mii: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC DMA Configuration Register
+    #[derive(Copy, Clone)]
+    pub struct DCFGRegister(u32) {
+        /// Size of the receive buffers, in units of 64 bytes.
+        u8: pub rx_buffer_size, pub set_rx_buffer_size: 23, 16;
+        bool: pub tx_checksum_offload, pub set_tx_checksum_offload: 11;
+        /// Length of the AHB bursts: 1, 4, 8 or 16 words.
+        u8: pub burst_length, pub set_burst_length: 4, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC Transmit Status Register, the bits are cleared by writing them.
+    #[derive(Copy, Clone)]
+    pub struct TSRegister(u32) {
+        bool: pub hresp_not_ok, pub set_hresp_not_ok: 8;
+        bool: pub underrun, pub set_underrun: 6;
+        bool: pub complete, pub set_complete: 5;
+        bool: pub corrupted, pub set_corrupted: 4;
+        bool: pub transmit_go, _: 3;
+        bool: pub retry_limit_exceeded, pub set_retry_limit_exceeded: 2;
+        bool: pub collision, pub set_collision: 1;
+        bool: pub used_bit_read, pub set_used_bit_read: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC Receive Status Register, the bits are cleared by writing them.
+    #[derive(Copy, Clone)]
+    pub struct RSRegister(u32) {
+        bool: pub hresp_not_ok, pub set_hresp_not_ok: 3;
+        bool: pub overrun, pub set_overrun: 2;
+        bool: pub frame_received, pub set_frame_received: 1;
+        /// No buffer was available for a frame.
+        bool: pub buffer_not_available, pub set_buffer_not_available: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC Interrupt Status Register, also the layout of the Interrupt Enable, Disable and
+    /// Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct IRegister(u32) {
+        bool: pub hresp_not_ok, pub set_hresp_not_ok: 11;
+        bool: pub rx_overrun, pub set_rx_overrun: 10;
+        bool: pub tx_complete, pub set_tx_complete: 7;
+        bool: pub tx_corrupted, pub set_tx_corrupted: 6;
+        bool: pub retry_limit_exceeded, pub set_retry_limit_exceeded: 5;
+        bool: pub tx_underrun, pub set_tx_underrun: 4;
+        bool: pub tx_used_bit_read, pub set_tx_used_bit_read: 3;
+        bool: pub rx_used_bit_read, pub set_rx_used_bit_read: 2;
+        bool: pub rx_complete, pub set_rx_complete: 1;
+        bool: pub management_done, pub set_management_done: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// GMAC PHY Maintenance Register
+    #[derive(Copy, Clone)]
+    pub struct MANRegister(u32) {
+        /// Clause 22 frames, the opposite of clause 45.
+        bool: pub clause_22, pub set_clause_22: 30;
+        /// 1 writes, 2 reads.
+        u8: pub operation, pub set_operation: 29, 28;
+        u8: pub phy_address, pub set_phy_address: 27, 23;
+        u8: pub register_address, pub set_register_address: 22, 18;
+        /// Must be 2.
+        u8: pub write_ten, pub set_write_ten: 17, 16;
+        u16: pub data, pub set_data: 15, 0;
+    }
+}
+
+/// A specific address: bottom and top registers.
+#[repr(C)]
+pub struct SpecificAddress {
+    /// First 4 bytes of the address, the first one in the low byte. Writing it disables the
+    /// filter until the top register is written.
+    pub bottom: RegisterCell<u32>,
+    /// Last 2 bytes of the address.
+    pub top: RegisterCell<u32>,
+}
+
+/// Ethernet MAC, up to the type ID match registers. The statistics and timestamp registers
+/// are not mapped.
+#[repr(C)]
+pub struct EthernetMac {
+    pub ncr: RegisterCell<NCRegister>,
+    pub ncfgr: RegisterCell<NCFGRegister>,
+    pub nsr: RoRegisterCell<NSRegister>,
+    pub ur: RegisterCell<URegister>,
+    pub dcfgr: RegisterCell<DCFGRegister>,
+    pub tsr: RegisterCell<TSRegister>,
+    /// Receive Buffer Queue Base Address Register
+    pub rbqb: RegisterCell<u32>,
+    /// Transmit Buffer Queue Base Address Register
+    pub tbqb: RegisterCell<u32>,
+    pub rsr: RegisterCell<RSRegister>,
+    pub isr: RoRegisterCell<IRegister>,
+    pub ier: RegisterCell<IRegister>,
+    pub idr: RegisterCell<IRegister>,
+    pub imr: RoRegisterCell<IRegister>,
+    pub man: RegisterCell<MANRegister>,
+    /// Received Pause Quantum Register
+    pub rpq: RoRegisterCell<u32>,
+    /// Transmit Pause Quantum Register
+    pub tpq: RegisterCell<u32>,
+    _reserved0: ReservedCell<[u32; 16]>,
+    /// Hash Register Bottom and Top
+    pub hash: [RegisterCell<u32>; 2],
+    pub specific_address: [SpecificAddress; 4],
+    /// Type ID Match Registers
+    pub tidm: [RegisterCell<u32>; 4],
+}
+
+/// Frames in the receive and transmit rings.
+pub const RX_BUFFERS: usize = 8;
+pub const TX_BUFFERS: usize = 4;
+/// A buffer holds a whole frame, the frame check sequence included.
+const BUFFER_SIZE: usize = 1536;
+
+/// Receive descriptor: the buffer holds a frame, given back by clearing it.
+const RX_OWNED: u32 = 1;
+const RX_WRAP: u32 = 1 << 1;
+const RX_LENGTH: u32 = 0x1FFF;
+const RX_START_OF_FRAME: u32 = 1 << 14;
+const RX_END_OF_FRAME: u32 = 1 << 15;
+/// Transmit descriptor: the buffer is free.
+const TX_USED: u32 = 1 << 31;
+const TX_WRAP: u32 = 1 << 30;
+const TX_LAST: u32 = 1 << 15;
+
+/// MII registers of the PHY.
+const BMCR: u8 = 0;
+const BMSR: u8 = 1;
+const ANAR: u8 = 4;
+const ANLPAR: u8 = 5;
+const BMCR_RESET: u16 = 1 << 15;
+const BMCR_AUTONEG: u16 = 1 << 12;
+const BMCR_RESTART_AUTONEG: u16 = 1 << 9;
+const BMSR_LINK: u16 = 1 << 2;
+const BMSR_AUTONEG_COMPLETE: u16 = 1 << 5;
+/// MDIO reads of the PHY reset bit, about 0.5s at 2.5MHz: 802.3 allows it that long.
+const PHY_RESET_POLLS: u32 = 20_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GmacError {
+    /// The PHY did not come out of reset, e.g. nothing answers at its address.
+    PhyReset,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    address: u32,
+    status: u32,
+}
+
+/// Descriptor rings and buffers, shared with the DMA of the GMAC.
+#[repr(C, align(8))]
+pub struct Buffers {
+    rx_descriptors: [Descriptor; RX_BUFFERS],
+    tx_descriptors: [Descriptor; TX_BUFFERS],
+    rx: [[u8; BUFFER_SIZE]; RX_BUFFERS],
+    tx: [[u8; BUFFER_SIZE]; TX_BUFFERS],
+}
+
+impl Buffers {
+    pub const fn new() -> Buffers {
+        Buffers {
+            rx_descriptors: [Descriptor {
+                address: 0,
+                status: 0,
+            }; RX_BUFFERS],
+            tx_descriptors: [Descriptor {
+                address: 0,
+                status: 0,
+            }; TX_BUFFERS],
+            rx: [[0; BUFFER_SIZE]; RX_BUFFERS],
+            tx: [[0; BUFFER_SIZE]; TX_BUFFERS],
+        }
+    }
+
+    /// Gives all the buffers to the GMAC, except the transmit ones which are empty.
+    fn init(&mut self) {
+        for i in 0..RX_BUFFERS {
+            let wrap = if i == RX_BUFFERS - 1 { RX_WRAP } else { 0 };
+            self.rx_descriptors[i] = Descriptor {
+                address: self.rx[i].as_ptr() as u32 | wrap,
+                status: 0,
+            };
+        }
+        for i in 0..TX_BUFFERS {
+            let wrap = if i == TX_BUFFERS - 1 { TX_WRAP } else { 0 };
+            self.tx_descriptors[i] = Descriptor {
+                address: self.tx[i].as_ptr() as u32,
+                status: TX_USED | wrap,
+            };
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    Mbps10,
+    Mbps100,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+/// Best mode advertised by both ends of the link, from their ability registers.
+pub fn negotiated(anar: u16, anlpar: u16) -> Option<(Speed, Duplex)> {
+    let common = anar & anlpar;
+    [
+        (8, Speed::Mbps100, Duplex::Full),
+        (7, Speed::Mbps100, Duplex::Half),
+        (6, Speed::Mbps10, Duplex::Full),
+        (5, Speed::Mbps10, Duplex::Half),
+    ]
+    .iter()
+    .find(|&&(bit, _, _)| common & 1 << bit != 0)
+    .map(|&(_, speed, duplex)| (speed, duplex))
+}
+
+/// Management clock divider field keeping MDC at 2.5MHz at most.
+pub fn mdc_divider(mck: u32) -> u8 {
+    let dividers = [8, 16, 32, 48, 64, 96];
+    dividers
+        .iter()
+        .position(|&d| mck / d <= 2_500_000)
+        .unwrap_or(dividers.len() - 1) as u8
+}
+
+pub struct Config {
+    mac: [u8; 6],
+    phy_address: u8,
+}
+impl Config {
+    /// The PHY at the address 0.
+    pub fn new(mac: [u8; 6]) -> Config {
+        Config {
+            mac,
+            phy_address: 0,
+        }
+    }
+    pub fn phy_address(mut self, address: u8) -> Config {
+        self.phy_address = address;
+        self
+    }
+}
+
+/// MII pins.
+pub struct Pins {
+    pub tx_clock: Pin<PD0, Peripheral<A>>,
+    pub tx_enable: Pin<PD1, Peripheral<A>>,
+    pub tx0: Pin<PD2, Peripheral<A>>,
+    pub tx1: Pin<PD3, Peripheral<A>>,
+    pub rx_valid: Pin<PD4, Peripheral<A>>,
+    pub rx0: Pin<PD5, Peripheral<A>>,
+    pub rx1: Pin<PD6, Peripheral<A>>,
+    pub rx_error: Pin<PD7, Peripheral<A>>,
+    pub mdc: Pin<PD8, Peripheral<A>>,
+    pub mdio: Pin<PD9, Peripheral<A>>,
+    pub carrier_sense: Pin<PD10, Peripheral<A>>,
+    pub rx2: Pin<PD11, Peripheral<A>>,
+    pub rx3: Pin<PD12, Peripheral<A>>,
+    pub collision: Pin<PD13, Peripheral<A>>,
+    pub rx_clock: Pin<PD14, Peripheral<A>>,
+    pub tx2: Pin<PD15, Peripheral<A>>,
+    pub tx3: Pin<PD16, Peripheral<A>>,
+    pub tx_error: Pin<PD17, Peripheral<A>>,
+}
+
+pub struct Gmac {
+    regs: &'static mut EthernetMac,
+    buffers: &'static mut Buffers,
+    pins: Pins,
+    mac: [u8; 6],
+    phy_address: u8,
+    rx_next: usize,
+    tx_next: usize,
+    /// Mode the MAC is configured for, while the link is up.
+    link: Option<(Speed, Duplex)>,
+}
+
+impl Gmac {
+    /// Starts the MAC and the autonegotiation of the PHY.
+    pub fn new(
+        regs: &'static mut EthernetMac,
+        buffers: &'static mut Buffers,
+        pins: Pins,
+        config: &Config,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Gmac, GmacError> {
+        pmc.enable_peripheral_clock(PeripheralId::Gmac);
+        regs.ncr.set(NCRegister(0));
+        regs.idr.set(IRegister(!0));
+        regs.tsr.set(TSRegister(!0));
+        regs.rsr.set(RSRegister(!0));
+        regs.ncr.get_mut().set_clear_statistics(true);
+        {
+            let mut ncfgr = regs.ncfgr.get_mut();
+            ncfgr.set_mdc_divider(mdc_divider(clocks.peripheral(PeripheralId::Gmac)));
+            ncfgr.set_remove_fcs(true);
+            ncfgr.set_max_frame_size(true);
+            ncfgr.set_copy_all_frames(false);
+            ncfgr.set_no_broadcast(false);
+            ncfgr.set_speed_100(true);
+            ncfgr.set_full_duplex(true);
+        }
+        regs.ur.get_mut().set_mii(true);
+        {
+            let mut dcfgr = regs.dcfgr.get_mut();
+            dcfgr.set_burst_length(4);
+            dcfgr.set_rx_buffer_size((BUFFER_SIZE / 64) as u8);
+        }
+
+        buffers.init();
+        regs.rbqb.set(buffers.rx_descriptors.as_ptr() as u32);
+        regs.tbqb.set(buffers.tx_descriptors.as_ptr() as u32);
+
+        let mac = config.mac;
+        let bottom = mac[..4]
+            .iter()
+            .rev()
+            .fold(0, |acc, &b| acc << 8 | u32::from(b));
+        regs.specific_address[0].bottom.set(bottom);
+        regs.specific_address[0]
+            .top
+            .set(u32::from(mac[5]) << 8 | u32::from(mac[4]));
+
+        {
+            let mut ncr = regs.ncr.get_mut();
+            ncr.set_management_port(true);
+            ncr.set_receive_enable(true);
+            ncr.set_transmit_enable(true);
+        }
+
+        let mut gmac = Gmac {
+            regs,
+            buffers,
+            pins,
+            mac,
+            phy_address: config.phy_address,
+            rx_next: 0,
+            tx_next: 0,
+            link: None,
+        };
+        gmac.mdio_write(BMCR, BMCR_RESET);
+        // without a PHY the data line stays high: the reset bit reads as set.
+        if !(0..PHY_RESET_POLLS).any(|_| gmac.mdio_read(BMCR) & BMCR_RESET == 0) {
+            gmac.release(pmc);
+            return Err(GmacError::PhyReset);
+        }
+        gmac.mdio_write(BMCR, BMCR_AUTONEG | BMCR_RESTART_AUTONEG);
+        Ok(gmac)
+    }
+
+    pub fn release(
+        self,
+        pmc: &mut PowerManagementController,
+    ) -> (&'static mut EthernetMac, &'static mut Buffers, Pins) {
+        self.regs.ncr.set(NCRegister(0));
+        pmc.disable_peripheral_clock(PeripheralId::Gmac);
+        (self.regs, self.buffers, self.pins)
+    }
+
+    fn mdio(&mut self, operation: u8, register: u8, data: u16) -> u16 {
+        let mut man = MANRegister(0);
+        man.set_clause_22(true);
+        man.set_operation(operation);
+        man.set_phy_address(self.phy_address);
+        man.set_register_address(register);
+        man.set_write_ten(2);
+        man.set_data(data);
+        self.regs.man.set(man);
+        while !self.regs.nsr.get().idle() {}
+        self.regs.man.get().data()
+    }
+
+    /// Reads a register of the PHY.
+    pub fn mdio_read(&mut self, register: u8) -> u16 {
+        self.mdio(2, register, 0)
+    }
+
+    pub fn mdio_write(&mut self, register: u8, value: u16) {
+        self.mdio(1, register, value);
+    }
+
+    /// The negotiated mode of the link, which the MAC is configured for, or None while the link
+    /// is down.
+    pub fn poll_link(&mut self) -> Option<(Speed, Duplex)> {
+        // the link status is latched low: the first read tells it went down.
+        let _ = self.mdio_read(BMSR);
+        let bmsr = self.mdio_read(BMSR);
+        let link = if bmsr & BMSR_LINK != 0 && bmsr & BMSR_AUTONEG_COMPLETE != 0 {
+            let anar = self.mdio_read(ANAR);
+            let anlpar = self.mdio_read(ANLPAR);
+            negotiated(anar, anlpar)
+        } else {
+            None
+        };
+        if link != self.link {
+            if let Some((speed, duplex)) = link {
+                let mut ncfgr = self.regs.ncfgr.get_mut();
+                ncfgr.set_speed_100(speed == Speed::Mbps100);
+                ncfgr.set_full_duplex(duplex == Duplex::Full);
+            }
+            self.link = link;
+        }
+        link
+    }
+}
+
+impl Device for Gmac {
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn link_up(&mut self) -> bool {
+        self.poll_link().is_some()
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        loop {
+            let i = self.rx_next;
+            let descriptor = &mut self.buffers.rx_descriptors[i];
+            let address = unsafe { read_volatile(&descriptor.address) };
+            if address & RX_OWNED == 0 {
+                return None;
+            }
+            compiler_fence(Ordering::Acquire);
+            let status = unsafe { read_volatile(&descriptor.status) };
+            let len = (status & RX_LENGTH) as usize;
+            let whole = RX_START_OF_FRAME | RX_END_OF_FRAME;
+            let received = if status & whole == whole && len <= buffer.len() {
+                buffer[..len].copy_from_slice(&self.buffers.rx[i][..len]);
+                Some(len)
+            } else {
+                None
+            };
+            compiler_fence(Ordering::Release);
+            unsafe { write_volatile(&mut descriptor.address, address & !RX_OWNED) };
+            self.rx_next = (i + 1) % RX_BUFFERS;
+            if received.is_some() {
+                return received;
+            }
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> bool {
+        let i = self.tx_next;
+        let descriptor = &mut self.buffers.tx_descriptors[i];
+        let status = unsafe { read_volatile(&descriptor.status) };
+        if status & TX_USED == 0 || frame.len() > BUFFER_SIZE {
+            return false;
+        }
+        compiler_fence(Ordering::Acquire);
+        self.buffers.tx[i][..frame.len()].copy_from_slice(frame);
+        let wrap = status & TX_WRAP;
+        compiler_fence(Ordering::Release);
+        unsafe { write_volatile(&mut descriptor.status, frame.len() as u32 | TX_LAST | wrap) };
+        self.regs.ncr.get_mut().set_start_transmission(true);
+        self.tx_next = (i + 1) % TX_BUFFERS;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiated() {
+        // 100BASE-TX full duplex and 10BASE-T.
+        let anar = 0x01E1;
+        assert_eq!(
+            Some((Speed::Mbps100, Duplex::Full)),
+            negotiated(anar, 0x45E1)
+        );
+        assert_eq!(
+            Some((Speed::Mbps100, Duplex::Half)),
+            negotiated(anar, 0x0081)
+        );
+        assert_eq!(
+            Some((Speed::Mbps10, Duplex::Full)),
+            negotiated(0x0061, 0x01E1)
+        );
+        assert_eq!(None, negotiated(anar, 0x0001));
+        assert_eq!(3, mdc_divider(120_000_000));
+        assert_eq!(0, mdc_divider(12_000_000));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0xB8, size_of::<EthernetMac>());
+        assert_eq!(
+            8 * (RX_BUFFERS + TX_BUFFERS),
+            size_of::<[Descriptor; RX_BUFFERS + TX_BUFFERS]>()
+        );
+    }
+}
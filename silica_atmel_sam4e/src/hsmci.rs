@@ -0,0 +1,767 @@
+//! High Speed MultiMedia Card Interface
+//!
+//! `Hsmci` is the `silica::sd::Host` of the SD card slot. The data blocks are moved by the PDC:
+//!
+//! ```ignore
+//! let hsmci = Hsmci::new(unsafe { &mut HSMCI }, pins, card_detect, &clocks, unsafe { &mut PMC });
+//! let mut card = Card::new(hsmci);
+//! card.init()?;
+//! ```
+//!
+//! `read_async` and `write_async` let the `silica::executor` run other tasks while the blocks
+//! are moved, the HSMCI interrupt handler calling `on_interrupt` to wake them up. The command
+//! itself is still sent synchronously:
+//!
+//! ```ignore
+//! let (status, buffer) = hsmci.read_async(17, block, 512, unsafe { &mut BLOCK }).await;
+//! ```
+use clock::Clocks;
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use core::future::Future;
+use core::task::{Context, Poll, Waker};
+use interrupts;
+use pdc::{self, PeripheralDmaController};
+use pio::pioa::{PA26, PA27, PA28, PA29, PA30, PA31};
+use pio::{Input, Peripheral, Pin, PinId, PullUp, C};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::executor::WakerCell;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use silica::sd::{BusWidth, Host, HostError, Response};
+use silica_arm_cortexm4::interrupt::{self, Mutex};
+
+/// Waker of the task awaiting a `Transfer`.
+static WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+
+register! {
+    /// HSMCI Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        bool: _, pub software_reset: 7;
+        bool: _, pub disable_power_save: 3;
+        bool: _, pub enable_power_save: 2;
+        bool: _, pub disable: 1;
+        bool: _, pub enable: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// Adds half a period to the clock divider.
+        bool: pub clock_odd, pub set_clock_odd: 16;
+        bool: pub pdc_mode, pub set_pdc_mode: 15;
+        bool: pub padding_value, pub set_padding_value: 14;
+        /// Transfers bytes rather than words, for buffers that are not word aligned.
+        bool: pub force_byte, pub set_force_byte: 13;
+        /// Stops the clock when the transmit buffer is empty rather than underrunning.
+        bool: pub write_proof, pub set_write_proof: 12;
+        /// Stops the clock when the receive buffer is full rather than overrunning.
+        bool: pub read_proof, pub set_read_proof: 11;
+        u8: pub power_save_divider, pub set_power_save_divider: 10, 8;
+        /// MCCK is MCK / (2 * (divider + 1)).
+        u8: pub clock_divider, pub set_clock_divider: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI Data Timeout Register
+    #[derive(Copy, Clone)]
+    pub struct DTORegister(u32) {
+        /// The timeout is cycles * 2^(2 * multiplier) with the multiplier 1 to 7 counting as
+        /// 2^(4 * multiplier) instead.
+        u8: pub multiplier, pub set_multiplier: 6, 4;
+        u8: pub cycles, pub set_cycles: 3, 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoBusError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bus {
+    OneBit,
+    FourBits,
+    EightBits,
+}
+impl TryFrom<u32> for Bus {
+    type Error = TryIntoBusError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Bus::OneBit),
+            2 => Ok(Bus::FourBits),
+            3 => Ok(Bus::EightBits),
+            _ => Err(TryIntoBusError(())),
+        }
+    }
+}
+impl From<Bus> for u32 {
+    fn from(v: Bus) -> u32 {
+        match v {
+            Bus::OneBit => 0,
+            Bus::FourBits => 2,
+            Bus::EightBits => 3,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI SD/SDIO Card Register
+    #[derive(Copy, Clone)]
+    pub struct SDCRegister(u32) {
+        Bus: pub bus_width, pub set_bus_width: 7, 6;
+        /// Only the slot A is bonded out.
+        u8: pub slot, pub set_slot: 1, 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoResponseTypeError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseType {
+    None,
+    Bits48,
+    Bits136,
+    /// 48 bits, then busy on the data line.
+    Busy48,
+}
+impl TryFrom<u32> for ResponseType {
+    type Error = TryIntoResponseTypeError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(ResponseType::None),
+            1 => Ok(ResponseType::Bits48),
+            2 => Ok(ResponseType::Bits136),
+            3 => Ok(ResponseType::Busy48),
+            _ => Err(TryIntoResponseTypeError(())),
+        }
+    }
+}
+impl From<ResponseType> for u32 {
+    fn from(v: ResponseType) -> u32 {
+        match v {
+            ResponseType::None => 0,
+            ResponseType::Bits48 => 1,
+            ResponseType::Bits136 => 2,
+            ResponseType::Busy48 => 3,
+        }
+    }
+}
+impl From<Response> for ResponseType {
+    fn from(r: Response) -> ResponseType {
+        match r {
+            Response::None => ResponseType::None,
+            Response::R1b => ResponseType::Busy48,
+            Response::R2 => ResponseType::Bits136,
+            _ => ResponseType::Bits48,
+        }
+    }
+}
+
+register! {
+    /// HSMCI Command Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CMDRegister(u32) {
+        /// 0 single block, 1 multiple blocks.
+        u8: _, pub set_transfer_type: 21, 19;
+        bool: _, pub set_read: 18;
+        /// 1 starts a data transfer, 2 stops it.
+        u8: _, pub set_transfer_command: 17, 16;
+        /// 64 cycles rather than 5 of maximum latency for the command to response.
+        bool: _, pub set_max_latency: 12;
+        bool: _, pub set_open_drain: 11;
+        /// 1 sends the 74 initialization clock cycles.
+        u8: _, pub set_special_command: 10, 8;
+        ResponseType: _, pub set_response_type: 7, 6;
+        u8: _, pub set_command: 5, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI Block Register
+    #[derive(Copy, Clone)]
+    pub struct BLKRegister(u32) {
+        u16: pub block_length, pub set_block_length: 31, 16;
+        u16: pub block_count, pub set_block_count: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI Status Register, also the layout of the Interrupt Enable, Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub underrun, pub set_underrun: 31;
+        bool: pub overrun, pub set_overrun: 30;
+        bool: pub transfer_done, pub set_transfer_done: 27;
+        bool: pub fifo_empty, pub set_fifo_empty: 26;
+        bool: pub completion_signal_timeout, pub set_completion_signal_timeout: 23;
+        bool: pub data_timeout, pub set_data_timeout: 22;
+        bool: pub data_crc_error, pub set_data_crc_error: 21;
+        bool: pub response_timeout, pub set_response_timeout: 20;
+        bool: pub response_end_bit_error, pub set_response_end_bit_error: 19;
+        bool: pub response_crc_error, pub set_response_crc_error: 18;
+        bool: pub response_direction_error, pub set_response_direction_error: 17;
+        bool: pub response_index_error, pub set_response_index_error: 16;
+        /// The transmit PDC buffers are both empty.
+        bool: pub tx_buffer_empty, pub set_tx_buffer_empty: 15;
+        /// The receive PDC buffers are both full.
+        bool: pub rx_buffer_full, pub set_rx_buffer_full: 14;
+        bool: pub end_of_tx, pub set_end_of_tx: 7;
+        bool: pub end_of_rx, pub set_end_of_rx: 6;
+        bool: pub not_busy, pub set_not_busy: 5;
+        bool: pub data_transfer_in_progress, pub set_data_transfer_in_progress: 4;
+        bool: pub block_ended, pub set_block_ended: 3;
+        bool: pub tx_ready, pub set_tx_ready: 2;
+        bool: pub rx_ready, pub set_rx_ready: 1;
+        bool: pub command_ready, pub set_command_ready: 0;
+    }
+}
+
+impl SRegister {
+    /// The R3 responses have neither CRC nor command index: `strict` is false for them.
+    fn response_error(self, strict: bool) -> Option<HostError> {
+        if self.response_timeout() {
+            Some(HostError::Timeout)
+        } else if strict && self.response_crc_error() {
+            Some(HostError::Crc)
+        } else if (strict && self.response_index_error())
+            || self.response_direction_error()
+            || self.response_end_bit_error()
+        {
+            Some(HostError::Response)
+        } else {
+            None
+        }
+    }
+
+    fn data_error(self) -> Option<HostError> {
+        if self.data_timeout() {
+            Some(HostError::Timeout)
+        } else if self.data_crc_error() {
+            Some(HostError::Crc)
+        } else if self.overrun() || self.underrun() {
+            Some(HostError::Data)
+        } else {
+            None
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// HSMCI Configuration Register
+    #[derive(Copy, Clone)]
+    pub struct CFGRegister(u32) {
+        /// The next command is sent after the last block's end bit rather than its start.
+        bool: pub synchronize_last_block, pub set_synchronize_last_block: 12;
+        bool: pub high_speed, pub set_high_speed: 8;
+        /// The status flags are cleared on read rather than on the next command.
+        bool: pub flags_cleared_on_read, pub set_flags_cleared_on_read: 4;
+        bool: pub fifo_mode, pub set_fifo_mode: 0;
+    }
+}
+
+/// High Speed MultiMedia Card Interface
+#[repr(C)]
+pub struct HighSpeedMci {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    pub dtor: RegisterCell<DTORegister>,
+    pub sdcr: RegisterCell<SDCRegister>,
+    /// Argument Register
+    pub argr: RegisterCell<u32>,
+    pub cmdr: RegisterCell<CMDRegister>,
+    pub blkr: RegisterCell<BLKRegister>,
+    /// Completion Signal Timeout Register
+    pub cstor: RegisterCell<u32>,
+    /// Response Registers, successive reads of any of them return the successive words.
+    pub rspr: [RoRegisterCell<u32>; 4],
+    /// Receive Data Register
+    pub rdr: RoRegisterCell<u32>,
+    /// Transmit Data Register
+    pub tdr: RegisterCell<u32>,
+    _reserved0: ReservedCell<[u32; 2]>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub ier: RegisterCell<SRegister>,
+    pub idr: RegisterCell<SRegister>,
+    pub imr: RoRegisterCell<SRegister>,
+    _reserved1: ReservedCell<[u32; 1]>,
+    pub cfg: RegisterCell<CFGRegister>,
+    _reserved2: ReservedCell<[u32; 35]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    _reserved3: ReservedCell<[u32; 5]>,
+    pub pdc: PeripheralDmaController,
+}
+
+/// Polls of the status before a card holding the data line busy is considered stuck, about a
+/// second at 120MHz.
+const BUSY_POLLS: u32 = 10_000_000;
+
+/// Clock divider giving the fastest MCCK not above `hz`, the slowest one for 0.
+pub fn clock_divider(mck: u32, hz: u32) -> u8 {
+    let hz = u64::from(hz.max(1));
+    let divisor = (u64::from(mck) + 2 * hz - 1) / (2 * hz);
+    (divisor.max(1).min(256) - 1) as u8
+}
+
+/// The card detect switch.
+pub trait CardDetect {
+    fn is_present(&self) -> bool;
+}
+
+/// For a slot without switch.
+pub struct AlwaysPresent;
+impl CardDetect for AlwaysPresent {
+    fn is_present(&self) -> bool {
+        true
+    }
+}
+
+/// A switch pulling the pin low when a card is inserted.
+impl<ID: PinId> CardDetect for Pin<ID, Input<PullUp>> {
+    fn is_present(&self) -> bool {
+        self.is_low()
+    }
+}
+
+/// Slot A pins, the data lines 1 to 3 are only used in 4-bit mode.
+pub struct Pins {
+    pub ck: Pin<PA29, Peripheral<C>>,
+    pub cda: Pin<PA28, Peripheral<C>>,
+    pub da0: Pin<PA30, Peripheral<C>>,
+    pub da1: Pin<PA31, Peripheral<C>>,
+    pub da2: Pin<PA26, Peripheral<C>>,
+    pub da3: Pin<PA27, Peripheral<C>>,
+}
+
+pub struct Hsmci<CD> {
+    regs: &'static mut HighSpeedMci,
+    pins: Pins,
+    card_detect: CD,
+    mck: u32,
+}
+
+impl<CD: CardDetect> Hsmci<CD> {
+    pub fn new(
+        regs: &'static mut HighSpeedMci,
+        pins: Pins,
+        card_detect: CD,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Hsmci<CD> {
+        pmc.enable_peripheral_clock(PeripheralId::Hsmci);
+        regs.cr.get_mut().software_reset(true);
+        regs.cr.get_mut().disable(true);
+        {
+            let mut dtor = regs.dtor.get_mut();
+            // 15 * 2^20 cycles, about 600ms at 25MHz.
+            dtor.set_cycles(15);
+            dtor.set_multiplier(7);
+        }
+        // 2^20 cycles to signal the completion.
+        regs.cstor.set(0x7F);
+        regs.cfg.get_mut().set_fifo_mode(true);
+        {
+            let mut mr = regs.mr.get_mut();
+            mr.set_read_proof(true);
+            mr.set_write_proof(true);
+        }
+        regs.cr.get_mut().enable(true);
+        regs.cr.get_mut().disable_power_save(true);
+        Hsmci {
+            regs,
+            pins,
+            card_detect,
+            mck: clocks.peripheral(PeripheralId::Hsmci),
+        }
+    }
+
+    pub fn release(
+        self,
+        pmc: &mut PowerManagementController,
+    ) -> (&'static mut HighSpeedMci, Pins, CD) {
+        self.regs.cr.get_mut().disable(true);
+        pmc.disable_peripheral_clock(PeripheralId::Hsmci);
+        (self.regs, self.pins, self.card_detect)
+    }
+
+    /// Sends a command and waits for its response.
+    fn send(
+        &mut self,
+        cmdr: CMDRegister,
+        argument: u32,
+        strict: bool,
+    ) -> Result<SRegister, HostError> {
+        self.regs.argr.set(argument);
+        self.regs.cmdr.set(cmdr);
+        loop {
+            let sr = self.regs.sr.get();
+            if sr.command_ready() {
+                return match sr.response_error(strict) {
+                    Some(e) => Err(e),
+                    None => Ok(sr),
+                };
+            }
+        }
+    }
+
+    fn command_register(index: u8, response: Response) -> CMDRegister {
+        let mut cmdr = CMDRegister(0);
+        cmdr.set_command(index);
+        cmdr.set_response_type(response.into());
+        cmdr.set_max_latency(true);
+        cmdr
+    }
+
+    /// Prepares the PDC for `len` bytes at `address`, in words when possible.
+    fn setup_transfer(
+        &mut self,
+        address: u32,
+        len: usize,
+        block_size: usize,
+    ) -> Result<u16, HostError> {
+        let bytes = address % 4 != 0 || len % 4 != 0;
+        let count = if bytes { len } else { len / 4 };
+        if count > pdc::MAX_TRANSFER {
+            return Err(HostError::TooLong);
+        }
+        {
+            let mut mr = self.regs.mr.get_mut();
+            mr.set_pdc_mode(true);
+            mr.set_force_byte(bytes);
+        }
+        {
+            let mut blkr = self.regs.blkr.get_mut();
+            blkr.set_block_length(block_size as u16);
+            blkr.set_block_count((len / block_size) as u16);
+        }
+        Ok(count as u16)
+    }
+
+    /// Waits for the end of a data transfer and stops the PDC.
+    fn finish_transfer(&mut self) -> Result<(), HostError> {
+        let result = loop {
+            let sr = self.regs.sr.get();
+            if let Some(e) = sr.data_error() {
+                break Err(e);
+            }
+            if sr.transfer_done() {
+                break Ok(());
+            }
+        };
+        self.stop_transfer();
+        result
+    }
+
+    fn stop_transfer(&mut self) {
+        self.regs.pdc.stop();
+        self.regs.mr.get_mut().set_pdc_mode(false);
+    }
+
+    /// Waits for the card to release the data line.
+    fn wait_not_busy(&mut self) -> Result<(), HostError> {
+        for _ in 0..BUSY_POLLS {
+            if self.regs.sr.get().not_busy() {
+                return Ok(());
+            }
+        }
+        Err(HostError::Timeout)
+    }
+
+    fn data_command(index: u8, count: usize, read: bool) -> CMDRegister {
+        let mut cmdr = Self::command_register(index, Response::R1);
+        cmdr.set_transfer_command(1);
+        cmdr.set_transfer_type(if count > 1 { 1 } else { 0 });
+        cmdr.set_read(read);
+        cmdr
+    }
+
+    /// Reads the blocks of the data command `index` into `buffer`, as `Host::read` does.
+    pub fn read_async(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &'static mut [u8],
+    ) -> Transfer<CD> {
+        self.start_async(index, argument, block_size, buffer, true)
+    }
+
+    /// Writes `buffer` with the data command `index`, as `Host::write` does. The card being
+    /// busy programming the blocks is waited for without timeout.
+    pub fn write_async(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &'static mut [u8],
+    ) -> Transfer<CD> {
+        self.start_async(index, argument, block_size, buffer, false)
+    }
+
+    fn start_async(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &'static mut [u8],
+        read: bool,
+    ) -> Transfer<CD> {
+        let (address, len) = (buffer.as_mut_ptr() as u32, buffer.len());
+        let status = self.start_data(index, argument, block_size, address, len, read);
+        Transfer {
+            state: if status.is_ok() {
+                State::Data
+            } else {
+                State::Done
+            },
+            hsmci: self,
+            buffer: Some(buffer),
+            read,
+            status,
+        }
+    }
+
+    /// Sends the data command and starts the PDC, returning the card status.
+    fn start_data(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        address: u32,
+        len: usize,
+        read: bool,
+    ) -> Result<u32, HostError> {
+        let count = self.setup_transfer(address, len, block_size)?;
+        if read {
+            // the buffer is kept by the `Transfer` until it stops the PDC.
+            unsafe { self.regs.pdc.start_read(address, count) };
+        }
+        let cmdr = Self::data_command(index, len / block_size, read);
+        if let Err(e) = self.send(cmdr, argument, true) {
+            self.stop_transfer();
+            return Err(e);
+        }
+        let status = self.regs.rspr[0].get();
+        if !read {
+            // the PDC must only start once the command is sent.
+            unsafe { self.regs.pdc.start_write(address, count) };
+        }
+        Ok(status)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Data,
+    /// The card holds the data line while it programs the written blocks.
+    Busy,
+    Done,
+}
+
+/// Future of an asynchronous data transfer, completing with the card status of the command and
+/// the buffer. Dropping it stops the transfer.
+pub struct Transfer<'a, CD: CardDetect + 'a> {
+    hsmci: &'a mut Hsmci<CD>,
+    buffer: Option<&'static mut [u8]>,
+    read: bool,
+    status: Result<u32, HostError>,
+    state: State,
+}
+impl<'a, CD: CardDetect> Future for Transfer<'a, CD> {
+    type Output = (Result<u32, HostError>, &'static mut [u8]);
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            let sr = this.hsmci.regs.sr.get();
+            let mut source = SRegister(0);
+            match this.state {
+                State::Data => {
+                    if let Some(e) = sr.data_error() {
+                        this.status = Err(e);
+                        this.state = State::Done;
+                    } else if sr.transfer_done() {
+                        this.state = if this.read { State::Done } else { State::Busy };
+                    } else {
+                        source.set_transfer_done(true);
+                        source.set_data_timeout(true);
+                        source.set_data_crc_error(true);
+                        source.set_overrun(true);
+                        source.set_underrun(true);
+                    }
+                    if this.state != State::Data {
+                        this.hsmci.stop_transfer();
+                    }
+                }
+                State::Busy if sr.not_busy() => this.state = State::Done,
+                State::Busy => source.set_not_busy(true),
+                State::Done => {
+                    let buffer = this
+                        .buffer
+                        .take()
+                        .expect("transfer polled after completion");
+                    return Poll::Ready((this.status, buffer));
+                }
+            }
+            if source.0 != 0 {
+                listen(cx.waker(), source);
+                return Poll::Pending;
+            }
+        }
+    }
+}
+impl<'a, CD: CardDetect> Drop for Transfer<'a, CD> {
+    fn drop(&mut self) {
+        if self.state == State::Data {
+            self.hsmci.stop_transfer();
+        }
+    }
+}
+
+/// Registers the task waiting on the transfer before enabling the interrupt `sources` waking it:
+/// the flags are levels, an already set one fires right away.
+fn listen(waker: &Waker, sources: SRegister) {
+    interrupt::free(|cs| WAKER.borrow(cs).register(waker));
+    unsafe { ::HSMCI.ier.set(sources) };
+    interrupts::enable(PeripheralId::Hsmci);
+}
+
+/// Wakes the task awaiting a `Transfer`, to be called from the HSMCI interrupt handler. The
+/// sources are disabled until the task waits again.
+pub fn on_interrupt() {
+    let regs = unsafe { &mut ::HSMCI };
+    let sources = regs.imr.get();
+    regs.idr.set(sources);
+    interrupt::free(|cs| WAKER.borrow(cs).wake());
+}
+
+impl<CD: CardDetect> Host for Hsmci<CD> {
+    fn card_present(&mut self) -> bool {
+        self.card_detect.is_present()
+    }
+
+    fn power_up(&mut self) {
+        let mut cmdr = CMDRegister(0);
+        cmdr.set_special_command(1);
+        let _ = self.send(cmdr, 0, false);
+    }
+
+    fn set_clock(&mut self, hz: u32) {
+        let divider = clock_divider(self.mck, hz);
+        self.regs.mr.get_mut().set_clock_divider(divider);
+        self.regs
+            .cfg
+            .get_mut()
+            .set_high_speed(hz > silica::sd::DEFAULT_CLOCK_HZ);
+    }
+
+    fn set_bus_width(&mut self, width: BusWidth) {
+        let bus = match width {
+            BusWidth::One => Bus::OneBit,
+            BusWidth::Four => Bus::FourBits,
+        };
+        let mut sdcr = self.regs.sdcr.get_mut();
+        sdcr.set_slot(0);
+        sdcr.set_bus_width(bus);
+    }
+
+    fn command(
+        &mut self,
+        index: u8,
+        argument: u32,
+        response: Response,
+    ) -> Result<[u32; 4], HostError> {
+        let cmdr = Self::command_register(index, response);
+        self.send(cmdr, argument, response != Response::R3)?;
+        let mut words = [0; 4];
+        match response {
+            Response::None => {}
+            Response::R2 => {
+                for word in words.iter_mut() {
+                    *word = self.regs.rspr[0].get();
+                }
+            }
+            _ => words[0] = self.regs.rspr[0].get(),
+        }
+        if response == Response::R1b {
+            self.wait_not_busy()?;
+        }
+        Ok(words)
+    }
+
+    fn read(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &mut [u8],
+    ) -> Result<u32, HostError> {
+        let address = buffer.as_mut_ptr() as u32;
+        let count = self.setup_transfer(address, buffer.len(), block_size)?;
+        // the buffer is borrowed until `finish_transfer` stops the PDC.
+        unsafe { self.regs.pdc.start_read(address, count) };
+        let cmdr = Self::data_command(index, buffer.len() / block_size, true);
+        if let Err(e) = self.send(cmdr, argument, true) {
+            let _ = self.finish_transfer();
+            return Err(e);
+        }
+        let status = self.regs.rspr[0].get();
+        self.finish_transfer()?;
+        Ok(status)
+    }
+
+    fn write(
+        &mut self,
+        index: u8,
+        argument: u32,
+        block_size: usize,
+        buffer: &[u8],
+    ) -> Result<u32, HostError> {
+        let address = buffer.as_ptr() as u32;
+        let count = self.setup_transfer(address, buffer.len(), block_size)?;
+        let cmdr = Self::data_command(index, buffer.len() / block_size, false);
+        if let Err(e) = self.send(cmdr, argument, true) {
+            let _ = self.finish_transfer();
+            return Err(e);
+        }
+        let status = self.regs.rspr[0].get();
+        // the PDC must only start once the command is sent.
+        unsafe { self.regs.pdc.start_write(address, count) };
+        self.finish_transfer()?;
+        self.wait_not_busy()?;
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_divider() {
+        // 120MHz / (2 * 150) = 400kHz
+        assert_eq!(149, clock_divider(120_000_000, 400_000));
+        // 120MHz / (2 * 3) = 20MHz, 30MHz would be too fast for a 25MHz card.
+        assert_eq!(2, clock_divider(120_000_000, 25_000_000));
+        assert_eq!(255, clock_divider(120_000_000, 100_000));
+        assert_eq!(0, clock_divider(120_000_000, 100_000_000));
+        assert_eq!(255, clock_divider(120_000_000, 0));
+        assert_eq!(0, clock_divider(120_000_000, u32::max_value()));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0x100 + 10 * 4, size_of::<HighSpeedMci>());
+    }
+}
@@ -0,0 +1,152 @@
+//! Peripheral interrupts vector.
+//!
+//! Each vector calls a symbol named after the peripheral (e.g. `pioa_handler`) that defaults to
+//! `default_interrupt_handler`. The application overrides it by defining it:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn pioa_handler() {
+//!     let status = PIOA.interrupt_status();
+//!     // ...
+//! }
+//! ```
+//!
+//! The interrupt numbers are the peripheral identifiers.
+
+use pmc::PeripheralId;
+use silica_arm_cortexm4::ppb::NVIC;
+
+/// Number of vectors in the table.
+pub const INTERRUPT_COUNT: usize = 46;
+
+impl PeripheralId {
+    /// Number of the peripheral's interrupt line.
+    pub fn irq(self) -> u8 {
+        self as u8
+    }
+}
+
+pub fn enable(id: PeripheralId) {
+    unsafe { NVIC.enable(id.irq()) }
+}
+pub fn disable(id: PeripheralId) {
+    unsafe { NVIC.disable(id.irq()) }
+}
+/// Lower values have a higher priority, only the 4 most significant bits are implemented.
+pub fn set_priority(id: PeripheralId, priority: u8) {
+    unsafe { NVIC.set_priority(id.irq(), priority) }
+}
+pub fn pend(id: PeripheralId) {
+    unsafe { NVIC.pend(id.irq()) }
+}
+pub fn unpend(id: PeripheralId) {
+    unsafe { NVIC.unpend(id.irq()) }
+}
+
+/// Disables the interrupts enabled without a handler: the peripheral would keep requesting it.
+#[cfg(target_arch = "arm")]
+#[no_mangle]
+pub unsafe extern "C" fn default_interrupt_handler() {
+    // the peripheral interrupts follow the 16 exceptions of the core.
+    let vector = silica_arm_cortexm4::ppb::SCB.icsr.get().active_vector();
+    NVIC.disable((vector - 16) as u8);
+}
+
+#[cfg(target_arch = "arm")]
+extern "C" {
+    fn supc_handler();
+    fn rstc_handler();
+    fn rtc_handler();
+    fn rtt_handler();
+    fn wdt_handler();
+    fn pmc_handler();
+    fn efc_handler();
+    fn uart0_handler();
+    fn smc_handler();
+    fn pioa_handler();
+    fn piob_handler();
+    fn pioc_handler();
+    fn piod_handler();
+    fn pioe_handler();
+    fn usart0_handler();
+    fn usart1_handler();
+    fn hsmci_handler();
+    fn twi0_handler();
+    fn twi1_handler();
+    fn spi_handler();
+    fn dmac_handler();
+    fn tc0_handler();
+    fn tc1_handler();
+    fn tc2_handler();
+    fn tc3_handler();
+    fn tc4_handler();
+    fn tc5_handler();
+    fn tc6_handler();
+    fn tc7_handler();
+    fn tc8_handler();
+    fn afec0_handler();
+    fn afec1_handler();
+    fn dacc_handler();
+    fn acc_handler();
+    fn fpu_handler();
+    fn udp_handler();
+    fn pwm_handler();
+    fn can0_handler();
+    fn can1_handler();
+    fn aes_handler();
+    fn gmac_handler();
+    fn uart1_handler();
+}
+
+#[cfg(target_arch = "arm")]
+#[link_section = ".vector_table.interrupts_vector"]
+#[no_mangle]
+#[used]
+pub static INTERRUPTS: [Option<unsafe extern "C" fn()>; INTERRUPT_COUNT] = [
+    Some(supc_handler),
+    Some(rstc_handler),
+    Some(rtc_handler),
+    Some(rtt_handler),
+    Some(wdt_handler),
+    Some(pmc_handler),
+    Some(efc_handler),
+    Some(uart0_handler),
+    Some(smc_handler),
+    Some(pioa_handler),
+    Some(piob_handler),
+    Some(pioc_handler),
+    Some(piod_handler),
+    Some(pioe_handler),
+    Some(usart0_handler),
+    Some(usart1_handler),
+    Some(hsmci_handler),
+    Some(twi0_handler),
+    Some(twi1_handler),
+    Some(spi_handler),
+    Some(dmac_handler),
+    Some(tc0_handler),
+    Some(tc1_handler),
+    Some(tc2_handler),
+    Some(tc3_handler),
+    Some(tc4_handler),
+    Some(tc5_handler),
+    Some(tc6_handler),
+    Some(tc7_handler),
+    Some(tc8_handler),
+    Some(afec0_handler),
+    Some(afec1_handler),
+    Some(dacc_handler),
+    Some(acc_handler),
+    Some(fpu_handler),
+    Some(udp_handler),
+    Some(pwm_handler),
+    Some(can0_handler),
+    Some(can1_handler),
+    Some(aes_handler),
+    None,
+    None,
+    None,
+    None,
+    Some(gmac_handler),
+    Some(uart1_handler),
+];
@@ -1,6 +1,74 @@
+//! Drivers of the SAM4E family.
+//!
+//! One of the `sam4e8c`, `sam4e8e`, `sam4e16c` or `sam4e16e` features selects the chip: its
+//! `link.x` is generated with the memory layout, and the ports missing from its package are
+//! compiled out. The `SILICA_STACK_SIZE`, `SILICA_HEAP_SIZE` and `SILICA_PANIC_MSG_SIZE`
+//! variables set the sizes in bytes reserved in the RAM when building, the heap taking the rest
+//! by default.
+#![feature(const_fn, core_intrinsics, futures_api)]
 #![no_std]
 
-extern crate silica_arm_cortexm4;
+#[macro_use]
+extern crate silica;
+pub extern crate silica_arm_cortexm4;
+
+pub mod afec;
+pub mod can;
+pub mod clock;
+pub mod efc;
+pub mod gmac;
+pub mod hsmci;
+pub mod interrupts;
+pub mod pdc;
+pub mod pio;
+pub mod pmc;
+pub mod power;
+pub mod pwm;
+pub mod rstc;
+pub mod rtc;
+pub mod rtt;
+pub mod serial;
+pub mod spi;
+pub mod supc;
+pub mod tc;
+pub mod twi;
+pub mod uart;
+pub mod udp;
+pub mod usart;
+pub mod wdt;
+
+extern "C" {
+    pub static mut AFEC0: afec::AnalogFrontEnd;
+    pub static mut AFEC1: afec::AnalogFrontEnd;
+    pub static mut CAN0: can::ControllerAreaNetwork;
+    pub static mut CAN1: can::ControllerAreaNetwork;
+    pub static mut EFC: efc::EnhancedEmbeddedFlashController;
+    pub static mut GMAC: gmac::EthernetMac;
+    pub static mut HSMCI: hsmci::HighSpeedMci;
+    pub static mut PIOA: pio::ParallelIo;
+    pub static mut PIOB: pio::ParallelIo;
+    pub static mut PIOC: pio::ParallelIo;
+    pub static mut PIOD: pio::ParallelIo;
+    pub static mut PIOE: pio::ParallelIo;
+    pub static mut PMC: pmc::PowerManagementController;
+    pub static mut PWM: pwm::PulseWidthModulation;
+    pub static mut RSTC: rstc::ResetController;
+    pub static mut RTC: rtc::RealTimeClock;
+    pub static mut RTT: rtt::RealTimeTimer;
+    pub static mut SPI: spi::SerialPeripheralInterface;
+    pub static mut SUPC: supc::SupplyController;
+    pub static mut TC0: tc::TimerCounter;
+    pub static mut TC1: tc::TimerCounter;
+    pub static mut TC2: tc::TimerCounter;
+    pub static mut TWI0: twi::TwoWireInterface;
+    pub static mut TWI1: twi::TwoWireInterface;
+    pub static mut UART0: uart::Uart;
+    pub static mut UART1: uart::Uart;
+    pub static mut UDP: udp::UsbDevicePort;
+    pub static mut USART0: usart::Usart;
+    pub static mut USART1: usart::Usart;
+    pub static mut WDT: wdt::WatchdogTimer;
+}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,345 @@
+//! Peripheral DMA Controller
+//!
+//! Each peripheral with PDC support has these registers at offset 0x100 of its own block.
+//!
+//! A transfer runs from the pointer and counter registers while the next ones hold the buffer
+//! to continue with: `Pdc` keeps both busy with `&'static mut` buffers, handed back in order as
+//! they complete, so that a stream never stops between two buffers:
+//!
+//! ```ignore
+//! let mut pdc = Pdc::new(unsafe { &mut ::UART0.pdc });
+//! pdc.read(unsafe { &mut BUFFER_A })?;
+//! pdc.read(unsafe { &mut BUFFER_B })?;
+//! loop {
+//!     if let Some(buffer) = pdc.read_done() {
+//!         // process then queue it again behind the other one.
+//!         pdc.read(buffer)?;
+//!     }
+//! }
+//! ```
+use core::convert::Into;
+use core::fmt;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+
+/// The counters are 16 bits wide.
+pub const MAX_TRANSFER: usize = 0xFFFF;
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+}
+
+/// Items the PDC moves: bytes, half-words or words, as set by the peripheral.
+pub trait Word: sealed::Sealed + Copy {}
+impl Word for u8 {}
+impl Word for u16 {}
+impl Word for u32 {}
+
+register! {
+    /// Transfer Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct TCRegister(u32) {
+        bool: _, pub disable_transmitter: 9;
+        bool: _, pub enable_transmitter: 8;
+        bool: _, pub disable_receiver: 1;
+        bool: _, pub enable_receiver: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Transfer Status Register
+    #[derive(Copy, Clone)]
+    pub struct TSRegister(u32) {
+        bool: pub transmitter_enabled, _: 8;
+        bool: pub receiver_enabled, _: 0;
+    }
+}
+
+#[repr(C)]
+pub struct PeripheralDmaController {
+    /// Receive Pointer Register
+    pub rpr: RegisterCell<u32>,
+    /// Receive Counter Register
+    pub rcr: RegisterCell<u32>,
+    /// Transmit Pointer Register
+    pub tpr: RegisterCell<u32>,
+    /// Transmit Counter Register
+    pub tcr: RegisterCell<u32>,
+    /// Receive Next Pointer Register
+    pub rnpr: RegisterCell<u32>,
+    /// Receive Next Counter Register
+    pub rncr: RegisterCell<u32>,
+    /// Transmit Next Pointer Register
+    pub tnpr: RegisterCell<u32>,
+    /// Transmit Next Counter Register
+    pub tncr: RegisterCell<u32>,
+    /// Transfer Control Register
+    pub ptcr: RegisterCell<TCRegister>,
+    /// Transfer Status Register
+    pub ptsr: RoRegisterCell<TSRegister>,
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Rx,
+    Tx,
+}
+
+impl PeripheralDmaController {
+    /// Starts receiving `count` items at `address`, dropping the queued transfer.
+    ///
+    /// The memory must stay valid until the transfer completes or is stopped.
+    pub unsafe fn start_read(&mut self, address: u32, count: u16) {
+        self.start(Direction::Rx, address, count)
+    }
+
+    /// Starts sending `count` items from `address`, dropping the queued transfer.
+    ///
+    /// The memory must stay valid until the transfer completes or is stopped.
+    pub unsafe fn start_write(&mut self, address: u32, count: u16) {
+        self.start(Direction::Tx, address, count)
+    }
+
+    /// Stops both directions, the counters keep their values.
+    pub fn stop(&mut self) {
+        let mut ptcr = TCRegister(0);
+        ptcr.disable_receiver(true);
+        ptcr.disable_transmitter(true);
+        self.ptcr.set(ptcr);
+    }
+
+    fn enable(&mut self, direction: Direction, enable: bool) {
+        let mut ptcr = TCRegister(0);
+        match (direction, enable) {
+            (Direction::Rx, true) => ptcr.enable_receiver(true),
+            (Direction::Rx, false) => ptcr.disable_receiver(true),
+            (Direction::Tx, true) => ptcr.enable_transmitter(true),
+            (Direction::Tx, false) => ptcr.disable_transmitter(true),
+        }
+        self.ptcr.set(ptcr);
+    }
+
+    fn start(&mut self, direction: Direction, address: u32, count: u16) {
+        self.enable(direction, false);
+        match direction {
+            Direction::Rx => {
+                self.rncr.set(0);
+                self.rpr.set(address);
+                self.rcr.set(u32::from(count));
+            }
+            Direction::Tx => {
+                self.tncr.set(0);
+                self.tpr.set(address);
+                self.tcr.set(u32::from(count));
+            }
+        }
+        self.enable(direction, true);
+    }
+
+    /// Queues a transfer behind the one in progress.
+    fn queue(&mut self, direction: Direction, address: u32, count: u16) {
+        match direction {
+            Direction::Rx => {
+                self.rnpr.set(address);
+                self.rncr.set(u32::from(count));
+            }
+            Direction::Tx => {
+                self.tnpr.set(address);
+                self.tncr.set(u32::from(count));
+            }
+        }
+    }
+
+    /// The counters of the transfer in progress and of the queued one.
+    fn counters(&self, direction: Direction) -> (u32, u32) {
+        // the next counter first: it is cleared when the transfer in progress completes.
+        match direction {
+            Direction::Rx => {
+                let next = self.rncr.get();
+                (self.rcr.get(), next)
+            }
+            Direction::Tx => {
+                let next = self.tncr.get();
+                (self.tcr.get(), next)
+            }
+        }
+    }
+}
+
+/// Transfers completed among the `queued` ones, from the counters.
+fn completed(queued: usize, current: u32, next: u32) -> usize {
+    match (queued, current, next) {
+        (0, _, _) => 0,
+        (_, _, n) if n != 0 => 0,
+        (_, 0, _) => queued,
+        _ => queued - 1,
+    }
+}
+
+/// The buffers of one direction, the one in progress first.
+struct Queue<W: Word + 'static> {
+    buffers: [Option<&'static mut [W]>; 2],
+    /// Set when the direction is stopped: all the buffers are then handed back.
+    stopped: bool,
+}
+
+impl<W: Word> Queue<W> {
+    fn len(&self) -> usize {
+        self.buffers.iter().filter(|b| b.is_some()).count()
+    }
+
+    fn push(
+        &mut self,
+        regs: &mut PeripheralDmaController,
+        direction: Direction,
+        buffer: &'static mut [W],
+    ) -> Result<(), &'static mut [W]> {
+        if buffer.len() > MAX_TRANSFER {
+            return Err(buffer);
+        }
+        let (address, count) = (buffer.as_ptr() as u32, buffer.len() as u16);
+        match self.len() {
+            0 => {
+                self.stopped = false;
+                regs.start(direction, address, count);
+            }
+            1 if !self.stopped => regs.queue(direction, address, count),
+            _ => return Err(buffer),
+        }
+        let len = self.len();
+        self.buffers[len] = Some(buffer);
+        Ok(())
+    }
+
+    fn pop(
+        &mut self,
+        regs: &PeripheralDmaController,
+        direction: Direction,
+    ) -> Option<&'static mut [W]> {
+        let (current, next) = regs.counters(direction);
+        if !self.stopped && completed(self.len(), current, next) == 0 {
+            return None;
+        }
+        let buffer = self.buffers[0].take();
+        self.buffers.swap(0, 1);
+        buffer
+    }
+
+    /// Items transferred in the buffer in progress.
+    fn progress(&self, regs: &PeripheralDmaController, direction: Direction) -> usize {
+        let (current, next) = regs.counters(direction);
+        match self.buffers[0] {
+            Some(ref buffer) if completed(self.len(), current, next) == 0 => {
+                buffer.len() - current as usize
+            }
+            Some(ref buffer) => buffer.len(),
+            None => 0,
+        }
+    }
+
+    fn stop(&mut self, regs: &mut PeripheralDmaController, direction: Direction) -> usize {
+        regs.enable(direction, false);
+        let progress = self.progress(regs, direction);
+        self.stopped = true;
+        progress
+    }
+}
+
+/// A PDC channel streaming `W` items to and from `&'static mut` buffers.
+///
+/// Each direction holds up to two buffers: the one in progress and the one continuing it.
+pub struct Pdc<W: Word + 'static> {
+    regs: &'static mut PeripheralDmaController,
+    rx: Queue<W>,
+    tx: Queue<W>,
+}
+
+impl<W: Word> Pdc<W> {
+    /// Stops both directions.
+    pub fn new(regs: &'static mut PeripheralDmaController) -> Pdc<W> {
+        regs.stop();
+        Pdc {
+            regs,
+            rx: Queue {
+                buffers: [None, None],
+                stopped: false,
+            },
+            tx: Queue {
+                buffers: [None, None],
+                stopped: false,
+            },
+        }
+    }
+
+    /// Stops both directions, the buffers still queued are lost.
+    pub fn release(self) -> &'static mut PeripheralDmaController {
+        self.regs.stop();
+        self.regs
+    }
+
+    /// Receives into `buffer` once the queued buffers are full, `buffer` is given back if two
+    /// are already queued or if it is longer than `MAX_TRANSFER`.
+    pub fn read(&mut self, buffer: &'static mut [W]) -> Result<(), &'static mut [W]> {
+        self.rx.push(self.regs, Direction::Rx, buffer)
+    }
+
+    /// The oldest buffer received, if full.
+    pub fn read_done(&mut self) -> Option<&'static mut [W]> {
+        self.rx.pop(self.regs, Direction::Rx)
+    }
+
+    /// Items received in the oldest buffer.
+    pub fn read_progress(&self) -> usize {
+        self.rx.progress(self.regs, Direction::Rx)
+    }
+
+    /// Stops the reception and returns the items received in the oldest buffer: `read_done`
+    /// then hands back all the buffers.
+    pub fn stop_read(&mut self) -> usize {
+        self.rx.stop(self.regs, Direction::Rx)
+    }
+
+    /// Sends `buffer` once the queued buffers are sent, `buffer` is given back if two are
+    /// already queued or if it is longer than `MAX_TRANSFER`.
+    pub fn write(&mut self, buffer: &'static mut [W]) -> Result<(), &'static mut [W]> {
+        self.tx.push(self.regs, Direction::Tx, buffer)
+    }
+
+    /// The oldest buffer sent, if complete.
+    pub fn write_done(&mut self) -> Option<&'static mut [W]> {
+        self.tx.pop(self.regs, Direction::Tx)
+    }
+
+    /// All the buffers are sent, not necessarily handed back.
+    pub fn is_write_idle(&self) -> bool {
+        let (current, next) = self.regs.counters(Direction::Tx);
+        current == 0 && next == 0
+    }
+
+    /// Stops the transmission and returns the items sent from the oldest buffer: `write_done`
+    /// then hands back all the buffers.
+    pub fn stop_write(&mut self) -> usize {
+        self.tx.stop(self.regs, Direction::Tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completed() {
+        assert_eq!(0, completed(0, 0, 0));
+        assert_eq!(0, completed(1, 10, 0));
+        assert_eq!(1, completed(1, 0, 0));
+        // the next buffer is not loaded yet.
+        assert_eq!(0, completed(2, 0, 20));
+        assert_eq!(0, completed(2, 10, 20));
+        assert_eq!(1, completed(2, 15, 0));
+        assert_eq!(2, completed(2, 0, 0));
+        assert_eq!(0x28, ::core::mem::size_of::<PeripheralDmaController>());
+    }
+}
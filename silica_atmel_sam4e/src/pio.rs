@@ -0,0 +1,648 @@
+//! Parallel Input/Output Controller
+//!
+//! Each pin is a distinct type whose mode is part of its type, the conversions consume the pin:
+//!
+//! ```ignore
+//! let pins = pioa::take(unsafe { &mut PMC }).unwrap();
+//! let mut led = pins.pa0.into_push_pull_output();
+//! led.set_high();
+//! let button = pins.pa1.into_pull_up_input();
+//! button.enable_interrupt(Trigger::FallingEdge);
+//! let rx = pins.pa9.into_peripheral_a();
+//! ```
+//!
+//! A pin handed over to a peripheral driver (e.g. the UART's RX in peripheral A mode) is owned
+//! by the driver and can no longer be used as a GPIO.
+//!
+//! The pins are in input mode with the pull-up enabled after reset. Some pins (e.g. the JTAG and
+//! ERASE pins on port B) are system I/Os until released through the bus matrix.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{RegisterCell, ReservedCell, RoRegisterCell};
+use silica_arm_cortexm4::interrupt;
+
+#[repr(C)]
+pub struct ParallelIo {
+    /// PIO Enable Register
+    pub per: RegisterCell<u32>,
+    /// PIO Disable Register
+    pub pdr: RegisterCell<u32>,
+    /// PIO Status Register
+    pub psr: RoRegisterCell<u32>,
+    reserved0: ReservedCell<u32>,
+    /// Output Enable Register
+    pub oer: RegisterCell<u32>,
+    /// Output Disable Register
+    pub odr: RegisterCell<u32>,
+    /// Output Status Register
+    pub osr: RoRegisterCell<u32>,
+    reserved1: ReservedCell<u32>,
+    /// Glitch Input Filter Enable Register
+    pub ifer: RegisterCell<u32>,
+    /// Glitch Input Filter Disable Register
+    pub ifdr: RegisterCell<u32>,
+    /// Glitch Input Filter Status Register
+    pub ifsr: RoRegisterCell<u32>,
+    reserved2: ReservedCell<u32>,
+    /// Set Output Data Register
+    pub sodr: RegisterCell<u32>,
+    /// Clear Output Data Register
+    pub codr: RegisterCell<u32>,
+    /// Output Data Status Register
+    pub odsr: RegisterCell<u32>,
+    /// Pin Data Status Register
+    pub pdsr: RoRegisterCell<u32>,
+    /// Interrupt Enable Register
+    pub ier: RegisterCell<u32>,
+    /// Interrupt Disable Register
+    pub idr: RegisterCell<u32>,
+    /// Interrupt Mask Register
+    pub imr: RoRegisterCell<u32>,
+    /// Interrupt Status Register, cleared on read.
+    pub isr: RoRegisterCell<u32>,
+    /// Multi-driver Enable Register
+    pub mder: RegisterCell<u32>,
+    /// Multi-driver Disable Register
+    pub mddr: RegisterCell<u32>,
+    /// Multi-driver Status Register
+    pub mdsr: RoRegisterCell<u32>,
+    reserved3: ReservedCell<u32>,
+    /// Pull-up Disable Register
+    pub pudr: RegisterCell<u32>,
+    /// Pull-up Enable Register
+    pub puer: RegisterCell<u32>,
+    /// Pad Pull-up Status Register, a bit cleared means the pull-up is enabled.
+    pub pusr: RoRegisterCell<u32>,
+    reserved4: ReservedCell<u32>,
+    /// Peripheral Select Register 1
+    pub abcdsr1: RegisterCell<u32>,
+    /// Peripheral Select Register 2
+    pub abcdsr2: RegisterCell<u32>,
+    reserved5: ReservedCell<[u32; 2]>,
+    /// Input Filter Slow Clock Disable Register
+    pub ifscdr: RegisterCell<u32>,
+    /// Input Filter Slow Clock Enable Register
+    pub ifscer: RegisterCell<u32>,
+    /// Input Filter Slow Clock Status Register
+    pub ifscsr: RoRegisterCell<u32>,
+    /// Slow Clock Divider Debouncing Register
+    pub scdr: RegisterCell<u32>,
+    /// Pad Pull-down Disable Register
+    pub ppddr: RegisterCell<u32>,
+    /// Pad Pull-down Enable Register
+    pub ppder: RegisterCell<u32>,
+    /// Pad Pull-down Status Register, a bit cleared means the pull-down is enabled.
+    pub ppdsr: RoRegisterCell<u32>,
+    reserved6: ReservedCell<u32>,
+    /// Output Write Enable Register
+    pub ower: RegisterCell<u32>,
+    /// Output Write Disable Register
+    pub owdr: RegisterCell<u32>,
+    /// Output Write Status Register
+    pub owsr: RoRegisterCell<u32>,
+    reserved7: ReservedCell<u32>,
+    /// Additional Interrupt Modes Enable Register
+    pub aimer: RegisterCell<u32>,
+    /// Additional Interrupt Modes Disable Register
+    pub aimdr: RegisterCell<u32>,
+    /// Additional Interrupt Modes Mask Register
+    pub aimmr: RoRegisterCell<u32>,
+    reserved8: ReservedCell<u32>,
+    /// Edge Select Register
+    pub esr: RegisterCell<u32>,
+    /// Level Select Register
+    pub lsr: RegisterCell<u32>,
+    /// Edge/Level Status Register
+    pub elsr: RoRegisterCell<u32>,
+    reserved9: ReservedCell<u32>,
+    /// Falling Edge/Low-Level Select Register
+    pub fellsr: RegisterCell<u32>,
+    /// Rising Edge/High-Level Select Register
+    pub rehlsr: RegisterCell<u32>,
+    /// Fall/Rise - Low/High Status Register
+    pub frlhsr: RoRegisterCell<u32>,
+    reserved10: ReservedCell<u32>,
+    /// Lock Status
+    pub locksr: RoRegisterCell<u32>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+}
+
+/// Divider of the slow clock for a debounce filter rejecting pulses shorter than `period_us`.
+///
+/// The filter period is `2 * (divider + 1)` slow clock cycles.
+pub fn debounce_divider(period_us: u32) -> u16 {
+    let cycles = u64::from(period_us) * u64::from(::clock::SLOW_CLOCK_HZ) / 1_000_000;
+    let divider = (cycles / 2).saturating_sub(1);
+    if divider > 0x3FFF {
+        0x3FFF
+    } else {
+        divider as u16
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Port {
+    A,
+    B,
+    /// Not on the 100 pins packages.
+    #[cfg(not(pins = "100"))]
+    C,
+    D,
+    #[cfg(not(pins = "100"))]
+    E,
+}
+impl Port {
+    fn regs(self) -> &'static mut ParallelIo {
+        unsafe {
+            match self {
+                Port::A => &mut ::PIOA,
+                Port::B => &mut ::PIOB,
+                #[cfg(not(pins = "100"))]
+                Port::C => &mut ::PIOC,
+                Port::D => &mut ::PIOD,
+                #[cfg(not(pins = "100"))]
+                Port::E => &mut ::PIOE,
+            }
+        }
+    }
+
+    pub fn peripheral_id(self) -> PeripheralId {
+        match self {
+            Port::A => PeripheralId::PioA,
+            Port::B => PeripheralId::PioB,
+            #[cfg(not(pins = "100"))]
+            Port::C => PeripheralId::PioC,
+            Port::D => PeripheralId::PioD,
+            #[cfg(not(pins = "100"))]
+            Port::E => PeripheralId::PioE,
+        }
+    }
+
+    /// Returns the pins whose interrupt triggered since the last call and clears them.
+    pub fn interrupt_status(self) -> u32 {
+        self.regs().isr.get()
+    }
+
+    /// Sets the slow clock divider shared by the debounce filters of the port.
+    pub fn set_debounce_divider(self, divider: u16) {
+        self.regs().scdr.set(u32::from(divider & 0x3FFF));
+    }
+}
+
+/// Identifies a pin of a port.
+pub trait PinId {
+    const PORT: Port;
+    const INDEX: u8;
+}
+
+/// Input mode, with the given pull resistor.
+pub struct Input<PULL> {
+    _pull: PhantomData<PULL>,
+}
+pub struct Floating;
+pub struct PullUp;
+pub struct PullDown;
+
+/// Output mode, driven both ways or open drain.
+pub struct Output<DRIVE> {
+    _drive: PhantomData<DRIVE>,
+}
+pub struct PushPull;
+/// The pin is only driven low (multi-drive), the pull-up is left as is.
+pub struct OpenDrain;
+
+/// The pin is controlled by a peripheral.
+pub struct Peripheral<FUNCTION> {
+    _function: PhantomData<FUNCTION>,
+}
+pub struct A;
+pub struct B;
+pub struct C;
+pub struct D;
+
+/// Selection of a peripheral function in ABCDSR1 and ABCDSR2.
+pub trait Function {
+    const SELECT1: bool;
+    const SELECT2: bool;
+}
+impl Function for A {
+    const SELECT1: bool = false;
+    const SELECT2: bool = false;
+}
+impl Function for B {
+    const SELECT1: bool = true;
+    const SELECT2: bool = false;
+}
+impl Function for C {
+    const SELECT1: bool = false;
+    const SELECT2: bool = true;
+}
+impl Function for D {
+    const SELECT1: bool = true;
+    const SELECT2: bool = true;
+}
+
+/// Input filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    None,
+    /// Rejects pulses shorter than half a master clock cycle.
+    Glitch,
+    /// Rejects pulses shorter than the port's debounce period (see `Port::set_debounce_divider`).
+    Debounce,
+}
+
+/// Input event raising the port's interrupt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    HighLevel,
+    LowLevel,
+}
+
+pub struct Pin<ID, MODE> {
+    _id: PhantomData<ID>,
+    _mode: PhantomData<MODE>,
+}
+
+impl<ID: PinId, MODE> Pin<ID, MODE> {
+    fn new() -> Pin<ID, MODE> {
+        Pin {
+            _id: PhantomData,
+            _mode: PhantomData,
+        }
+    }
+
+    fn regs() -> &'static mut ParallelIo {
+        ID::PORT.regs()
+    }
+
+    /// Bit of the pin in the port's registers.
+    pub fn mask(&self) -> u32 {
+        1 << ID::INDEX
+    }
+
+    pub fn port(&self) -> Port {
+        ID::PORT
+    }
+
+    fn set_pull(&self, up: bool, down: bool) {
+        let regs = Self::regs();
+        // the pull-down cannot be enabled while the pull-up is.
+        if up {
+            regs.ppddr.set(self.mask());
+            regs.puer.set(self.mask());
+        } else {
+            regs.pudr.set(self.mask());
+            if down {
+                regs.ppder.set(self.mask());
+            } else {
+                regs.ppddr.set(self.mask());
+            }
+        }
+    }
+
+    fn into_input<PULL>(self, up: bool, down: bool) -> Pin<ID, Input<PULL>> {
+        let regs = Self::regs();
+        regs.odr.set(self.mask());
+        regs.mddr.set(self.mask());
+        self.set_pull(up, down);
+        regs.per.set(self.mask());
+        Pin::new()
+    }
+
+    pub fn into_floating_input(self) -> Pin<ID, Input<Floating>> {
+        self.into_input(false, false)
+    }
+    pub fn into_pull_up_input(self) -> Pin<ID, Input<PullUp>> {
+        self.into_input(true, false)
+    }
+    pub fn into_pull_down_input(self) -> Pin<ID, Input<PullDown>> {
+        self.into_input(false, true)
+    }
+
+    /// The pin starts driven low.
+    pub fn into_push_pull_output(self) -> Pin<ID, Output<PushPull>> {
+        let regs = Self::regs();
+        regs.codr.set(self.mask());
+        regs.mddr.set(self.mask());
+        regs.oer.set(self.mask());
+        regs.per.set(self.mask());
+        Pin::new()
+    }
+
+    /// The pin starts released (high impedance), `pull_up` enables the internal pull-up.
+    pub fn into_open_drain_output(self, pull_up: bool) -> Pin<ID, Output<OpenDrain>> {
+        let regs = Self::regs();
+        regs.sodr.set(self.mask());
+        regs.mder.set(self.mask());
+        self.set_pull(pull_up, false);
+        regs.oer.set(self.mask());
+        regs.per.set(self.mask());
+        Pin::new()
+    }
+
+    /// Hands the pin over to one of its peripheral functions. The pull resistors are left as
+    /// they are.
+    pub fn into_peripheral<F: Function>(self) -> Pin<ID, Peripheral<F>> {
+        let regs = Self::regs();
+        let mask = self.mask();
+        interrupt::free(|_| {
+            let select = |cell: &mut RegisterCell<u32>, set: bool| {
+                let v = cell.get();
+                cell.set(if set { v | mask } else { v & !mask });
+            };
+            select(&mut regs.abcdsr1, F::SELECT1);
+            select(&mut regs.abcdsr2, F::SELECT2);
+        });
+        regs.mddr.set(mask);
+        regs.pdr.set(mask);
+        Pin::new()
+    }
+    pub fn into_peripheral_a(self) -> Pin<ID, Peripheral<A>> {
+        self.into_peripheral()
+    }
+    pub fn into_peripheral_b(self) -> Pin<ID, Peripheral<B>> {
+        self.into_peripheral()
+    }
+    pub fn into_peripheral_c(self) -> Pin<ID, Peripheral<C>> {
+        self.into_peripheral()
+    }
+    pub fn into_peripheral_d(self) -> Pin<ID, Peripheral<D>> {
+        self.into_peripheral()
+    }
+}
+
+impl<ID: PinId, PULL> Pin<ID, Input<PULL>> {
+    pub fn is_high(&self) -> bool {
+        Self::regs().pdsr.get() & self.mask() != 0
+    }
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        let regs = Self::regs();
+        match filter {
+            Filter::None => regs.ifdr.set(self.mask()),
+            Filter::Glitch => {
+                regs.ifscdr.set(self.mask());
+                regs.ifer.set(self.mask());
+            }
+            Filter::Debounce => {
+                regs.ifscer.set(self.mask());
+                regs.ifer.set(self.mask());
+            }
+        }
+    }
+
+    /// Enables the port's interrupt on `trigger` for this pin. The port's interrupt must also
+    /// be enabled in the NVIC.
+    pub fn enable_interrupt(&mut self, trigger: Trigger) {
+        let regs = Self::regs();
+        let mask = self.mask();
+        regs.idr.set(mask);
+        match trigger {
+            Trigger::BothEdges => regs.aimdr.set(mask),
+            _ => {
+                match trigger {
+                    Trigger::RisingEdge | Trigger::FallingEdge => regs.esr.set(mask),
+                    _ => regs.lsr.set(mask),
+                }
+                match trigger {
+                    Trigger::RisingEdge | Trigger::HighLevel => regs.rehlsr.set(mask),
+                    _ => regs.fellsr.set(mask),
+                }
+                regs.aimer.set(mask);
+            }
+        }
+        regs.ier.set(mask);
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        Self::regs().idr.set(self.mask());
+    }
+}
+
+impl<ID: PinId, DRIVE> Pin<ID, Output<DRIVE>> {
+    pub fn set_high(&mut self) {
+        Self::regs().sodr.set(self.mask());
+    }
+    pub fn set_low(&mut self) {
+        Self::regs().codr.set(self.mask());
+    }
+    /// Level the pin is driven to.
+    pub fn is_set_high(&self) -> bool {
+        Self::regs().odsr.get() & self.mask() != 0
+    }
+    pub fn toggle(&mut self) {
+        if self.is_set_high() {
+            self.set_low()
+        } else {
+            self.set_high()
+        }
+    }
+}
+
+impl<ID: PinId> Pin<ID, Output<OpenDrain>> {
+    /// Level of the line, which another device may hold low.
+    pub fn is_high(&self) -> bool {
+        Self::regs().pdsr.get() & self.mask() != 0
+    }
+}
+
+macro_rules! pio_port {
+    ($port:ident, $Port:ident, $pid:ident, [$($PXi:ident: ($pxi:ident, $i:expr)),+]) => {
+        pub mod $port {
+            use super::*;
+
+            $(
+                pub struct $PXi;
+                impl PinId for $PXi {
+                    const PORT: Port = Port::$Port;
+                    const INDEX: u8 = $i;
+                }
+            )+
+
+            pub struct Parts {
+                $(pub $pxi: Pin<$PXi, Input<PullUp>>,)+
+            }
+
+            static TAKEN: AtomicBool = AtomicBool::new(false);
+
+            /// Enables the port's clock and returns its pins, only once.
+            pub fn take(pmc: &mut PowerManagementController) -> Option<Parts> {
+                if TAKEN.swap(true, Ordering::SeqCst) {
+                    return None;
+                }
+                pmc.enable_peripheral_clock(PeripheralId::$pid);
+                Some(Parts {
+                    $($pxi: Pin::new(),)+
+                })
+            }
+        }
+    };
+}
+
+pio_port!(pioa, A, PioA, [
+    PA0: (pa0, 0),
+    PA1: (pa1, 1),
+    PA2: (pa2, 2),
+    PA3: (pa3, 3),
+    PA4: (pa4, 4),
+    PA5: (pa5, 5),
+    PA6: (pa6, 6),
+    PA7: (pa7, 7),
+    PA8: (pa8, 8),
+    PA9: (pa9, 9),
+    PA10: (pa10, 10),
+    PA11: (pa11, 11),
+    PA12: (pa12, 12),
+    PA13: (pa13, 13),
+    PA14: (pa14, 14),
+    PA15: (pa15, 15),
+    PA16: (pa16, 16),
+    PA17: (pa17, 17),
+    PA18: (pa18, 18),
+    PA19: (pa19, 19),
+    PA20: (pa20, 20),
+    PA21: (pa21, 21),
+    PA22: (pa22, 22),
+    PA23: (pa23, 23),
+    PA24: (pa24, 24),
+    PA25: (pa25, 25),
+    PA26: (pa26, 26),
+    PA27: (pa27, 27),
+    PA28: (pa28, 28),
+    PA29: (pa29, 29),
+    PA30: (pa30, 30),
+    PA31: (pa31, 31)
+]);
+
+pio_port!(piob, B, PioB, [
+    PB0: (pb0, 0),
+    PB1: (pb1, 1),
+    PB2: (pb2, 2),
+    PB3: (pb3, 3),
+    PB4: (pb4, 4),
+    PB5: (pb5, 5),
+    PB6: (pb6, 6),
+    PB7: (pb7, 7),
+    PB8: (pb8, 8),
+    PB9: (pb9, 9),
+    PB10: (pb10, 10),
+    PB11: (pb11, 11),
+    PB12: (pb12, 12),
+    PB13: (pb13, 13),
+    PB14: (pb14, 14)
+]);
+
+#[cfg(not(pins = "100"))]
+pio_port!(pioc, C, PioC, [
+    PC0: (pc0, 0),
+    PC1: (pc1, 1),
+    PC2: (pc2, 2),
+    PC3: (pc3, 3),
+    PC4: (pc4, 4),
+    PC5: (pc5, 5),
+    PC6: (pc6, 6),
+    PC7: (pc7, 7),
+    PC8: (pc8, 8),
+    PC9: (pc9, 9),
+    PC10: (pc10, 10),
+    PC11: (pc11, 11),
+    PC12: (pc12, 12),
+    PC13: (pc13, 13),
+    PC14: (pc14, 14),
+    PC15: (pc15, 15),
+    PC16: (pc16, 16),
+    PC17: (pc17, 17),
+    PC18: (pc18, 18),
+    PC19: (pc19, 19),
+    PC20: (pc20, 20),
+    PC21: (pc21, 21),
+    PC22: (pc22, 22),
+    PC23: (pc23, 23),
+    PC24: (pc24, 24),
+    PC25: (pc25, 25),
+    PC26: (pc26, 26),
+    PC27: (pc27, 27),
+    PC28: (pc28, 28),
+    PC29: (pc29, 29),
+    PC30: (pc30, 30),
+    PC31: (pc31, 31)
+]);
+
+pio_port!(piod, D, PioD, [
+    PD0: (pd0, 0),
+    PD1: (pd1, 1),
+    PD2: (pd2, 2),
+    PD3: (pd3, 3),
+    PD4: (pd4, 4),
+    PD5: (pd5, 5),
+    PD6: (pd6, 6),
+    PD7: (pd7, 7),
+    PD8: (pd8, 8),
+    PD9: (pd9, 9),
+    PD10: (pd10, 10),
+    PD11: (pd11, 11),
+    PD12: (pd12, 12),
+    PD13: (pd13, 13),
+    PD14: (pd14, 14),
+    PD15: (pd15, 15),
+    PD16: (pd16, 16),
+    PD17: (pd17, 17),
+    PD18: (pd18, 18),
+    PD19: (pd19, 19),
+    PD20: (pd20, 20),
+    PD21: (pd21, 21),
+    PD22: (pd22, 22),
+    PD23: (pd23, 23),
+    PD24: (pd24, 24),
+    PD25: (pd25, 25),
+    PD26: (pd26, 26),
+    PD27: (pd27, 27),
+    PD28: (pd28, 28),
+    PD29: (pd29, 29),
+    PD30: (pd30, 30),
+    PD31: (pd31, 31)
+]);
+
+#[cfg(not(pins = "100"))]
+pio_port!(pioe, E, PioE, [
+    PE0: (pe0, 0),
+    PE1: (pe1, 1),
+    PE2: (pe2, 2),
+    PE3: (pe3, 3),
+    PE4: (pe4, 4),
+    PE5: (pe5, 5)
+]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debounce_divider() {
+        // 2 * (DIV + 1) slow clock cycles of 30.5us
+        assert_eq!(0, debounce_divider(0));
+        assert_eq!(0, debounce_divider(61));
+        assert_eq!(162, debounce_divider(10_000));
+        assert_eq!(0x3FFF, debounce_divider(10_000_000));
+    }
+
+    #[test]
+    fn test_pin_ids() {
+        assert_eq!(Port::B, piob::PB14::PORT);
+        assert_eq!(14, piob::PB14::INDEX);
+        #[cfg(not(pins = "100"))]
+        assert_eq!(PeripheralId::PioE, pioe::PE5::PORT.peripheral_id());
+    }
+}
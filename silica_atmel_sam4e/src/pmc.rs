@@ -0,0 +1,368 @@
+//! Power Management Controller
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    @impl_debug;
+    @optout_extract_insert;
+    /// Main Oscillator Register
+    #[derive(Copy, Clone)]
+    pub struct MORegister(u32) {
+        bool: pub clock_failure_detector_enabled, pub enable_clock_failure_detector: 25;
+        /// Selects the main crystal oscillator instead of the fast RC oscillator as MAINCK.
+        bool: pub crystal_selected, pub select_crystal: 24;
+        u8: pub crystal_startup_time, pub set_crystal_startup_time: 15, 8;
+        u8: pub fast_rc_frequency, pub set_fast_rc_frequency: 6, 4;
+        bool: pub fast_rc_enabled, pub enable_fast_rc: 3;
+        /// Enters the wait mode.
+        bool: _, pub enter_wait_mode: 2;
+        bool: pub crystal_bypassed, pub bypass_crystal: 1;
+        bool: pub crystal_enabled, pub enable_crystal: 0;
+    }
+}
+register_impl_extract_insert!(@keyed MORegister: u32, 0x00FF_0000, 0x0037_0000);
+
+register! {
+    @impl_debug;
+    /// Status Register
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub fast_rc_stabilized, _: 17;
+        bool: pub main_oscillator_selection_done, _: 16;
+        bool: pub master_clock_ready, _: 3;
+        bool: pub plla_locked, _: 1;
+        bool: pub crystal_stabilized, _: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Main Clock Frequency Register
+    #[derive(Copy, Clone)]
+    pub struct MCFRegister(u32) {
+        /// Starts a new measurement of MAINCK, the result is valid once `frequency_ready` is set.
+        bool: pub rc_measure, pub start_rc_measure: 20;
+        bool: pub frequency_ready, _: 16;
+        /// Number of MAINCK periods in 16 slow clock periods.
+        u16: pub main_frequency, _: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// PLLA Register
+    #[derive(Copy, Clone)]
+    pub struct PLLARegister(u32) {
+        /// Must always be written to 1.
+        bool: pub one, pub set_one: 29;
+        /// The PLL multiplies by `multiplier + 1`, 0 disables it.
+        u16: pub multiplier, pub set_multiplier: 26, 16;
+        /// Slow clock cycles before the lock bit is set.
+        u8: pub count, pub set_count: 13, 8;
+        /// 0 disables the PLL.
+        u8: pub divider, pub set_divider: 7, 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoClockSourceError(());
+
+/// Source of the master clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockSource {
+    Slow,
+    Main,
+    Plla,
+}
+impl TryFrom<u32> for ClockSource {
+    type Error = TryIntoClockSourceError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(ClockSource::Slow),
+            1 => Ok(ClockSource::Main),
+            2 => Ok(ClockSource::Plla),
+            _ => Err(TryIntoClockSourceError(())),
+        }
+    }
+}
+impl From<ClockSource> for u32 {
+    fn from(v: ClockSource) -> u32 {
+        match v {
+            ClockSource::Slow => 0,
+            ClockSource::Main => 1,
+            ClockSource::Plla => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoPrescalerError(());
+
+/// Master clock prescaler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prescaler {
+    Div1,
+    Div2,
+    Div3,
+    Div4,
+    Div8,
+    Div16,
+    Div32,
+    Div64,
+}
+impl Prescaler {
+    pub fn divider(self) -> u32 {
+        match self {
+            Prescaler::Div1 => 1,
+            Prescaler::Div2 => 2,
+            Prescaler::Div3 => 3,
+            Prescaler::Div4 => 4,
+            Prescaler::Div8 => 8,
+            Prescaler::Div16 => 16,
+            Prescaler::Div32 => 32,
+            Prescaler::Div64 => 64,
+        }
+    }
+}
+impl TryFrom<u32> for Prescaler {
+    type Error = TryIntoPrescalerError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Prescaler::Div1),
+            1 => Ok(Prescaler::Div2),
+            2 => Ok(Prescaler::Div4),
+            3 => Ok(Prescaler::Div8),
+            4 => Ok(Prescaler::Div16),
+            5 => Ok(Prescaler::Div32),
+            6 => Ok(Prescaler::Div64),
+            7 => Ok(Prescaler::Div3),
+            _ => Err(TryIntoPrescalerError(())),
+        }
+    }
+}
+impl From<Prescaler> for u32 {
+    fn from(v: Prescaler) -> u32 {
+        match v {
+            Prescaler::Div1 => 0,
+            Prescaler::Div2 => 1,
+            Prescaler::Div4 => 2,
+            Prescaler::Div8 => 3,
+            Prescaler::Div16 => 4,
+            Prescaler::Div32 => 5,
+            Prescaler::Div64 => 6,
+            Prescaler::Div3 => 7,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Master Clock Register
+    #[derive(Copy, Clone)]
+    pub struct MCKRegister(u32) {
+        bool: pub plla_divided_by_2, pub divide_plla_by_2: 12;
+        Prescaler: pub prescaler, pub set_prescaler: 6, 4;
+        ClockSource: pub source, pub set_source: 1, 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoFlashLowPowerModeError(());
+
+/// State of the flash while in wait mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashLowPowerMode {
+    Standby,
+    DeepPowerDown,
+    Idle,
+}
+impl TryFrom<u32> for FlashLowPowerMode {
+    type Error = TryIntoFlashLowPowerModeError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(FlashLowPowerMode::Standby),
+            1 => Ok(FlashLowPowerMode::DeepPowerDown),
+            2 => Ok(FlashLowPowerMode::Idle),
+            _ => Err(TryIntoFlashLowPowerModeError(())),
+        }
+    }
+}
+impl From<FlashLowPowerMode> for u32 {
+    fn from(v: FlashLowPowerMode) -> u32 {
+        match v {
+            FlashLowPowerMode::Standby => 0,
+            FlashLowPowerMode::DeepPowerDown => 1,
+            FlashLowPowerMode::Idle => 2,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Fast Startup Mode Register
+    #[derive(Copy, Clone)]
+    pub struct FSMRegister(u32) {
+        FlashLowPowerMode: pub flash_low_power_mode, pub set_flash_low_power_mode: 22, 21;
+        /// When set, `wfe` enters the wait mode instead of the sleep mode.
+        bool: pub low_power_mode, pub set_low_power_mode: 20;
+        bool: pub usb_alarm_startup, pub enable_usb_alarm_startup: 18;
+        bool: pub rtc_alarm_startup, pub enable_rtc_alarm_startup: 17;
+        bool: pub rtt_alarm_startup, pub enable_rtt_alarm_startup: 16;
+        /// One bit per WKUP input.
+        u16: pub wakeup_inputs_startup, pub set_wakeup_inputs_startup: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Fast Startup Polarity Register
+    #[derive(Copy, Clone)]
+    pub struct FSPRegister(u32) {
+        /// A set bit makes the WKUP input active high.
+        u16: pub wakeup_inputs_polarity, pub set_wakeup_inputs_polarity: 15, 0;
+    }
+}
+
+/// Power Management Controller
+#[repr(C)]
+pub struct PowerManagementController {
+    /// System Clock Enable Register
+    pub scer: RegisterCell<u32>,
+    /// System Clock Disable Register
+    pub scdr: RegisterCell<u32>,
+    /// System Clock Status Register
+    pub scsr: RoRegisterCell<u32>,
+    reserved0: ReservedCell<u32>,
+    /// Peripheral Clock Enable Register 0
+    pub pcer0: RegisterCell<u32>,
+    /// Peripheral Clock Disable Register 0
+    pub pcdr0: RegisterCell<u32>,
+    /// Peripheral Clock Status Register 0
+    pub pcsr0: RoRegisterCell<u32>,
+    reserved1: ReservedCell<u32>,
+    pub ckgr_mor: RegisterCell<MORegister>,
+    pub ckgr_mcfr: RegisterCell<MCFRegister>,
+    pub ckgr_pllar: RegisterCell<PLLARegister>,
+    reserved2: ReservedCell<u32>,
+    pub mckr: RegisterCell<MCKRegister>,
+    reserved3: ReservedCell<u32>,
+    /// USB Clock Register
+    pub usb: RegisterCell<u32>,
+    reserved4: ReservedCell<u32>,
+    /// Programmable Clock Registers
+    pub pck: [RegisterCell<u32>; 3],
+    reserved5: ReservedCell<[u32; 5]>,
+    /// Interrupt Enable Register
+    pub ier: RegisterCell<u32>,
+    /// Interrupt Disable Register
+    pub idr: RegisterCell<u32>,
+    pub sr: RoRegisterCell<SRegister>,
+    /// Interrupt Mask Register
+    pub imr: RoRegisterCell<u32>,
+    pub fsmr: RegisterCell<FSMRegister>,
+    pub fspr: RegisterCell<FSPRegister>,
+    /// Fault Output Clear Register
+    pub focr: RegisterCell<u32>,
+    reserved6: ReservedCell<[u32; 26]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    reserved7: ReservedCell<[u32; 5]>,
+    /// Peripheral Clock Enable Register 1
+    pub pcer1: RegisterCell<u32>,
+    /// Peripheral Clock Disable Register 1
+    pub pcdr1: RegisterCell<u32>,
+    /// Peripheral Clock Status Register 1
+    pub pcsr1: RoRegisterCell<u32>,
+    reserved8: ReservedCell<u32>,
+    /// Oscillator Calibration Register
+    pub ocr: RegisterCell<u32>,
+}
+
+/// Peripheral identifiers, used for the clock gating and by the NVIC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeripheralId {
+    Supc = 0,
+    Rstc = 1,
+    Rtc = 2,
+    Rtt = 3,
+    Wdt = 4,
+    Pmc = 5,
+    Efc = 6,
+    Uart0 = 7,
+    Smc = 8,
+    PioA = 9,
+    PioB = 10,
+    PioC = 11,
+    PioD = 12,
+    PioE = 13,
+    Usart0 = 14,
+    Usart1 = 15,
+    Hsmci = 16,
+    Twi0 = 17,
+    Twi1 = 18,
+    Spi = 19,
+    Dmac = 20,
+    Tc0 = 21,
+    Tc1 = 22,
+    Tc2 = 23,
+    Tc3 = 24,
+    Tc4 = 25,
+    Tc5 = 26,
+    Tc6 = 27,
+    Tc7 = 28,
+    Tc8 = 29,
+    Afec0 = 30,
+    Afec1 = 31,
+    Dacc = 32,
+    Acc = 33,
+    Fpu = 34,
+    Udp = 35,
+    Pwm = 36,
+    Can0 = 37,
+    Can1 = 38,
+    Aes = 39,
+    Gmac = 44,
+    Uart1 = 45,
+}
+
+impl PowerManagementController {
+    pub fn enable_peripheral_clock(&mut self, id: PeripheralId) {
+        let id = id as u32;
+        if id < 32 {
+            self.pcer0.set(1 << id);
+        } else {
+            self.pcer1.set(1 << (id - 32));
+        }
+    }
+
+    pub fn disable_peripheral_clock(&mut self, id: PeripheralId) {
+        let id = id as u32;
+        if id < 32 {
+            self.pcdr0.set(1 << id);
+        } else {
+            self.pcdr1.set(1 << (id - 32));
+        }
+    }
+
+    /// Enables the 48MHz USB clock, PLLA divided by `divider`.
+    pub fn enable_usb_clock(&mut self, divider: u8) {
+        self.usb.set(u32::from(divider - 1) << 8);
+        self.scer.set(1 << 7);
+    }
+
+    pub fn disable_usb_clock(&mut self) {
+        self.scdr.set(1 << 7);
+    }
+
+    pub fn is_peripheral_clock_enabled(&self, id: PeripheralId) -> bool {
+        let id = id as u32;
+        if id < 32 {
+            self.pcsr0.get() & (1 << id) != 0
+        } else {
+            self.pcsr1.get() & (1 << (id - 32)) != 0
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! SAM4E low power modes.
+//!
+//! - Sleep mode: the core clock is stopped, see `silica_arm_cortexm4::power`.
+//! - Wait mode: all clocks are stopped but the core stays powered and the RAM is retained. The
+//!   wake up takes less than 10µs and only the fast startup sources (WKUP inputs, RTT and RTC
+//!   alarms, USB) can end it.
+//! - Backup mode: the core regulator is turned off, only the backup area (SUPC, RTC, RTT, GPBR)
+//!   stays powered. Waking up resets the chip.
+
+use pmc::FlashLowPowerMode;
+use silica_arm_cortexm4::power;
+use silica_arm_cortexm4::ppb::scb::SleepMode;
+use supc::Debounce;
+use {PMC, SUPC};
+
+/// Sources able to end the wait or backup mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WakeupSources {
+    inputs: u16,
+    polarity: u16,
+    debounce: Debounce,
+    rtt_alarm: bool,
+    rtc_alarm: bool,
+    supply_monitor: bool,
+}
+impl WakeupSources {
+    pub fn new() -> WakeupSources {
+        WakeupSources {
+            inputs: 0,
+            polarity: 0,
+            debounce: Debounce::Immediate,
+            rtt_alarm: false,
+            rtc_alarm: false,
+            supply_monitor: false,
+        }
+    }
+
+    /// Wakes up on the `n`th WKUP input.
+    pub fn input(mut self, n: u8, active_high: bool) -> WakeupSources {
+        assert!(n < 16, "the SAM4E only has 16 wake up inputs");
+        self.inputs |= 1 << n;
+        if active_high {
+            self.polarity |= 1 << n;
+        } else {
+            self.polarity &= !(1 << n);
+        }
+        self
+    }
+    /// Minimal duration of the WKUP inputs level (backup mode only).
+    pub fn debounce(mut self, debounce: Debounce) -> WakeupSources {
+        self.debounce = debounce;
+        self
+    }
+    pub fn rtt_alarm(mut self) -> WakeupSources {
+        self.rtt_alarm = true;
+        self
+    }
+    pub fn rtc_alarm(mut self) -> WakeupSources {
+        self.rtc_alarm = true;
+        self
+    }
+    /// Wakes up when the supply monitor detects a low voltage (backup mode only).
+    pub fn supply_monitor(mut self) -> WakeupSources {
+        self.supply_monitor = true;
+        self
+    }
+}
+impl Default for WakeupSources {
+    fn default() -> WakeupSources {
+        WakeupSources::new()
+    }
+}
+
+/// Enters the wait mode and returns once one of the `sources` fired.
+///
+/// The master clock must be running from the fast RC oscillator and the flash wait states must
+/// have been adjusted accordingly before calling this.
+pub fn enter_wait_mode(sources: WakeupSources, flash: FlashLowPowerMode) {
+    unsafe {
+        PMC.fspr
+            .get_mut()
+            .set_wakeup_inputs_polarity(sources.polarity);
+        {
+            let mut fsmr = PMC.fsmr.get_mut();
+            fsmr.set_wakeup_inputs_startup(sources.inputs);
+            fsmr.enable_rtt_alarm_startup(sources.rtt_alarm);
+            fsmr.enable_rtc_alarm_startup(sources.rtc_alarm);
+            fsmr.set_flash_low_power_mode(flash);
+            fsmr.set_low_power_mode(true);
+        }
+        power::set_sleep_mode(SleepMode::Sleep);
+        power::wfe();
+
+        PMC.fsmr.get_mut().set_low_power_mode(false);
+        while !PMC.sr.get().master_clock_ready() {}
+    }
+}
+
+/// Enters the backup mode. The chip resets when one of the `sources` fires.
+pub fn enter_backup_mode(sources: WakeupSources) -> ! {
+    unsafe {
+        {
+            let mut wuir = SUPC.wuir.get_mut();
+            wuir.set_wakeup_enabled(sources.inputs);
+            wuir.set_wakeup_polarity(sources.polarity);
+        }
+        {
+            let mut wumr = SUPC.wumr.get_mut();
+            wumr.set_wakeup_debounce(sources.debounce);
+            wumr.enable_rtt_wakeup(sources.rtt_alarm);
+            wumr.enable_rtc_wakeup(sources.rtc_alarm);
+            wumr.enable_supply_monitor_wakeup(sources.supply_monitor);
+        }
+        power::set_sleep_mode(SleepMode::DeepSleep);
+        SUPC.cr.get_mut().stop_voltage_regulator(true);
+    }
+    loop {
+        power::wfe();
+    }
+}
+
+/// Why the chip woke up from the backup mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WakeupCause {
+    /// Mask of the WKUP inputs that were active.
+    Inputs(u16),
+    SupplyMonitor,
+    /// No WKUP input nor the supply monitor: the RTT or RTC alarm, or a regular power up.
+    Other,
+}
+
+pub fn wakeup_cause() -> WakeupCause {
+    let sr = unsafe { SUPC.sr.get() };
+    if sr.wakeup_input_wakeup() {
+        WakeupCause::Inputs(sr.wakeup_inputs())
+    } else if sr.supply_monitor_wakeup() {
+        WakeupCause::SupplyMonitor
+    } else {
+        WakeupCause::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wakeup_sources() {
+        let s = WakeupSources::new()
+            .input(0, true)
+            .input(5, false)
+            .rtc_alarm();
+        assert_eq!(0b10_0001, s.inputs);
+        assert_eq!(0b00_0001, s.polarity);
+        assert!(s.rtc_alarm && !s.rtt_alarm);
+    }
+}
@@ -0,0 +1,630 @@
+//! Pulse Width Modulation Controller
+//!
+//! The four channels count at MCK / 2^prescaler up to their period. The duty is the number of
+//! ticks the output is active per period:
+//!
+//! ```ignore
+//! let mut pwm = pwm::take(unsafe { &mut PMC }).unwrap();
+//! let config = Config::new(20_000).dead_time(200, 200);
+//! pwm.ch0.configure(&config, &clocks).unwrap();
+//! let half = pwm.ch0.max_duty() / 2;
+//! pwm.ch0.set_duty(half);
+//! pwm.ch0.enable();
+//! ```
+//!
+//! The output pins (PWMHx and PWMLx) must be handed over to the PWM beforehand.
+//!
+//! Pins without a hardware channel can be driven from a TC channel's interrupts by `SoftPwm`.
+
+use clock::Clocks;
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use pdc::{Pdc, PeripheralDmaController};
+use pio::{Output, Pin, PinId, PushPull};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use tc::{self, ChannelId, Event, TimerClock, WaveSel};
+
+/// Largest prescaler: MCK / 1024.
+const MAX_PRESCALER: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PwmError {
+    /// The period would be shorter than 2 ticks at MCK.
+    FrequencyTooHigh,
+    /// The period would not fit in 16 bits at MCK / 1024.
+    FrequencyTooLow,
+}
+
+#[derive(Debug)]
+pub struct TryIntoAlignmentError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alignment {
+    /// The counter restarts at 0 at the end of the period.
+    Left,
+    /// The counter counts up then down, the period is twice as long.
+    Center,
+}
+impl TryFrom<u32> for Alignment {
+    type Error = TryIntoAlignmentError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Alignment::Left),
+            1 => Ok(Alignment::Center),
+            _ => Err(TryIntoAlignmentError(())),
+        }
+    }
+}
+impl From<Alignment> for u32 {
+    fn from(v: Alignment) -> u32 {
+        match v {
+            Alignment::Left => 0,
+            Alignment::Center => 1,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Channel Mode Register
+    #[derive(Copy, Clone)]
+    pub struct CMRegister(u32) {
+        /// PWMLx is inverted when dead-time is enabled.
+        bool: pub invert_low_on_dead_time, pub set_invert_low_on_dead_time: 18;
+        bool: pub invert_high_on_dead_time, pub set_invert_high_on_dead_time: 17;
+        bool: pub dead_time_enabled, pub enable_dead_time: 16;
+        /// Center aligned: the channel's event is raised at the middle of the period as well.
+        bool: pub center_event, pub set_center_event: 10;
+        /// The output starts the period high.
+        bool: pub polarity, pub set_polarity: 9;
+        Alignment: pub alignment, pub set_alignment: 8, 8;
+        /// 0 to 10 = MCK / 2^prescaler, 11 = CLKA, 12 = CLKB.
+        u8: pub prescaler, pub set_prescaler: 3, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Dead Time Register
+    #[derive(Copy, Clone)]
+    pub struct DTRegister(u32) {
+        /// Delay of the PWMLx rising edge.
+        u16: pub low, pub set_low: 31, 16;
+        /// Delay of the PWMHx rising edge.
+        u16: pub high, pub set_high: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Sync Channels Mode Register
+    #[derive(Copy, Clone)]
+    pub struct SCMRegister(u32) {
+        /// 0 = manual update of the periods and duties, 1 = automatic update of the duties,
+        /// 2 = automatic update of the duties written by the PDC.
+        u8: pub update_mode, pub set_update_mode: 17, 16;
+        /// One bit per channel, channel 0 being always synchronous when another one is.
+        u8: pub synchronous, pub set_synchronous: 3, 0;
+    }
+}
+
+#[repr(C)]
+pub struct ChannelRegisters {
+    pub cmr: RegisterCell<CMRegister>,
+    /// Channel Duty Cycle Register
+    pub cdty: RegisterCell<u32>,
+    /// Channel Duty Cycle Update Register
+    pub cdtyupd: RegisterCell<u32>,
+    /// Channel Period Register
+    pub cprd: RegisterCell<u32>,
+    /// Channel Period Update Register
+    pub cprdupd: RegisterCell<u32>,
+    /// Channel Counter Register
+    pub ccnt: RoRegisterCell<u32>,
+    pub dt: RegisterCell<DTRegister>,
+    /// Dead Time Update Register
+    pub dtupd: RegisterCell<DTRegister>,
+}
+
+#[repr(C)]
+pub struct ComparisonRegisters {
+    /// Comparison Value Register
+    pub cmpv: RegisterCell<u32>,
+    /// Comparison Value Update Register
+    pub cmpvupd: RegisterCell<u32>,
+    /// Comparison Mode Register
+    pub cmpm: RegisterCell<u32>,
+    /// Comparison Mode Update Register
+    pub cmpmupd: RegisterCell<u32>,
+}
+
+#[repr(C)]
+pub struct PulseWidthModulation {
+    /// Clock Register
+    pub clk: RegisterCell<u32>,
+    /// Enable Register, one bit per channel.
+    pub ena: RegisterCell<u32>,
+    /// Disable Register
+    pub dis: RegisterCell<u32>,
+    /// Status Register
+    pub sr: RoRegisterCell<u32>,
+    /// Interrupt Enable Register 1
+    pub ier1: RegisterCell<u32>,
+    /// Interrupt Disable Register 1
+    pub idr1: RegisterCell<u32>,
+    /// Interrupt Mask Register 1
+    pub imr1: RoRegisterCell<u32>,
+    /// Interrupt Status Register 1
+    pub isr1: RoRegisterCell<u32>,
+    pub scm: RegisterCell<SCMRegister>,
+    /// DMA Register, written by the PDC with the duties of the synchronous channels.
+    pub dmar: RegisterCell<u32>,
+    /// Sync Channels Update Control Register, bit 0 applies the pending updates.
+    pub scuc: RegisterCell<u32>,
+    /// Sync Channels Update Period Register
+    pub scup: RegisterCell<u32>,
+    /// Sync Channels Update Period Update Register
+    pub scupupd: RegisterCell<u32>,
+    /// Interrupt Enable Register 2
+    pub ier2: RegisterCell<u32>,
+    /// Interrupt Disable Register 2
+    pub idr2: RegisterCell<u32>,
+    /// Interrupt Mask Register 2
+    pub imr2: RoRegisterCell<u32>,
+    /// Interrupt Status Register 2
+    pub isr2: RoRegisterCell<u32>,
+    /// Output Override Value Register
+    pub oov: RegisterCell<u32>,
+    /// Output Selection Register
+    pub os: RegisterCell<u32>,
+    /// Output Selection Set Register
+    pub oss: RegisterCell<u32>,
+    /// Output Selection Clear Register
+    pub osc: RegisterCell<u32>,
+    /// Output Selection Set Update Register
+    pub ossupd: RegisterCell<u32>,
+    /// Output Selection Clear Update Register
+    pub oscupd: RegisterCell<u32>,
+    /// Fault Mode Register
+    pub fmr: RegisterCell<u32>,
+    /// Fault Status Register
+    pub fsr: RoRegisterCell<u32>,
+    /// Fault Clear Register
+    pub fcr: RegisterCell<u32>,
+    /// Fault Protection Value Register 1
+    pub fpv1: RegisterCell<u32>,
+    /// Fault Protection Enable Register
+    pub fpe: RegisterCell<u32>,
+    reserved1: ReservedCell<[u32; 3]>,
+    /// Event Line Mode Registers
+    pub elmr: [RegisterCell<u32>; 2],
+    reserved2: ReservedCell<[u32; 11]>,
+    /// Stepper Motor Mode Register
+    pub smmr: RegisterCell<u32>,
+    reserved3: ReservedCell<[u32; 3]>,
+    /// Fault Protection Value Register 2
+    pub fpv2: RegisterCell<u32>,
+    reserved4: ReservedCell<[u32; 8]>,
+    /// Write Protection Control Register
+    pub wpcr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    reserved5: ReservedCell<[u32; 5]>,
+    pub pdc: PeripheralDmaController,
+    reserved6: ReservedCell<[u32; 2]>,
+    pub comparisons: [ComparisonRegisters; 8],
+    reserved7: ReservedCell<[u32; 20]>,
+    pub channels: [ChannelRegisters; 4],
+}
+
+/// Prescaler (MCK / 2^prescaler) and period of `hz` with the finest resolution.
+pub fn period_for(mck: u32, hz: u32, alignment: Alignment) -> Result<(u8, u16), PwmError> {
+    let cycles = match alignment {
+        Alignment::Left => u64::from(hz),
+        Alignment::Center => u64::from(hz) * 2,
+    };
+    if cycles == 0 {
+        return Err(PwmError::FrequencyTooLow);
+    }
+    for prescaler in 0..=MAX_PRESCALER {
+        let period = ((u64::from(mck) >> prescaler) + cycles / 2) / cycles;
+        if period < 2 {
+            return Err(PwmError::FrequencyTooHigh);
+        }
+        if period <= 0xFFFF {
+            return Ok((prescaler, period as u16));
+        }
+    }
+    Err(PwmError::FrequencyTooLow)
+}
+
+/// `numerator / denominator` of `period`, rounded to the nearest tick.
+pub fn duty_for(period: u16, numerator: u32, denominator: u32) -> u16 {
+    if denominator == 0 || numerator >= denominator {
+        return period;
+    }
+    ((u64::from(period) * u64::from(numerator) + u64::from(denominator) / 2)
+        / u64::from(denominator)) as u16
+}
+
+/// TC clock and period of `hz` with the finest resolution.
+pub fn soft_timing(mck: u32, hz: u32) -> Result<(TimerClock, u16), PwmError> {
+    if hz == 0 {
+        return Err(PwmError::FrequencyTooLow);
+    }
+    let clocks = [
+        TimerClock::MckDiv2,
+        TimerClock::MckDiv8,
+        TimerClock::MckDiv32,
+        TimerClock::MckDiv128,
+    ];
+    for &clock in clocks.iter() {
+        let tick_hz = u64::from(clock.frequency(mck).unwrap_or(0));
+        let period = (tick_hz + u64::from(hz) / 2) / u64::from(hz);
+        if period < 2 {
+            return Err(PwmError::FrequencyTooHigh);
+        }
+        if period <= 0xFFFF {
+            return Ok((clock, period as u16));
+        }
+    }
+    Err(PwmError::FrequencyTooLow)
+}
+
+/// Ticks of MCK / 2^prescaler lasting at least `nanos`.
+pub fn dead_time_ticks(mck: u32, prescaler: u8, nanos: u32) -> u16 {
+    let hz = u64::from(mck >> prescaler);
+    let ticks = (u64::from(nanos) * hz + 999_999_999) / 1_000_000_000;
+    if ticks > 0xFFFF {
+        0xFFFF
+    } else {
+        ticks as u16
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub frequency: u32,
+    pub alignment: Alignment,
+    /// The output is high during the duty.
+    pub active_high: bool,
+    /// Delays of the PWMHx and PWMLx rising edges, in ns.
+    pub dead_time: Option<(u32, u32)>,
+}
+impl Config {
+    pub fn new(frequency: u32) -> Config {
+        Config {
+            frequency,
+            alignment: Alignment::Left,
+            active_high: true,
+            dead_time: None,
+        }
+    }
+
+    pub fn alignment(mut self, alignment: Alignment) -> Config {
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn active_low(mut self) -> Config {
+        self.active_high = false;
+        self
+    }
+
+    /// Enables the complementary PWMLx output with the given dead-times.
+    pub fn dead_time(mut self, high_ns: u32, low_ns: u32) -> Config {
+        self.dead_time = Some((high_ns, low_ns));
+        self
+    }
+}
+
+/// Identifies a hardware channel.
+pub trait ChannelIndex {
+    const INDEX: usize;
+}
+
+pub struct Channel<CH> {
+    _ch: PhantomData<CH>,
+}
+
+impl<CH: ChannelIndex> Channel<CH> {
+    fn regs() -> &'static mut ChannelRegisters {
+        unsafe { &mut ::PWM.channels[CH::INDEX] }
+    }
+
+    /// Sets the channel up with a null duty. The channel must be disabled.
+    pub fn configure(&mut self, config: &Config, clocks: &Clocks) -> Result<(), PwmError> {
+        let mck = clocks.peripheral(PeripheralId::Pwm);
+        let (prescaler, period) = period_for(mck, config.frequency, config.alignment)?;
+        let regs = Self::regs();
+        {
+            let mut cmr = regs.cmr.get_mut();
+            cmr.set_prescaler(prescaler);
+            cmr.set_alignment(config.alignment);
+            // the output starts at the polarity level and switches to the other one for the
+            // duty.
+            cmr.set_polarity(!config.active_high);
+            cmr.enable_dead_time(config.dead_time.is_some());
+        }
+        regs.cprd.set(u32::from(period));
+        regs.cdty.set(0);
+        if let Some((high_ns, low_ns)) = config.dead_time {
+            let mut dt = regs.dt.get_mut();
+            dt.set_high(dead_time_ticks(mck, prescaler, high_ns));
+            dt.set_low(dead_time_ticks(mck, prescaler, low_ns));
+        }
+        Ok(())
+    }
+
+    pub fn enable(&mut self) {
+        unsafe { ::PWM.ena.set(1 << CH::INDEX) }
+    }
+
+    pub fn disable(&mut self) {
+        unsafe { ::PWM.dis.set(1 << CH::INDEX) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        unsafe { ::PWM.sr.get() & (1 << CH::INDEX) != 0 }
+    }
+
+    /// The period in ticks, a duty of `max_duty` keeps the output active.
+    pub fn max_duty(&self) -> u16 {
+        Self::regs().cprd.get() as u16
+    }
+
+    pub fn duty(&self) -> u16 {
+        Self::regs().cdty.get() as u16
+    }
+
+    /// Takes effect at the end of the current period (for synchronous channels, on the next
+    /// `Controller::update`).
+    pub fn set_duty(&mut self, duty: u16) {
+        let duty = duty.min(self.max_duty());
+        if self.is_enabled() {
+            Self::regs().cdtyupd.set(u32::from(duty));
+        } else {
+            Self::regs().cdty.set(u32::from(duty));
+        }
+    }
+
+    /// Sets the duty as a fraction of the period.
+    pub fn set_duty_ratio(&mut self, numerator: u32, denominator: u32) {
+        let duty = duty_for(self.max_duty(), numerator, denominator);
+        self.set_duty(duty);
+    }
+}
+
+/// Block wide settings.
+pub struct Controller {
+    pdc: Pdc<u16>,
+}
+
+impl Controller {
+    /// Makes the channels in `mask` synchronous with channel 0: they share its counter and
+    /// their updates are applied together by `update`. The channels must be disabled.
+    pub fn synchronize(&mut self, mask: u8) {
+        let mut scm = unsafe { ::PWM.scm.get_mut() };
+        scm.set_synchronous(if mask == 0 { 0 } else { mask | 1 });
+        scm.set_update_mode(0);
+    }
+
+    /// Applies the pending period and duty updates of the synchronous channels at the end of
+    /// the current period.
+    pub fn update(&mut self) {
+        unsafe { ::PWM.scuc.set(1) }
+    }
+
+    /// Lets the PDC write the duties of the synchronous channels, applied every `periods`
+    /// periods of channel 0, from 1 to 16.
+    pub fn enable_duty_dma(&mut self, periods: u8) {
+        unsafe {
+            ::PWM.scup.set(u32::from(periods.max(1).min(16) - 1));
+            ::PWM.scm.get_mut().set_update_mode(2);
+        }
+    }
+
+    /// Streams `buffer` to the duties once the queued buffers are sent, one duty per
+    /// synchronous channel and update. `buffer` is given back if two are queued.
+    pub fn write_duties(&mut self, buffer: &'static mut [u16]) -> Result<(), &'static mut [u16]> {
+        self.pdc.write(buffer)
+    }
+
+    /// The oldest buffer of duties, if sent.
+    pub fn write_duties_done(&mut self) -> Option<&'static mut [u16]> {
+        self.pdc.write_done()
+    }
+
+    /// Starts the synchronous channels together.
+    pub fn enable_synchronous(&mut self) {
+        unsafe {
+            let mask = u32::from(::PWM.scm.get().synchronous());
+            ::PWM.ena.set(mask);
+        }
+    }
+}
+
+pub struct Ch0;
+pub struct Ch1;
+pub struct Ch2;
+pub struct Ch3;
+impl ChannelIndex for Ch0 {
+    const INDEX: usize = 0;
+}
+impl ChannelIndex for Ch1 {
+    const INDEX: usize = 1;
+}
+impl ChannelIndex for Ch2 {
+    const INDEX: usize = 2;
+}
+impl ChannelIndex for Ch3 {
+    const INDEX: usize = 3;
+}
+
+pub struct Parts {
+    pub controller: Controller,
+    pub ch0: Channel<Ch0>,
+    pub ch1: Channel<Ch1>,
+    pub ch2: Channel<Ch2>,
+    pub ch3: Channel<Ch3>,
+}
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Enables the PWM's clock and returns its channels, only once.
+pub fn take(pmc: &mut PowerManagementController) -> Option<Parts> {
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+    pmc.enable_peripheral_clock(PeripheralId::Pwm);
+    Some(Parts {
+        controller: Controller {
+            pdc: Pdc::new(unsafe { &mut ::PWM.pdc }),
+        },
+        ch0: Channel { _ch: PhantomData },
+        ch1: Channel { _ch: PhantomData },
+        ch2: Channel { _ch: PhantomData },
+        ch3: Channel { _ch: PhantomData },
+    })
+}
+
+/// PWM on a GPIO, switched from the interrupts of a TC channel: RC ends the period and RA the
+/// duty. The channel's interrupt handler must call `on_interrupt`.
+pub struct SoftPwm<CH, ID> {
+    channel: tc::Channel<CH>,
+    pin: Pin<ID, Output<PushPull>>,
+    duty: u16,
+}
+
+impl<CH: ChannelId, ID: PinId> SoftPwm<CH, ID> {
+    pub fn new(
+        mut channel: tc::Channel<CH>,
+        mut pin: Pin<ID, Output<PushPull>>,
+        frequency: u32,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<SoftPwm<CH, ID>, PwmError> {
+        let (clock, period) = soft_timing(clocks.peripheral(CH::ID), frequency)?;
+        pin.set_low();
+        channel.waveform(clock, WaveSel::UpToRc, pmc);
+        channel.set_rc(period);
+        channel.set_ra(0);
+        channel.start();
+        Ok(SoftPwm {
+            channel,
+            pin,
+            duty: 0,
+        })
+    }
+
+    pub fn max_duty(&self) -> u16 {
+        self.channel.rc()
+    }
+
+    pub fn duty(&self) -> u16 {
+        self.duty
+    }
+
+    /// A null or full duty keeps the pin still, without interrupts.
+    pub fn set_duty(&mut self, duty: u16) {
+        let max = self.max_duty();
+        self.duty = duty.min(max);
+        if self.duty == 0 || self.duty == max {
+            self.channel.unlisten(Event::CompareA);
+            self.channel.unlisten(Event::CompareC);
+            if self.duty == 0 {
+                self.pin.set_low();
+            } else {
+                self.pin.set_high();
+            }
+        } else {
+            self.channel.set_ra(self.duty);
+            self.channel.listen(Event::CompareA);
+            self.channel.listen(Event::CompareC);
+        }
+    }
+
+    pub fn set_duty_ratio(&mut self, numerator: u32, denominator: u32) {
+        let duty = duty_for(self.max_duty(), numerator, denominator);
+        self.set_duty(duty);
+    }
+
+    pub fn on_interrupt(&mut self) {
+        let sr = self.channel.status();
+        if self.duty == 0 || self.duty == self.max_duty() {
+            return;
+        }
+        if sr.compare_c() {
+            self.pin.set_high();
+        }
+        if sr.compare_a() {
+            self.pin.set_low();
+        }
+    }
+
+    pub fn release(mut self) -> (tc::Channel<CH>, Pin<ID, Output<PushPull>>) {
+        self.channel.unlisten(Event::CompareA);
+        self.channel.unlisten(Event::CompareC);
+        self.channel.stop();
+        self.pin.set_low();
+        (self.channel, self.pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        // the channels start at 0x200
+        assert_eq!(0x280, ::core::mem::size_of::<PulseWidthModulation>());
+    }
+
+    #[test]
+    fn test_period() {
+        assert_eq!(
+            Ok((0, 6000)),
+            period_for(120_000_000, 20_000, Alignment::Left)
+        );
+        assert_eq!(
+            Ok((0, 3000)),
+            period_for(120_000_000, 20_000, Alignment::Center)
+        );
+        assert_eq!(Ok((8, 46875)), period_for(120_000_000, 10, Alignment::Left));
+        assert_eq!(
+            Err(PwmError::FrequencyTooLow),
+            period_for(120_000_000, 1, Alignment::Left)
+        );
+        assert_eq!(
+            Err(PwmError::FrequencyTooHigh),
+            period_for(120_000_000, 100_000_000, Alignment::Left)
+        );
+    }
+
+    #[test]
+    fn test_duty_and_dead_time() {
+        assert_eq!(3000, duty_for(6000, 1, 2));
+        assert_eq!(6000, duty_for(6000, 3, 2));
+        assert_eq!(2, duty_for(6000, 1, 4000));
+        // 120MHz: 8.33ns per tick
+        assert_eq!(24, dead_time_ticks(120_000_000, 0, 200));
+        assert_eq!(0xFFFF, dead_time_ticks(120_000_000, 0, 1_000_000));
+    }
+
+    #[test]
+    fn test_soft_timing() {
+        assert_eq!(
+            Ok((TimerClock::MckDiv2, 60_000)),
+            soft_timing(120_000_000, 1_000)
+        );
+        assert_eq!(
+            Ok((TimerClock::MckDiv128, 46_875)),
+            soft_timing(120_000_000, 20)
+        );
+        assert_eq!(Err(PwmError::FrequencyTooLow), soft_timing(120_000_000, 10));
+    }
+}
@@ -0,0 +1,171 @@
+//! Reset Controller
+//!
+//! The cause of the last reset is latched until the next one: it can be read at any time after
+//! the boot.
+//!
+//! ```ignore
+//! if rstc::reset_cause() == ResetCause::Watchdog {
+//!     // the previous run hung
+//! }
+//! ```
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+use silica_arm_cortexm4::power;
+
+const KEY_MASK: u32 = 0xFF00_0000;
+const KEY: u32 = 0xA500_0000;
+
+register! {
+    @optout_extract_insert;
+    /// Reset Controller Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        /// Asserts the NRST pin for the duration set in the mode register.
+        bool: _, pub external_reset: 3;
+        bool: _, pub peripheral_reset: 2;
+        bool: _, pub processor_reset: 0;
+    }
+}
+register_impl_extract_insert!(@keyed CRegister: u32, KEY_MASK, KEY);
+
+#[derive(Debug)]
+pub struct TryIntoResetCauseError(());
+
+/// Cause of the last reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    /// First power-up or brownout.
+    PowerOn,
+    /// Wake up from the backup mode.
+    Backup,
+    Watchdog,
+    /// Requested by the software, e.g. `sys_reset_request` or `ResetController::reset`.
+    Software,
+    /// The NRST pin was asserted.
+    User,
+}
+impl TryFrom<u32> for ResetCause {
+    type Error = TryIntoResetCauseError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(ResetCause::PowerOn),
+            1 => Ok(ResetCause::Backup),
+            2 => Ok(ResetCause::Watchdog),
+            3 => Ok(ResetCause::Software),
+            4 => Ok(ResetCause::User),
+            _ => Err(TryIntoResetCauseError(())),
+        }
+    }
+}
+impl From<ResetCause> for u32 {
+    fn from(v: ResetCause) -> u32 {
+        match v {
+            ResetCause::PowerOn => 0,
+            ResetCause::Backup => 1,
+            ResetCause::Watchdog => 2,
+            ResetCause::Software => 3,
+            ResetCause::User => 4,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Reset Controller Status Register
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        /// A software reset is in progress.
+        bool: pub software_reset_pending, _: 17;
+        bool: pub nrst_level, _: 16;
+        ResetCause: pub reset_cause, _: 10, 8;
+        /// NRST fell since the last read. Cleared on read.
+        bool: pub user_reset_detected, _: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    @optout_extract_insert;
+    /// Reset Controller Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// NRST is asserted for 2^(length + 1) slow clock cycles on an external reset.
+        u8: pub external_reset_length, pub set_external_reset_length: 11, 8;
+        bool: pub user_reset_interrupt_enabled, pub enable_user_reset_interrupt: 4;
+        /// A low level on NRST resets the chip, otherwise it only sets `user_reset_detected`.
+        bool: pub user_reset_enabled, pub enable_user_reset: 0;
+    }
+}
+register_impl_extract_insert!(@keyed MRegister: u32, KEY_MASK, KEY);
+
+/// Reset Controller
+#[repr(C)]
+pub struct ResetController {
+    pub cr: RegisterCell<CRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub mr: RegisterCell<MRegister>,
+}
+
+/// Smallest external reset length lasting at least `duration_us`, it is capped at 2s.
+pub fn external_reset_length(duration_us: u32) -> u8 {
+    let cycles = (u64::from(duration_us) * 32_768 + 999_999) / 1_000_000;
+    let mut length = 0;
+    while length < 15 && (2u64 << length) < cycles {
+        length += 1;
+    }
+    length
+}
+
+impl ResetController {
+    pub fn reset_cause(&self) -> ResetCause {
+        self.sr.get().reset_cause()
+    }
+
+    /// Asserts NRST for about `duration_us`, to reset the external devices.
+    pub fn assert_external_reset(&mut self, duration_us: u32) {
+        self.mr
+            .get_mut()
+            .set_external_reset_length(external_reset_length(duration_us));
+        self.cr.get_mut().external_reset(true);
+    }
+
+    /// Resets the processor and the peripherals.
+    pub fn reset(&mut self, external: bool) -> ! {
+        {
+            let mut cr = self.cr.get_mut();
+            cr.processor_reset(true);
+            cr.peripheral_reset(true);
+            cr.external_reset(external);
+        }
+        // the reset takes a few slow clock cycles.
+        loop {
+            power::wfi();
+        }
+    }
+}
+
+/// Cause of the last reset.
+pub fn reset_cause() -> ResetCause {
+    unsafe { ::RSTC.reset_cause() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_reset_length() {
+        assert_eq!(0, external_reset_length(0));
+        // 1ms is 33 slow clock cycles.
+        assert_eq!(5, external_reset_length(1_000));
+        assert_eq!(15, external_reset_length(10_000_000));
+    }
+
+    #[test]
+    fn test_reset_cause() {
+        let sr = SRegister(0x0001_0300);
+        assert_eq!(ResetCause::Software, sr.reset_cause());
+        assert!(sr.nrst_level());
+    }
+}
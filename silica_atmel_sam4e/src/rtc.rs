@@ -0,0 +1,407 @@
+//! Real-time Clock
+//!
+//! The calendar runs from the slow clock in the backup domain: it keeps the time across resets
+//! and, with a backup supply, power downs. It is accurate only on the 32kHz crystal oscillator.
+//!
+//! ```ignore
+//! let mut rtc = Rtc::new(unsafe { &mut RTC }, &Config::new().crystal_oscillator(true),
+//!                        unsafe { &mut SUPC });
+//! rtc.set_date_time(DateTime::parse("2018-06-01", "12:00:00").unwrap())?;
+//! fs.set_clock(|| unsafe { RTC.date_time() }.unwrap_or(DateTime::MIN));
+//! ```
+use core::convert::{Into, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+use silica::time::DateTime;
+use supc::SupplyController;
+
+register! {
+    @impl_debug;
+    /// RTC Control Register
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        /// Stops the calendar to update it.
+        bool: pub update_calendar, pub set_update_calendar: 1;
+        /// Stops the time to update it.
+        bool: pub update_time, pub set_update_time: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        bool: pub high_ppm, pub set_high_ppm: 15;
+        /// Slow clock correction, see the datasheet.
+        u8: pub correction, pub set_correction: 14, 8;
+        bool: pub negative_ppm, pub set_negative_ppm: 4;
+        bool: pub persian_calendar, pub set_persian_calendar: 1;
+        bool: pub hour_mode_12, pub set_hour_mode_12: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Time Register, in BCD.
+    #[derive(Copy, Clone)]
+    pub struct TIMRegister(u32) {
+        bool: pub pm, pub set_pm: 22;
+        u8: pub hour, pub set_hour: 21, 16;
+        u8: pub minute, pub set_minute: 14, 8;
+        u8: pub second, pub set_second: 6, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Calendar Register, in BCD.
+    #[derive(Copy, Clone)]
+    pub struct CALRegister(u32) {
+        u8: pub date, pub set_date: 29, 24;
+        /// Day of the week, from 1 to 7.
+        u8: pub day, pub set_day: 23, 21;
+        u8: pub month, pub set_month: 20, 16;
+        u8: pub year, pub set_year: 15, 8;
+        u8: pub century, pub set_century: 6, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Time Alarm Register, in BCD.
+    #[derive(Copy, Clone)]
+    pub struct TIMALRegister(u32) {
+        bool: pub hour_enabled, pub enable_hour: 23;
+        bool: pub pm, pub set_pm: 22;
+        u8: pub hour, pub set_hour: 21, 16;
+        bool: pub minute_enabled, pub enable_minute: 15;
+        u8: pub minute, pub set_minute: 14, 8;
+        bool: pub second_enabled, pub enable_second: 7;
+        u8: pub second, pub set_second: 6, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Calendar Alarm Register, in BCD.
+    #[derive(Copy, Clone)]
+    pub struct CALALRegister(u32) {
+        bool: pub date_enabled, pub enable_date: 31;
+        u8: pub date, pub set_date: 29, 24;
+        bool: pub month_enabled, pub enable_month: 23;
+        u8: pub month, pub set_month: 20, 16;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Status Register, also the layout of the Status Clear Command Register.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub time_and_date_error, pub set_time_and_date_error: 5;
+        bool: pub calendar_event, pub set_calendar_event: 4;
+        bool: pub time_event, pub set_time_event: 3;
+        bool: pub second, pub set_second: 2;
+        bool: pub alarm, pub set_alarm: 1;
+        /// The time and calendar are stopped and can be updated.
+        bool: pub update_acknowledge, pub set_update_acknowledge: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Interrupt Enable Register, also the layout of the Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct IRegister(u32) {
+        bool: pub calendar_event, pub set_calendar_event: 4;
+        bool: pub time_event, pub set_time_event: 3;
+        bool: pub second, pub set_second: 2;
+        bool: pub alarm, pub set_alarm: 1;
+        bool: pub update_acknowledge, pub set_update_acknowledge: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTC Valid Entry Register: the last values written were invalid.
+    #[derive(Copy, Clone)]
+    pub struct VERegister(u32) {
+        bool: pub calendar_alarm_invalid, _: 3;
+        bool: pub time_alarm_invalid, _: 2;
+        bool: pub calendar_invalid, _: 1;
+        bool: pub time_invalid, _: 0;
+    }
+}
+
+/// Real-time Clock
+#[repr(C)]
+pub struct RealTimeClock {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    pub timr: RegisterCell<TIMRegister>,
+    pub calr: RegisterCell<CALRegister>,
+    pub timalr: RegisterCell<TIMALRegister>,
+    pub calalr: RegisterCell<CALALRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub sccr: RegisterCell<SRegister>,
+    pub ier: RegisterCell<IRegister>,
+    pub idr: RegisterCell<IRegister>,
+    pub imr: RoRegisterCell<IRegister>,
+    pub ver: RoRegisterCell<VERegister>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcError {
+    /// The calendar refused the values written.
+    InvalidEntry,
+}
+
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | (value % 10)
+}
+
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0xF)
+}
+
+/// The time and calendar registers holding `dt`, in the 24-hour mode.
+pub fn registers(dt: DateTime) -> (TIMRegister, CALRegister) {
+    let mut timr = TIMRegister(0);
+    timr.set_hour(to_bcd(dt.hour()));
+    timr.set_minute(to_bcd(dt.minute()));
+    timr.set_second(to_bcd(dt.second()));
+    let mut calr = CALRegister(0);
+    calr.set_century(to_bcd((dt.year() / 100) as u8));
+    calr.set_year(to_bcd((dt.year() % 100) as u8));
+    calr.set_month(to_bcd(dt.month()));
+    calr.set_date(to_bcd(dt.day()));
+    calr.set_day(dt.weekday());
+    (timr, calr)
+}
+
+/// The date and time held by the time and calendar registers, in the 24-hour mode.
+pub fn date_time(timr: TIMRegister, calr: CALRegister) -> Option<DateTime> {
+    DateTime::new(
+        u16::from(from_bcd(calr.century())) * 100 + u16::from(from_bcd(calr.year())),
+        from_bcd(calr.month()),
+        from_bcd(calr.date()),
+        from_bcd(timr.hour()),
+        from_bcd(timr.minute()),
+        from_bcd(timr.second()),
+    )
+}
+
+/// Fields of the date and time to match, the others are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Alarm {
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
+}
+impl Alarm {
+    /// Fires once, at `dt`, the year aside.
+    pub fn at(dt: DateTime) -> Alarm {
+        Alarm {
+            month: Some(dt.month()),
+            day: Some(dt.day()),
+            hour: Some(dt.hour()),
+            minute: Some(dt.minute()),
+            second: Some(dt.second()),
+        }
+    }
+}
+
+pub struct Config {
+    crystal_oscillator: bool,
+}
+impl Config {
+    /// Keeps the slow clock as it is.
+    pub fn new() -> Config {
+        Config {
+            crystal_oscillator: false,
+        }
+    }
+    /// Selects the 32kHz crystal oscillator, which cannot be undone.
+    pub fn crystal_oscillator(mut self, crystal_oscillator: bool) -> Config {
+        self.crystal_oscillator = crystal_oscillator;
+        self
+    }
+}
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+pub struct Rtc {
+    regs: &'static mut RealTimeClock,
+}
+
+impl Rtc {
+    /// Keeps the time the calendar may already hold.
+    pub fn new(
+        regs: &'static mut RealTimeClock,
+        config: &Config,
+        supc: &mut SupplyController,
+    ) -> Rtc {
+        if config.crystal_oscillator {
+            supc.select_crystal_oscillator();
+        }
+        regs.mr.get_mut().set_hour_mode_12(false);
+        regs.idr.set(IRegister(!0));
+        Rtc { regs }
+    }
+
+    pub fn release(self) -> &'static mut RealTimeClock {
+        self.regs
+    }
+
+    /// The current date and time, None if the calendar holds no valid date from 1980.
+    pub fn date_time(&self) -> Option<DateTime> {
+        self.regs.date_time()
+    }
+
+    /// Sets the calendar. This takes up to a second: the update waits for the next second.
+    pub fn set_date_time(&mut self, dt: DateTime) -> Result<(), RtcError> {
+        let mut clear = SRegister(0);
+        clear.set_second(true);
+        self.regs.sccr.set(clear);
+        while !self.regs.sr.get().second() {}
+
+        {
+            let mut cr = self.regs.cr.get_mut();
+            cr.set_update_time(true);
+            cr.set_update_calendar(true);
+        }
+        while !self.regs.sr.get().update_acknowledge() {}
+        let mut clear = SRegister(0);
+        clear.set_update_acknowledge(true);
+        self.regs.sccr.set(clear);
+
+        let (timr, calr) = registers(dt);
+        self.regs.timr.set(timr);
+        self.regs.calr.set(calr);
+        self.regs.cr.set(CRegister(0));
+
+        let ver = self.regs.ver.get();
+        if ver.time_invalid() || ver.calendar_invalid() {
+            Err(RtcError::InvalidEntry)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Programs the alarm and enables its interrupt.
+    pub fn set_alarm(&mut self, alarm: &Alarm) -> Result<(), RtcError> {
+        let mut disable = IRegister(0);
+        disable.set_alarm(true);
+        self.regs.idr.set(disable);
+
+        let mut timalr = TIMALRegister(0);
+        if let Some(hour) = alarm.hour {
+            timalr.set_hour(to_bcd(hour));
+            timalr.enable_hour(true);
+        }
+        if let Some(minute) = alarm.minute {
+            timalr.set_minute(to_bcd(minute));
+            timalr.enable_minute(true);
+        }
+        if let Some(second) = alarm.second {
+            timalr.set_second(to_bcd(second));
+            timalr.enable_second(true);
+        }
+        // the disabled fields must still hold valid values.
+        let mut calalr = CALALRegister(0);
+        calalr.set_month(1);
+        calalr.set_date(1);
+        if let Some(month) = alarm.month {
+            calalr.set_month(to_bcd(month));
+            calalr.enable_month(true);
+        }
+        if let Some(day) = alarm.day {
+            calalr.set_date(to_bcd(day));
+            calalr.enable_date(true);
+        }
+        self.regs.timalr.set(timalr);
+        self.regs.calalr.set(calalr);
+
+        let ver = self.regs.ver.get();
+        if ver.time_alarm_invalid() || ver.calendar_alarm_invalid() {
+            return Err(RtcError::InvalidEntry);
+        }
+        let mut clear = SRegister(0);
+        clear.set_alarm(true);
+        self.regs.sccr.set(clear);
+        let mut enable = IRegister(0);
+        enable.set_alarm(true);
+        self.regs.ier.set(enable);
+        Ok(())
+    }
+
+    pub fn disable_alarm(&mut self) {
+        let mut disable = IRegister(0);
+        disable.set_alarm(true);
+        self.regs.idr.set(disable);
+        self.regs.timalr.set(TIMALRegister(0));
+        self.regs.calalr.set(CALALRegister(0));
+    }
+
+    /// Tells whether the alarm fired since the last call.
+    pub fn alarm_fired(&mut self) -> bool {
+        if !self.regs.sr.get().alarm() {
+            return false;
+        }
+        let mut clear = SRegister(0);
+        clear.set_alarm(true);
+        self.regs.sccr.set(clear);
+        true
+    }
+}
+
+impl RealTimeClock {
+    /// The current date and time, None if the calendar holds no valid date from 1980.
+    ///
+    /// This reads the registers directly, as a FAT clock may do behind the `Rtc`.
+    pub fn date_time(&self) -> Option<DateTime> {
+        // the registers are updated asynchronously: two equal reads are consistent.
+        loop {
+            let (timr, calr) = (self.timr.get(), self.calr.get());
+            if timr.0 == self.timr.get().0 && calr.0 == self.calr.get().0 {
+                let ver = self.ver.get();
+                if ver.time_invalid() || ver.calendar_invalid() {
+                    return None;
+                }
+                return date_time(timr, calr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_registers() {
+        assert_eq!(0x59, to_bcd(59));
+        assert_eq!(42, from_bcd(0x42));
+
+        let dt = DateTime::new(2018, 6, 1, 12, 34, 56).unwrap();
+        let (timr, calr) = registers(dt);
+        assert_eq!(0x0012_3456, timr.0);
+        // a Friday.
+        assert_eq!(0x01A6_1820, calr.0);
+        assert_eq!(Some(dt), date_time(timr, calr));
+        // the calendar starts in 1900.
+        assert_eq!(None, date_time(TIMRegister(0), CALRegister(0x0121_0719)));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0x30, size_of::<RealTimeClock>());
+    }
+}
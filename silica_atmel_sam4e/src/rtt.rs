@@ -0,0 +1,141 @@
+//! Real-time Timer
+//!
+//! A 32-bit counter of the slow clock divided by a prescaler, running in the backup domain. It
+//! keeps counting through the wait and backup modes, and measures the uptime for the print
+//! statistics:
+//!
+//! ```ignore
+//! // one tick per second.
+//! let rtt = Rtt::new(unsafe { &mut RTT }, 32_768)?;
+//! let uptime = rtt.now();
+//! ```
+use clock::SLOW_CLOCK_HZ;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+use silica::time::{Clock, Instant};
+
+register! {
+    @impl_debug;
+    /// RTT Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// Counts the 1Hz clock of the RTC instead of the prescaled slow clock.
+        bool: pub rtc_1hz, pub set_rtc_1hz: 24;
+        bool: pub disabled, pub set_disabled: 20;
+        /// Clears the counter and reloads the prescaler (write only).
+        bool: _, pub restart: 18;
+        bool: pub increment_interrupt_enabled, pub enable_increment_interrupt: 17;
+        bool: pub alarm_interrupt_enabled, pub enable_alarm_interrupt: 16;
+        /// Slow clock cycles per increment, 0 for 65536. 1 and 2 are forbidden.
+        u16: pub prescaler, pub set_prescaler: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// RTT Status Register, cleared by reading it.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub increment, _: 1;
+        bool: pub alarm, _: 0;
+    }
+}
+
+/// Real-time Timer
+#[repr(C)]
+pub struct RealTimeTimer {
+    pub mr: RegisterCell<MRegister>,
+    /// Alarm Register
+    pub ar: RegisterCell<u32>,
+    /// Value Register
+    pub vr: RoRegisterCell<u32>,
+    pub sr: RoRegisterCell<SRegister>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RttError {
+    /// The prescaler is 3 to 65536 slow clock cycles.
+    InvalidPrescaler,
+}
+
+/// Microseconds elapsed after `ticks` of `prescaler` slow clock cycles.
+fn to_micros(ticks: u32, prescaler: u32) -> u64 {
+    let cycles = u64::from(ticks) * u64::from(prescaler);
+    let hz = u64::from(SLOW_CLOCK_HZ);
+    cycles / hz * 1_000_000 + cycles % hz * 1_000_000 / hz
+}
+
+pub struct Rtt {
+    regs: &'static mut RealTimeTimer,
+    prescaler: u32,
+}
+
+impl Rtt {
+    /// Restarts the counter from 0, with one increment every `prescaler` slow clock cycles.
+    pub fn new(regs: &'static mut RealTimeTimer, prescaler: u32) -> Result<Rtt, RttError> {
+        if prescaler < 3 || prescaler > 65_536 {
+            return Err(RttError::InvalidPrescaler);
+        }
+        let mut mr = MRegister(0);
+        mr.set_prescaler(prescaler as u16);
+        mr.restart(true);
+        regs.mr.set(mr);
+        Ok(Rtt { regs, prescaler })
+    }
+
+    pub fn release(self) -> &'static mut RealTimeTimer {
+        self.regs.mr.get_mut().set_disabled(true);
+        self.regs
+    }
+
+    /// The counter value.
+    pub fn ticks(&self) -> u32 {
+        // the counter is updated asynchronously: two equal reads are consistent.
+        loop {
+            let value = self.regs.vr.get();
+            if value == self.regs.vr.get() {
+                return value;
+            }
+        }
+    }
+
+    /// Programs the alarm for when the counter reaches `ticks` and enables its interrupt.
+    pub fn set_alarm(&mut self, ticks: u32) {
+        self.regs.mr.get_mut().enable_alarm_interrupt(false);
+        self.regs.ar.set(ticks.wrapping_sub(1));
+        self.regs.mr.get_mut().enable_alarm_interrupt(true);
+    }
+
+    pub fn disable_alarm(&mut self) {
+        self.regs.mr.get_mut().enable_alarm_interrupt(false);
+    }
+
+    /// Tells whether the alarm fired since the last call.
+    pub fn alarm_fired(&mut self) -> bool {
+        self.regs.sr.get().alarm()
+    }
+}
+
+/// The time elapsed since the counter started, wrapping with it.
+impl Clock for Rtt {
+    fn now(&self) -> Instant {
+        Instant::from_micros(to_micros(self.ticks(), self.prescaler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_micros() {
+        assert_eq!(3_000_000, to_micros(3, 32_768));
+        assert_eq!(
+            u64::from(u32::max_value()) * 2_000_000,
+            to_micros(!0, 65_536)
+        );
+        // the finest resolution is about 92us.
+        assert_eq!(91, to_micros(1, 3));
+    }
+}
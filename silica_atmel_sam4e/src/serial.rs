@@ -0,0 +1,589 @@
+//! Serial ports over the UARTs and the USARTs in asynchronous mode.
+//!
+//! ```ignore
+//! let pins = pioa::take(unsafe { &mut PMC }).unwrap();
+//! let tx = pins.pa10.into_peripheral_a();
+//! let rx = pins.pa9.into_peripheral_a();
+//! let mut serial = Serial::new(Uart0, tx, rx, Config::new(57_600), &clocks, unsafe { &mut PMC })
+//!     .unwrap();
+//! writeln!(serial, "ok").unwrap();
+//! ```
+//!
+//! `Serial` polls the peripheral, its `read_async` and `write_async` futures letting the
+//! `silica::executor` run other tasks meanwhile. They are woken up by `on_interrupt`, called
+//! from the peripheral's interrupt handler:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn uart0_handler() {
+//!     serial::on_interrupt::<Uart0>();
+//! }
+//!
+//! serial.write_async(b"ok\n").await?;
+//! ```
+//!
+//! `Buffered` moves the bytes between ring buffers and the
+//! peripheral from the interrupt handler, in which case it is shared with the handler through a
+//! `Mutex`:
+//!
+//! ```ignore
+//! static PANEL: Mutex<RefCell<Option<Buffered<Uart0, Tx, Rx>>>> = Mutex::new(RefCell::new(None));
+//!
+//! #[no_mangle]
+//! pub unsafe extern "C" fn uart0_handler() {
+//!     interrupt::free(|cs| PANEL.borrow(cs).borrow_mut().as_mut().map(Buffered::on_interrupt));
+//! }
+//! ```
+
+use clock::Clocks;
+use core::fmt;
+use core::future::Future;
+use core::task::Waker;
+use interrupts;
+use pdc::Pdc;
+use pio::{pioa, piob, Peripheral, Pin, A, C};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::executor::WakerCell;
+use silica::ring::RingBuffer;
+use silica::serial::{self, block};
+use silica_arm_cortexm4::interrupt::{self, Mutex};
+use uart::{Parity, SRegister, Uart};
+
+/// Maximum deviation of the generated baud rate, in percent.
+const MAX_BAUD_RATE_ERROR: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigError {
+    /// The baud rate cannot be generated from the master clock within 2%.
+    UnreachableBaudRate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// A byte arrived before the previous one was read, or the receive buffer is full.
+    Overrun,
+    /// The stop bit was not found.
+    Framing,
+    Parity,
+}
+
+/// Divider of MCK generating `baud` from 16 samples per bit.
+pub fn clock_divider(mck: u32, baud: u32) -> Result<u16, ConfigError> {
+    let rate = u64::from(baud) * 16;
+    if rate == 0 {
+        return Err(ConfigError::UnreachableBaudRate);
+    }
+    let divider = (u64::from(mck) + rate / 2) / rate;
+    if divider == 0 || divider > 0xFFFF {
+        return Err(ConfigError::UnreachableBaudRate);
+    }
+    let actual = u64::from(mck) / (divider * 16);
+    let deviation = if actual > u64::from(baud) {
+        actual - u64::from(baud)
+    } else {
+        u64::from(baud) - actual
+    };
+    if deviation * 100 > u64::from(baud) * u64::from(MAX_BAUD_RATE_ERROR) {
+        return Err(ConfigError::UnreachableBaudRate);
+    }
+    Ok(divider as u16)
+}
+
+/// Frame format: 8 data bits, 1 stop bit and the given parity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub baud_rate: u32,
+    pub parity: Parity,
+}
+impl Config {
+    pub fn new(baud_rate: u32) -> Config {
+        Config {
+            baud_rate,
+            parity: Parity::None,
+        }
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Config {
+        self.parity = parity;
+        self
+    }
+}
+
+/// A UART or a USART.
+pub trait Instance {
+    const ID: PeripheralId;
+
+    fn regs() -> &'static mut Uart;
+
+    /// Waker of the task awaiting the port.
+    fn waker() -> &'static Mutex<WakerCell>;
+
+    /// Sets up the instance specific part of the mode register, before the parity is set.
+    fn init_mode() {}
+}
+
+pub struct Uart0;
+pub struct Uart1;
+pub struct Usart0;
+pub struct Usart1;
+
+impl Instance for Uart0 {
+    const ID: PeripheralId = PeripheralId::Uart0;
+    fn regs() -> &'static mut Uart {
+        unsafe { &mut ::UART0 }
+    }
+    fn waker() -> &'static Mutex<WakerCell> {
+        static WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+        &WAKER
+    }
+}
+impl Instance for Uart1 {
+    const ID: PeripheralId = PeripheralId::Uart1;
+    fn regs() -> &'static mut Uart {
+        unsafe { &mut ::UART1 }
+    }
+    fn waker() -> &'static Mutex<WakerCell> {
+        static WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+        &WAKER
+    }
+}
+
+macro_rules! usart_instance {
+    ($name:ident, $id:ident, $usart:ident) => {
+        impl Instance for $name {
+            const ID: PeripheralId = PeripheralId::$id;
+            fn regs() -> &'static mut Uart {
+                // the asynchronous mode registers and the PDC share the UART's layout.
+                unsafe { &mut *(&mut ::$usart as *mut ::usart::Usart as *mut Uart) }
+            }
+            fn waker() -> &'static Mutex<WakerCell> {
+                static WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+                &WAKER
+            }
+            fn init_mode() {
+                let usart = unsafe { &mut ::$usart };
+                let mut mr = usart.mr.get_mut();
+                mr.set_mode(0);
+                mr.set_clock_selection(0);
+                mr.set_synchronous(false);
+                mr.set_char_length(3);
+                mr.set_stop_bits(0);
+                mr.set_msb_first(false);
+                mr.set_nine_bits(false);
+                mr.set_oversampling_8(false);
+            }
+        }
+    };
+}
+usart_instance!(Usart0, Usart0, USART0);
+usart_instance!(Usart1, Usart1, USART1);
+
+/// Pin able to transmit for `U`.
+pub trait TxPin<U> {}
+/// Pin able to receive for `U`.
+pub trait RxPin<U> {}
+
+impl RxPin<Uart0> for Pin<pioa::PA9, Peripheral<A>> {}
+impl TxPin<Uart0> for Pin<pioa::PA10, Peripheral<A>> {}
+impl RxPin<Uart1> for Pin<pioa::PA5, Peripheral<C>> {}
+impl TxPin<Uart1> for Pin<pioa::PA6, Peripheral<C>> {}
+impl RxPin<Usart0> for Pin<piob::PB0, Peripheral<C>> {}
+impl TxPin<Usart0> for Pin<piob::PB1, Peripheral<C>> {}
+impl RxPin<Usart1> for Pin<pioa::PA21, Peripheral<A>> {}
+impl TxPin<Usart1> for Pin<pioa::PA22, Peripheral<A>> {}
+
+/// Polled serial port.
+pub struct Serial<U, TX, RX> {
+    instance: U,
+    tx: TX,
+    rx: RX,
+    pdc: Pdc<u8>,
+}
+
+impl<U, TX, RX> Serial<U, TX, RX>
+where
+    U: Instance,
+    TX: TxPin<U>,
+    RX: RxPin<U>,
+{
+    pub fn new(
+        instance: U,
+        tx: TX,
+        rx: RX,
+        config: Config,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Serial<U, TX, RX>, ConfigError> {
+        let divider = clock_divider(clocks.peripheral(U::ID), config.baud_rate)?;
+        pmc.enable_peripheral_clock(U::ID);
+
+        let regs = U::regs();
+        {
+            let mut cr = regs.cr.get_mut();
+            cr.reset_receiver(true);
+            cr.reset_transmitter(true);
+            cr.disable_receiver(true);
+            cr.disable_transmitter(true);
+            cr.reset_status(true);
+        }
+        {
+            let mut idr = regs.idr.get_mut();
+            idr.set_rx_ready(true);
+            idr.set_tx_ready(true);
+            idr.set_tx_empty(true);
+            idr.set_overrun(true);
+            idr.set_framing_error(true);
+            idr.set_parity_error(true);
+            idr.set_end_of_rx(true);
+            idr.set_end_of_tx(true);
+        }
+        let pdc = Pdc::new(&mut U::regs().pdc);
+        U::init_mode();
+        {
+            let mut mr = regs.mr.get_mut();
+            mr.set_channel_mode(0);
+            mr.set_parity(config.parity);
+        }
+        regs.brgr.get_mut().set_clock_divider(divider);
+        {
+            let mut cr = regs.cr.get_mut();
+            cr.enable_receiver(true);
+            cr.enable_transmitter(true);
+        }
+        Ok(Serial {
+            instance,
+            tx,
+            rx,
+            pdc,
+        })
+    }
+
+    /// Disables the port and gives its resources back.
+    pub fn release(self, pmc: &mut PowerManagementController) -> (U, TX, RX) {
+        self.pdc.release();
+        {
+            let mut cr = U::regs().cr.get_mut();
+            cr.disable_receiver(true);
+            cr.disable_transmitter(true);
+        }
+        pmc.disable_peripheral_clock(U::ID);
+        (self.instance, self.tx, self.rx)
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32, clocks: &Clocks) -> Result<(), ConfigError> {
+        let divider = clock_divider(clocks.peripheral(U::ID), baud_rate)?;
+        U::regs().brgr.get_mut().set_clock_divider(divider);
+        Ok(())
+    }
+
+    /// Sends `buffer` through the PDC after the queued buffers, given back if two are queued.
+    pub fn write_dma(&mut self, buffer: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+        self.pdc.write(buffer)
+    }
+
+    /// The oldest buffer sent, if complete.
+    pub fn write_dma_done(&mut self) -> Option<&'static mut [u8]> {
+        self.pdc.write_done()
+    }
+
+    /// Receives into `buffer` through the PDC once the queued buffers are full, `buffer` is
+    /// given back if two are queued.
+    pub fn read_dma(&mut self, buffer: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+        self.pdc.read(buffer)
+    }
+
+    /// The oldest buffer received, if full.
+    pub fn read_dma_done(&mut self) -> Option<&'static mut [u8]> {
+        self.pdc.read_done()
+    }
+
+    /// Bytes received in the oldest buffer.
+    pub fn read_dma_progress(&self) -> usize {
+        self.pdc.read_progress()
+    }
+
+    /// Stops both PDC channels and returns the bytes received in the oldest buffer. The
+    /// `_done` methods then hand back all the buffers.
+    pub fn stop_dma(&mut self) -> usize {
+        self.pdc.stop_write();
+        self.pdc.stop_read()
+    }
+
+    /// Receives `buffer.len()` bytes, the task sleeping until each of them arrives. Errors end
+    /// the reception.
+    pub fn read_async<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+    ) -> impl Future<Output = Result<(), Error>> + 'a {
+        serial::read_async(self, buffer, |_, waker| {
+            listen::<U, _>(waker, |ier| {
+                ier.set_rx_ready(true);
+                ier.set_overrun(true);
+                ier.set_framing_error(true);
+                ier.set_parity_error(true);
+            })
+        })
+    }
+
+    /// Sends `bytes`, the task sleeping while the transmitter is busy. The future completes once
+    /// the last byte is handed to the peripheral.
+    pub fn write_async<'a>(
+        &'a mut self,
+        bytes: &'a [u8],
+    ) -> impl Future<Output = Result<(), Error>> + 'a {
+        serial::write_async(self, bytes, |_, waker| {
+            listen::<U, _>(waker, |ier| ier.set_tx_ready(true))
+        })
+    }
+
+    /// Switches to the interrupt driven mode. The peripheral's interrupt is enabled in the
+    /// NVIC, its handler must call `Buffered::on_interrupt`.
+    pub fn into_buffered(
+        self,
+        rx_buffer: &'static mut [u8],
+        tx_buffer: &'static mut [u8],
+    ) -> Buffered<U, TX, RX> {
+        {
+            let mut ier = U::regs().ier.get_mut();
+            ier.set_rx_ready(true);
+            ier.set_overrun(true);
+            ier.set_framing_error(true);
+            ier.set_parity_error(true);
+        }
+        interrupts::enable(U::ID);
+        Buffered {
+            serial: self,
+            rx: RingBuffer::new(rx_buffer),
+            tx: RingBuffer::new(tx_buffer),
+            error: None,
+        }
+    }
+}
+
+/// Registers the task waiting on the port before `enable` sets the interrupt sources waking it:
+/// a source already active fires right away.
+fn listen<U, F>(waker: &Waker, enable: F)
+where
+    U: Instance,
+    F: FnOnce(&mut SRegister),
+{
+    interrupt::free(|cs| U::waker().borrow(cs).register(waker));
+    U::regs().ier.update(enable);
+    interrupts::enable(U::ID);
+}
+
+/// Wakes the task awaiting `Serial::read_async` or `Serial::write_async`, to be called from the
+/// peripheral's interrupt handler. The sources are disabled until the task waits again.
+pub fn on_interrupt<U: Instance>() {
+    let regs = U::regs();
+    let sources = regs.imr.get();
+    regs.idr.set(sources);
+    interrupt::free(|cs| U::waker().borrow(cs).wake());
+}
+
+/// Takes the error flags, discarding the received byte they relate to.
+fn take_error<U: Instance>() -> Option<Error> {
+    let regs = U::regs();
+    let sr = regs.sr.get();
+    let error = if sr.overrun() {
+        Error::Overrun
+    } else if sr.framing_error() {
+        Error::Framing
+    } else if sr.parity_error() {
+        Error::Parity
+    } else {
+        return None;
+    };
+    if sr.framing_error() || sr.parity_error() {
+        regs.rhr.get();
+    }
+    regs.cr.get_mut().reset_status(true);
+    Some(error)
+}
+
+impl<U, TX, RX> serial::Read for Serial<U, TX, RX>
+where
+    U: Instance,
+{
+    type Error = Error;
+
+    fn read(&mut self) -> Result<u8, serial::Error<Error>> {
+        if let Some(e) = take_error::<U>() {
+            return Err(serial::Error::Other(e));
+        }
+        let regs = U::regs();
+        if regs.sr.get().rx_ready() {
+            Ok(regs.rhr.get() as u8)
+        } else {
+            Err(serial::Error::WouldBlock)
+        }
+    }
+}
+
+impl<U, TX, RX> serial::Write for Serial<U, TX, RX>
+where
+    U: Instance,
+{
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), serial::Error<Error>> {
+        let regs = U::regs();
+        if regs.sr.get().tx_ready() {
+            regs.thr.set(u32::from(byte));
+            Ok(())
+        } else {
+            Err(serial::Error::WouldBlock)
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), serial::Error<Error>> {
+        if U::regs().sr.get().tx_empty() {
+            Ok(())
+        } else {
+            Err(serial::Error::WouldBlock)
+        }
+    }
+}
+
+impl<U, TX, RX> fmt::Write for Serial<U, TX, RX>
+where
+    U: Instance,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::write_all(self, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Interrupt driven serial port.
+pub struct Buffered<U, TX, RX> {
+    serial: Serial<U, TX, RX>,
+    rx: RingBuffer<u8, &'static mut [u8]>,
+    tx: RingBuffer<u8, &'static mut [u8]>,
+    error: Option<Error>,
+}
+
+impl<U, TX, RX> Buffered<U, TX, RX>
+where
+    U: Instance,
+{
+    /// Moves the received bytes to the receive buffer and the bytes to send to the peripheral.
+    pub fn on_interrupt(&mut self) {
+        let regs = U::regs();
+        if let Some(e) = take_error::<U>() {
+            self.error = Some(e);
+        }
+        if regs.sr.get().rx_ready() {
+            let byte = regs.rhr.get() as u8;
+            if self.rx.push(byte).is_err() {
+                self.error = Some(Error::Overrun);
+            }
+        }
+        if regs.imr.get().tx_ready() && regs.sr.get().tx_ready() {
+            match self.tx.pop() {
+                Some(byte) => regs.thr.set(u32::from(byte)),
+                None => regs.idr.get_mut().set_tx_ready(true),
+            }
+        }
+    }
+
+    /// Bytes waiting in the receive buffer.
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Goes back to the polled mode, returning the receive and transmit buffers.
+    pub fn release(self) -> (Serial<U, TX, RX>, &'static mut [u8], &'static mut [u8]) {
+        interrupts::disable(U::ID);
+        {
+            let mut idr = U::regs().idr.get_mut();
+            idr.set_rx_ready(true);
+            idr.set_tx_ready(true);
+            idr.set_overrun(true);
+            idr.set_framing_error(true);
+            idr.set_parity_error(true);
+        }
+        (self.serial, self.rx.release(), self.tx.release())
+    }
+}
+
+impl<U, TX, RX> serial::Read for Buffered<U, TX, RX>
+where
+    U: Instance,
+{
+    type Error = Error;
+
+    /// Errors are reported once, before the bytes received after them.
+    fn read(&mut self) -> Result<u8, serial::Error<Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(serial::Error::Other(e));
+        }
+        self.rx.pop().ok_or(serial::Error::WouldBlock)
+    }
+}
+
+impl<U, TX, RX> serial::Write for Buffered<U, TX, RX>
+where
+    U: Instance,
+{
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), serial::Error<Error>> {
+        self.tx.push(byte).map_err(|_| serial::Error::WouldBlock)?;
+        U::regs().ier.get_mut().set_tx_ready(true);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), serial::Error<Error>> {
+        if self.tx.is_empty() && U::regs().sr.get().tx_empty() {
+            Ok(())
+        } else {
+            Err(serial::Error::WouldBlock)
+        }
+    }
+}
+
+impl<U, TX, RX> fmt::Write for Buffered<U, TX, RX>
+where
+    U: Instance,
+{
+    /// When the transmit buffer is full, it is drained by polling: this also works from within
+    /// a critical section.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            while self.tx.is_full() {
+                let regs = U::regs();
+                if regs.sr.get().tx_ready() {
+                    if let Some(b) = self.tx.pop() {
+                        regs.thr.set(u32::from(b));
+                    }
+                }
+            }
+            block(|| serial::Write::write(self, byte)).map_err(|_: Error| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_divider() {
+        assert_eq!(Ok(65), clock_divider(120_000_000, 115_200));
+        assert_eq!(Ok(130), clock_divider(120_000_000, 57_600));
+        assert_eq!(Ok(26), clock_divider(4_000_000, 9_600));
+        assert_eq!(
+            Err(ConfigError::UnreachableBaudRate),
+            clock_divider(4_000_000, 230_400)
+        );
+        assert_eq!(
+            Err(ConfigError::UnreachableBaudRate),
+            clock_divider(120_000_000, 100)
+        );
+        assert_eq!(
+            Err(ConfigError::UnreachableBaudRate),
+            clock_divider(120_000_000, 0)
+        );
+    }
+}
@@ -0,0 +1,503 @@
+//! Serial Peripheral Interface
+//!
+//! `Spi` is a master with a mode, a clock and delays for each of its 4 chip selects, moving the
+//! data with the PDC:
+//!
+//! ```ignore
+//! let mut spi = Spi::new(unsafe { &mut SPI }, pins, &clocks, unsafe { &mut PMC });
+//! spi.configure(0, &Config::new(Mode::Mode3, 4_000_000))?;
+//! spi.transfer(0, &mut datagram)?;
+//! ```
+//!
+//! The NPCS lines used are switched to their peripheral by the application. Devices selected by
+//! a GPIO (`silica::bus::ChipSelect`) share one of the 4 configurations.
+//!
+//! `transfer_async` and `write_async` let the `silica::executor` run other tasks during the
+//! transfer, the SPI interrupt handler calling `on_interrupt` to wake them up. The buffer is
+//! handed back once the transfer completes:
+//!
+//! ```ignore
+//! let (result, buffer) = spi.transfer_async(0, unsafe { &mut BUFFER }).await;
+//! ```
+//!
+//! `transfer_words` uses the variable peripheral select: a single PDC transfer can address
+//! several devices, the chip select of each byte being part of its `word`.
+use clock::Clocks;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use core::future::Future;
+use core::task::{Context, Poll, Waker};
+use interrupts;
+use pdc::{self, PeripheralDmaController};
+use pio::pioa::{PA12, PA13, PA14};
+use pio::{Output, Peripheral, Pin, PinId, PushPull, A};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::bus::{self, ChipSelect, Mode};
+use silica::executor::WakerCell;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use silica_arm_cortexm4::interrupt::{self, Mutex};
+
+/// Waker of the task awaiting a `Transfer`.
+static WAKER: Mutex<WakerCell> = Mutex::new(WakerCell::new());
+
+register! {
+    /// SPI Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        /// Releases the chip select kept asserted after the current byte.
+        bool: _, pub last_transfer: 24;
+        bool: _, pub software_reset: 7;
+        bool: _, pub disable: 1;
+        bool: _, pub enable: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// SPI Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// Clock cycles between the release of a chip select and the assertion of another one.
+        u8: pub delay_between_selects, pub set_delay_between_selects: 31, 24;
+        /// Chip select of the fixed mode, its line is the only bit cleared.
+        u8: pub peripheral_select, pub set_peripheral_select: 19, 16;
+        bool: pub local_loopback, pub set_local_loopback: 7;
+        /// Holds the transfers while the received byte is not read.
+        bool: pub wait_data_read, pub set_wait_data_read: 5;
+        bool: pub mode_fault_disabled, pub set_mode_fault_disabled: 4;
+        bool: pub select_decode, pub set_select_decode: 2;
+        bool: pub variable_select, pub set_variable_select: 1;
+        bool: pub master, pub set_master: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// SPI Status Register, also the layout of the Interrupt Enable, Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub enabled, _: 16;
+        bool: pub underrun, pub set_underrun: 10;
+        /// Both the transmit data and shift registers are empty.
+        bool: pub tx_empty, pub set_tx_empty: 9;
+        bool: pub nss_rising, pub set_nss_rising: 8;
+        bool: pub tx_buffer_empty, pub set_tx_buffer_empty: 7;
+        bool: pub rx_buffer_full, pub set_rx_buffer_full: 6;
+        bool: pub end_of_tx, pub set_end_of_tx: 5;
+        bool: pub end_of_rx, pub set_end_of_rx: 4;
+        bool: pub overrun, pub set_overrun: 3;
+        bool: pub mode_fault, pub set_mode_fault: 2;
+        bool: pub tx_ready, pub set_tx_ready: 1;
+        bool: pub rx_ready, pub set_rx_ready: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// SPI Chip Select Register
+    #[derive(Copy, Clone)]
+    pub struct CSRegister(u32) {
+        /// 32 * cycles between consecutive bytes.
+        u8: pub delay_between_transfers, pub set_delay_between_transfers: 31, 24;
+        /// Cycles between the chip select assertion and the first clock edge.
+        u8: pub delay_before_clock, pub set_delay_before_clock: 23, 16;
+        /// SPCK is MCK / divider.
+        u8: pub baud_divider, pub set_baud_divider: 15, 8;
+        /// 8 + bits per transfer.
+        u8: pub bits, pub set_bits: 7, 4;
+        /// Keeps the chip select asserted until `last_transfer` or another chip select.
+        bool: pub keep_selected, pub set_keep_selected: 3;
+        /// Releases the chip select between consecutive bytes.
+        bool: pub release_between_transfers, pub set_release_between_transfers: 2;
+        /// Data captured on the leading edge: the inverse of the usual phase.
+        bool: pub clock_phase, pub set_clock_phase: 1;
+        bool: pub clock_polarity, pub set_clock_polarity: 0;
+    }
+}
+
+/// Serial Peripheral Interface
+#[repr(C)]
+pub struct SerialPeripheralInterface {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    /// Receive Data Register
+    pub rdr: RoRegisterCell<u32>,
+    /// Transmit Data Register, see `word` for the variable peripheral select.
+    pub tdr: RegisterCell<u32>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub ier: RegisterCell<SRegister>,
+    pub idr: RegisterCell<SRegister>,
+    pub imr: RoRegisterCell<SRegister>,
+    _reserved0: ReservedCell<[u32; 4]>,
+    pub csr: [RegisterCell<CSRegister>; 4],
+    _reserved1: ReservedCell<[u32; 41]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    _reserved2: ReservedCell<[u32; 5]>,
+    pub pdc: PeripheralDmaController,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpiError {
+    /// There are 4 chip selects.
+    InvalidChipSelect,
+    /// `transfer_words` needs as many received bytes as words.
+    LengthMismatch,
+    /// The SPCK frequency is 0.
+    InvalidClock,
+    /// More than `pdc::MAX_TRANSFER` bytes at once.
+    TooLong,
+}
+
+/// Value of a peripheral select field for the chip select `cs`, without decoder.
+fn peripheral_select(cs: u8) -> u8 {
+    !(1 << cs) & 0xF
+}
+
+/// Transmit data register word sending `byte` to the chip select `cs`. `last` releases the chip
+/// select after it.
+pub fn word(cs: u8, byte: u8, last: bool) -> u32 {
+    let last = if last { 1 << 24 } else { 0 };
+    u32::from(byte) | u32::from(peripheral_select(cs)) << 16 | last
+}
+
+/// Divider giving the fastest SPCK not above `hz`, the slowest one if MCK is too fast.
+pub fn baud_divider(mck: u32, hz: u32) -> Option<u8> {
+    if hz == 0 {
+        return None;
+    }
+    let divider = (u64::from(mck) + u64::from(hz) - 1) / u64::from(hz);
+    Some(divider.max(1).min(255) as u8)
+}
+
+/// Delay field lasting at least `ns`, the field counting `unit` clock cycles.
+pub fn delay_cycles(mck: u32, ns: u32, unit: u32) -> u8 {
+    let cycles = u64::from(mck) * u64::from(ns) + 999_999_999;
+    let units = (cycles / 1_000_000_000 + u64::from(unit) - 1) / u64::from(unit);
+    units.min(255) as u8
+}
+
+pub struct Config {
+    mode: Mode,
+    hz: u32,
+    delay_before_clock_ns: u32,
+    delay_between_bytes_ns: u32,
+}
+impl Config {
+    /// 8 bit transfers at `hz` at most, without extra delays.
+    pub fn new(mode: Mode, hz: u32) -> Config {
+        Config {
+            mode,
+            hz,
+            delay_before_clock_ns: 0,
+            delay_between_bytes_ns: 0,
+        }
+    }
+    /// Setup time of the device between its selection and the first clock edge.
+    pub fn delay_before_clock(mut self, ns: u32) -> Config {
+        self.delay_before_clock_ns = ns;
+        self
+    }
+    pub fn delay_between_bytes(mut self, ns: u32) -> Config {
+        self.delay_between_bytes_ns = ns;
+        self
+    }
+}
+
+pub struct Pins {
+    pub miso: Pin<PA12, Peripheral<A>>,
+    pub mosi: Pin<PA13, Peripheral<A>>,
+    pub spck: Pin<PA14, Peripheral<A>>,
+}
+
+/// A GPIO chip select, active low.
+impl<ID: PinId> ChipSelect for Pin<ID, Output<PushPull>> {
+    fn select(&mut self) {
+        self.set_low();
+    }
+    fn deselect(&mut self) {
+        self.set_high();
+    }
+}
+
+pub struct Spi {
+    regs: &'static mut SerialPeripheralInterface,
+    pins: Pins,
+    mck: u32,
+}
+
+impl Spi {
+    pub fn new(
+        regs: &'static mut SerialPeripheralInterface,
+        pins: Pins,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Spi {
+        pmc.enable_peripheral_clock(PeripheralId::Spi);
+        regs.cr.get_mut().software_reset(true);
+        regs.pdc.stop();
+        {
+            let mut mr = regs.mr.get_mut();
+            mr.set_master(true);
+            mr.set_mode_fault_disabled(true);
+            mr.set_peripheral_select(peripheral_select(0));
+        }
+        regs.cr.get_mut().enable(true);
+        Spi {
+            regs,
+            pins,
+            mck: clocks.peripheral(PeripheralId::Spi),
+        }
+    }
+
+    pub fn release(
+        self,
+        pmc: &mut PowerManagementController,
+    ) -> (&'static mut SerialPeripheralInterface, Pins) {
+        self.regs.cr.get_mut().disable(true);
+        pmc.disable_peripheral_clock(PeripheralId::Spi);
+        (self.regs, self.pins)
+    }
+
+    pub fn configure(&mut self, cs: u8, config: &Config) -> Result<(), SpiError> {
+        if cs > 3 {
+            return Err(SpiError::InvalidChipSelect);
+        }
+        let divider = baud_divider(self.mck, config.hz).ok_or(SpiError::InvalidClock)?;
+        let mut csr = self.regs.csr[usize::from(cs)].get_mut();
+        csr.set_clock_polarity(config.mode.polarity());
+        csr.set_clock_phase(!config.mode.phase());
+        csr.set_bits(0);
+        csr.set_keep_selected(true);
+        csr.set_release_between_transfers(false);
+        csr.set_baud_divider(divider);
+        csr.set_delay_before_clock(delay_cycles(self.mck, config.delay_before_clock_ns, 1));
+        csr.set_delay_between_transfers(delay_cycles(self.mck, config.delay_between_bytes_ns, 32));
+        Ok(())
+    }
+
+    /// Minimum time between the release of a chip select and the assertion of another one.
+    pub fn set_delay_between_selects(&mut self, ns: u32) {
+        let cycles = delay_cycles(self.mck, ns, 1);
+        self.regs.mr.get_mut().set_delay_between_selects(cycles);
+    }
+
+    /// Runs a PDC transfer of `count` items and releases the chip select. The buffers are
+    /// borrowed by the caller for the whole transfer.
+    fn run(&mut self, tx: u32, rx: Option<u32>, count: usize) -> Result<(), SpiError> {
+        unsafe { self.start(tx, rx, count)? };
+        while self.pending(rx.is_some()).is_some() {}
+        self.finish();
+        Ok(())
+    }
+
+    /// Starts a PDC transfer of `count` items, the memory must stay valid until `finish`.
+    unsafe fn start(&mut self, tx: u32, rx: Option<u32>, count: usize) -> Result<(), SpiError> {
+        if count > pdc::MAX_TRANSFER {
+            return Err(SpiError::TooLong);
+        }
+        // nothing stale must be taken for the first received byte.
+        let _ = self.regs.rdr.get();
+        let _ = self.regs.sr.get();
+        if let Some(rx) = rx {
+            self.regs.pdc.start_read(rx, count as u16);
+        }
+        self.regs.pdc.start_write(tx, count as u16);
+        Ok(())
+    }
+
+    /// Interrupt source of the first completion condition of the transfer not met yet.
+    fn pending(&self, read: bool) -> Option<SRegister> {
+        // TXEMPTY is already set before the PDC writes the first byte: it only tells the last
+        // byte was shifted out once the PDC counter reached 0.
+        let sr = self.regs.sr.get();
+        let mut source = SRegister(0);
+        if !sr.end_of_tx() {
+            source.set_end_of_tx(true);
+        } else if !sr.tx_empty() {
+            source.set_tx_empty(true);
+        } else if read && !sr.end_of_rx() {
+            source.set_end_of_rx(true);
+        } else {
+            return None;
+        }
+        Some(source)
+    }
+
+    fn finish(&mut self) {
+        self.regs.pdc.stop();
+        self.regs.cr.get_mut().last_transfer(true);
+    }
+
+    fn select_fixed(&mut self, cs: u8) -> Result<(), SpiError> {
+        if cs > 3 {
+            return Err(SpiError::InvalidChipSelect);
+        }
+        let mut mr = self.regs.mr.get_mut();
+        mr.set_variable_select(false);
+        mr.set_peripheral_select(peripheral_select(cs));
+        Ok(())
+    }
+
+    /// Sends `buffer` to the chip select `cs`, replacing it with the received bytes.
+    pub fn transfer_async(&mut self, cs: u8, buffer: &'static mut [u8]) -> Transfer {
+        self.start_async(cs, buffer, true)
+    }
+
+    /// Sends `bytes` to the chip select `cs`, dropping the received ones.
+    pub fn write_async(&mut self, cs: u8, bytes: &'static mut [u8]) -> Transfer {
+        self.start_async(cs, bytes, false)
+    }
+
+    fn start_async(&mut self, cs: u8, buffer: &'static mut [u8], read: bool) -> Transfer {
+        let address = buffer.as_mut_ptr() as u32;
+        let started = match self.select_fixed(cs) {
+            Ok(()) if buffer.is_empty() => Ok(false),
+            Ok(()) => {
+                let rx = if read { Some(address) } else { None };
+                // the buffer is kept by the transfer until it completes or is dropped.
+                unsafe { self.start(address, rx, buffer.len()) }.map(|()| true)
+            }
+            Err(e) => Err(e),
+        };
+        Transfer {
+            spi: self,
+            buffer: Some(buffer),
+            read,
+            started,
+        }
+    }
+
+    /// Sends the `word`s, each to its own chip select, and receives a byte for each of them.
+    pub fn transfer_words(&mut self, words: &[u32], rx: &mut [u8]) -> Result<(), SpiError> {
+        if words.len() != rx.len() {
+            return Err(SpiError::LengthMismatch);
+        }
+        if words.is_empty() {
+            return Ok(());
+        }
+        self.regs.mr.get_mut().set_variable_select(true);
+        let (tx, count) = (words.as_ptr() as u32, words.len());
+        let result = self.run(tx, Some(rx.as_mut_ptr() as u32), count);
+        self.regs.mr.get_mut().set_variable_select(false);
+        result
+    }
+}
+
+impl bus::Spi for Spi {
+    type Error = SpiError;
+
+    fn transfer(&mut self, cs: u8, buffer: &mut [u8]) -> Result<(), SpiError> {
+        self.select_fixed(cs)?;
+        if !buffer.is_empty() {
+            // each byte is sent before its replacement is received.
+            let address = buffer.as_mut_ptr() as u32;
+            self.run(address, Some(address), buffer.len())?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, cs: u8, bytes: &[u8]) -> Result<(), SpiError> {
+        self.select_fixed(cs)?;
+        if !bytes.is_empty() {
+            self.run(bytes.as_ptr() as u32, None, bytes.len())?;
+            // the overrun from the dropped bytes is cleared with the status.
+            let _ = self.regs.rdr.get();
+            let _ = self.regs.sr.get();
+        }
+        Ok(())
+    }
+}
+
+/// Future of an asynchronous transfer, completing with its result and buffer. Dropping it
+/// stops the transfer.
+pub struct Transfer<'a> {
+    spi: &'a mut Spi,
+    buffer: Option<&'static mut [u8]>,
+    read: bool,
+    /// Whether the PDC was started, or the error preventing it.
+    started: Result<bool, SpiError>,
+}
+impl<'a> Future for Transfer<'a> {
+    type Output = (Result<(), SpiError>, &'static mut [u8]);
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.started == Ok(true) {
+            if let Some(source) = this.spi.pending(this.read) {
+                listen(cx.waker(), source);
+                return Poll::Pending;
+            }
+            this.spi.finish();
+            if !this.read {
+                // the overrun from the dropped bytes is cleared with the status.
+                let _ = this.spi.regs.rdr.get();
+                let _ = this.spi.regs.sr.get();
+            }
+        }
+        let result = this.started.map(|_| ());
+        this.started = Ok(false);
+        let buffer = this
+            .buffer
+            .take()
+            .expect("transfer polled after completion");
+        Poll::Ready((result, buffer))
+    }
+}
+impl<'a> Drop for Transfer<'a> {
+    fn drop(&mut self) {
+        if self.started == Ok(true) {
+            self.spi.finish();
+        }
+    }
+}
+
+/// Registers the task waiting on the transfer before enabling the interrupt `source` waking it:
+/// the flags are levels, an already set one fires right away.
+fn listen(waker: &Waker, source: SRegister) {
+    interrupt::free(|cs| WAKER.borrow(cs).register(waker));
+    unsafe { ::SPI.ier.set(source) };
+    interrupts::enable(PeripheralId::Spi);
+}
+
+/// Wakes the task awaiting a `Transfer`, to be called from the SPI interrupt handler. The
+/// sources are disabled until the task waits again.
+pub fn on_interrupt() {
+    let regs = unsafe { &mut ::SPI };
+    let sources = regs.imr.get();
+    regs.idr.set(sources);
+    interrupt::free(|cs| WAKER.borrow(cs).wake());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dividers() {
+        assert_eq!(Some(30), baud_divider(120_000_000, 4_000_000));
+        // 120MHz / 3 = 40MHz would be too fast.
+        assert_eq!(Some(4), baud_divider(120_000_000, 35_000_000));
+        assert_eq!(Some(255), baud_divider(120_000_000, 100_000));
+        assert_eq!(Some(1), baud_divider(120_000_000, 200_000_000));
+        assert_eq!(None, baud_divider(120_000_000, 0));
+        // 100ns is 12 cycles, or 1 unit of 32 cycles.
+        assert_eq!(12, delay_cycles(120_000_000, 100, 1));
+        assert_eq!(1, delay_cycles(120_000_000, 100, 32));
+        assert_eq!(0, delay_cycles(120_000_000, 0, 32));
+        assert_eq!(255, delay_cycles(120_000_000, 1_000_000, 1));
+    }
+
+    #[test]
+    fn test_word() {
+        assert_eq!(0x000E_00A5, word(0, 0xA5, false));
+        assert_eq!(0x0107_0012, word(3, 0x12, true));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0x100 + 10 * 4, size_of::<SerialPeripheralInterface>());
+    }
+}
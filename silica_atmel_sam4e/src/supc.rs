@@ -0,0 +1,170 @@
+//! Supply Controller
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+
+const KEY_MASK: u32 = 0xFF00_0000;
+const KEY: u32 = 0xA500_0000;
+
+register! {
+    @optout_extract_insert;
+    /// Supply Controller Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        /// Stops the core voltage regulator, entering the backup mode.
+        bool: _, pub stop_voltage_regulator: 2;
+        /// Switches the slow clock to the 32kHz crystal oscillator. This cannot be undone but by
+        /// a VDDIO power-on reset.
+        bool: _, pub select_crystal_oscillator: 3;
+    }
+}
+register_impl_extract_insert!(@keyed CRegister: u32, KEY_MASK, KEY);
+
+register! {
+    @impl_debug;
+    /// Supply Controller Supply Monitor Mode Register
+    #[derive(Copy, Clone)]
+    pub struct SMMRegister(u32) {
+        bool: pub supply_monitor_interrupt_enabled, pub enable_supply_monitor_interrupt: 13;
+        bool: pub supply_monitor_reset_enabled, pub enable_supply_monitor_reset: 12;
+        /// 0 = disabled, 1 = continuous, 2-4 = one sample every 32, 256, 2048 slow clock cycles.
+        u8: pub supply_monitor_sampling, pub set_supply_monitor_sampling: 10, 8;
+        u8: pub supply_monitor_threshold, pub set_supply_monitor_threshold: 3, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    @optout_extract_insert;
+    /// Supply Controller Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        bool: pub oscillator_bypassed, pub bypass_oscillator: 20;
+        bool: pub regulator_on, pub set_regulator_on: 14;
+        bool: pub brownout_detector_disabled, pub disable_brownout_detector: 13;
+        bool: pub brownout_reset_enabled, pub enable_brownout_reset: 12;
+    }
+}
+register_impl_extract_insert!(@keyed MRegister: u32, KEY_MASK, KEY);
+
+#[derive(Debug)]
+pub struct TryIntoDebounceError(());
+
+/// Minimal level duration of a WKUP pin to trigger a wake up, in slow clock cycles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Debounce {
+    Immediate,
+    Cycles3,
+    Cycles32,
+    Cycles512,
+    Cycles4096,
+    Cycles32768,
+}
+impl TryFrom<u32> for Debounce {
+    type Error = TryIntoDebounceError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Debounce::Immediate),
+            1 => Ok(Debounce::Cycles3),
+            2 => Ok(Debounce::Cycles32),
+            3 => Ok(Debounce::Cycles512),
+            4 => Ok(Debounce::Cycles4096),
+            5 => Ok(Debounce::Cycles32768),
+            _ => Err(TryIntoDebounceError(())),
+        }
+    }
+}
+impl From<Debounce> for u32 {
+    fn from(v: Debounce) -> u32 {
+        match v {
+            Debounce::Immediate => 0,
+            Debounce::Cycles3 => 1,
+            Debounce::Cycles32 => 2,
+            Debounce::Cycles512 => 3,
+            Debounce::Cycles4096 => 4,
+            Debounce::Cycles32768 => 5,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Supply Controller Wake-up Mode Register
+    #[derive(Copy, Clone)]
+    pub struct WUMRegister(u32) {
+        u8: pub low_power_debounce, pub set_low_power_debounce: 18, 16;
+        Debounce: pub wakeup_debounce, pub set_wakeup_debounce: 14, 12;
+        bool: pub low_power_debounce_clear_enabled, pub enable_low_power_debounce_clear: 7;
+        bool: pub low_power_debounce1_enabled, pub enable_low_power_debounce1: 6;
+        bool: pub low_power_debounce0_enabled, pub enable_low_power_debounce0: 5;
+        bool: pub rtc_wakeup_enabled, pub enable_rtc_wakeup: 3;
+        bool: pub rtt_wakeup_enabled, pub enable_rtt_wakeup: 2;
+        bool: pub supply_monitor_wakeup_enabled, pub enable_supply_monitor_wakeup: 1;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Supply Controller Wake-up Inputs Register
+    #[derive(Copy, Clone)]
+    pub struct WUIRegister(u32) {
+        /// A set bit makes the wake up input active high.
+        u16: pub wakeup_polarity, pub set_wakeup_polarity: 31, 16;
+        u16: pub wakeup_enabled, pub set_wakeup_enabled: 15, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Supply Controller Status Register
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        /// WKUP inputs that were active at the last wake up.
+        u16: pub wakeup_inputs, _: 31, 16;
+        bool: pub low_power_debounce1_wakeup, _: 14;
+        bool: pub low_power_debounce0_wakeup, _: 13;
+        /// True when the slow clock runs on the 32kHz crystal oscillator.
+        bool: pub crystal_selected, _: 7;
+        bool: pub supply_monitor_output, _: 6;
+        bool: pub supply_monitor_status, _: 5;
+        bool: pub supply_monitor_reset, _: 4;
+        bool: pub brownout_reset, _: 3;
+        bool: pub supply_monitor_wakeup, _: 2;
+        bool: pub wakeup_input_wakeup, _: 1;
+    }
+}
+
+/// Supply Controller
+#[repr(C)]
+pub struct SupplyController {
+    pub cr: RegisterCell<CRegister>,
+    pub smmr: RegisterCell<SMMRegister>,
+    pub mr: RegisterCell<MRegister>,
+    pub wumr: RegisterCell<WUMRegister>,
+    pub wuir: RegisterCell<WUIRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+}
+
+impl SupplyController {
+    /// Switches the slow clock to the 32kHz crystal oscillator, which the RTC and RTT need to
+    /// keep an accurate time, and waits for it.
+    pub fn select_crystal_oscillator(&mut self) {
+        if self.sr.get().crystal_selected() {
+            return;
+        }
+        let mut cr = CRegister(0);
+        cr.select_crystal_oscillator(true);
+        self.cr.set(cr);
+        while !self.sr.get().crystal_selected() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_keyed_write() {
+        let mut cr = super::CRegister(0);
+        cr.stop_voltage_regulator(true);
+        assert_eq!(0xA500_0004, cr.0);
+    }
+}
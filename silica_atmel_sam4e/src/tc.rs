@@ -0,0 +1,766 @@
+//! Timer Counter
+//!
+//! The three TC blocks have three 16-bit channels each. Channel n is channel n % 3 of block
+//! n / 3 and has the peripheral identifier TCn.
+//!
+//! ```ignore
+//! let tc = tc::take().unwrap();
+//! let mut clock = StepClock::new(tc.ch0.chain(tc.ch1, TimerClock::MckDiv32, &clocks, pmc));
+//! clock.schedule(clock.now() + clock.ticks_from_micros(250)).unwrap();
+//! // from both tc0_handler and tc1_handler
+//! if clock.on_interrupt() {
+//!     step();
+//! }
+//! ```
+//!
+//! TIOA and TIOB only reach the pins once those are handed over to the TC (peripheral B).
+
+use clock::Clocks;
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use interrupts;
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    /// Channel Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CCRegister(u32) {
+        /// Resets the counter and starts the clock.
+        bool: _, pub software_trigger: 2;
+        bool: _, pub disable_clock: 1;
+        bool: _, pub enable_clock: 0;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoTimerClockError(());
+
+/// Clock of a channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimerClock {
+    MckDiv2,
+    MckDiv8,
+    MckDiv32,
+    MckDiv128,
+    Slow,
+    Xc0,
+    Xc1,
+    Xc2,
+}
+impl TimerClock {
+    /// Frequency of the internal clocks, None for the external ones.
+    pub fn frequency(self, mck: u32) -> Option<u32> {
+        match self {
+            TimerClock::MckDiv2 => Some(mck / 2),
+            TimerClock::MckDiv8 => Some(mck / 8),
+            TimerClock::MckDiv32 => Some(mck / 32),
+            TimerClock::MckDiv128 => Some(mck / 128),
+            TimerClock::Slow => Some(::clock::SLOW_CLOCK_HZ),
+            _ => None,
+        }
+    }
+}
+impl TryFrom<u32> for TimerClock {
+    type Error = TryIntoTimerClockError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(TimerClock::MckDiv2),
+            1 => Ok(TimerClock::MckDiv8),
+            2 => Ok(TimerClock::MckDiv32),
+            3 => Ok(TimerClock::MckDiv128),
+            4 => Ok(TimerClock::Slow),
+            5 => Ok(TimerClock::Xc0),
+            6 => Ok(TimerClock::Xc1),
+            7 => Ok(TimerClock::Xc2),
+            _ => Err(TryIntoTimerClockError(())),
+        }
+    }
+}
+impl From<TimerClock> for u32 {
+    fn from(v: TimerClock) -> u32 {
+        match v {
+            TimerClock::MckDiv2 => 0,
+            TimerClock::MckDiv8 => 1,
+            TimerClock::MckDiv32 => 2,
+            TimerClock::MckDiv128 => 3,
+            TimerClock::Slow => 4,
+            TimerClock::Xc0 => 5,
+            TimerClock::Xc1 => 6,
+            TimerClock::Xc2 => 7,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoWaveSelError(());
+
+/// Counting sequence in waveform mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaveSel {
+    /// 0 to 0xFFFF.
+    Up,
+    /// 0 to 0xFFFF and back.
+    UpDown,
+    /// 0 to RC.
+    UpToRc,
+    /// 0 to RC and back.
+    UpDownToRc,
+}
+impl TryFrom<u32> for WaveSel {
+    type Error = TryIntoWaveSelError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(WaveSel::Up),
+            1 => Ok(WaveSel::UpDown),
+            2 => Ok(WaveSel::UpToRc),
+            3 => Ok(WaveSel::UpDownToRc),
+            _ => Err(TryIntoWaveSelError(())),
+        }
+    }
+}
+impl From<WaveSel> for u32 {
+    fn from(v: WaveSel) -> u32 {
+        match v {
+            WaveSel::Up => 0,
+            WaveSel::UpDown => 1,
+            WaveSel::UpToRc => 2,
+            WaveSel::UpDownToRc => 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoPinActionError(());
+
+/// Effect of an event on TIOA or TIOB in waveform mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PinAction {
+    None,
+    Set,
+    Clear,
+    Toggle,
+}
+impl TryFrom<u32> for PinAction {
+    type Error = TryIntoPinActionError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(PinAction::None),
+            1 => Ok(PinAction::Set),
+            2 => Ok(PinAction::Clear),
+            3 => Ok(PinAction::Toggle),
+            _ => Err(TryIntoPinActionError(())),
+        }
+    }
+}
+impl From<PinAction> for u32 {
+    fn from(v: PinAction) -> u32 {
+        match v {
+            PinAction::None => 0,
+            PinAction::Set => 1,
+            PinAction::Clear => 2,
+            PinAction::Toggle => 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoEdgeError(());
+
+/// Edge of an input in capture mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    None,
+    Rising,
+    Falling,
+    Both,
+}
+impl TryFrom<u32> for Edge {
+    type Error = TryIntoEdgeError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Edge::None),
+            1 => Ok(Edge::Rising),
+            2 => Ok(Edge::Falling),
+            3 => Ok(Edge::Both),
+            _ => Err(TryIntoEdgeError(())),
+        }
+    }
+}
+impl From<Edge> for u32 {
+    fn from(v: Edge) -> u32 {
+        match v {
+            Edge::None => 0,
+            Edge::Rising => 1,
+            Edge::Falling => 2,
+            Edge::Both => 3,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Channel Mode Register. Bits 6 to 31 have a different meaning in capture and waveform mode.
+    #[derive(Copy, Clone)]
+    pub struct CMRegister(u32) {
+        PinAction: pub tiob_on_software, pub set_tiob_on_software: 31, 30;
+        PinAction: pub tiob_on_external, pub set_tiob_on_external: 29, 28;
+        PinAction: pub tiob_on_rc, pub set_tiob_on_rc: 27, 26;
+        PinAction: pub tiob_on_rb, pub set_tiob_on_rb: 25, 24;
+        PinAction: pub tioa_on_software, pub set_tioa_on_software: 23, 22;
+        PinAction: pub tioa_on_external, pub set_tioa_on_external: 21, 20;
+        PinAction: pub tioa_on_rc, pub set_tioa_on_rc: 19, 18;
+        PinAction: pub tioa_on_ra, pub set_tioa_on_ra: 17, 16;
+        /// Capture mode: TIOA edge loading RB.
+        Edge: pub load_b_on, pub set_load_b_on: 19, 18;
+        /// Capture mode: TIOA edge loading RA.
+        Edge: pub load_a_on, pub set_load_a_on: 17, 16;
+        bool: pub waveform, pub set_waveform: 15;
+        WaveSel: pub wave_select, pub set_wave_select: 14, 13;
+        /// Capture mode: an RC compare resets the counter.
+        bool: pub reset_on_rc, pub set_reset_on_rc: 14;
+        /// Waveform mode: 0 = TIOB (an input then), 1 = XC0, 2 = XC1, 3 = XC2.
+        u8: pub external_event, pub set_external_event: 11, 10;
+        /// Capture mode: the external trigger is TIOA instead of TIOB.
+        bool: pub trigger_on_tioa, pub set_trigger_on_tioa: 10;
+        Edge: pub external_trigger_edge, pub set_external_trigger_edge: 9, 8;
+        /// Waveform mode: an RC compare disables the clock.
+        bool: pub disable_on_rc, pub set_disable_on_rc: 7;
+        /// Waveform mode: an RC compare stops the clock.
+        bool: pub stop_on_rc, pub set_stop_on_rc: 6;
+        bool: pub invert_clock, pub set_invert_clock: 3;
+        TimerClock: pub clock, pub set_clock: 2, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Status Register, also the layout of the Interrupt Enable, Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub tiob, _: 18;
+        bool: pub tioa, _: 17;
+        bool: pub clock_enabled, _: 16;
+        bool: pub external_trigger, pub set_external_trigger: 7;
+        bool: pub loaded_b, pub set_loaded_b: 6;
+        bool: pub loaded_a, pub set_loaded_a: 5;
+        bool: pub compare_c, pub set_compare_c: 4;
+        bool: pub compare_b, pub set_compare_b: 3;
+        bool: pub compare_a, pub set_compare_a: 2;
+        /// RA or RB was loaded before being read.
+        bool: pub load_overrun, pub set_load_overrun: 1;
+        bool: pub counter_overflow, pub set_counter_overflow: 0;
+    }
+}
+
+register! {
+    /// Block Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct BCRegister(u32) {
+        /// Triggers the three channels at once.
+        bool: _, pub sync: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Block Mode Register
+    #[derive(Copy, Clone)]
+    pub struct BMRegister(u32) {
+        /// 0 = TCLK2, 2 = TIOA0, 3 = TIOA1.
+        u8: pub xc2_source, pub set_xc2_source: 5, 4;
+        /// 0 = TCLK1, 2 = TIOA0, 3 = TIOA2.
+        u8: pub xc1_source, pub set_xc1_source: 3, 2;
+        /// 0 = TCLK0, 2 = TIOA1, 3 = TIOA2.
+        u8: pub xc0_source, pub set_xc0_source: 1, 0;
+    }
+}
+
+#[repr(C)]
+pub struct ChannelRegisters {
+    pub ccr: RegisterCell<CCRegister>,
+    pub cmr: RegisterCell<CMRegister>,
+    /// Stepper Motor Mode Register
+    pub smmr: RegisterCell<u32>,
+    /// Register AB, the last captured value
+    pub rab: RoRegisterCell<u32>,
+    /// Counter Value
+    pub cv: RoRegisterCell<u32>,
+    pub ra: RegisterCell<u32>,
+    pub rb: RegisterCell<u32>,
+    pub rc: RegisterCell<u32>,
+    /// Status Register, cleared on read.
+    pub sr: RoRegisterCell<SRegister>,
+    pub ier: RegisterCell<SRegister>,
+    pub idr: RegisterCell<SRegister>,
+    pub imr: RoRegisterCell<SRegister>,
+    /// Extended Mode Register
+    pub emr: RegisterCell<u32>,
+    reserved0: ReservedCell<[u32; 3]>,
+}
+
+#[repr(C)]
+pub struct TimerCounter {
+    pub channels: [ChannelRegisters; 3],
+    pub bcr: RegisterCell<BCRegister>,
+    pub bmr: RegisterCell<BMRegister>,
+    /// QDEC Interrupt Enable Register
+    pub qier: RegisterCell<u32>,
+    /// QDEC Interrupt Disable Register
+    pub qidr: RegisterCell<u32>,
+    /// QDEC Interrupt Mask Register
+    pub qimr: RoRegisterCell<u32>,
+    /// QDEC Interrupt Status Register
+    pub qisr: RoRegisterCell<u32>,
+    /// Fault Mode Register
+    pub fmr: RegisterCell<u32>,
+    reserved0: ReservedCell<[u32; 2]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+}
+
+/// Identifies a channel.
+pub trait ChannelId {
+    /// Index of the block.
+    const BLOCK: usize;
+    /// Index of the channel in its block.
+    const INDEX: usize;
+    const ID: PeripheralId;
+}
+
+/// Channel event raising an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Overflow,
+    LoadOverrun,
+    CompareA,
+    CompareB,
+    CompareC,
+    LoadedA,
+    LoadedB,
+    ExternalTrigger,
+}
+
+pub struct Channel<CH> {
+    _ch: PhantomData<CH>,
+}
+
+impl<CH: ChannelId> Channel<CH> {
+    fn block() -> &'static mut TimerCounter {
+        unsafe {
+            match CH::BLOCK {
+                0 => &mut ::TC0,
+                1 => &mut ::TC1,
+                _ => &mut ::TC2,
+            }
+        }
+    }
+
+    fn regs() -> &'static mut ChannelRegisters {
+        &mut Self::block().channels[CH::INDEX]
+    }
+
+    /// Enables the channel's peripheral clock and sets it in waveform mode, counting `clock`
+    /// along `wave`. The TIO outputs do not change until actions are set.
+    pub fn waveform(
+        &mut self,
+        clock: TimerClock,
+        wave: WaveSel,
+        pmc: &mut PowerManagementController,
+    ) {
+        pmc.enable_peripheral_clock(CH::ID);
+        let regs = Self::regs();
+        regs.ccr.get_mut().disable_clock(true);
+        let mut cmr = regs.cmr.get_mut();
+        *cmr = CMRegister(0);
+        cmr.set_clock(clock);
+        cmr.set_waveform(true);
+        cmr.set_wave_select(wave);
+        // XC0 as external event keeps TIOB an output.
+        cmr.set_external_event(1);
+    }
+
+    /// TIOA actions on an RA and an RC compare.
+    pub fn set_tioa_actions(&mut self, on_ra: PinAction, on_rc: PinAction) {
+        let mut cmr = Self::regs().cmr.get_mut();
+        cmr.set_tioa_on_ra(on_ra);
+        cmr.set_tioa_on_rc(on_rc);
+    }
+
+    /// TIOB actions on an RB and an RC compare.
+    pub fn set_tiob_actions(&mut self, on_rb: PinAction, on_rc: PinAction) {
+        let mut cmr = Self::regs().cmr.get_mut();
+        cmr.set_tiob_on_rb(on_rb);
+        cmr.set_tiob_on_rc(on_rc);
+    }
+
+    /// Enables the channel's peripheral clock and sets it in capture mode: RA and RB are loaded
+    /// on TIOA edges, a `reset_on` TIOA edge restarts the counter.
+    pub fn capture(
+        &mut self,
+        clock: TimerClock,
+        load_a: Edge,
+        load_b: Edge,
+        reset_on: Edge,
+        pmc: &mut PowerManagementController,
+    ) {
+        pmc.enable_peripheral_clock(CH::ID);
+        let regs = Self::regs();
+        regs.ccr.get_mut().disable_clock(true);
+        let mut cmr = regs.cmr.get_mut();
+        *cmr = CMRegister(0);
+        cmr.set_clock(clock);
+        cmr.set_load_a_on(load_a);
+        cmr.set_load_b_on(load_b);
+        cmr.set_trigger_on_tioa(true);
+        cmr.set_external_trigger_edge(reset_on);
+    }
+
+    /// Enables the clock and resets the counter.
+    pub fn start(&mut self) {
+        let mut ccr = Self::regs().ccr.get_mut();
+        ccr.enable_clock(true);
+        ccr.software_trigger(true);
+    }
+
+    pub fn stop(&mut self) {
+        Self::regs().ccr.get_mut().disable_clock(true);
+    }
+
+    pub fn counter(&self) -> u16 {
+        Self::regs().cv.get() as u16
+    }
+
+    pub fn ra(&self) -> u16 {
+        Self::regs().ra.get() as u16
+    }
+    pub fn rb(&self) -> u16 {
+        Self::regs().rb.get() as u16
+    }
+    pub fn rc(&self) -> u16 {
+        Self::regs().rc.get() as u16
+    }
+    /// RA and RB are read only in capture mode.
+    pub fn set_ra(&mut self, v: u16) {
+        Self::regs().ra.set(u32::from(v));
+    }
+    pub fn set_rb(&mut self, v: u16) {
+        Self::regs().rb.set(u32::from(v));
+    }
+    pub fn set_rc(&mut self, v: u16) {
+        Self::regs().rc.set(u32::from(v));
+    }
+
+    /// Reads and clears the status flags.
+    pub fn status(&mut self) -> SRegister {
+        Self::regs().sr.get()
+    }
+
+    fn set_event(r: &mut SRegister, event: Event) {
+        match event {
+            Event::Overflow => r.set_counter_overflow(true),
+            Event::LoadOverrun => r.set_load_overrun(true),
+            Event::CompareA => r.set_compare_a(true),
+            Event::CompareB => r.set_compare_b(true),
+            Event::CompareC => r.set_compare_c(true),
+            Event::LoadedA => r.set_loaded_a(true),
+            Event::LoadedB => r.set_loaded_b(true),
+            Event::ExternalTrigger => r.set_external_trigger(true),
+        }
+    }
+
+    /// Raises the channel's interrupt on `event`, also enabling it in the NVIC.
+    pub fn listen(&mut self, event: Event) {
+        Self::set_event(&mut Self::regs().ier.get_mut(), event);
+        interrupts::enable(CH::ID);
+    }
+
+    pub fn unlisten(&mut self, event: Event) {
+        Self::set_event(&mut Self::regs().idr.get_mut(), event);
+    }
+
+    /// Chains this channel with the next one of its block, counting its overflows, into a 32-bit
+    /// counter clocked by `clock`.
+    pub fn chain<HI>(
+        self,
+        mut high: Channel<HI>,
+        clock: TimerClock,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Chained<CH, HI>
+    where
+        HI: ChannelId,
+        CH: ChainsInto<HI>,
+    {
+        let mut low = self;
+        // TIOA rises when the low counter wraps to 0, clocking the high counter.
+        low.waveform(clock, WaveSel::Up, pmc);
+        low.set_rc(0);
+        low.set_ra(0x8000);
+        low.set_tioa_actions(PinAction::Clear, PinAction::Set);
+
+        {
+            let mut bmr = Self::block().bmr.get_mut();
+            match HI::INDEX {
+                1 => bmr.set_xc1_source(2),
+                _ => bmr.set_xc2_source(3),
+            }
+        }
+        let external = if HI::INDEX == 1 {
+            TimerClock::Xc1
+        } else {
+            TimerClock::Xc2
+        };
+        high.waveform(external, WaveSel::Up, pmc);
+
+        high.start();
+        low.start();
+        Chained {
+            low,
+            high,
+            hz: clock.frequency(clocks.mck()).unwrap_or(0),
+        }
+    }
+}
+
+/// Channels whose TIOA can clock `HI`.
+pub trait ChainsInto<HI> {}
+
+/// Two channels counting as a 32-bit counter.
+pub struct Chained<LO, HI> {
+    low: Channel<LO>,
+    high: Channel<HI>,
+    hz: u32,
+}
+
+impl<LO: ChannelId, HI: ChannelId> Chained<LO, HI> {
+    /// Frequency of the counter, 0 for an external clock.
+    pub fn frequency(&self) -> u32 {
+        self.hz
+    }
+
+    /// The high half is read before and after the low one until both agree. The high counter
+    /// is clocked a few cycles after the low one wraps, a low half at 0 is read again.
+    pub fn now(&self) -> u32 {
+        loop {
+            let high = self.high.counter();
+            let low = self.low.counter();
+            if low != 0 && self.high.counter() == high {
+                return (u32::from(high) << 16) | u32::from(low);
+            }
+        }
+    }
+
+    pub fn release(mut self) -> (Channel<LO>, Channel<HI>) {
+        self.low.stop();
+        self.high.stop();
+        (self.low, self.high)
+    }
+}
+
+/// How to arm a one-shot event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Plan {
+    /// The instant is already (or almost) reached.
+    Late,
+    /// Compare the low counter with this value.
+    Low(u16),
+    /// Wait for the high counter to reach this value first.
+    High(u16),
+}
+
+/// Minimum ticks between now and a scheduled event.
+pub const MIN_LEAD_TICKS: u32 = 4;
+
+/// Instants more than 2^31 ticks ahead are considered past.
+pub fn plan(now: u32, at: u32) -> Plan {
+    let delta = at.wrapping_sub(now);
+    if delta < MIN_LEAD_TICKS || delta >= 0x8000_0000 {
+        Plan::Late
+    } else if now >> 16 == at >> 16 {
+        Plan::Low(at as u16)
+    } else {
+        Plan::High((at >> 16) as u16)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Late;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Idle,
+    WaitHigh(u32),
+    WaitLow(u32),
+}
+
+/// Free running 32-bit clock scheduling one event at a time, e.g. the next step pulse.
+///
+/// Both channels' interrupts must call `on_interrupt`.
+pub struct StepClock<LO, HI> {
+    counter: Chained<LO, HI>,
+    stage: Stage,
+}
+
+impl<LO: ChannelId, HI: ChannelId> StepClock<LO, HI> {
+    pub fn new(counter: Chained<LO, HI>) -> StepClock<LO, HI> {
+        StepClock {
+            counter,
+            stage: Stage::Idle,
+        }
+    }
+
+    pub fn now(&self) -> u32 {
+        self.counter.now()
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.counter.frequency()
+    }
+
+    pub fn ticks_from_micros(&self, micros: u32) -> u32 {
+        (u64::from(micros) * u64::from(self.frequency()) / 1_000_000) as u32
+    }
+
+    fn has_passed(&self, at: u32) -> bool {
+        at.wrapping_sub(self.now()) as i32 <= 0
+    }
+
+    fn arm_low(&mut self, at: u32) -> Result<(), Late> {
+        self.counter.low.set_rb(at as u16);
+        self.counter.low.status();
+        self.counter.low.listen(Event::CompareB);
+        self.stage = Stage::WaitLow(at);
+        if self.has_passed(at) {
+            self.cancel();
+            return Err(Late);
+        }
+        Ok(())
+    }
+
+    /// Raises an event at `at`, replacing the pending one. Returns `Late` if `at` is already
+    /// reached: the caller handles the event right away.
+    pub fn schedule(&mut self, at: u32) -> Result<(), Late> {
+        self.cancel();
+        match plan(self.now(), at) {
+            Plan::Late => Err(Late),
+            Plan::Low(_) => self.arm_low(at),
+            Plan::High(high) => {
+                self.counter.high.set_rb(high);
+                self.counter.high.status();
+                self.counter.high.listen(Event::CompareB);
+                self.stage = Stage::WaitHigh(at);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.counter.low.unlisten(Event::CompareB);
+        self.counter.high.unlisten(Event::CompareB);
+        self.stage = Stage::Idle;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    /// Returns true when the scheduled instant is reached.
+    pub fn on_interrupt(&mut self) -> bool {
+        let low = self.counter.low.status();
+        let high = self.counter.high.status();
+        match self.stage {
+            Stage::WaitLow(_) if low.compare_b() => {
+                self.cancel();
+                true
+            }
+            Stage::WaitHigh(at) if high.compare_b() => {
+                self.counter.high.unlisten(Event::CompareB);
+                self.arm_low(at).is_err()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn release(mut self) -> Chained<LO, HI> {
+        self.cancel();
+        self.counter
+    }
+}
+
+macro_rules! channels {
+    ($($Ch:ident: ($ch:ident, $id:ident, $block:expr, $index:expr)),+) => {
+        $(
+            pub struct $Ch;
+            impl ChannelId for $Ch {
+                const BLOCK: usize = $block;
+                const INDEX: usize = $index;
+                const ID: PeripheralId = PeripheralId::$id;
+            }
+        )+
+
+        pub struct Channels {
+            $(pub $ch: Channel<$Ch>,)+
+        }
+
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+
+        /// Returns the nine channels, only once.
+        pub fn take() -> Option<Channels> {
+            if TAKEN.swap(true, Ordering::SeqCst) {
+                return None;
+            }
+            Some(Channels {
+                $($ch: Channel { _ch: PhantomData },)+
+            })
+        }
+    };
+}
+
+channels!(
+    Ch0: (ch0, Tc0, 0, 0),
+    Ch1: (ch1, Tc1, 0, 1),
+    Ch2: (ch2, Tc2, 0, 2),
+    Ch3: (ch3, Tc3, 1, 0),
+    Ch4: (ch4, Tc4, 1, 1),
+    Ch5: (ch5, Tc5, 1, 2),
+    Ch6: (ch6, Tc6, 2, 0),
+    Ch7: (ch7, Tc7, 2, 1),
+    Ch8: (ch8, Tc8, 2, 2)
+);
+
+impl ChainsInto<Ch1> for Ch0 {}
+impl ChainsInto<Ch2> for Ch1 {}
+impl ChainsInto<Ch4> for Ch3 {}
+impl ChainsInto<Ch5> for Ch4 {}
+impl ChainsInto<Ch7> for Ch6 {}
+impl ChainsInto<Ch8> for Ch7 {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        assert_eq!(Plan::Late, plan(1000, 1000));
+        assert_eq!(Plan::Late, plan(1000, 1002));
+        assert_eq!(Plan::Late, plan(1000, 999));
+        assert_eq!(Plan::Low(2000), plan(1000, 2000));
+        assert_eq!(Plan::High(1), plan(0xFFF0, 0x1_0010));
+        assert_eq!(Plan::High(0), plan(0xFFFF_FFF0, 0x10));
+    }
+
+    #[test]
+    fn test_mode_register() {
+        let mut cmr = CMRegister(0);
+        cmr.set_clock(TimerClock::MckDiv32);
+        cmr.set_waveform(true);
+        cmr.set_wave_select(WaveSel::UpToRc);
+        cmr.set_tioa_on_ra(PinAction::Clear);
+        cmr.set_tioa_on_rc(PinAction::Set);
+        assert_eq!(0x0006_C002, cmr.0);
+        assert_eq!(Some(3_750_000), TimerClock::MckDiv32.frequency(120_000_000));
+    }
+}
@@ -0,0 +1,422 @@
+//! Two-wire Interface
+//!
+//! `Twi` is an I2C master implementing `silica::bus::I2c`:
+//!
+//! ```ignore
+//! let mut twi = Twi::new(Twi0, pins, 400_000, &clocks, unsafe { &mut PMC })?;
+//! twi.read_registers(Address::Seven(0x20), 0x12, &mut inputs)?;
+//! ```
+//!
+//! The controller sends up to 3 bytes after the address before a repeated start: this bounds
+//! the `bytes` of `write_read`, one of them being taken by a 10-bit address.
+//!
+//! The bytes are moved by polling rather than through the PDC: the transfers are a few register
+//! bytes long, and a PDC read still needs the CPU to request the STOP before its last byte.
+//!
+//! TWI1 shares its pins with the JTAG TDI and TDO, which must be released in the matrix first.
+use clock::Clocks;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use pdc::PeripheralDmaController;
+use pio::pioa::{PA3, PA4};
+use pio::piob::{PB4, PB5};
+use pio::{Peripheral, Pin, PinId, A};
+use pmc::{PeripheralId, PowerManagementController};
+use silica::bus::{self, Address, I2cError};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    /// TWI Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        bool: _, pub software_reset: 7;
+        /// Addresses the device without data, its acknowledge is the only information.
+        bool: _, pub quick: 6;
+        bool: _, pub slave_disable: 5;
+        bool: _, pub slave_enable: 4;
+        bool: _, pub master_disable: 3;
+        bool: _, pub master_enable: 2;
+        bool: _, pub stop: 1;
+        bool: _, pub start: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// TWI Master Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MMRegister(u32) {
+        u8: pub device_address, pub set_device_address: 22, 16;
+        bool: pub read, pub set_read: 12;
+        /// Bytes of the internal address, sent after the device address.
+        u8: pub internal_address_size, pub set_internal_address_size: 9, 8;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// TWI Clock Waveform Generator Register
+    #[derive(Copy, Clone)]
+    pub struct CWGRegister(u32) {
+        u8: pub clock_divider, pub set_clock_divider: 18, 16;
+        /// The high period is (divider << clock_divider) + 4 cycles.
+        u8: pub high_divider, pub set_high_divider: 15, 8;
+        u8: pub low_divider, pub set_low_divider: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// TWI Status Register, also the layout of the Interrupt Enable, Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        bool: pub tx_buffer_empty, pub set_tx_buffer_empty: 15;
+        bool: pub rx_buffer_full, pub set_rx_buffer_full: 14;
+        bool: pub end_of_tx, pub set_end_of_tx: 13;
+        bool: pub end_of_rx, pub set_end_of_rx: 12;
+        bool: pub end_of_slave_access, pub set_end_of_slave_access: 11;
+        bool: pub clock_wait_state, pub set_clock_wait_state: 10;
+        bool: pub arbitration_lost, pub set_arbitration_lost: 9;
+        bool: pub nack, pub set_nack: 8;
+        bool: pub overrun, pub set_overrun: 6;
+        bool: pub general_call, pub set_general_call: 5;
+        bool: pub slave_access, pub set_slave_access: 4;
+        bool: pub slave_read, pub set_slave_read: 3;
+        bool: pub tx_ready, pub set_tx_ready: 2;
+        bool: pub rx_ready, pub set_rx_ready: 1;
+        bool: pub tx_complete, pub set_tx_complete: 0;
+    }
+}
+
+/// Two-wire Interface
+#[repr(C)]
+pub struct TwoWireInterface {
+    pub cr: RegisterCell<CRegister>,
+    pub mmr: RegisterCell<MMRegister>,
+    /// Slave Mode Register
+    pub smr: RegisterCell<u32>,
+    /// Internal Address Register
+    pub iadr: RegisterCell<u32>,
+    pub cwgr: RegisterCell<CWGRegister>,
+    _reserved0: ReservedCell<[u32; 3]>,
+    pub sr: RoRegisterCell<SRegister>,
+    pub ier: RegisterCell<SRegister>,
+    pub idr: RegisterCell<SRegister>,
+    pub imr: RoRegisterCell<SRegister>,
+    /// Receive Holding Register
+    pub rhr: RoRegisterCell<u32>,
+    /// Transmit Holding Register
+    pub thr: RegisterCell<u32>,
+    _reserved1: ReservedCell<[u32; 50]>,
+    pub pdc: PeripheralDmaController,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TwiError {
+    Bus(I2cError),
+    /// The clock is 0 or cannot be that slow.
+    InvalidClock,
+    /// More bytes than the internal address can carry before a repeated start.
+    TooLong,
+}
+impl From<I2cError> for TwiError {
+    fn from(e: I2cError) -> TwiError {
+        TwiError::Bus(e)
+    }
+}
+
+/// Polls of the status before a transfer is considered stuck.
+const TIMEOUT_POLLS: u32 = 1_000_000;
+
+/// Clock divider and low and high dividers giving a clock not above `hz`.
+pub fn clock_dividers(mck: u32, hz: u32) -> Option<(u8, u8)> {
+    if hz == 0 {
+        return None;
+    }
+    let half_period = (u64::from(mck) + 2 * u64::from(hz) - 1) / (2 * u64::from(hz));
+    let cycles = half_period.saturating_sub(4);
+    (0..8)
+        .map(|shift| (shift, (cycles + (1 << shift) - 1) >> shift))
+        .find(|&(_, divider)| divider <= 255)
+        .map(|(shift, divider)| (shift as u8, divider as u8))
+}
+
+/// Device address, internal address and its size for `address` followed by `internal`.
+fn address_fields(address: Address, internal: &[u8]) -> Result<(u8, u32, u8), TwiError> {
+    let (device, mut iadr, mut size) = match address {
+        Address::Seven(a) => (a & 0x7F, 0, 0),
+        // 0b11110 and the 2 upper bits, the lower byte goes first in the internal address.
+        Address::Ten(a) => (0x78 | ((a >> 8) & 0x3) as u8, u32::from(a as u8), 1),
+    };
+    if internal.len() + usize::from(size) > 3 {
+        return Err(TwiError::TooLong);
+    }
+    for &b in internal {
+        iadr = iadr << 8 | u32::from(b);
+        size += 1;
+    }
+    Ok((device, iadr, size))
+}
+
+pub trait Instance {
+    const ID: PeripheralId;
+    type Data: PinId;
+    type Clock: PinId;
+
+    fn regs() -> &'static mut TwoWireInterface;
+}
+
+pub struct Twi0;
+pub struct Twi1;
+
+impl Instance for Twi0 {
+    const ID: PeripheralId = PeripheralId::Twi0;
+    type Data = PA3;
+    type Clock = PA4;
+    fn regs() -> &'static mut TwoWireInterface {
+        unsafe { &mut ::TWI0 }
+    }
+}
+impl Instance for Twi1 {
+    const ID: PeripheralId = PeripheralId::Twi1;
+    type Data = PB4;
+    type Clock = PB5;
+    fn regs() -> &'static mut TwoWireInterface {
+        unsafe { &mut ::TWI1 }
+    }
+}
+
+pub struct Pins<I: Instance> {
+    pub twd: Pin<I::Data, Peripheral<A>>,
+    pub twck: Pin<I::Clock, Peripheral<A>>,
+}
+
+pub struct Twi<I: Instance> {
+    instance: I,
+    /// Taken while the bus is recovered.
+    pins: Option<Pins<I>>,
+    dividers: (u8, u8),
+    /// Status polls lasting about half a clock period, for the recovery.
+    half_period_polls: u32,
+}
+
+impl<I: Instance> Twi<I> {
+    pub fn new(
+        instance: I,
+        pins: Pins<I>,
+        hz: u32,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Twi<I>, TwiError> {
+        let mck = clocks.peripheral(I::ID);
+        let dividers = clock_dividers(mck, hz).ok_or(TwiError::InvalidClock)?;
+        pmc.enable_peripheral_clock(I::ID);
+        let mut twi = Twi {
+            instance,
+            pins: Some(pins),
+            dividers,
+            half_period_polls: mck / hz / 8,
+        };
+        twi.reset();
+        Ok(twi)
+    }
+
+    pub fn release(self, pmc: &mut PowerManagementController) -> (I, Pins<I>) {
+        I::regs().cr.get_mut().master_disable(true);
+        pmc.disable_peripheral_clock(I::ID);
+        let pins = self.pins.expect("pins taken during a recovery");
+        (self.instance, pins)
+    }
+
+    fn reset(&mut self) {
+        let regs = I::regs();
+        regs.cr.get_mut().software_reset(true);
+        let _ = regs.rhr.get();
+        {
+            let mut cwgr = regs.cwgr.get_mut();
+            cwgr.set_clock_divider(self.dividers.0);
+            cwgr.set_low_divider(self.dividers.1);
+            cwgr.set_high_divider(self.dividers.1);
+        }
+        {
+            let mut cr = regs.cr.get_mut();
+            cr.slave_disable(true);
+            cr.master_enable(true);
+        }
+    }
+
+    /// Waits for `done`, `sent` telling a refusal of data from one of the address.
+    fn wait<F>(&mut self, done: F, sent: bool) -> Result<(), TwiError>
+    where
+        F: Fn(&SRegister) -> bool,
+    {
+        for _ in 0..TIMEOUT_POLLS {
+            let sr = I::regs().sr.get();
+            if sr.arbitration_lost() {
+                return Err(I2cError::ArbitrationLost.into());
+            }
+            if sr.nack() {
+                let e = if sent {
+                    I2cError::DataNack
+                } else {
+                    I2cError::AddressNack
+                };
+                return Err(e.into());
+            }
+            if done(&sr) {
+                return Ok(());
+            }
+        }
+        Err(I2cError::BusStuck.into())
+    }
+
+    fn set_mode(&mut self, address: Address, internal: &[u8], read: bool) -> Result<(), TwiError> {
+        let (device, iadr, size) = address_fields(address, internal)?;
+        let regs = I::regs();
+        {
+            let mut mmr = regs.mmr.get_mut();
+            mmr.set_device_address(device);
+            mmr.set_internal_address_size(size);
+            mmr.set_read(read);
+        }
+        regs.iadr.set(iadr);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), TwiError> {
+        let regs = I::regs();
+        if bytes.is_empty() {
+            regs.cr.get_mut().quick(true);
+        } else {
+            for (i, &b) in bytes.iter().enumerate() {
+                regs.thr.set(u32::from(b));
+                self.wait(SRegister::tx_ready, i > 0)?;
+            }
+            regs.cr.get_mut().stop(true);
+        }
+        self.wait(SRegister::tx_complete, !bytes.is_empty())
+    }
+
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), TwiError> {
+        let regs = I::regs();
+        let len = buffer.len();
+        {
+            let mut cr = regs.cr.get_mut();
+            cr.start(true);
+            // the stop of a single byte read goes with its start.
+            cr.stop(len == 1);
+        }
+        for (i, b) in buffer.iter_mut().enumerate() {
+            // the stop is requested before receiving the last byte.
+            if i + 1 == len && len > 1 {
+                regs.cr.get_mut().stop(true);
+            }
+            self.wait(SRegister::rx_ready, false)?;
+            *b = I::regs().rhr.get() as u8;
+        }
+        self.wait(SRegister::tx_complete, false)
+    }
+
+    /// Clocks out a device holding the data line low, then sends a stop.
+    pub fn recover(&mut self) -> Result<(), TwiError> {
+        let pins = self.pins.take().expect("pins taken during a recovery");
+        let mut sda = pins.twd.into_open_drain_output(true);
+        let mut scl = pins.twck.into_open_drain_output(true);
+        sda.set_high();
+        scl.set_high();
+        self.half_period();
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low();
+            self.half_period();
+            scl.set_high();
+            self.half_period();
+        }
+        sda.set_low();
+        self.half_period();
+        sda.set_high();
+        self.half_period();
+        let released = sda.is_high() && scl.is_high();
+        self.pins = Some(Pins {
+            twd: sda.into_peripheral(),
+            twck: scl.into_peripheral(),
+        });
+        self.reset();
+        if released {
+            Ok(())
+        } else {
+            Err(I2cError::BusStuck.into())
+        }
+    }
+
+    fn half_period(&mut self) {
+        for _ in 0..self.half_period_polls {
+            // the register read keeps the loop from being optimised out.
+            let _ = I::regs().sr.get();
+        }
+    }
+}
+
+impl<I: Instance> bus::I2c for Twi<I> {
+    type Error = TwiError;
+
+    fn write(&mut self, address: Address, bytes: &[u8]) -> Result<(), TwiError> {
+        self.set_mode(address, &[], false)?;
+        self.write_bytes(bytes)
+    }
+
+    fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<(), TwiError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.set_mode(address, &[], true)?;
+        self.read_bytes(buffer)
+    }
+
+    fn write_read(
+        &mut self,
+        address: Address,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), TwiError> {
+        if buffer.is_empty() {
+            return self.write(address, bytes);
+        }
+        // the bytes go as the internal address, followed by a repeated start.
+        self.set_mode(address, bytes, true)?;
+        self.read_bytes(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_dividers() {
+        // (149 << 2) + 4 = 600 cycles per half period at 120MHz: 100kHz.
+        assert_eq!(Some((2, 149)), clock_dividers(120_000_000, 100_000));
+        assert_eq!(Some((0, 146)), clock_dividers(120_000_000, 400_000));
+        assert_eq!(None, clock_dividers(120_000_000, 1_000));
+        assert_eq!(None, clock_dividers(120_000_000, 0));
+    }
+
+    #[test]
+    fn test_address_fields() {
+        assert_eq!(Ok((0x50, 0, 0)), address_fields(Address::Seven(0x50), &[]));
+        assert_eq!(
+            Ok((0x50, 0x0123, 2)),
+            address_fields(Address::Seven(0x50), &[0x01, 0x23])
+        );
+        assert_eq!(
+            Ok((0x7A, 0xBC_12, 2)),
+            address_fields(Address::Ten(0x2BC), &[0x12])
+        );
+        assert_eq!(
+            Err(TwiError::TooLong),
+            address_fields(Address::Ten(0x2BC), &[1, 2, 3])
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Universal Asynchronous Receiver Transmitter
+use core::convert::{Into, TryFrom, TryInto};
+use core::fmt;
+use pdc::PeripheralDmaController;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+
+register! {
+    /// Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        /// Clears the overrun, framing and parity error flags.
+        bool: _, pub reset_status: 8;
+        bool: _, pub disable_transmitter: 7;
+        bool: _, pub enable_transmitter: 6;
+        bool: _, pub disable_receiver: 5;
+        bool: _, pub enable_receiver: 4;
+        bool: _, pub reset_transmitter: 3;
+        bool: _, pub reset_receiver: 2;
+    }
+}
+
+#[derive(Debug)]
+pub struct TryIntoParityError(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    Even,
+    Odd,
+    /// Forced to 0.
+    Space,
+    /// Forced to 1.
+    Mark,
+    None,
+}
+impl TryFrom<u32> for Parity {
+    type Error = TryIntoParityError;
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Parity::Even),
+            1 => Ok(Parity::Odd),
+            2 => Ok(Parity::Space),
+            3 => Ok(Parity::Mark),
+            4 => Ok(Parity::None),
+            _ => Err(TryIntoParityError(())),
+        }
+    }
+}
+impl From<Parity> for u32 {
+    fn from(v: Parity) -> u32 {
+        match v {
+            Parity::Even => 0,
+            Parity::Odd => 1,
+            Parity::Space => 2,
+            Parity::Mark => 3,
+            Parity::None => 4,
+        }
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// 0 = normal, 1 = automatic echo, 2 = local loopback, 3 = remote loopback.
+        u8: pub channel_mode, pub set_channel_mode: 15, 14;
+        Parity: pub parity, pub set_parity: 11, 9;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Status Register, also the layout of the Interrupt Enable, Disable and Mask Registers.
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        /// The receive PDC buffers are both full.
+        bool: pub rx_buffer_full, pub set_rx_buffer_full: 12;
+        /// The transmit PDC buffers are both empty.
+        bool: pub tx_buffer_empty, pub set_tx_buffer_empty: 11;
+        /// The shift register and THR are empty.
+        bool: pub tx_empty, pub set_tx_empty: 9;
+        bool: pub parity_error, pub set_parity_error: 7;
+        bool: pub framing_error, pub set_framing_error: 6;
+        bool: pub overrun, pub set_overrun: 5;
+        /// The transmit PDC counter reached 0.
+        bool: pub end_of_tx, pub set_end_of_tx: 4;
+        /// The receive PDC counter reached 0.
+        bool: pub end_of_rx, pub set_end_of_rx: 3;
+        bool: pub tx_ready, pub set_tx_ready: 1;
+        bool: pub rx_ready, pub set_rx_ready: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Baud Rate Generator Register
+    #[derive(Copy, Clone)]
+    pub struct BRGRegister(u32) {
+        /// The baud rate is MCK / (16 * divider), 0 disables the clock.
+        u16: pub clock_divider, pub set_clock_divider: 15, 0;
+    }
+}
+
+#[repr(C)]
+pub struct Uart {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    /// Interrupt Enable Register
+    pub ier: RegisterCell<SRegister>,
+    /// Interrupt Disable Register
+    pub idr: RegisterCell<SRegister>,
+    /// Interrupt Mask Register
+    pub imr: RoRegisterCell<SRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+    /// Receive Holding Register
+    pub rhr: RoRegisterCell<u32>,
+    /// Transmit Holding Register
+    pub thr: RegisterCell<u32>,
+    pub brgr: RegisterCell<BRGRegister>,
+    reserved0: ReservedCell<[u32; 55]>,
+    pub pdc: PeripheralDmaController,
+}
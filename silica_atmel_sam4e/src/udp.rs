@@ -0,0 +1,410 @@
+//! USB Device Port
+//!
+//! `Udp` is the `silica::usb::Bus` of the full speed device controller. Its DM and DP lines are
+//! dedicated pins, attached by enabling the pull up:
+//!
+//! ```ignore
+//! let udp = Udp::new(unsafe { &mut UDP }, &clocks, unsafe { &mut PMC })?;
+//! let mut usb = UsbDevice::new(udp, CdcAcm::new(&mut rx, &mut tx), &descriptors);
+//! ```
+//!
+//! The control endpoint and the bus events raise the UDP interrupt, in which `UsbDevice::poll`
+//! may be called. The other endpoints are polled.
+use clock::Clocks;
+use core::convert::{Into, TryInto};
+use core::fmt;
+use pmc::{PeripheralId, PowerManagementController};
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use silica::usb::{Bus, EndpointType, Event, SetupPacket};
+
+pub const ENDPOINTS: usize = 8;
+/// Endpoints with two banks, read alternately.
+const DUAL_BANK: [bool; ENDPOINTS] = [false, true, true, false, true, true, true, true];
+const USB_CLOCK_HZ: u32 = 48_000_000;
+
+register! {
+    @impl_debug;
+    /// UDP Frame Number Register
+    #[derive(Copy, Clone)]
+    pub struct FRMNUMRegister(u32) {
+        bool: pub frame_ok, _: 17;
+        bool: pub frame_error, _: 16;
+        u16: pub frame_number, _: 10, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// UDP Global State Register
+    #[derive(Copy, Clone)]
+    pub struct GLBSTATRegister(u32) {
+        bool: pub configured, pub set_configured: 1;
+        bool: pub address_enabled, pub set_address_enabled: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// UDP Function Address Register
+    #[derive(Copy, Clone)]
+    pub struct FADDRRegister(u32) {
+        bool: pub function_enable, pub set_function_enable: 8;
+        u8: pub function_address, pub set_function_address: 6, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// UDP Interrupt Status Register, also the layout of the Interrupt Enable, Disable, Mask and
+    /// Clear Registers. The end of bus reset cannot be masked.
+    #[derive(Copy, Clone)]
+    pub struct IRegister(u32) {
+        bool: pub wakeup, pub set_wakeup: 13;
+        bool: pub end_of_bus_reset, pub set_end_of_bus_reset: 12;
+        bool: pub start_of_frame, pub set_start_of_frame: 11;
+        bool: pub resume, pub set_resume: 9;
+        bool: pub suspend, pub set_suspend: 8;
+        /// One bit per endpoint.
+        u8: pub endpoints, pub set_endpoints: 7, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// UDP Endpoint Control and Status Register
+    ///
+    /// The flags cleared by the software are left as is by writing 1 to them.
+    #[derive(Copy, Clone)]
+    pub struct CSRegister(u32) {
+        u16: pub rx_byte_count, _: 26, 16;
+        bool: pub endpoint_enabled, pub set_endpoint_enabled: 15;
+        bool: pub data_toggle, _: 11;
+        /// Control, then isochronous, bulk and interrupt OUT, then IN from 5.
+        u8: pub endpoint_type, pub set_endpoint_type: 10, 8;
+        /// The data stage of the control transfer is IN.
+        bool: pub direction, pub set_direction: 7;
+        bool: pub rx_data_bank1, pub set_rx_data_bank1: 6;
+        bool: pub force_stall, pub set_force_stall: 5;
+        bool: pub tx_packet_ready, pub set_tx_packet_ready: 4;
+        bool: pub stall_sent, pub set_stall_sent: 3;
+        bool: pub rx_setup, pub set_rx_setup: 2;
+        bool: pub rx_data_bank0, pub set_rx_data_bank0: 1;
+        bool: pub tx_complete, pub set_tx_complete: 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// UDP Transceiver Control Register
+    #[derive(Copy, Clone)]
+    pub struct TXVCRegister(u32) {
+        /// Enables the pull up of DP, attaching the device.
+        bool: pub pull_up, pub set_pull_up: 9;
+        bool: pub transceiver_disable, pub set_transceiver_disable: 8;
+    }
+}
+
+#[repr(C)]
+pub struct UsbDevicePort {
+    pub frm_num: RoRegisterCell<FRMNUMRegister>,
+    pub glb_stat: RegisterCell<GLBSTATRegister>,
+    pub faddr: RegisterCell<FADDRRegister>,
+    _reserved0: ReservedCell<u32>,
+    pub ier: RegisterCell<IRegister>,
+    pub idr: RegisterCell<IRegister>,
+    pub imr: RoRegisterCell<IRegister>,
+    pub isr: RoRegisterCell<IRegister>,
+    pub icr: RegisterCell<IRegister>,
+    _reserved1: ReservedCell<u32>,
+    /// Reset Endpoint Register, one bit per endpoint held in reset.
+    pub rst_ep: RegisterCell<u32>,
+    _reserved2: ReservedCell<u32>,
+    pub csr: [RegisterCell<CSRegister>; ENDPOINTS],
+    /// FIFO Data Registers
+    pub fdr: [RegisterCell<u32>; ENDPOINTS],
+    _reserved3: ReservedCell<u32>,
+    pub txvc: RegisterCell<TXVCRegister>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UdpError {
+    /// The USB clock is PLLA divided by up to 16, which must give 48MHz.
+    InvalidClock,
+}
+
+/// Divider of PLLA giving the 48MHz USB clock.
+pub fn usb_divider(plla: u32) -> Option<u8> {
+    match plla / USB_CLOCK_HZ {
+        d @ 1..=16 if plla % USB_CLOCK_HZ == 0 => Some(d as u8),
+        _ => None,
+    }
+}
+
+fn endpoint_type(kind: EndpointType, is_in: bool) -> u8 {
+    let kind = match kind {
+        EndpointType::Control => return 0,
+        EndpointType::Isochronous => 1,
+        EndpointType::Bulk => 2,
+        EndpointType::Interrupt => 3,
+    };
+    if is_in {
+        kind + 4
+    } else {
+        kind
+    }
+}
+
+pub struct Udp {
+    regs: &'static mut UsbDevicePort,
+    /// The bank to read first when both hold a packet.
+    next_bank1: [bool; ENDPOINTS],
+}
+
+impl Udp {
+    /// Starts the USB clock and attaches the device.
+    pub fn new(
+        regs: &'static mut UsbDevicePort,
+        clocks: &Clocks,
+        pmc: &mut PowerManagementController,
+    ) -> Result<Udp, UdpError> {
+        let divider = usb_divider(clocks.plla()).ok_or(UdpError::InvalidClock)?;
+        pmc.enable_peripheral_clock(PeripheralId::Udp);
+        pmc.enable_usb_clock(divider);
+
+        regs.idr.set(IRegister(!0));
+        regs.icr.set(IRegister(!0));
+        regs.glb_stat.set(GLBSTATRegister(0));
+        regs.faddr.set(FADDRRegister(0));
+        let mut ier = IRegister(0);
+        ier.set_endpoints(1);
+        ier.set_suspend(true);
+        ier.set_resume(true);
+        ier.set_wakeup(true);
+        regs.ier.set(ier);
+
+        let mut udp = Udp {
+            regs,
+            next_bank1: [false; ENDPOINTS],
+        };
+        udp.attach();
+        Ok(udp)
+    }
+
+    pub fn release(self, pmc: &mut PowerManagementController) -> &'static mut UsbDevicePort {
+        let mut txvc = TXVCRegister(0);
+        txvc.set_transceiver_disable(true);
+        self.regs.txvc.set(txvc);
+        pmc.disable_usb_clock();
+        pmc.disable_peripheral_clock(PeripheralId::Udp);
+        self.regs
+    }
+
+    /// Enables the pull up: the host sees the device and resets the bus.
+    pub fn attach(&mut self) {
+        let mut txvc = TXVCRegister(0);
+        txvc.set_pull_up(true);
+        self.regs.txvc.set(txvc);
+    }
+
+    pub fn detach(&mut self) {
+        self.regs.txvc.set(TXVCRegister(0));
+    }
+
+    fn set_transceiver(&mut self, enabled: bool) {
+        self.regs.txvc.get_mut().set_transceiver_disable(!enabled);
+    }
+
+    /// Updates the control and status register of `endpoint`, without clearing the flags set
+    /// meanwhile.
+    fn update_csr<F: FnOnce(&mut CSRegister)>(&mut self, endpoint: usize, f: F) {
+        let mut csr = self.regs.csr[endpoint].get();
+        csr.set_tx_complete(true);
+        csr.set_rx_data_bank0(true);
+        csr.set_rx_setup(true);
+        csr.set_stall_sent(true);
+        csr.set_rx_data_bank1(true);
+        f(&mut csr);
+        self.regs.csr[endpoint].set(csr);
+        // the register is written in the USB clock domain: a few cycles later.
+        for _ in 0..20 {
+            let _ = self.regs.csr[endpoint].get();
+        }
+    }
+
+    fn reset_endpoint(&mut self, endpoint: usize) {
+        self.regs.rst_ep.set(1 << endpoint);
+        self.regs.rst_ep.set(0);
+        self.next_bank1[endpoint] = false;
+    }
+}
+
+impl Bus for Udp {
+    fn poll(&mut self) -> Option<Event> {
+        let isr = self.regs.isr.get();
+        if isr.end_of_bus_reset() {
+            let mut icr = IRegister(0);
+            icr.set_end_of_bus_reset(true);
+            self.regs.icr.set(icr);
+            for ep in 0..ENDPOINTS {
+                self.reset_endpoint(ep);
+                self.update_csr(ep, |csr| csr.set_endpoint_enabled(false));
+            }
+            self.update_csr(0, |csr| {
+                csr.set_endpoint_type(0);
+                csr.set_endpoint_enabled(true);
+            });
+            self.set_address(0);
+            self.set_configured(false);
+            return Some(Event::Reset);
+        }
+        if isr.suspend() {
+            let mut icr = IRegister(0);
+            icr.set_suspend(true);
+            self.regs.icr.set(icr);
+            // the resume is still detected, asynchronously.
+            self.set_transceiver(false);
+            return Some(Event::Suspend);
+        }
+        if isr.resume() || isr.wakeup() {
+            let mut icr = IRegister(0);
+            icr.set_resume(true);
+            icr.set_wakeup(true);
+            self.regs.icr.set(icr);
+            self.set_transceiver(true);
+            return Some(Event::Resume);
+        }
+
+        let csr = self.regs.csr[0].get();
+        if csr.rx_setup() {
+            let mut packet = [0; 8];
+            for b in packet.iter_mut() {
+                *b = self.regs.fdr[0].get() as u8;
+            }
+            let setup = SetupPacket::parse(packet);
+            // the direction is set before releasing the FIFO.
+            self.update_csr(0, |csr| csr.set_direction(setup.is_in()));
+            self.update_csr(0, |csr| {
+                csr.set_rx_setup(false);
+                csr.set_force_stall(false);
+            });
+            return Some(Event::Setup(setup));
+        }
+        if csr.stall_sent() {
+            self.update_csr(0, |csr| csr.set_stall_sent(false));
+        }
+        if csr.rx_data_bank0() {
+            return Some(Event::ControlOut);
+        }
+        if csr.tx_complete() {
+            self.update_csr(0, |csr| csr.set_tx_complete(false));
+            return Some(Event::ControlInComplete);
+        }
+        None
+    }
+
+    fn set_address(&mut self, address: u8) {
+        let mut faddr = FADDRRegister(0);
+        faddr.set_function_address(address);
+        faddr.set_function_enable(true);
+        self.regs.faddr.set(faddr);
+        self.regs
+            .glb_stat
+            .get_mut()
+            .set_address_enabled(address != 0);
+    }
+
+    fn set_configured(&mut self, configured: bool) {
+        self.regs.glb_stat.get_mut().set_configured(configured);
+    }
+
+    fn configure_endpoint(&mut self, endpoint: u8, kind: EndpointType, is_in: bool) {
+        let ep = usize::from(endpoint);
+        self.reset_endpoint(ep);
+        self.update_csr(ep, |csr| {
+            csr.set_endpoint_type(endpoint_type(kind, is_in));
+            csr.set_endpoint_enabled(true);
+        });
+    }
+
+    fn read(&mut self, endpoint: u8, buffer: &mut [u8]) -> Option<usize> {
+        let ep = usize::from(endpoint);
+        let csr = self.regs.csr[ep].get();
+        let bank1 = match (csr.rx_data_bank0(), csr.rx_data_bank1()) {
+            (false, false) => return None,
+            (true, false) => false,
+            (false, true) => true,
+            (true, true) => self.next_bank1[ep],
+        };
+        let count = usize::from(csr.rx_byte_count());
+        let len = count.min(buffer.len());
+        for b in buffer[..len].iter_mut() {
+            *b = self.regs.fdr[ep].get() as u8;
+        }
+        // what does not fit is dropped.
+        for _ in len..count {
+            let _ = self.regs.fdr[ep].get();
+        }
+        self.update_csr(ep, |csr| {
+            if bank1 {
+                csr.set_rx_data_bank1(false)
+            } else {
+                csr.set_rx_data_bank0(false)
+            }
+        });
+        if DUAL_BANK[ep] {
+            self.next_bank1[ep] = !bank1;
+        }
+        Some(len)
+    }
+
+    fn write(&mut self, endpoint: u8, data: &[u8]) -> bool {
+        let ep = usize::from(endpoint);
+        let csr = self.regs.csr[ep].get();
+        if csr.tx_packet_ready() {
+            return false;
+        }
+        // the completion of the control endpoint is an event reported by `poll`.
+        if ep != 0 && csr.tx_complete() {
+            self.update_csr(ep, |csr| csr.set_tx_complete(false));
+        }
+        for &b in data {
+            self.regs.fdr[ep].set(u32::from(b));
+        }
+        self.update_csr(ep, |csr| csr.set_tx_packet_ready(true));
+        true
+    }
+
+    fn set_stalled(&mut self, endpoint: u8, stalled: bool) {
+        let ep = usize::from(endpoint);
+        self.update_csr(ep, |csr| csr.set_force_stall(stalled));
+        if !stalled && ep != 0 {
+            // restarts the data toggle.
+            self.reset_endpoint(ep);
+        }
+    }
+
+    fn is_stalled(&self, endpoint: u8) -> bool {
+        self.regs.csr[usize::from(endpoint)].get().force_stall()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_divider() {
+        assert_eq!(Some(5), usb_divider(240_000_000));
+        assert_eq!(Some(2), usb_divider(96_000_000));
+        assert_eq!(None, usb_divider(120_000_000));
+        assert_eq!(None, usb_divider(960_000_000));
+        assert_eq!(6, endpoint_type(EndpointType::Bulk, true));
+        assert_eq!(2, endpoint_type(EndpointType::Bulk, false));
+    }
+
+    #[test]
+    fn test_register_block_layout() {
+        use core::mem::size_of;
+        assert_eq!(0x78, size_of::<UsbDevicePort>());
+    }
+}
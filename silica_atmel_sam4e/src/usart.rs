@@ -0,0 +1,63 @@
+//! Universal Synchronous Asynchronous Receiver Transmitter
+//!
+//! Only the asynchronous mode is supported. It is then programmed like the UART: the control,
+//! status, interrupt, holding and baud rate registers as well as the PDC share its layout.
+use core::convert::{Into, TryInto};
+use core::fmt;
+use pdc::PeripheralDmaController;
+use silica::register::{Field, RegisterCell, ReservedCell, RoRegisterCell};
+use uart::{BRGRegister, CRegister, Parity, SRegister};
+
+register! {
+    @impl_debug;
+    /// Mode Register
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        /// Samples each bit 8 times instead of 16, doubling the baud rate.
+        bool: pub oversampling_8, pub set_oversampling_8: 19;
+        bool: pub nine_bits, pub set_nine_bits: 17;
+        bool: pub msb_first, pub set_msb_first: 16;
+        /// 0 = normal, 1 = automatic echo, 2 = local loopback, 3 = remote loopback.
+        u8: pub channel_mode, pub set_channel_mode: 15, 14;
+        /// 0 = 1 bit, 1 = 1.5 bits, 2 = 2 bits.
+        u8: pub stop_bits, pub set_stop_bits: 13, 12;
+        Parity: pub parity, pub set_parity: 11, 9;
+        bool: pub synchronous, pub set_synchronous: 8;
+        /// Character length minus 5.
+        u8: pub char_length, pub set_char_length: 7, 6;
+        /// 0 = MCK, 1 = MCK / 8, 3 = SCK.
+        u8: pub clock_selection, pub set_clock_selection: 5, 4;
+        /// 0 = normal, other values select RS485, hardware handshaking, IrDA, SPI, etc.
+        u8: pub mode, pub set_mode: 3, 0;
+    }
+}
+
+#[repr(C)]
+pub struct Usart {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    /// Interrupt Enable Register
+    pub ier: RegisterCell<SRegister>,
+    /// Interrupt Disable Register
+    pub idr: RegisterCell<SRegister>,
+    /// Interrupt Mask Register
+    pub imr: RoRegisterCell<SRegister>,
+    /// Channel Status Register
+    pub csr: RoRegisterCell<SRegister>,
+    /// Receive Holding Register
+    pub rhr: RoRegisterCell<u32>,
+    /// Transmit Holding Register
+    pub thr: RegisterCell<u32>,
+    pub brgr: RegisterCell<BRGRegister>,
+    /// Receiver Time-out Register
+    pub rtor: RegisterCell<u32>,
+    /// Transmitter Timeguard Register
+    pub ttgr: RegisterCell<u32>,
+    reserved0: ReservedCell<[u32; 46]>,
+    /// Write Protection Mode Register
+    pub wpmr: RegisterCell<u32>,
+    /// Write Protection Status Register
+    pub wpsr: RoRegisterCell<u32>,
+    reserved1: ReservedCell<[u32; 5]>,
+    pub pdc: PeripheralDmaController,
+}
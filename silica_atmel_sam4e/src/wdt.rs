@@ -0,0 +1,194 @@
+//! Watchdog Timer
+//!
+//! The watchdog runs from reset with a 16s timeout. Its mode register can be written only once
+//! after a reset: it is either configured or disabled for good.
+//!
+//! ```ignore
+//! let mut wdt = Watchdog::configure(unsafe { &mut WDT }, &Config::new(2_000))?;
+//! loop {
+//!     wdt.feed();
+//! }
+//! ```
+use core::convert::{Into, TryInto};
+use core::fmt;
+use interrupts;
+use pmc::PeripheralId;
+use silica::register::{Field, RegisterCell, RoRegisterCell};
+
+/// Watchdog clock: the slow clock divided by 128.
+pub const CLOCK_HZ: u32 = 32_768 / 128;
+/// Largest counter value, about 16s.
+pub const MAX_COUNTER: u16 = 0xFFF;
+
+register! {
+    @optout_extract_insert;
+    /// Watchdog Timer Control Register (write only)
+    #[derive(Copy, Clone)]
+    pub struct CRegister(u32) {
+        bool: _, pub restart: 0;
+    }
+}
+register_impl_extract_insert!(@keyed CRegister: u32, 0xFF00_0000, 0xA500_0000);
+
+register! {
+    @impl_debug;
+    /// Watchdog Timer Mode Register (write once)
+    #[derive(Copy, Clone)]
+    pub struct MRegister(u32) {
+        bool: pub idle_halt, pub set_idle_halt: 29;
+        bool: pub debug_halt, pub set_debug_halt: 28;
+        /// A restart is an error while the counter is above this value.
+        u16: pub delta, pub set_delta: 27, 16;
+        bool: pub disabled, pub disable: 15;
+        /// Resets the processor only, not the peripherals.
+        bool: pub processor_reset_only, pub set_processor_reset_only: 14;
+        bool: pub reset_enabled, pub enable_reset: 13;
+        bool: pub fault_interrupt_enabled, pub enable_fault_interrupt: 12;
+        u16: pub counter, pub set_counter: 11, 0;
+    }
+}
+
+register! {
+    @impl_debug;
+    /// Watchdog Timer Status Register
+    #[derive(Copy, Clone)]
+    pub struct SRegister(u32) {
+        /// The watchdog was restarted too early. Cleared on read.
+        bool: pub error, _: 1;
+        /// The watchdog expired. Cleared on read.
+        bool: pub underflow, _: 0;
+    }
+}
+
+/// Watchdog Timer
+#[repr(C)]
+pub struct WatchdogTimer {
+    pub cr: RegisterCell<CRegister>,
+    pub mr: RegisterCell<MRegister>,
+    pub sr: RoRegisterCell<SRegister>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WdtError {
+    /// The timeout is not between 4ms and 16s.
+    InvalidTimeout,
+}
+
+/// Counter value expiring after `timeout_ms`.
+pub fn counter_for(timeout_ms: u32) -> Result<u16, WdtError> {
+    let counter = u64::from(timeout_ms) * u64::from(CLOCK_HZ) / 1000;
+    if counter == 0 || counter > u64::from(MAX_COUNTER) {
+        Err(WdtError::InvalidTimeout)
+    } else {
+        Ok(counter as u16)
+    }
+}
+
+/// What happens when the watchdog expires.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Resets the processor and the peripherals.
+    Reset,
+    /// Resets the processor only.
+    ProcessorReset,
+    /// Raises the WDT interrupt.
+    Interrupt,
+}
+
+pub struct Config {
+    timeout_ms: u32,
+    action: Action,
+    debug_halt: bool,
+    idle_halt: bool,
+}
+impl Config {
+    /// A full reset after `timeout_ms`, halted while debugging.
+    pub fn new(timeout_ms: u32) -> Config {
+        Config {
+            timeout_ms,
+            action: Action::Reset,
+            debug_halt: true,
+            idle_halt: false,
+        }
+    }
+    pub fn action(mut self, action: Action) -> Config {
+        self.action = action;
+        self
+    }
+    pub fn debug_halt(mut self, halt: bool) -> Config {
+        self.debug_halt = halt;
+        self
+    }
+    /// Stops the watchdog while the processor sleeps.
+    pub fn idle_halt(mut self, halt: bool) -> Config {
+        self.idle_halt = halt;
+        self
+    }
+}
+
+pub struct Watchdog {
+    wdt: &'static mut WatchdogTimer,
+}
+
+impl Watchdog {
+    /// Configures the watchdog. This can only be done once per reset.
+    pub fn configure(
+        wdt: &'static mut WatchdogTimer,
+        config: &Config,
+    ) -> Result<Watchdog, WdtError> {
+        let counter = counter_for(config.timeout_ms)?;
+
+        {
+            let mut mr = wdt.mr.get_mut();
+            mr.set_counter(counter);
+            mr.set_delta(MAX_COUNTER);
+            mr.disable(false);
+            mr.enable_reset(config.action != Action::Interrupt);
+            mr.set_processor_reset_only(config.action == Action::ProcessorReset);
+            mr.enable_fault_interrupt(config.action == Action::Interrupt);
+            mr.set_debug_halt(config.debug_halt);
+            mr.set_idle_halt(config.idle_halt);
+        }
+
+        if config.action == Action::Interrupt {
+            interrupts::enable(PeripheralId::Wdt);
+        }
+        Ok(Watchdog { wdt })
+    }
+
+    /// Stops the watchdog until the next reset.
+    pub fn disable(wdt: &'static mut WatchdogTimer) {
+        wdt.mr.get_mut().disable(true);
+    }
+
+    /// Restarts the countdown.
+    pub fn feed(&mut self) {
+        self.wdt.cr.get_mut().restart(true);
+    }
+
+    /// Reads and clears the status, from the WDT interrupt.
+    pub fn status(&mut self) -> SRegister {
+        self.wdt.sr.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_for() {
+        assert_eq!(Ok(512), counter_for(2_000));
+        assert_eq!(Ok(MAX_COUNTER), counter_for(15_997));
+        assert_eq!(Err(WdtError::InvalidTimeout), counter_for(17_000));
+        assert_eq!(Err(WdtError::InvalidTimeout), counter_for(1));
+        assert_eq!(Err(WdtError::InvalidTimeout), counter_for(u32::max_value()));
+    }
+
+    #[test]
+    fn test_keyed_restart() {
+        let mut cr = CRegister(0);
+        cr.restart(true);
+        assert_eq!(0xA500_0001, cr.0);
+    }
+}
@@ -1,6 +1,6 @@
 #![no_std]
 
-extern crate silica_atmel_sam4e;
+pub extern crate silica_atmel_sam4e;
 
 #[cfg(test)]
 mod tests {
@@ -1,6 +1,10 @@
 #![no_std]
 
-extern crate silica_atmel_sam4e8e;
+pub extern crate silica_atmel_sam4e8e;
+
+pub use cortexm4::silica;
+pub use sam4e::silica_arm_cortexm4 as cortexm4;
+pub use silica_atmel_sam4e8e::silica_atmel_sam4e as sam4e;
 
 #[cfg(test)]
 mod tests {